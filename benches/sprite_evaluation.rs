@@ -0,0 +1,43 @@
+// Per-scanline sprite work: clearing secondary OAM, evaluating all 64 sprites and loading the sprite units.
+// Run with `cargo bench --bench sprite_evaluation`.
+
+use std::{cell::RefCell, hint::black_box, rc::Rc};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use nemsys::ppu::PPU;
+
+/// OAM with sprites spread down the screen, `per_band` of them on every 16-line band
+fn spread_oam(per_band: usize) -> [u8; 256] {
+    let mut oam = [0xFF; 256];
+    for (i, sprite) in oam.chunks_mut(4).enumerate() {
+        let (band, slot) = (i / per_band, i % per_band);
+        sprite.copy_from_slice(&[(band * 16) as u8, i as u8, 0, (slot * 24) as u8]);
+    }
+    oam
+}
+
+fn evaluate_frame(ppu: &mut PPU) {
+    for scanline in 0..240 {
+        ppu.curr_scanline = scanline;
+        ppu.clear_secondary_oam();
+        ppu.evaluate_sprite();
+        ppu.fetch_sprite_data();
+    }
+}
+
+fn sprite_evaluation(c: &mut Criterion) {
+    for (name, oam) in [
+        ("hidden", [0xFF; 256]),
+        ("4 per band", spread_oam(4)),
+        ("16 per band", spread_oam(16)),
+    ] {
+        let mut ppu = PPU::new(Rc::new(RefCell::new(vec![0; 256 * 240])));
+        ppu.oam_dma(2, &oam);
+        c.bench_function(&format!("sprite evaluation, {}", name), |b| {
+            b.iter(|| evaluate_frame(black_box(&mut ppu)))
+        });
+    }
+}
+
+criterion_group!(benches, sprite_evaluation);
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+// Per-page read/write counters for the CPU and PPU buses.
+// Meant for finding out which parts of RAM a game actually uses, e.g. to narrow down a cheat search.
+
+use std::fmt::Write;
+
+pub const PAGE_SIZE: usize = 256;
+
+pub struct AccessStats {
+    pub reads: Vec<u64>,
+    pub writes: Vec<u64>,
+}
+
+impl AccessStats {
+    /// Counters for an address space of `size` bytes
+    pub fn new(size: usize) -> Self {
+        let pages = size / PAGE_SIZE;
+        Self {
+            reads: vec![0; pages],
+            writes: vec![0; pages],
+        }
+    }
+
+    pub fn record_read(&mut self, address: u16) {
+        let page = address as usize / PAGE_SIZE % self.reads.len();
+        self.reads[page] += 1;
+    }
+
+    pub fn record_write(&mut self, address: u16) {
+        let page = address as usize / PAGE_SIZE % self.writes.len();
+        self.writes[page] += 1;
+    }
+
+    /// One `space,start,end,reads,writes` row per page, with the header only when `header` is set
+    pub fn to_csv(&self, space: &str, header: bool) -> String {
+        let mut csv = String::new();
+        if header {
+            csv.push_str("space,start,end,reads,writes\n");
+        }
+        for (page, (reads, writes)) in self.reads.iter().zip(self.writes.iter()).enumerate() {
+            let start = page * PAGE_SIZE;
+            let _ = writeln!(
+                csv,
+                "{},${:04X},${:04X},{},{}",
+                space,
+                start,
+                start + PAGE_SIZE - 1,
+                reads,
+                writes
+            );
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_accesses_per_page() {
+        let mut stats = AccessStats::new(0x4000);
+        stats.record_read(0x0000);
+        stats.record_read(0x00FF);
+        stats.record_write(0x3FFF);
+
+        let csv = stats.to_csv("ppu", true);
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("space,start,end,reads,writes"));
+        assert_eq!(lines.next(), Some("ppu,$0000,$00FF,2,0"));
+        assert_eq!(lines.last(), Some("ppu,$3F00,$3FFF,0,1"));
+    }
+}
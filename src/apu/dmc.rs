@@ -0,0 +1,189 @@
+// Delta modulation channel ($4010-$4013)
+// Plays 1-bit delta encoded samples that it fetches from CPU memory itself. The fetch needs the CPU bus,
+// so the channel only requests it through pending_fetch() and the bus answers with load_sample().
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::get_bit;
+
+// NTSC output rates in CPU cycles
+const RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Dmc {
+    irq_enabled: bool,
+    loop_flag: bool,
+    timer_period: u16,
+    timer: u16,
+    pub output_level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    pub irq: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Self {
+            irq_enabled: false,
+            loop_flag: false,
+            timer_period: RATES[0],
+            timer: 0,
+            output_level: 0,
+
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+
+            irq: false,
+        }
+    }
+
+    /// `register` is 0-3 for $4010-$4013
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = get_bit(value.into(), 7) == 1;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.loop_flag = get_bit(value.into(), 6) == 1;
+                self.timer_period = RATES[(value & 0x0F) as usize];
+            }
+            1 => self.output_level = value & 0x7F,
+            2 => self.sample_address = 0xC000 + value as u16 * 64,
+            3 => self.sample_length = value as u16 * 16 + 1,
+            _ => {}
+        }
+    }
+
+    /// Bit 4 of $4015. Any write to $4015 also acknowledges the DMC IRQ.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    pub fn bytes_remaining(&self) -> u16 {
+        self.bytes_remaining
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// Address the channel wants read into its sample buffer, if it's empty and the sample isn't over
+    pub fn pending_fetch(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// Completes the fetch asked for by pending_fetch()
+    pub fn load_sample(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+        // the address wraps around to $8000, not $0000
+        self.current_address = match self.current_address {
+            0xFFFF => 0x8000,
+            address => address + 1,
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raises_irq_after_last_byte() {
+        let mut dmc = Dmc::new();
+        dmc.write(0, 0x80); // IRQ on, no loop
+        dmc.write(2, 0xFF); // $FFC0
+        dmc.write(3, 0x04); // 65 bytes
+        dmc.set_enabled(true);
+
+        for i in 0..65 {
+            assert!(!dmc.irq);
+            let address = dmc.pending_fetch().unwrap();
+            assert_eq!(address, if i < 64 { 0xFFC0 + i } else { 0x8000 });
+            dmc.load_sample(0);
+            // nothing more is fetched until the buffer has been emptied into the shift register
+            assert_eq!(dmc.pending_fetch(), None);
+            for _ in 0..(8 * RATES[0]) {
+                dmc.clock_timer();
+            }
+        }
+
+        assert!(dmc.irq);
+        assert_eq!(dmc.pending_fetch(), None);
+        dmc.set_enabled(false);
+        assert!(!dmc.irq);
+    }
+}
@@ -0,0 +1,170 @@
+// Frame counter ($4017)
+// Divides the CPU clock down to ~240Hz quarter frames and ~120Hz half frames for the length counters,
+// envelopes and linear counter, and raises the frame IRQ at the end of every 4-step sequence.
+// A $4017 write changes the mode and IRQ inhibit right away, but only restarts the sequence 3 or 4 CPU cycles later,
+// depending on whether it landed on an APU cycle (every other CPU cycle) or between two.
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::get_bit;
+
+pub enum FrameStep {
+    Quarter,
+    /// A half frame is always a quarter frame as well
+    Half,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FrameCounter {
+    five_step: bool,
+    irq_inhibit: bool,
+    pub irq: bool,
+    cycle: u32, // CPU cycles into the current sequence
+    // CPU cycles until a $4017 write restarts the sequence
+    pending_reset: Option<u8>,
+    // the next clock is the second CPU cycle of an APU cycle
+    between_apu_cycles: bool,
+}
+
+impl Default for FrameCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        Self {
+            five_step: false,
+            irq_inhibit: false,
+            irq: false,
+            cycle: 0,
+            pending_reset: None,
+            between_apu_cycles: false,
+        }
+    }
+
+    /// Sets the mode and IRQ inhibit (setting it acknowledges a pending IRQ) and schedules the restart of
+    /// the sequence. Selecting 5-step mode also clocks a half frame when the restart happens.
+    pub fn write(&mut self, value: u8) {
+        self.five_step = get_bit(value.into(), 7) == 1;
+        self.irq_inhibit = get_bit(value.into(), 6) == 1;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.restart();
+    }
+
+    /// Schedule the restart of the sequence a $4017 write does, keeping the current mode. A reset does this.
+    pub fn restart(&mut self) {
+        self.pending_reset = Some(if self.between_apu_cycles { 4 } else { 3 });
+    }
+
+    fn raise_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.irq = true;
+        }
+    }
+
+    /// Advance by one CPU cycle (NTSC timings)
+    pub fn clock(&mut self) -> Option<FrameStep> {
+        self.between_apu_cycles = !self.between_apu_cycles;
+        match self.pending_reset {
+            Some(1) => {
+                self.pending_reset = None;
+                self.cycle = 0;
+                return self.five_step.then_some(FrameStep::Half);
+            }
+            Some(delay) => self.pending_reset = Some(delay - 1),
+            None => {}
+        }
+
+        self.cycle += 1;
+        match (self.cycle, self.five_step) {
+            (7457, _) | (22371, _) => Some(FrameStep::Quarter),
+            (14913, _) => Some(FrameStep::Half),
+            // the 4-step IRQ flag is set on three consecutive cycles around the last step
+            (29828, false) => {
+                self.raise_irq();
+                None
+            }
+            (29829, false) => {
+                self.raise_irq();
+                Some(FrameStep::Half)
+            }
+            (29830, false) => {
+                self.raise_irq();
+                self.cycle = 0;
+                None
+            }
+            (37281, true) => Some(FrameStep::Half),
+            (37282, true) => {
+                self.cycle = 0;
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(counter: &mut FrameCounter, cycles: u32) -> (usize, usize) {
+        let (mut quarters, mut halves) = (0, 0);
+        for _ in 0..cycles {
+            match counter.clock() {
+                Some(FrameStep::Quarter) => quarters += 1,
+                Some(FrameStep::Half) => halves += 1,
+                None => {}
+            }
+        }
+        (quarters, halves)
+    }
+
+    #[test]
+    fn four_step_raises_irq_once_per_sequence() {
+        let mut counter = FrameCounter::new();
+        assert_eq!(run(&mut counter, 29827), (2, 1));
+        assert!(!counter.irq);
+        assert_eq!(run(&mut counter, 3), (0, 1));
+        assert!(counter.irq);
+        // wrapped around to the start of the next sequence
+        assert_eq!(run(&mut counter, 7457), (1, 0));
+    }
+
+    #[test]
+    fn inhibit_clears_and_blocks_irq() {
+        let mut counter = FrameCounter::new();
+        run(&mut counter, 29830);
+        assert!(counter.irq);
+
+        counter.write(0x40);
+        assert!(!counter.irq);
+        run(&mut counter, 3);
+        run(&mut counter, 29830);
+        assert!(!counter.irq);
+    }
+
+    #[test]
+    fn write_restarts_sequence_3_or_4_cycles_later() {
+        for (odd_cycles, delay) in [(0, 3), (1, 4)] {
+            let mut counter = FrameCounter::new();
+            run(&mut counter, 7000 + odd_cycles);
+            counter.write(0x00);
+            // the old sequence's quarter frame at 7457 never comes, the new one's is 7457 cycles after the restart
+            assert_eq!(run(&mut counter, delay + 7456), (0, 0));
+            assert_eq!(run(&mut counter, 1), (1, 0));
+        }
+    }
+
+    #[test]
+    fn five_step_never_raises_irq() {
+        let mut counter = FrameCounter::new();
+        counter.write(0x80);
+        assert_eq!(run(&mut counter, 3), (0, 1));
+        assert_eq!(run(&mut counter, 37282), (2, 2));
+        assert!(!counter.irq);
+    }
+}
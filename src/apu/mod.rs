@@ -0,0 +1,256 @@
+// Audio processing unit ($4000-$4017).
+// Channel timers are clocked once per CPU cycle through APU::clock, with their periods given in CPU cycles.
+// Length counters, envelopes and the triangle's linear counter are driven by quarter_frame/half_frame,
+// which the frame counter calls.
+
+pub mod dmc;
+pub mod frame_counter;
+pub mod noise;
+pub mod resampler;
+pub mod triangle;
+
+use dmc::Dmc;
+use frame_counter::{FrameCounter, FrameStep};
+use noise::Noise;
+use resampler::Resampler;
+use triangle::Triangle;
+
+use serde::{Deserialize, Serialize};
+
+use crate::utils::get_bit;
+
+/// Length counter load values, indexed by the top 5 bits of a channel's 4th register
+pub const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Volume envelope shared by the pulse and noise channels
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Envelope {
+    start: bool,
+    loop_flag: bool,
+    constant_volume: bool,
+    volume: u8, // constant volume, or the divider period when decaying
+    divider: u8,
+    decay_level: u8,
+}
+
+impl Envelope {
+    /// Low 6 bits of the channel's first register
+    pub fn write(&mut self, value: u8) {
+        self.loop_flag = get_bit(value.into(), 5) == 1;
+        self.constant_volume = get_bit(value.into(), 4) == 1;
+        self.volume = value & 0x0F;
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    pub fn quarter_frame(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay_level = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay_level > 0 {
+                self.decay_level -= 1;
+            } else if self.loop_flag {
+                self.decay_level = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay_level
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct APU {
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    pub frame_counter: FrameCounter,
+
+    // only set when something consumes audio, so headless runs don't pile up samples
+    #[serde(skip)]
+    resampler: Option<Resampler>,
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl APU {
+    pub fn new() -> Self {
+        Self {
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::new(),
+            resampler: None,
+        }
+    }
+
+    /// Reset: every channel silenced as by writing 0 to $4015, and the frame counter restarted in the mode
+    /// $4017 last set
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0);
+        self.frame_counter.restart();
+    }
+
+    /// Power-on: every register at 0, so the frame counter runs the 4-step sequence with its IRQ enabled.
+    /// Sample collection carries on.
+    pub fn power_on(&mut self) {
+        *self = Self {
+            resampler: self.resampler.take(),
+            ..Self::new()
+        };
+    }
+
+    /// The channels and frame counter, for a save state
+    pub fn save_state(&self) -> APU {
+        Self {
+            triangle: self.triangle.clone(),
+            noise: self.noise.clone(),
+            dmc: self.dmc.clone(),
+            frame_counter: self.frame_counter.clone(),
+            resampler: None,
+        }
+    }
+
+    /// Carry on from a save state's channels and frame counter. Sample collection carries on too.
+    pub fn load_state(&mut self, state: &APU) {
+        *self = Self {
+            resampler: self.resampler.take(),
+            ..state.save_state()
+        };
+    }
+
+    /// Start collecting output as samples at `sample_rate` Hz, drained with take_samples()
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler = Some(Resampler::new(sample_rate));
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.resampler
+            .as_mut()
+            .map(Resampler::take_samples)
+            .unwrap_or_default()
+    }
+
+    /// CPU write to $4000-$4017
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x4008..=0x400B => self.triangle.write(address - 0x4008, value),
+            0x400C..=0x400F => self.noise.write(address - 0x400C, value),
+            0x4010..=0x4013 => self.dmc.write(address - 0x4010, value),
+            0x4015 => {
+                self.triangle.set_enabled(get_bit(value.into(), 2) == 1);
+                self.noise.set_enabled(get_bit(value.into(), 3) == 1);
+                self.dmc.set_enabled(get_bit(value.into(), 4) == 1);
+            }
+            0x4017 => {
+                self.frame_counter.write(value);
+            }
+            _ => {}
+        }
+    }
+
+    /// $4015 read, which acknowledges the frame IRQ.
+    /// The pulse channel bits (0-1) stay clear until those channels exist.
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_counter.irq = false;
+        status
+    }
+
+    /// What a $4015 read would return, leaving the frame IRQ alone
+    pub fn peek_status(&self) -> u8 {
+        let mut status = 0;
+        if self.triangle.length_counter > 0 {
+            status |= 0b0000_0100;
+        }
+        if self.noise.length_counter > 0 {
+            status |= 0b0000_1000;
+        }
+        if self.dmc.bytes_remaining() > 0 {
+            status |= 0b0001_0000;
+        }
+        if self.frame_counter.irq {
+            status |= 0b0100_0000;
+        }
+        if self.dmc.irq {
+            status |= 0b1000_0000;
+        }
+        status
+    }
+
+    /// Advance every channel timer by one CPU cycle
+    pub fn clock(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        let step = self.frame_counter.clock();
+        self.frame_step(step);
+
+        if let Some(resampler) = self.resampler.as_mut() {
+            resampler.push(mix(
+                self.triangle.output(),
+                self.noise.output(),
+                self.dmc.output_level,
+            ));
+        }
+    }
+
+    fn frame_step(&mut self, step: Option<FrameStep>) {
+        match step {
+            Some(FrameStep::Quarter) => self.quarter_frame(),
+            Some(FrameStep::Half) => {
+                self.quarter_frame();
+                self.half_frame();
+            }
+            None => {}
+        }
+    }
+
+    pub fn quarter_frame(&mut self) {
+        self.triangle.quarter_frame();
+        self.noise.envelope.quarter_frame();
+    }
+
+    pub fn half_frame(&mut self) {
+        self.triangle.half_frame();
+        self.noise.half_frame();
+    }
+
+    /// Mixed triangle/noise/DMC output in 0.0-1.0
+    pub fn output(&self) -> f32 {
+        mix(
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output_level,
+        )
+    }
+}
+
+/// The nonlinear DAC approximation for the triangle/noise/DMC output pin
+fn mix(triangle: u8, noise: u8, dmc: u8) -> f32 {
+    if triangle == 0 && noise == 0 && dmc == 0 {
+        return 0.0;
+    }
+    let (triangle, noise, dmc) = (triangle as f32, noise as f32, dmc as f32);
+    159.79 / (1.0 / (triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0) + 100.0)
+}
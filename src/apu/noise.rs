@@ -0,0 +1,99 @@
+// Noise channel ($400C-$400F)
+
+use serde::{Deserialize, Serialize};
+
+use super::{Envelope, LENGTH_TABLE};
+use crate::utils::get_bit;
+
+// NTSC timer periods in CPU cycles
+const PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Noise {
+    enabled: bool,
+    halt: bool,
+    pub envelope: Envelope,
+    short_mode: bool,
+    timer_period: u16,
+    timer: u16,
+    shift_register: u16,
+    pub length_counter: u8,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            halt: false,
+            envelope: Envelope::default(),
+            short_mode: false,
+            timer_period: PERIODS[0],
+            timer: 0,
+            shift_register: 1, // loaded with 1 on power-up
+            length_counter: 0,
+        }
+    }
+
+    /// `register` is 0-3 for $400C-$400F
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.halt = get_bit(value.into(), 5) == 1;
+                self.envelope.write(value);
+            }
+            2 => {
+                self.short_mode = get_bit(value.into(), 7) == 1;
+                self.timer_period = PERIODS[(value & 0x0F) as usize];
+            }
+            3 => {
+                if self.enabled {
+                    self.length_counter = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.envelope.restart();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        // feedback is bit 0 XOR bit 6 in short mode, bit 0 XOR bit 1 otherwise
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    pub fn half_frame(&mut self) {
+        if !self.halt && self.length_counter > 0 {
+            self.length_counter -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 1 == 1 || self.length_counter == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
@@ -0,0 +1,62 @@
+// Downsamples the APU's per-CPU-cycle output to an audio device rate.
+// Every output sample is the average of the ~40 input samples that fall into its period, which doubles as a
+// crude low-pass filter against the aliasing a plain decimation would produce.
+
+/// NTSC CPU clock, the rate APU output is produced at
+pub const CPU_CLOCK_HZ: f64 = 1_789_773.0;
+
+pub struct Resampler {
+    cycles_per_sample: f64,
+    elapsed: f64,
+    sum: f32,
+    count: u32,
+    samples: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            cycles_per_sample: CPU_CLOCK_HZ / sample_rate as f64,
+            elapsed: 0.0,
+            sum: 0.0,
+            count: 0,
+            samples: vec![],
+        }
+    }
+
+    /// Feed one CPU cycle's worth of output
+    pub fn push(&mut self, value: f32) {
+        self.sum += value;
+        self.count += 1;
+        self.elapsed += 1.0;
+        if self.elapsed >= self.cycles_per_sample {
+            self.elapsed -= self.cycles_per_sample;
+            self.samples.push(self.sum / self.count as f32);
+            self.sum = 0.0;
+            self.count = 0;
+        }
+    }
+
+    /// Samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn produces_samples_at_the_output_rate() {
+        let mut resampler = Resampler::new(44_100);
+        for cycle in 0..(CPU_CLOCK_HZ as usize) {
+            resampler.push(if cycle % 2 == 0 { 1.0 } else { 0.0 });
+        }
+
+        let samples = resampler.take_samples();
+        assert!(samples.len().abs_diff(44_100) <= 1, "{}", samples.len());
+        assert!(samples.iter().all(|&sample| (sample - 0.5).abs() < 0.05));
+        assert!(resampler.take_samples().is_empty());
+    }
+}
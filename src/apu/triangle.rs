@@ -0,0 +1,105 @@
+// Triangle channel ($4008-$400B)
+
+use serde::{Deserialize, Serialize};
+
+use super::LENGTH_TABLE;
+use crate::utils::get_bit;
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Triangle {
+    enabled: bool,
+    control: bool, // also halts the length counter
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    timer_period: u16,
+    timer: u16,
+    step: usize,
+    pub length_counter: u8,
+}
+
+impl Default for Triangle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            timer_period: 0,
+            timer: 0,
+            step: 0,
+            length_counter: 0,
+        }
+    }
+
+    /// `register` is 0-3 for $4008-$400B
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = get_bit(value.into(), 7) == 1;
+                self.linear_reload_value = value & 0x7F;
+            }
+            2 => self.timer_period = (self.timer_period & 0x700) | value as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0xFF) | ((value as u16 & 0b111) << 8);
+                if self.enabled {
+                    self.length_counter = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period;
+        if self.linear_counter > 0 && self.length_counter > 0 {
+            self.step = (self.step + 1) % SEQUENCE.len();
+        }
+    }
+
+    pub fn quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn half_frame(&mut self) {
+        if !self.control && self.length_counter > 0 {
+            self.length_counter -= 1;
+        }
+    }
+
+    /// The sequencer just stops when silenced, so the last level keeps being output
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step]
+    }
+}
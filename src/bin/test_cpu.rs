@@ -6,27 +6,44 @@ extern crate simplelog;
 
 use std::cell::RefCell;
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
 use std::panic;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::rc::Rc;
 use std::thread::sleep;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, Result};
-use clap::{Parser, Subcommand};
-use nemsys::mappers::{Mapper, NROM};
-use nemsys::ppu::memory::VRAM;
+use clap::{Args, Parser, Subcommand};
+use nemsys::access_stats::AccessStats;
+use nemsys::capture;
+use nemsys::data_dirs::DataDirs;
+use nemsys::emulator::Emulator;
+use nemsys::game_genie::GameGenieCode;
+use nemsys::mappers::{self, Cartridge};
+use nemsys::memory_map::MemoryMap;
 use nemsys::ppu::PPU;
+use nemsys::sav;
 use simplelog::*;
 
-use nemsys::cpu::jsontest::{self, CpuTestState, InstructionTestCase, MemTest};
-use nemsys::cpu::Cpu;
+use nemsys::cpu::jsontest::{self, InstructionTestCase, OpcodeStatus, Progress};
+use nemsys::cpu::{trace, Cpu};
+use nemsys::test_rom::{self, TestRomStatus};
+use nemsys::test_suites;
+
+// what real time is for `bench`, in NTSC frames per second
+const NTSC_FRAME_RATE: f64 = 60.0988;
 
 #[derive(Parser)]
 #[command(name = "nemsys")]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Keep saves, logs and test progress in nemsys-data beside the executable instead of the XDG directories
+    #[arg(long, global = true)]
+    portable: bool,
 }
 
 #[derive(Subcommand)]
@@ -35,26 +52,449 @@ enum Commands {
         #[command(subcommand)]
         subcommand: TestSubcommand,
     },
+    /// Download the SingleStepTests JSON suite and the test ROMs into the cache
+    FetchTests {
+        /// Cache directory to fill (defaults to $NEMSYS_CACHE_DIR or ~/.cache/nemsys/tests)
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        /// Only fetch the test ROMs, not the ~1 GB of JSON tests
+        #[arg(long)]
+        roms_only: bool,
+    },
+    /// Show how the CPU and PPU address spaces are populated for a cartridge
+    Map { rom: String },
+    /// Run a ROM headless
+    Run(RunArgs),
+    /// Run a ROM headless as fast as possible and report frames per second and MIPS
+    Bench {
+        rom: String,
+        /// How many frames to run
+        #[arg(long, default_value_t = 600)]
+        frames: usize,
+        /// Sync CPU and PPU once per scanline instead of after every instruction
+        #[arg(long)]
+        fast: bool,
+    },
+    /// Report the pixels that differ between same-named PNGs in two directories, like two --burst-screenshots runs
+    CompareFrames { dir_a: PathBuf, dir_b: PathBuf },
+    /// Move battery saves between nemsys and other emulators (the .sav is a raw PRG-RAM dump either way)
+    Sav {
+        #[command(subcommand)]
+        subcommand: SavSubcommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum SavSubcommand {
+    /// Use FILE, a .sav from another emulator, as the save for ROM
+    Import { rom: String, file: PathBuf },
+    /// Copy the save for ROM to FILE, for another emulator
+    Export { rom: String, file: PathBuf },
+}
+
+#[derive(Args)]
+struct RunArgs {
+    rom: String,
+    /// Echo the test ROM text at $6004 to stdout once the status at $6000 is final, and exit with that status
+    #[arg(long)]
+    test_output: bool,
+    /// Stop after this many frames
+    #[arg(long)]
+    max_frames: Option<usize>,
+    /// Game Genie code to apply, can be given more than once
+    #[arg(long = "game-genie", value_name = "CODE", value_parser = GameGenieCode::parse)]
+    game_genie: Vec<GameGenieCode>,
+    /// Power-on CPU/PPU clock alignment: PPU dots the CPU starts ahead by, which moves register accesses relative
+    /// to the PPU's dots
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..4))]
+    alignment: u8,
+    /// Sync CPU and PPU once per scanline instead of after every instruction
+    #[arg(long)]
+    fast: bool,
+    /// Print every OAM DMA: source page, scanline and the sprite bytes it changed
+    #[arg(long)]
+    log_oam_dma: bool,
+    /// Print OAM bytes that $2004 or sprite evaluation read before anything wrote them, once per frame
+    #[arg(long)]
+    oam_poisoning: bool,
+    /// Set the sprite overflow flag with the hardware's buggy scan, false positives and negatives included
+    #[arg(long)]
+    accurate_sprite_overflow: bool,
+    /// Stop once the CPU sits in a JMP to itself that no interrupt can leave
+    #[arg(long)]
+    detect_loop: bool,
+    /// Count reads and writes per 256-byte page of CPU and PPU memory and write them here as CSV when the run ends
+    #[arg(long)]
+    access_stats: Option<PathBuf>,
+    /// Save the last frame here as a PPM when the run ends
+    #[arg(long)]
+    screenshot: Option<PathBuf>,
+    /// Save the last frame's NES color indices here (see capture::index_bytes) when the run ends
+    #[arg(long)]
+    index_dump: Option<PathBuf>,
+    /// Save every frame in START..END (END excluded, the first frame is 1) as a numbered PNG
+    #[arg(long, value_name = "START..END", value_parser = parse_frame_range)]
+    burst_screenshots: Option<Range<usize>>,
+    /// Directory for --burst-screenshots
+    #[arg(long, default_value = ".")]
+    burst_dir: PathBuf,
+    /// Write each frame's number and a checksum of CPU, RAM and PPU state here, one line per frame.
+    /// Diffing the logs of two builds finds the first frame where they diverge.
+    #[arg(long)]
+    checksum_log: Option<PathBuf>,
+    /// Write every instruction here in nestest.log's format, with the PPU's real scanline and dot, to diff against
+    /// other emulators' traces. Positions are only exact without --fast.
+    #[arg(long)]
+    trace: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum TestSubcommand {
-    Nestest,
-    Singlestep,
+    Nestest {
+        /// Start at $C000 like nestest.log does and compare the trace with it, stopping at the first line that
+        /// differs
+        #[arg(long)]
+        compare: bool,
+    },
+    Singlestep {
+        /// Where per-opcode results are kept between runs [default: singlestep-progress.json in the data directory]
+        #[arg(long)]
+        progress: Option<PathBuf>,
+        /// Skip opcodes that already passed; fails if there is no progress file to resume from
+        #[arg(long, conflicts_with = "fresh")]
+        resume: bool,
+        /// Ignore any saved progress and test every opcode again
+        #[arg(long)]
+        fresh: bool,
+        /// Also compare every bus access with the tests' per-cycle log, dummy reads and writes included. The tests
+        /// assume flat RAM, so cases touching $2000-$401F reach the PPU and APU registers here and can fail.
+        #[arg(long)]
+        bus: bool,
+    },
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    let dirs = DataDirs::new(cli.portable);
 
     match &cli.command {
         Commands::Test { subcommand } => match subcommand {
-            TestSubcommand::Nestest => run_nestest(),
-            TestSubcommand::Singlestep => run_single_step_tests(),
+            TestSubcommand::Nestest { compare } => {
+                if *compare {
+                    compare_nestest()
+                } else {
+                    run_nestest(&dirs)
+                }
+            }
+            TestSubcommand::Singlestep {
+                progress,
+                resume,
+                fresh,
+                bus,
+            } => {
+                let progress = match progress {
+                    Some(path) => path.clone(),
+                    None => dirs.test_runs()?.join("singlestep-progress.json"),
+                };
+                run_single_step_tests(&progress, *resume, *fresh, *bus)
+            }
         },
+        Commands::FetchTests { dir, roms_only } => fetch_tests(dir.clone(), *roms_only),
+        Commands::Map { rom } => print_memory_map(rom),
+        Commands::Run(args) => run_rom(args, &dirs),
+        Commands::Bench { rom, frames, fast } => bench(rom, *frames, *fast),
+        Commands::CompareFrames { dir_a, dir_b } => compare_frames(dir_a, dir_b),
+        Commands::Sav { subcommand } => match subcommand {
+            SavSubcommand::Import { rom, file } => {
+                let path = sav::import(rom, file, &dirs.saves()?)?;
+                println!("Imported {} to {}", file.display(), path.display());
+                Ok(())
+            }
+            SavSubcommand::Export { rom, file } => {
+                let path = sav::export(rom, file, &dirs.saves()?)?;
+                println!("Exported {} to {}", path.display(), file.display());
+                Ok(())
+            }
+        },
+    }
+}
+
+fn run_rom(args: &RunArgs, dirs: &DataDirs) -> Result<()> {
+    let mut emulator = Emulator::headless();
+    emulator.set_save_dir(dirs.saves()?);
+    emulator.set_alignment(args.alignment);
+    emulator.set_fast_stepping(args.fast);
+    emulator.set_game_genie_codes(args.game_genie.clone());
+    emulator.ppu.borrow_mut().record_oam_dma = args.log_oam_dma;
+    emulator.ppu.borrow_mut().check_oam_poisoning = args.oam_poisoning;
+    emulator.ppu.borrow_mut().accurate_sprite_overflow = args.accurate_sprite_overflow;
+    emulator.load_rom(&args.rom)?;
+    if args.access_stats.is_some() {
+        emulator.cpu.memory.access_stats = Some(AccessStats::new(0x10000));
+        *emulator.ppu.borrow().vram.access_stats.borrow_mut() = Some(AccessStats::new(0x4000));
+    }
+
+    let mut checksum_log = match &args.checksum_log {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+
+    if args.burst_screenshots.is_some() {
+        std::fs::create_dir_all(&args.burst_dir)?;
+    }
+
+    let trace = match &args.trace {
+        Some(path) => {
+            let file = Rc::new(RefCell::new(BufWriter::new(File::create(path)?)));
+            let sink = Rc::clone(&file);
+            emulator.cpu.set_trace_hook(move |traced| {
+                // a failed write shows up when the file is flushed at the end
+                let _ = writeln!(
+                    sink.borrow_mut(),
+                    "{}",
+                    trace::nestest_line(traced, traced.ppu)
+                );
+            });
+            Some(file)
+        }
+        None => None,
+    };
+
+    let mut last_status = TestRomStatus::NotStarted;
+    let mut reset_frame = None;
+
+    while args.max_frames.is_none_or(|max| emulator.frame_count < max) {
+        emulator.run_frame();
+        if let Some(range) = &args.burst_screenshots {
+            if range.contains(&emulator.frame_count) {
+                let path = args.burst_dir.join(burst_file_name(emulator.frame_count));
+                capture::write_png(&path, &emulator.framebuffer.borrow())?;
+            }
+        }
+        if let Some(log) = &mut checksum_log {
+            writeln!(
+                log,
+                "{} {:016x}",
+                emulator.frame_count,
+                emulator.state_checksum()
+            )?;
+        }
+        for transfer in emulator.ppu.borrow_mut().take_oam_dma_log() {
+            println!("frame {}: {}", emulator.frame_count, transfer);
+        }
+        let poisoned = emulator.ppu.borrow_mut().take_oam_poisoned_reads();
+        if !poisoned.is_empty() {
+            let bytes: Vec<String> = poisoned
+                .iter()
+                .map(|offset| {
+                    let field = ["y", "tile", "attributes", "x"][(offset % 4) as usize];
+                    format!("sprite {} {}", offset / 4, field)
+                })
+                .collect();
+            println!(
+                "frame {}: read uninitialized OAM: {}",
+                emulator.frame_count,
+                bytes.join(", ")
+            );
+        }
+
+        if args.test_output {
+            let status = test_rom::status(&mut emulator.cpu.memory);
+            match status {
+                TestRomStatus::ResetRequested if last_status != status => {
+                    // the ROM needs at least 100ms before the reset arrives
+                    reset_frame = Some(emulator.frame_count + 6);
+                }
+                TestRomStatus::Finished(code) => {
+                    println!(
+                        "{}",
+                        test_rom::output_text(&mut emulator.cpu.memory).trim_end()
+                    );
+                    if let Some(path) = &args.access_stats {
+                        write_access_stats(&emulator, path)?;
+                    }
+                    write_captures(&emulator, args)?;
+                    if let Some(log) = &mut checksum_log {
+                        log.flush()?;
+                    }
+                    if let Some(trace) = &trace {
+                        trace.borrow_mut().flush()?;
+                    }
+                    process::exit(code as i32);
+                }
+                _ => {}
+            }
+            if reset_frame == Some(emulator.frame_count) {
+                reset_frame = None;
+                emulator.reset();
+            }
+            last_status = status;
+        }
+
+        // a ROM waiting for its requested reset spins the same way, so leave it be until the reset is delivered
+        if args.detect_loop && reset_frame.is_none() {
+            if let Some(pc) = emulator.stuck_at() {
+                eprintln!(
+                    "stopped at frame {}: JMP to itself at ${:04X} with interrupts disabled",
+                    emulator.frame_count, pc
+                );
+                break;
+            }
+        }
+    }
+
+    if let Some(trace) = &trace {
+        trace.borrow_mut().flush()?;
+    }
+    if let Some(path) = &args.access_stats {
+        write_access_stats(&emulator, path)?;
+    }
+    write_captures(&emulator, args)?;
+    emulator.save_battery_ram()?;
+    if args.test_output {
+        return Err(anyhow!(
+            "test ROM didn't report a result within {} frames",
+            emulator.frame_count
+        ));
+    }
+    Ok(())
+}
+
+/// Frames rendered and instructions executed per second of wall time. Nothing is saved afterwards.
+fn bench(rom: &str, frames: usize, fast: bool) -> Result<()> {
+    let mut emulator = Emulator::headless();
+    emulator.set_fast_stepping(fast);
+    emulator.load_rom(rom)?;
+
+    let start = Instant::now();
+    while emulator.frame_count < frames {
+        emulator.run_frame();
+    }
+    let seconds = start.elapsed().as_secs_f64();
+
+    let fps = frames as f64 / seconds;
+    println!(
+        "{} frames in {:.2}s: {:.1} fps ({:.1}x real time), {:.2} MIPS",
+        frames,
+        seconds,
+        fps,
+        fps / NTSC_FRAME_RATE,
+        emulator.cpu.num_instructions as f64 / seconds / 1e6
+    );
+    Ok(())
+}
+
+fn write_captures(emulator: &Emulator, args: &RunArgs) -> Result<()> {
+    if let Some(path) = &args.screenshot {
+        capture::write_ppm(path, &emulator.framebuffer.borrow())?;
     }
+    if let Some(path) = &args.index_dump {
+        capture::write_indices(path, emulator.ppu.borrow().index_frame())?;
+    }
+    Ok(())
+}
+
+fn parse_frame_range(range: &str) -> Result<Range<usize>> {
+    let (start, end) = range
+        .split_once("..")
+        .ok_or_else(|| anyhow!("expected START..END"))?;
+    let (start, end) = (start.parse()?, end.parse()?);
+    if start >= end {
+        return Err(anyhow!("{} is an empty range", range));
+    }
+    Ok(start..end)
+}
+
+// zero-padded so the files list in frame order
+fn burst_file_name(frame: usize) -> String {
+    format!("frame-{:06}.png", frame)
+}
+
+fn compare_frames(dir_a: &Path, dir_b: &Path) -> Result<()> {
+    let mut names: Vec<_> = std::fs::read_dir(dir_a)?
+        .map(|entry| Ok(entry?.file_name()))
+        .collect::<Result<_>>()?;
+    names.retain(|name| Path::new(name).extension().is_some_and(|ext| ext == "png"));
+    names.sort();
+
+    let (mut compared, mut differing) = (0, 0);
+    for name in names {
+        let path_b = dir_b.join(&name);
+        if !path_b.exists() {
+            println!(
+                "{}: missing from {}",
+                name.to_string_lossy(),
+                dir_b.display()
+            );
+            continue;
+        }
+        let a = capture::read_png(&dir_a.join(&name))?;
+        let b = capture::read_png(&path_b)?;
+        compared += 1;
+        let diff = capture::diff_frames(&a, &b);
+        if let Some((x, y)) = diff.first {
+            differing += 1;
+            println!(
+                "{}: {} pixels differ, first at ({}, {})",
+                name.to_string_lossy(),
+                diff.pixels,
+                x,
+                y
+            );
+        }
+    }
+    println!("{} of {} frames differ", differing, compared);
+    if differing > 0 {
+        process::exit(1);
+    }
+    Ok(())
 }
 
-fn run_nestest() -> Result<()> {
+fn write_access_stats(emulator: &Emulator, path: &Path) -> Result<()> {
+    let mut csv = String::new();
+    if let Some(stats) = &emulator.cpu.memory.access_stats {
+        csv.push_str(&stats.to_csv("cpu", true));
+    }
+    if let Some(stats) = emulator.ppu.borrow().vram.access_stats.borrow().as_ref() {
+        csv.push_str(&stats.to_csv("ppu", csv.is_empty()));
+    }
+    std::fs::write(path, csv)?;
+    Ok(())
+}
+
+fn fetch_tests(dir: Option<PathBuf>, roms_only: bool) -> Result<()> {
+    let dir = dir.unwrap_or_else(test_suites::cache_dir);
+    let mut files = test_suites::test_rom_files();
+    if !roms_only {
+        files.extend(test_suites::single_step_files());
+    }
+
+    println!("Fetching {} files into {}", files.len(), dir.display());
+    let report = test_suites::fetch(&dir, &files)?;
+    println!(
+        "{} downloaded, {} already cached, {} failed",
+        report.downloaded,
+        report.cached,
+        report.failed.len()
+    );
+    for (path, err) in &report.failed {
+        println!("  {}: {}", path, err);
+    }
+
+    if !report.failed.is_empty() {
+        return Err(anyhow!("{} files couldn't be fetched", report.failed.len()));
+    }
+    Ok(())
+}
+
+fn print_memory_map(rom: &str) -> Result<()> {
+    let mapper = mappers::load_rom(rom)?;
+    print!("{}", MemoryMap::new(mapper.as_ref()));
+
+    Ok(())
+}
+
+fn run_nestest(dirs: &DataDirs) -> Result<()> {
     CombinedLogger::init(vec![
         TermLogger::new(
             LevelFilter::Info,
@@ -65,7 +505,7 @@ fn run_nestest() -> Result<()> {
         WriteLogger::new(
             LevelFilter::Info,
             Config::default(),
-            File::create("nemsys.log").unwrap(),
+            File::create(dirs.logs()?.join("nestest.log")).unwrap(),
         ),
     ])
     .unwrap();
@@ -73,12 +513,16 @@ fn run_nestest() -> Result<()> {
     let temp_fb = Rc::new(RefCell::new(vec![]));
     let ppu = Rc::new(RefCell::new(PPU::new(Rc::clone(&temp_fb))));
     let mut cpu = Cpu::new(Rc::clone(&ppu));
-    let mem = &mut cpu.memory;
-    let mut vram = VRAM::new(); // unused for our tests
 
-    NROM::from_ines_rom("nestest/nestest.nes", &mut vram, mem)?;
+    let rom = test_suites::locate("nestest/nestest.nes");
+    cpu.memory.mapper = Some(Cartridge::from_ines_rom(&rom.to_string_lossy())?.into_mapper()?);
 
     cpu.init_pc();
+    // the PPU isn't run here, so its position is worked out from the cycle count as nestest.log does
+    cpu.set_trace_hook(|traced| {
+        let ppu = trace::nestest_ppu_position(traced.start_cycle);
+        info!("{}", trace::nestest_line(traced, ppu));
+    });
 
     let start_time = SystemTime::now();
 
@@ -98,7 +542,44 @@ fn run_nestest() -> Result<()> {
     Ok(())
 }
 
-fn run_single_step_tests() -> Result<()> {
+/// Runs nestest.nes in its automated mode and checks each instruction's trace line against nestest.log
+fn compare_nestest() -> Result<()> {
+    let golden = std::fs::read_to_string(test_suites::locate("nestest/nestest.log"))?;
+    let golden: Vec<&str> = golden.lines().collect();
+
+    let ppu = Rc::new(RefCell::new(PPU::new(Rc::new(RefCell::new(vec![])))));
+    let mut cpu = Cpu::new(ppu);
+    let rom = test_suites::locate("nestest/nestest.nes");
+    cpu.memory.mapper = Some(Cartridge::from_ines_rom(&rom.to_string_lossy())?.into_mapper()?);
+    cpu.registers.program_counter = 0xC000;
+    // the reset sequence nestest.log starts after
+    cpu.num_cycles = 7;
+
+    let lines = Rc::new(RefCell::new(vec![]));
+    let sink = Rc::clone(&lines);
+    cpu.set_trace_hook(move |traced| {
+        let ppu = trace::nestest_ppu_position(traced.start_cycle);
+        sink.borrow_mut().push(trace::nestest_line(traced, ppu));
+    });
+
+    for (i, expected) in golden.iter().enumerate() {
+        cpu.tick_ins();
+        let actual = lines.borrow_mut().pop().unwrap_or_default();
+        if actual != *expected {
+            return Err(anyhow!(
+                "trace differs from nestest.log at line {}:\nexpected {}\n     got {}",
+                i + 1,
+                expected,
+                actual
+            ));
+        }
+    }
+    println!("all {} lines match nestest.log", golden.len());
+    Ok(())
+}
+
+/// Without `--fresh` or `--resume`, saved progress is used if there is any
+fn run_single_step_tests(progress_path: &Path, resume: bool, fresh: bool, bus: bool) -> Result<()> {
     CombinedLogger::init(vec![TermLogger::new(
         LevelFilter::Error,
         Config::default(),
@@ -107,70 +588,85 @@ fn run_single_step_tests() -> Result<()> {
     )])
     .unwrap();
 
-    let all_tests = jsontest::load_json_tests("nes6502/v1")?.enumerate();
+    if resume && !progress_path.exists() {
+        return Err(anyhow!(
+            "no progress to resume from at {}",
+            progress_path.display()
+        ));
+    }
+    let mut progress = if fresh {
+        Progress::default()
+    } else {
+        Progress::load(progress_path)?
+    };
+    let skip = progress.passed();
+    if !skip.is_empty() {
+        println!(
+            "Resuming from {}, skipping {} passed opcodes",
+            progress_path.display(),
+            skip.len()
+        );
+    }
+
+    let suite = test_suites::locate("nes6502/v1");
+    let all_tests = jsontest::load_json_tests(&suite.to_string_lossy(), &skip)?;
 
-    for (i, case_set) in all_tests {
+    for case_set in all_tests {
         let num_cases = case_set.test_cases.len();
-        for case in case_set.test_cases {
+        if num_cases == 0 {
+            // unimplemented opcode, nothing was loaded for it
+            println!("{:02x}.................... [SKIPPED]", case_set.opcode);
+            continue;
+        }
+        let mut status = OpcodeStatus::Passed { cases: num_cases };
+        for (i, case) in case_set.test_cases.into_iter().enumerate() {
             let result = panic::catch_unwind(|| {
-                test_instruction(case.clone());
+                test_instruction(case.clone(), bus);
             });
             if result.is_err() {
                 error!("{:#?}", case);
-                println!("{:x}.................... [FAILED]", case_set.opcode);
-                println!("Passed {}/{} test cases", i + 1, num_cases);
-                return Err(anyhow!(case.name));
+                println!("{:02x}.................... [FAILED]", case_set.opcode);
+                println!("Passed {}/{} test cases", i, num_cases);
+                status = OpcodeStatus::Failed {
+                    case: i,
+                    name: case.name,
+                };
+                break;
             }
         }
 
-        println!("{:x}.................... [PASSED]", case_set.opcode);
-        let mut checkpoint_file = File::create("/tmp/nemsys.ck").unwrap();
-        writeln!(checkpoint_file, "{:x}", case_set.opcode).unwrap();
+        if let OpcodeStatus::Passed { .. } = status {
+            println!("{:02x}.................... [PASSED]", case_set.opcode);
+        }
+        progress.record(case_set.opcode, status);
+        progress.save(progress_path)?;
     }
 
-    Ok(())
-}
-
-fn init_cpu_test_state(state: CpuTestState, cpu: &mut Cpu) {
-    cpu.registers.stack_pointer = state.s;
-    cpu.registers.accumulator = state.a;
-    cpu.registers.index_x = state.x;
-    cpu.registers.index_y = state.y;
-    cpu.registers.processor_status = state.p;
-    cpu.registers.program_counter = state.pc;
-
-    for MemTest(address, value) in state.ram {
-        cpu.memory.store_absolute(address, value);
+    let failed = progress.failed();
+    if failed.is_empty() {
+        return Ok(());
     }
-}
-
-fn assert_cpu_test_state(state: CpuTestState, cpu: &Cpu) {
-    assert_eq!(cpu.registers.stack_pointer, state.s);
-    assert_eq!(cpu.registers.accumulator, state.a);
-    assert_eq!(cpu.registers.index_x, state.x);
-    assert_eq!(cpu.registers.index_y, state.y);
-    assert_eq!(
-        cpu.registers.processor_status | 0b0010_0000,
-        state.p | 0b0010_0000
-    );
-    assert_eq!(cpu.registers.program_counter, state.pc);
-
-    for MemTest(address, value) in state.ram {
-        assert_eq!(cpu.memory.buffer[address as usize], value);
+    for (opcode, status) in failed.iter() {
+        if let OpcodeStatus::Failed { case, name } = status {
+            println!("{}: case {} ({})", opcode, case, name);
+        }
     }
+    Err(anyhow!("{} opcodes failed", failed.len()))
 }
 
-fn test_instruction(case: InstructionTestCase) {
+fn test_instruction(case: InstructionTestCase, bus: bool) {
     let temp_fb = Rc::new(RefCell::new(vec![]));
     let ppu = Rc::new(RefCell::new(PPU::new(Rc::clone(&temp_fb))));
     let mut cpu = Cpu::new(Rc::clone(&ppu));
 
-    let initial_state = case.initial;
-    init_cpu_test_state(initial_state.clone(), &mut cpu);
+    case.initial.load_into(&mut cpu);
+    cpu.memory.databus_logger.enabled = bus;
 
     cpu.tick_ins();
 
-    let final_state = case.r#final;
-    assert_cpu_test_state(final_state, &cpu); // assert after
-                                              // assert_eq!(cpu.memory.databus_logger.log, case.cycles);
+    case.r#final.assert_matches(&cpu); // assert after
+    case.assert_cycle_count(&cpu);
+    if bus {
+        case.assert_bus_activity(&cpu);
+    }
 }
@@ -1,20 +1,34 @@
 use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use std::{default, process};
 
+use clap::builder::PossibleValuesParser;
+use clap::{Parser, ValueEnum};
 use log::{error, LevelFilter};
-use nemsys::cpu::Cpu;
+use nemsys::capture;
+use nemsys::cheats::Cheat;
+use nemsys::data_dirs::DataDirs;
+use nemsys::emulator::{Emulator, SCREEN_HEIGHT, SCREEN_WIDTH};
+use nemsys::filters::{self, Frame, FrameFilter, FILTER_NAMES};
+use nemsys::game_genie::GameGenieCode;
+use nemsys::hotkeys::{Hotkey, Hotkeys};
+use nemsys::movie::Movie;
 use sdl2::video::{Window, WindowContext};
+use serde::Serialize;
 
-use nemsys::mappers::{Mapper, NROM};
+use nemsys::ppu::palette::NtscPalette;
 use nemsys::ppu::{self, PPU};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
 use sdl2::pixels::{Color, PixelFormat};
 use sdl2::rect::Rect;
-use sdl2::render::{Canvas, Texture, WindowCanvas};
+use sdl2::render::{Canvas, Texture, TextureCreator, WindowCanvas};
 use sdl2::Sdl;
-use simplelog::{ColorChoice, CombinedLogger, Config, TermLogger, TerminalMode};
+use simplelog::{ColorChoice, CombinedLogger, Config, ConfigBuilder, TermLogger, TerminalMode};
 use std::thread::sleep;
 use std::time::Duration;
 
@@ -36,82 +50,163 @@ static WHITE: Color = Color::RGB(255, 255, 255);
 static WIDTH: usize = 256;
 static HEIGHT: usize = 240;
 
-struct Display {
+const SAMPLE_RATE: i32 = 44_100;
+// ~100ms: when emulation runs ahead of playback the oldest samples are dropped so latency can't build up
+const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize / 10;
+
+type SampleBuffer = Arc<Mutex<VecDeque<f32>>>;
+
+/// Plays whatever the emulator pushed into the shared ring buffer since the last callback
+struct AudioOutput {
+    samples: SampleBuffer,
+    last: f32,
+}
+
+impl AudioCallback for AudioOutput {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let mut samples = self.samples.lock().unwrap();
+        for sample in out.iter_mut() {
+            // on an underrun hold the last level rather than dropping to 0, which would click
+            if let Some(next) = samples.pop_front() {
+                self.last = next;
+            }
+            *sample = self.last;
+        }
+    }
+}
+
+/// Where frames or sound go: through SDL, or nowhere
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Backend {
+    Sdl,
+    Null,
+}
+
+/// The window and the renderer drawing into it. The renderer's TextureCreator has to be made from the canvas by
+/// the caller and outlive the Display, which borrows it for its texture.
+fn open_window(ctx: &Sdl, width: u32, height: u32) -> Result<WindowCanvas, String> {
+    let window = ctx
+        .video()?
+        .window("Nemsys", width * 2, height * 2)
+        .position_centered()
+        .opengl()
+        .build()
+        .map_err(|err| format!("failed to create window: {}", err))?;
+
+    window
+        .into_canvas()
+        .present_vsync()
+        .build()
+        .map_err(|err| format!("failed to create canvas: {}", err))
+}
+
+/// The window side of a Display
+struct Screen<'a> {
+    canvas: WindowCanvas,
+    tex_creator: &'a TextureCreator<WindowContext>,
+    texture: RefCell<Texture<'a>>,
+}
+
+struct Display<'a> {
     pub width: u32,
     pub height: u32,
     pub ctx: Rc<RefCell<Sdl>>,
-    pub sdl_canvas: sdl2::render::Canvas<sdl2::video::Window>,
-    pub tex_creator: sdl2::render::TextureCreator<sdl2::video::WindowContext>,
-    pub texture: RefCell<Texture<'static>>,
+    // None with --video=null or when no window could be opened, frames are then dropped instead of shown
+    screen: Option<Screen<'a>>,
     pub data: Rc<RefCell<Vec<u32>>>,
+    // applied to `data` on every flush, `filtered` holds its output and is what the texture shows
+    filter: Box<dyn FrameFilter>,
+    filtered: Vec<u32>,
 }
 
-impl Display {
-    fn new(width: u32, height: u32) -> Self {
-        let ctx = sdl2::init().unwrap();
-        let video_ctx = ctx.video().unwrap();
-
-        let window = match video_ctx
-            .window("Nemsys", width * 2, height * 2)
-            .position_centered()
-            .opengl()
-            .build()
-        {
-            Ok(window) => window,
-            Err(err) => panic!("failed to create window: {}", err),
-        };
-
-        let sdl_canvas = match window.into_canvas().present_vsync().build() {
-            Ok(canvas) => canvas,
-            Err(err) => panic!("failed to create canvas: {}", err),
-        };
-        let tex_creator = sdl_canvas.texture_creator();
-        let texture = tex_creator
-            .create_texture(
-                sdl2::pixels::PixelFormatEnum::RGBA8888,
-                sdl2::render::TextureAccess::Streaming,
-                width as u32,
-                height as u32,
-            )
-            .unwrap();
-
-        let texture = unsafe { std::mem::transmute::<_, Texture<'static>>(texture) };
-        let texture = RefCell::new(texture);
+impl<'a> Display<'a> {
+    fn new(
+        ctx: Sdl,
+        window: Option<(WindowCanvas, &'a TextureCreator<WindowContext>)>,
+        width: u32,
+        height: u32,
+        filter: Box<dyn FrameFilter>,
+    ) -> Self {
+        let screen = window.map(|(canvas, tex_creator)| Screen {
+            canvas,
+            tex_creator,
+            texture: RefCell::new(Self::create_texture(tex_creator, filter.as_ref())),
+        });
 
         let ctx = Rc::new(RefCell::new(ctx));
 
         let default_color = Color::RGB(255, 255, 255)
             .to_u32(&sdl2::pixels::PixelFormatEnum::RGBA8888.try_into().unwrap());
+        let (filtered_width, filtered_height) = filter.output_size();
 
         Self {
             width,
             height,
             ctx,
-            sdl_canvas,
-            texture,
-            tex_creator,
+            screen,
             data: Rc::new(RefCell::new(vec![default_color; (width * height) as usize])),
+            filtered: vec![default_color; filtered_width * filtered_height],
+            filter,
+        }
+    }
+
+    /// Streaming texture the size of `filter`'s output
+    fn create_texture(
+        tex_creator: &'a TextureCreator<WindowContext>,
+        filter: &dyn FrameFilter,
+    ) -> Texture<'a> {
+        let (width, height) = filter.output_size();
+        tex_creator
+            .create_texture(
+                sdl2::pixels::PixelFormatEnum::RGBA8888,
+                sdl2::render::TextureAccess::Streaming,
+                width as u32,
+                height as u32,
+            )
+            .unwrap()
+    }
+
+    fn set_filter(&mut self, filter: Box<dyn FrameFilter>) {
+        let (width, height) = filter.output_size();
+        self.filtered = vec![0; width * height];
+        if let Some(screen) = &self.screen {
+            *screen.texture.borrow_mut() =
+                Self::create_texture(screen.tex_creator, filter.as_ref());
         }
+        self.filter = filter;
     }
 
-    fn flush(&mut self) {
-        let mut texture = self.texture.borrow_mut();
+    /// Run the frame through the active filter and present it. `indices` is the same frame as NES colors.
+    fn flush(&mut self, indices: &[u16]) {
+        let Some(screen) = &mut self.screen else {
+            return;
+        };
+        // filters only take whole NES frames
+        debug_assert_eq!(
+            (self.width as usize, self.height as usize),
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        );
+        let frame = Frame {
+            rgb: &self.data.borrow(),
+            indices,
+        };
+        self.filter.apply(frame, &mut self.filtered);
+
+        let mut texture = screen.texture.borrow_mut();
+        let pitch = self.filter.output_size().0 * 4;
         texture
-            .update(None, self.data_raw(), (self.width * 4) as usize)
+            .update(None, Self::data_raw(&self.filtered), pitch)
             .unwrap();
-        self.sdl_canvas.clear();
-        self.sdl_canvas.copy(&texture, None, None).unwrap();
-        // Self::draw_grid_over_texture(&mut self.sdl_canvas, &texture, 32, 30).unwrap();
-        self.sdl_canvas.present();
+        screen.canvas.clear();
+        screen.canvas.copy(&texture, None, None).unwrap();
+        // Self::draw_grid_over_texture(&mut screen.canvas, &texture, 32, 30).unwrap();
+        screen.canvas.present();
     }
 
-    fn data_raw(&self) -> &[u8] {
-        unsafe {
-            std::slice::from_raw_parts(
-                self.data.borrow().as_ptr() as *const u8,
-                self.data.borrow().len() * 4,
-            )
-        }
+    fn data_raw(filtered: &[u32]) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(filtered.as_ptr() as *const u8, filtered.len() * 4) }
     }
 
     fn draw_grid_over_texture(
@@ -156,141 +251,646 @@ impl Display {
         Ok(())
     }
 
-    fn main_loop(&mut self) {
-        let mut events = self.ctx.borrow_mut().event_pump().unwrap();
+    /// Opens the default audio device and points the APU at it. Returns the device, which stops playing when dropped,
+    /// and the buffer to push the APU's samples into.
+    fn open_audio(
+        &self,
+        emulator: &mut Emulator,
+    ) -> Result<(AudioDevice<AudioOutput>, SampleBuffer), String> {
+        let audio_ctx = self.ctx.borrow().audio()?;
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(1024),
+        };
+        let samples = Arc::new(Mutex::new(VecDeque::with_capacity(MAX_BUFFERED_SAMPLES)));
+        let device = audio_ctx.open_playback(None, &desired, |spec| {
+            emulator.cpu.memory.apu.set_sample_rate(spec.freq as u32);
+            AudioOutput {
+                samples: Arc::clone(&samples),
+                last: 0.0,
+            }
+        })?;
+        device.resume();
+        Ok((device, samples))
+    }
+
+    fn main_loop(&mut self, args: &Args) {
+        // input comes from the window, so without one there are no events to poll
+        let mut events = match &self.screen {
+            Some(_) => match self.ctx.borrow_mut().event_pump() {
+                Ok(events) => Some(events),
+                Err(err) => {
+                    eprintln!("no input: {}", err);
+                    None
+                }
+            },
+            None => None,
+        };
 
-        let ppu = Rc::new(RefCell::new(PPU::new(Rc::clone(&self.data))));
-        let mut cpu = Cpu::new(Rc::clone(&ppu));
-        let rom = NROM::from_ines_rom(
-            "test_buttons.nes",
-            &mut ppu.borrow_mut().vram,
-            &mut cpu.memory,
-        )
-        .unwrap();
+        let mut emulator = Emulator::new(Rc::clone(&self.data));
+        match DataDirs::new(args.portable).saves() {
+            Ok(dir) => emulator.set_save_dir(dir),
+            Err(err) => eprintln!("no save directory, saving next to the ROM: {}", err),
+        }
+        emulator.set_game_genie_codes(args.game_genie.clone());
+        emulator.set_cheats(args.cheats.clone());
+        emulator.set_fast_stepping(args.fast);
+        emulator.ppu.borrow_mut().accurate_sprite_overflow = args.accurate_sprite_overflow;
+        emulator.cpu.memory.warn_ignored_writes = args.warn_rom_writes;
+        if args.ntsc_palette {
+            let palette = NtscPalette {
+                hue: args.hue,
+                saturation: args.saturation,
+                brightness: args.brightness,
+                ..Default::default()
+            };
+            emulator.ppu.borrow_mut().set_palette(&palette.generate());
+        }
+        if let Err(err) = emulator.load_rom(&args.rom) {
+            eprintln!("couldn't load {}: {}", args.rom, err);
+            return;
+        }
+        report_status(args, StatusEvent::RomLoaded { rom: &args.rom });
+        if let Some(vs) = &mut emulator.cpu.memory.vs_system {
+            vs.dip_switches = args.dip_switches;
+        }
+        // at 60 frames a second, no rewinding with an interval of 0
+        if let Some(snapshots) = (args.rewind_seconds * 60).checked_div(args.rewind_interval) {
+            emulator.enable_rewind(args.rewind_interval, snapshots.max(1));
+        }
+
+        let audio = if args.mute || args.audio == Backend::Null {
+            None
+        } else {
+            match self.open_audio(&mut emulator) {
+                Ok(audio) => Some(audio),
+                Err(err) => {
+                    eprintln!("no audio: {}", err);
+                    None
+                }
+            }
+        };
 
-        cpu.init_pc();
+        let hotkeys = DataDirs::new(args.portable)
+            .config()
+            .and_then(|dir| Hotkeys::load(&dir.join("hotkeys.json")))
+            .unwrap_or_else(|err| {
+                eprintln!("using the default hotkeys: {}", err);
+                Hotkeys::default()
+            });
+        // nothing could unpause without a window, so run straight through and stop at --frames
+        let windowless = self.screen.is_none();
+        let mut paused = args.paused && !windowless;
+        let mut rewinding = false;
+        let mut pattern_view = false;
+
+        // attract mode: the --attract movie plays in a loop once nobody has pressed a key for a while
+        let attract_movie = args.attract.as_deref().and_then(|path| {
+            Movie::read_fm2(path)
+                .map_err(|err| eprintln!("no attract mode: {}", err))
+                .ok()
+        });
+        let mut attracting = false;
+        let mut last_input = Instant::now();
 
         loop {
-            for event in events.poll_iter() {
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => {
+            for event in events.iter_mut().flat_map(|events| events.poll_iter()) {
+                let (key, pressed) = match event {
+                    Event::Quit { .. } => (None, true),
+                    Event::KeyDown { keycode, .. } => (keycode, true),
+                    Event::KeyUp { keycode, .. } => (keycode, false),
+                    _ => continue,
+                };
+                let action = match key {
+                    Some(key) => hotkeys.action(key),
+                    None => Some(Hotkey::Quit),
+                };
+
+                if pressed {
+                    last_input = Instant::now();
+                    // the key that ends the demo does nothing else, the game starts over for the player with
+                    // their save, not whatever the demo wrote to PRG-RAM
+                    if attracting && action != Some(Hotkey::Quit) {
+                        attracting = false;
+                        emulator.stop_movie();
+                        emulator.power_cycle();
+                        if let Err(err) = emulator.reload_battery_ram() {
+                            eprintln!("couldn't reload the save: {}", err);
+                        }
+                        report_status(
+                            args,
+                            StatusEvent::AttractEnded {
+                                frame: emulator.frame_count,
+                            },
+                        );
+                        continue;
+                    }
+                }
+
+                match (action, pressed) {
+                    (Some(Hotkey::Quit), true) => {
+                        // the demo's PRG-RAM isn't the player's, theirs was saved when it started
+                        if !attracting {
+                            save_battery_ram(&emulator, args);
+                        }
+                        report_status(
+                            args,
+                            StatusEvent::Quit {
+                                frame: emulator.frame_count,
+                                lag_frames: emulator.lag_frame_count,
+                            },
+                        );
                         process::exit(1);
                     }
-                    Event::KeyDown {
-                        keycode:
-                            Some(
-                                key @ (Keycode::A
-                                | Keycode::S
-                                | Keycode::MINUS
-                                | Keycode::EQUALS
-                                | Keycode::UP
-                                | Keycode::DOWN
-                                | Keycode::LEFT
-                                | Keycode::RIGHT),
-                            ),
-                        ..
-                    } => {
-                        cpu.memory.input.handle_keypress(key);
+                    (Some(Hotkey::Pause), true) => {
+                        paused = !paused;
+                        let (frame, lag_frames) = (emulator.frame_count, emulator.lag_frame_count);
+                        report_status(
+                            args,
+                            if paused {
+                                StatusEvent::Paused { frame, lag_frames }
+                            } else {
+                                StatusEvent::Resumed { frame, lag_frames }
+                            },
+                        );
+                    }
+                    (Some(Hotkey::Rewind), _) => rewinding = pressed,
+                    (Some(Hotkey::InsertCoin), _) => {
+                        // coin slot 1 on Vs. System boards, held for as long as the key is
+                        if let Some(vs) = &mut emulator.cpu.memory.vs_system {
+                            vs.coins[0] = pressed;
+                        }
+                    }
+                    (Some(Hotkey::Reset), true) => emulator.reset(),
+                    (Some(Hotkey::Screenshot), true) => {
+                        if let Err(err) = save_screenshot(&emulator, args) {
+                            eprintln!("couldn't save screenshot: {}", err);
+                        }
+                    }
+                    (Some(Hotkey::SaveState), true) => match emulator.store_state() {
+                        Ok(location) => {
+                            eprintln!("saved {}", location);
+                            report_status(args, StatusEvent::StateSaved { location });
+                        }
+                        Err(err) => eprintln!("couldn't save state: {}", err),
+                    },
+                    (Some(Hotkey::LoadState), true) => match emulator.restore_stored_state() {
+                        Ok(location) => {
+                            eprintln!("loaded {}", location);
+                            report_status(args, StatusEvent::StateLoaded { location });
+                        }
+                        Err(err) => eprintln!("couldn't load state: {}", err),
+                    },
+                    (Some(Hotkey::CycleFilter), true) => {
+                        let current = FILTER_NAMES
+                            .iter()
+                            .position(|&name| name == self.filter.name())
+                            .unwrap_or(0);
+                        let name = FILTER_NAMES[(current + 1) % FILTER_NAMES.len()];
+                        self.set_filter(filters::by_name(name).unwrap());
+                        eprintln!("filter: {}", name);
                     }
-                    Event::KeyUp {
-                        keycode:
-                            Some(
-                                key @ (Keycode::A
-                                | Keycode::S
-                                | Keycode::MINUS
-                                | Keycode::EQUALS
-                                | Keycode::UP
-                                | Keycode::DOWN
-                                | Keycode::LEFT
-                                | Keycode::RIGHT),
-                            ),
-                        ..
-                    } => {
-                        cpu.memory.input.handle_release(key);
+                    (Some(Hotkey::PixelSources), true) => {
+                        let mut ppu = emulator.ppu.borrow_mut();
+                        ppu.show_pixel_sources = !ppu.show_pixel_sources;
                     }
-                    _ => {}
+                    (Some(Hotkey::PatternTables), true) => pattern_view = !pattern_view,
+                    (
+                        Some(
+                            action @ (Hotkey::Cheat1
+                            | Hotkey::Cheat2
+                            | Hotkey::Cheat3
+                            | Hotkey::Cheat4),
+                        ),
+                        true,
+                    ) => {
+                        let slot = Hotkey::CHEATS.iter().position(|&cheat| cheat == action);
+                        match slot.and_then(|slot| emulator.toggle_cheat(slot)) {
+                            Some(cheat) if cheat.enabled => eprintln!("cheat {} on", cheat),
+                            Some(cheat) => eprintln!("cheat {} off", cheat),
+                            None => {}
+                        }
+                    }
+                    (Some(Hotkey::CycleBgPatterns), true) => {
+                        let mut ppu = emulator.ppu.borrow_mut();
+                        ppu.bg_pattern_override = next_pattern_override(ppu.bg_pattern_override);
+                        eprintln!(
+                            "background patterns: {}",
+                            describe_pattern_override(ppu.bg_pattern_override)
+                        );
+                    }
+                    (Some(Hotkey::CycleSpritePatterns), true) => {
+                        let mut ppu = emulator.ppu.borrow_mut();
+                        ppu.sprite_pattern_override =
+                            next_pattern_override(ppu.sprite_pattern_override);
+                        eprintln!(
+                            "sprite patterns: {}",
+                            describe_pattern_override(ppu.sprite_pattern_override)
+                        );
+                    }
+                    (Some(_), false) => {}
+                    // everything that isn't a shortcut goes to the controller
+                    (None, true) => emulator.cpu.memory.input.handle_keypress(key.unwrap()),
+                    (None, false) => emulator.cpu.memory.input.handle_release(key.unwrap()),
+                }
+            }
+
+            if paused {
+                // keep presenting so the window stays responsive, vsync paces the loop
+                self.flush(emulator.ppu.borrow().index_frame());
+                continue;
+            }
+
+            if rewinding {
+                // a snapshot per displayed frame, so it goes back --rewind-interval times as fast as it played
+                if let Err(err) = emulator.rewind() {
+                    eprintln!("couldn't rewind: {}", err);
+                    rewinding = false;
                 }
+                self.flush(emulator.ppu.borrow().index_frame());
+                continue;
             }
 
-            if cpu.num_cycles > 100000 {
-                // error!("{:#?}", &ppu.borrow().vram.buffer[0x2100..0x2200]);
-                // panic!();
+            if let Some(movie) = &attract_movie {
+                let idle = last_input.elapsed() >= Duration::from_secs(args.attract_idle);
+                if !attracting && idle {
+                    save_battery_ram(&emulator, args);
+                    attracting = true;
+                    report_status(
+                        args,
+                        StatusEvent::AttractStarted {
+                            frame: emulator.frame_count,
+                        },
+                    );
+                }
+                // from power-on, the way the movie was recorded, and again every time it runs out
+                if attracting && !emulator.playing_movie() {
+                    emulator.power_cycle();
+                    emulator.play_movie(movie.clone());
+                }
             }
 
-            cpu.tick((341 / 3) as usize); // runs cpu for equivalent num_cycles
-            ppu.borrow_mut().tick(); // runs ppu for 1 scanline
+            if emulator.step_scanline() {
+                // taken every frame whether they're shown or not, so they only ever cover the last one
+                let (modified, bank_changed) = {
+                    let mut ppu = emulator.ppu.borrow_mut();
+                    (ppu.take_modified_tiles(), ppu.take_chr_bank_changes())
+                };
+                if pattern_view {
+                    self.display_pattern_tables(&emulator.ppu.borrow(), &modified, &bank_changed);
+                } else {
+                    self.flush(emulator.ppu.borrow().index_frame());
+                }
 
-            if ppu.borrow().is_vblank {
-                self.flush();
+                if let Some((_, buffer)) = &audio {
+                    let mut buffer = buffer.lock().unwrap();
+                    buffer.extend(emulator.cpu.memory.apu.take_samples());
+                    let excess = buffer.len().saturating_sub(MAX_BUFFERED_SAMPLES);
+                    buffer.drain(..excess);
+                }
 
-                if ppu.borrow().generate_nmi {
-                    cpu.generate_nmi();
+                if args.frames == Some(emulator.frame_count) {
+                    let (frame, lag_frames) = (emulator.frame_count, emulator.lag_frame_count);
+                    if args.exit || windowless {
+                        if !attracting {
+                            save_battery_ram(&emulator, args);
+                        }
+                        report_status(args, StatusEvent::Quit { frame, lag_frames });
+                        return;
+                    }
+                    paused = true;
+                    report_status(args, StatusEvent::Paused { frame, lag_frames });
                 }
             }
         }
     }
 
-    pub fn display_pattern_table(&mut self, ppu: Rc<RefCell<PPU>>) {
+    /// Draws the pattern tables side by side, $0000 on the left, outlining in red the tiles flagged in `modified`
+    /// (see PPU::take_modified_tiles) and in yellow the 1 KB regions flagged in `bank_changed`
+    /// (see PPU::take_chr_bank_changes)
+    fn display_pattern_tables(
+        &mut self,
+        ppu: &PPU,
+        modified: &[bool; 512],
+        bank_changed: &[bool; 8],
+    ) {
+        let Some(screen) = &mut self.screen else {
+            return;
+        };
         let palette = [
             BLACK,
             Color::RGB(219, 1, 84),
             Color::RGB(82, 221, 78),
             Color::RGB(143, 225, 237),
         ];
-        let pixsize: usize = 3;
-        let tile_size: usize = pixsize * 8;
-        let mut last_tile_pos = 0x1000;
-        for k in 0..256 {
-            let tile = &ppu.borrow().vram.buffer[last_tile_pos..(last_tile_pos + 16)];
-            for r in 0..8 {
-                for c in 0..8 {
-                    let first_bit = (tile[r].reverse_bits() >> c) & 1;
-                    let second_bit = (tile[r + 8].reverse_bits() >> c) & 1;
-                    let color_index = (second_bit << 1) | first_bit;
-                    let color = palette[color_index as usize];
-
-                    // draw pixel with color at given square coordinates
-                    self.sdl_canvas.set_draw_color(color);
-                    let x_offset = (k * tile_size) % self.width as usize;
-                    let y_offset = ((k * tile_size) / self.width as usize) * tile_size;
-                    let x = r * pixsize + x_offset; // X-coordinate
-                    let y = c * pixsize + y_offset; // Y-coordinate
-                    self.sdl_canvas
+        // window pixels per pattern pixel, the 256x128 tables fill the window's width
+        let pixsize = 2;
+        let tile_size = pixsize * 8;
+        // tiles 0-255 are the $0000 table, 16 to a row
+        let tile_rect = |tile: usize, width: usize, height: usize| {
+            let x = (tile / 256) * 16 * tile_size + (tile % 16) * tile_size;
+            let y = (tile % 256) / 16 * tile_size;
+            Rect::new(x as i32, y as i32, width as u32, height as u32)
+        };
+
+        screen.canvas.set_draw_color(BLACK);
+        screen.canvas.clear();
+        for tile in 0..512 {
+            let origin = tile_rect(tile, 0, 0);
+            for row in 0..8 {
+                let low = ppu.vram.get(tile * 16 + row);
+                let high = ppu.vram.get(tile * 16 + row + 8);
+                for col in 0..8 {
+                    let color_index = ((high >> (7 - col)) & 1) << 1 | ((low >> (7 - col)) & 1);
+                    screen.canvas.set_draw_color(palette[color_index as usize]);
+                    screen
+                        .canvas
                         .fill_rect(Rect::new(
-                            y as i32,
-                            x as i32,
+                            origin.x() + (col * pixsize) as i32,
+                            origin.y() + (row * pixsize) as i32,
                             pixsize as u32,
                             pixsize as u32,
                         ))
                         .unwrap();
                 }
             }
-            last_tile_pos = last_tile_pos + 16;
         }
 
-        self.sdl_canvas.present();
+        screen.canvas.set_draw_color(Color::RGB(255, 0, 0));
+        for tile in (0..512).filter(|&tile| modified[tile]) {
+            screen
+                .canvas
+                .draw_rect(tile_rect(tile, tile_size, tile_size))
+                .unwrap();
+        }
+        // a 1 KB region is 64 tiles, 4 rows of a table
+        screen.canvas.set_draw_color(Color::RGB(255, 255, 0));
+        for region in (0..8).filter(|&region| bank_changed[region]) {
+            screen
+                .canvas
+                .draw_rect(tile_rect(region * 64, 16 * tile_size, 4 * tile_size))
+                .unwrap();
+        }
+
+        screen.canvas.present();
+    }
+}
+
+#[derive(Parser)]
+#[command(name = "nemsys")]
+struct Args {
+    /// iNES ROM to load
+    #[arg(default_value = "test_buttons.nes")]
+    rom: String,
+    /// Start with emulation halted at the reset vector (the pause hotkey, P by default, resumes)
+    #[arg(long)]
+    paused: bool,
+    /// Pause once this many frames have been emulated
+    #[arg(long)]
+    frames: Option<usize>,
+    /// Quit instead of pausing when --frames is reached
+    #[arg(long, requires = "frames")]
+    exit: bool,
+    /// Game Genie code to apply, can be given more than once
+    #[arg(long = "game-genie", value_name = "CODE", value_parser = GameGenieCode::parse)]
+    game_genie: Vec<GameGenieCode>,
+    /// RAM cheat to poke every frame, ADDR:VALUE in hex, can be given more than once. The cheat hotkeys
+    /// (F1-F4 by default) switch the first four on and off.
+    #[arg(long = "cheat", value_name = "ADDR:VALUE", value_parser = Cheat::parse)]
+    cheats: Vec<Cheat>,
+    /// Vs. System DIP switches 1-8 as bits 0-7 (the coin hotkey, C by default, inserts a coin)
+    #[arg(long, default_value_t = 0)]
+    dip_switches: u8,
+    /// Also save the NES color indices (.idx) when F12 takes a screenshot
+    #[arg(long)]
+    capture_indices: bool,
+    /// How frames are scaled for the window (the filter hotkey, F by default, cycles through them)
+    #[arg(long, default_value = "nearest", value_parser = PossibleValuesParser::new(FILTER_NAMES))]
+    filter: String,
+    /// Generate the palette from a model of the NTSC signal, tuned with --hue, --saturation and --brightness
+    #[arg(long)]
+    ntsc_palette: bool,
+    /// Degrees to rotate the generated palette's colors by
+    #[arg(
+        long,
+        requires = "ntsc_palette",
+        default_value_t = 0.0,
+        allow_negative_numbers = true
+    )]
+    hue: f32,
+    /// Of the generated palette, 0 for greyscale
+    #[arg(long, requires = "ntsc_palette", default_value_t = 1.0)]
+    saturation: f32,
+    /// Of the generated palette
+    #[arg(long, requires = "ntsc_palette", default_value_t = 1.0)]
+    brightness: f32,
+    /// Sync CPU and PPU once per scanline instead of after every instruction
+    #[arg(long)]
+    fast: bool,
+    /// Set the sprite overflow flag with the hardware's buggy scan, false positives and negatives included
+    #[arg(long)]
+    accurate_sprite_overflow: bool,
+    /// Log a warning, with the PC and value, when the game writes to ROM the cartridge has no registers in
+    /// ($8000-$FFFF on NROM), which is usually a bug in homebrew
+    #[arg(long)]
+    warn_rom_writes: bool,
+    /// Don't open an audio device (same as --audio=null)
+    #[arg(long)]
+    mute: bool,
+    /// null runs without a window, stopping at --frames if given. Also used when no window can be opened.
+    #[arg(long, value_enum, default_value_t = Backend::Sdl)]
+    video: Backend,
+    /// null doesn't open an audio device. Also used when none can be opened.
+    #[arg(long, value_enum, default_value_t = Backend::Sdl)]
+    audio: Backend,
+    /// Keep saves in nemsys-data beside the executable instead of the XDG data directory
+    #[arg(long)]
+    portable: bool,
+    /// Print state changes (ROM loaded, paused, saved, ...) to stdout as one JSON object per line
+    #[arg(long)]
+    status: bool,
+    /// Frames between the snapshots the rewind hotkey (Backspace by default) steps back through, 0 to turn it off
+    #[arg(long, default_value_t = 5)]
+    rewind_interval: usize,
+    /// How far back rewinding can go
+    #[arg(long, default_value_t = 30)]
+    rewind_seconds: usize,
+    /// FCEUX movie (.fm2) recorded from power-on to play in a loop after --attract-idle seconds without a key
+    /// being pressed, for kiosks and demos. Any key ends it and restarts the game. The movie is only read.
+    #[arg(long, value_name = "MOVIE")]
+    attract: Option<PathBuf>,
+    /// Seconds without input before the --attract movie starts
+    #[arg(long, requires = "attract", default_value_t = 30)]
+    attract_idle: u64,
+}
+
+/// What --status reports, e.g. {"event":"paused","frame":120,"lag_frames":3}
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum StatusEvent<'a> {
+    RomLoaded { rom: &'a str },
+    Paused { frame: usize, lag_frames: usize },
+    Resumed { frame: usize, lag_frames: usize },
+    BatterySaved { path: String },
+    StateSaved { location: String },
+    StateLoaded { location: String },
+    AttractStarted { frame: usize },
+    AttractEnded { frame: usize },
+    Quit { frame: usize, lag_frames: usize },
+}
+
+fn report_status(args: &Args, event: StatusEvent) {
+    if args.status {
+        println!("{}", serde_json::to_string(&event).unwrap());
     }
 }
 
+/// `<rom name>-<frame>.ppm` in the screenshots directory, with the .idx next to it if asked for
+fn save_screenshot(emulator: &Emulator, args: &Args) -> anyhow::Result<()> {
+    let name = Path::new(&args.rom)
+        .file_stem()
+        .map_or("nemsys".into(), |stem| stem.to_string_lossy());
+    let path = DataDirs::new(args.portable)
+        .screenshots()?
+        .join(format!("{}-{}.ppm", name, emulator.frame_count));
+    capture::write_ppm(&path, &emulator.framebuffer.borrow())?;
+    if args.capture_indices {
+        capture::write_indices(
+            &path.with_extension("idx"),
+            emulator.ppu.borrow().index_frame(),
+        )?;
+    }
+    eprintln!("saved {}", path.display());
+    Ok(())
+}
+
+fn save_battery_ram(emulator: &Emulator, args: &Args) {
+    match (emulator.save_battery_ram(), emulator.save_location()) {
+        (Err(err), _) => eprintln!("couldn't save: {}", err),
+        (Ok(()), Some(path)) => report_status(args, StatusEvent::BatterySaved { path }),
+        (Ok(()), None) => {}
+    }
+}
+
+/// PPUCTRL -> forced $0000 -> forced $1000 -> PPUCTRL
+fn next_pattern_override(current: Option<u16>) -> Option<u16> {
+    match current {
+        None => Some(0x0000),
+        Some(0x0000) => Some(0x1000),
+        _ => None,
+    }
+}
+
+fn describe_pattern_override(table: Option<u16>) -> String {
+    match table {
+        Some(address) => format!("forced to ${:04X}", address),
+        None => "from PPUCTRL".to_string(),
+    }
+}
+
+/// What `--warn-rom-writes` logs: the CPU's warnings, not whatever the rest of the core has to say
+fn rom_write_log_config() -> Config {
+    ConfigBuilder::new()
+        .add_filter_allow_str("nemsys::cpu")
+        .build()
+}
+
 fn main() {
+    let args = Args::parse();
+
+    let level = if args.warn_rom_writes {
+        LevelFilter::Warn
+    } else {
+        LevelFilter::Off
+    };
     CombinedLogger::init(vec![TermLogger::new(
-        LevelFilter::Off,
-        Config::default(),
+        level,
+        rom_write_log_config(),
         TerminalMode::Mixed,
         ColorChoice::Auto,
     )])
     .unwrap();
-    let mut canvas = Display::new(256, 240);
+    let ctx = sdl2::init().unwrap_or_else(|err| {
+        eprintln!("couldn't initialize SDL: {}", err);
+        process::exit(1);
+    });
+    let sdl_canvas = match args.video {
+        Backend::Sdl => open_window(&ctx, 256, 240)
+            .map_err(|err| eprintln!("no video, running without a window: {}", err))
+            .ok(),
+        Backend::Null => None,
+    };
+    let tex_creator = sdl_canvas.as_ref().map(WindowCanvas::texture_creator);
+    let window = sdl_canvas.zip(tex_creator.as_ref());
+    let filter = filters::by_name(&args.filter).unwrap();
+    let mut canvas = Display::new(ctx, window, 256, 240, filter);
 
     // #[cfg(target_family = "wasm")]
     // emscripten::set_main_loop_callback(canvas.main_loop());
 
     #[cfg(not(target_family = "wasm"))]
     {
-        canvas.main_loop();
+        canvas.main_loop(&args);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use nemsys::mappers::test_image;
+    use simplelog::WriteLogger;
+
+    use super::*;
+
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn warn_rom_writes_logs_nothing_else() {
+        let log = Arc::new(Mutex::new(vec![]));
+        WriteLogger::init(
+            LevelFilter::Warn,
+            rom_write_log_config(),
+            SharedBuffer(Arc::clone(&log)),
+        )
+        .unwrap();
+
+        // LDA #$21 / STA $2006 x2 / STA $2007 / LDA $2007 / STA $C000 / JMP $8000
+        let program = [
+            0xA9, 0x21, 0x8D, 0x06, 0x20, 0x8D, 0x06, 0x20, 0x8D, 0x07, 0x20, 0xAD, 0x07, 0x20,
+            0x8D, 0x00, 0xC0, 0x4C, 0x00, 0x80,
+        ];
+        let mut image = test_image(0, 1, 1);
+        let prg = &mut image[16..16 + 0x4000];
+        prg.fill(0xEA);
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+
+        let mut emulator = Emulator::headless();
+        emulator.load_rom_bytes(&image).unwrap();
+        emulator.cpu.memory.warn_ignored_writes = true;
+        for _ in 0..6 {
+            emulator.cpu.tick_ins();
+        }
+
+        let log = String::from_utf8(log.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 1, "{}", log);
+        assert!(
+            lines[0].ends_with("$800E wrote $00 to $C000, which the cartridge ignores"),
+            "{}",
+            lines[0]
+        );
     }
 }
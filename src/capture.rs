@@ -0,0 +1,272 @@
+// Screenshots of the emulated screen, in two forms.
+// A PPM holds what is displayed: the palette lookup with greyscale and color emphasis already applied.
+// An index dump holds the NES color each pixel was drawn with (see PPU::index_frame), which compares exactly
+// across emulators whatever palette or filter they display with.
+// What is displayed can also be saved as a PNG, left uncompressed (stored deflate blocks) so no codec is needed to
+// write it, or to read it back when comparing frames.
+
+use std::{fs, path::Path};
+
+use anyhow::{bail, Result};
+
+use crate::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Binary PPM (P6) of a framebuffer of 0xRRGGBBAA pixels
+pub fn ppm_bytes(framebuffer: &[u32]) -> Vec<u8> {
+    let mut bytes = format!("P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT).into_bytes();
+    for pixel in framebuffer {
+        let [r, g, b, _] = pixel.to_be_bytes();
+        bytes.extend([r, g, b]);
+    }
+    bytes
+}
+
+/// One little-endian u16 per pixel, row by row with no header: bits 0-5 the color, bits 6-8 the emphasis bits
+/// (red, green, blue), like the 9-bit indices NTSC filters take
+pub fn index_bytes(indices: &[u16]) -> Vec<u8> {
+    indices
+        .iter()
+        .flat_map(|index| index.to_le_bytes())
+        .collect()
+}
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+// bytes of a stored deflate block's data, at most
+const STORED_BLOCK_LEN: usize = 0xFFFF;
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+/// 8-bit RGB PNG of a framebuffer of 0xRRGGBBAA pixels
+pub fn png_bytes(framebuffer: &[u32]) -> Vec<u8> {
+    // every row starts with its filter type, 0 for none
+    let mut raw = Vec::with_capacity(SCREEN_HEIGHT * (1 + SCREEN_WIDTH * 3));
+    for row in framebuffer.chunks(SCREEN_WIDTH) {
+        raw.push(0);
+        for pixel in row {
+            let [r, g, b, _] = pixel.to_be_bytes();
+            raw.extend([r, g, b]);
+        }
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(STORED_BLOCK_LEN);
+    let num_blocks = blocks.len();
+    for (i, block) in blocks.enumerate() {
+        zlib.push((i + 1 == num_blocks) as u8);
+        let len = block.len() as u16;
+        zlib.extend(len.to_le_bytes());
+        zlib.extend((!len).to_le_bytes());
+        zlib.extend(block);
+    }
+    zlib.extend(adler32(&raw).to_be_bytes());
+
+    let mut header = vec![];
+    header.extend((SCREEN_WIDTH as u32).to_be_bytes());
+    header.extend((SCREEN_HEIGHT as u32).to_be_bytes());
+    // 8 bits per channel, RGB, default compression, filtering and no interlacing
+    header.extend([8, 2, 0, 0, 0]);
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    push_chunk(&mut png, b"IHDR", &header);
+    push_chunk(&mut png, b"IDAT", &zlib);
+    push_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Framebuffer back from a PNG made by `png_bytes`. Compressed or filtered PNGs, like the ones other programs save,
+/// aren't supported.
+pub fn parse_png(png: &[u8]) -> Result<Vec<u32>> {
+    if png.len() < PNG_SIGNATURE.len() || png[..PNG_SIGNATURE.len()] != PNG_SIGNATURE {
+        bail!("not a PNG");
+    }
+    let mut header = None;
+    let mut zlib = vec![];
+    let mut rest = &png[PNG_SIGNATURE.len()..];
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        if rest.len() < 12 + len {
+            bail!("truncated PNG chunk");
+        }
+        let data = &rest[8..8 + len];
+        match &rest[4..8] {
+            b"IHDR" => header = Some(data.to_vec()),
+            b"IDAT" => zlib.extend(data),
+            _ => {}
+        }
+        rest = &rest[12 + len..];
+    }
+    let mut expected = vec![];
+    expected.extend((SCREEN_WIDTH as u32).to_be_bytes());
+    expected.extend((SCREEN_HEIGHT as u32).to_be_bytes());
+    expected.extend([8, 2, 0, 0, 0]);
+    if header != Some(expected) {
+        bail!(
+            "only {}x{} 8-bit RGB PNGs are supported",
+            SCREEN_WIDTH,
+            SCREEN_HEIGHT
+        );
+    }
+
+    let mut raw = vec![];
+    let mut pos = 2;
+    loop {
+        let Some(&block_header) = zlib.get(pos) else {
+            bail!("truncated deflate stream");
+        };
+        if block_header & 0b110 != 0 {
+            bail!("compressed PNGs aren't supported");
+        }
+        let Some(len) = zlib.get(pos + 1..pos + 3) else {
+            bail!("truncated deflate stream");
+        };
+        let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+        let Some(block) = zlib.get(pos + 5..pos + 5 + len) else {
+            bail!("truncated deflate stream");
+        };
+        raw.extend(block);
+        pos += 5 + len;
+        if block_header & 1 != 0 {
+            break;
+        }
+    }
+
+    let row_len = 1 + SCREEN_WIDTH * 3;
+    if raw.len() != SCREEN_HEIGHT * row_len {
+        bail!(
+            "PNG has {} bytes of pixel data, expected {}",
+            raw.len(),
+            SCREEN_HEIGHT * row_len
+        );
+    }
+    let mut framebuffer = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT);
+    for row in raw.chunks(row_len) {
+        if row[0] != 0 {
+            bail!("filtered PNGs aren't supported");
+        }
+        framebuffer.extend(
+            row[1..]
+                .chunks(3)
+                .map(|rgb| u32::from_be_bytes([rgb[0], rgb[1], rgb[2], 0xFF])),
+        );
+    }
+    Ok(framebuffer)
+}
+
+/// How two frames differ, pixel by pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameDiff {
+    pub pixels: usize,
+    /// (x, y) of the first differing pixel, reading row by row
+    pub first: Option<(usize, usize)>,
+}
+
+pub fn diff_frames(a: &[u32], b: &[u32]) -> FrameDiff {
+    let mut differing = a.iter().zip(b).enumerate().filter(|(_, (a, b))| a != b);
+    let first = differing
+        .next()
+        .map(|(i, _)| (i % SCREEN_WIDTH, i / SCREEN_WIDTH));
+    FrameDiff {
+        pixels: first.map_or(0, |_| 1 + differing.count()),
+        first,
+    }
+}
+
+pub fn write_png(path: &Path, framebuffer: &[u32]) -> Result<()> {
+    fs::write(path, png_bytes(framebuffer))?;
+    Ok(())
+}
+
+pub fn read_png(path: &Path) -> Result<Vec<u32>> {
+    parse_png(&fs::read(path)?)
+}
+
+pub fn write_ppm(path: &Path, framebuffer: &[u32]) -> Result<()> {
+    fs::write(path, ppm_bytes(framebuffer))?;
+    Ok(())
+}
+
+pub fn write_indices(path: &Path, indices: &[u16]) -> Result<()> {
+    fs::write(path, index_bytes(indices))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_both_formats() {
+        let mut framebuffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        framebuffer[0] = 0x1122_33FF;
+        let ppm = ppm_bytes(&framebuffer);
+        let header = b"P6\n256 240\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        assert_eq!(&ppm[header.len()..header.len() + 3], [0x11, 0x22, 0x33]);
+        assert_eq!(ppm.len(), header.len() + SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+
+        assert_eq!(index_bytes(&[0x1C0 | 0x16, 0x0F]), [0xD6, 0x01, 0x0F, 0x00]);
+    }
+
+    #[test]
+    fn pngs_read_back_and_compare() {
+        let mut framebuffer: Vec<u32> = (0..SCREEN_WIDTH * SCREEN_HEIGHT)
+            .map(|i| ((i as u32) << 8) | 0xFF)
+            .collect();
+        let png = png_bytes(&framebuffer);
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        // the IHDR chunk's CRC
+        assert_eq!(png[29..33], [0x60, 0xF5, 0x2D, 0x9C]);
+        let decoded = parse_png(&png).unwrap();
+        assert_eq!(decoded, framebuffer);
+        assert_eq!(
+            diff_frames(&decoded, &framebuffer),
+            FrameDiff {
+                pixels: 0,
+                first: None
+            }
+        );
+
+        framebuffer[SCREEN_WIDTH + 3] = 0xFF;
+        framebuffer[SCREEN_WIDTH * 7] = 0xFF;
+        assert_eq!(
+            diff_frames(&decoded, &framebuffer),
+            FrameDiff {
+                pixels: 2,
+                first: Some((3, 1))
+            }
+        );
+
+        assert!(parse_png(&ppm_bytes(&framebuffer)).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+// RAM cheats: a value written to an address through the CPU bus at the end of every frame (see Emulator::poke),
+// which is how lives counters and timers get frozen. Unlike Game Genie codes they reach RAM and registers, not
+// just PRG reads, and each can be switched on and off while the game runs.
+
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    /// Parse `ADDR:VALUE` in hex, such as `075A:09`. Cheats start out enabled.
+    pub fn parse(cheat: &str) -> Result<Self> {
+        let invalid = || anyhow!("{} is not a cheat: ADDR:VALUE in hex, like 075A:09", cheat);
+        let (address, value) = cheat.split_once(':').ok_or_else(invalid)?;
+        let address = address.strip_prefix('$').unwrap_or(address);
+        Ok(Self {
+            address: u16::from_str_radix(address, 16).map_err(|_| invalid())?,
+            value: u8::from_str_radix(value, 16).map_err(|_| invalid())?,
+            enabled: true,
+        })
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "${:04X}:{:02X}", self.address, self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_address_and_value() {
+        let cheat = Cheat::parse("075a:09").unwrap();
+        assert_eq!(
+            (cheat.address, cheat.value, cheat.enabled),
+            (0x075A, 0x09, true)
+        );
+        assert_eq!(Cheat::parse("$075A:09").unwrap(), cheat);
+        assert_eq!(cheat.to_string(), "$075A:09");
+        assert!(Cheat::parse("075A").is_err());
+        assert!(Cheat::parse("075A:100").is_err());
+        assert!(Cheat::parse("10000:00").is_err());
+    }
+}
@@ -1,14 +1,12 @@
-use anyhow::Result;
-use serde::Deserialize;
-use std::io::{BufRead, BufReader};
-use std::iter::Skip;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::ErrorKind;
 use std::path::Path;
 use std::vec::IntoIter;
-use std::{
-    fmt,
-    fs::{self, File},
-    path::PathBuf,
-};
+use std::{fmt, fs, path::PathBuf};
+
+use crate::cpu::Cpu;
 
 #[derive(Deserialize, Clone, fmt::Debug)]
 pub struct MemTest(pub u16, pub u8); // address, data
@@ -27,13 +25,57 @@ pub struct CpuTestState {
     pub ram: Vec<MemTest>,
 }
 
+impl CpuTestState {
+    pub fn load_into(&self, cpu: &mut Cpu) {
+        cpu.registers.stack_pointer = self.s;
+        cpu.registers.accumulator = self.a;
+        cpu.registers.index_x = self.x;
+        cpu.registers.index_y = self.y;
+        cpu.registers.processor_status = self.p;
+        cpu.registers.program_counter = self.pc;
+
+        // straight into RAM, a store would have side effects like starting an OAM DMA for $4014
+        for MemTest(address, value) in self.ram.iter() {
+            cpu.memory.buffer[*address as usize] = *value;
+        }
+    }
+
+    pub fn assert_matches(&self, cpu: &Cpu) {
+        assert_eq!(cpu.registers.stack_pointer, self.s);
+        assert_eq!(cpu.registers.accumulator, self.a);
+        assert_eq!(cpu.registers.index_x, self.x);
+        assert_eq!(cpu.registers.index_y, self.y);
+        assert_eq!(
+            cpu.registers.processor_status | 0b0010_0000,
+            self.p | 0b0010_0000
+        );
+        assert_eq!(cpu.registers.program_counter, self.pc);
+
+        for MemTest(address, value) in self.ram.iter() {
+            assert_eq!(cpu.memory.buffer[*address as usize], *value);
+        }
+    }
+}
+
 #[derive(Deserialize, Clone, fmt::Debug)]
 pub struct InstructionTestCase {
     pub name: String,
     pub initial: CpuTestState,
     pub r#final: CpuTestState,
-    // atm, not sure if we want to be comparing all the cycles
-    // pub cycles: Vec<DatabusLog>,
+    /// One bus access per cycle
+    #[serde(default)]
+    pub cycles: Vec<DatabusLog>,
+}
+
+impl InstructionTestCase {
+    pub fn assert_cycle_count(&self, cpu: &Cpu) {
+        assert_eq!(cpu.num_cycles, self.cycles.len(), "cycles taken");
+    }
+
+    /// Needs the CPU's databus logger enabled before the instruction ran
+    pub fn assert_bus_activity(&self, cpu: &Cpu) {
+        assert_eq!(cpu.memory.databus_logger.log, self.cycles, "bus activity");
+    }
 }
 
 pub struct TestCaseIterator<I> {
@@ -50,15 +92,11 @@ impl<I: Iterator<Item = PathBuf>> Iterator for TestCaseIterator<I> {
 
     fn next(&mut self) -> Option<Self::Item> {
         self.json_file_it.next().map(|path| {
+            // the JAMs, which halt the CPU instead of finishing (see Cpu::halted)
             let unimplemented_opcodes: Vec<u8> = vec![
-                0x4B, 0x0B, 0x2B, 0x8B, 0x6B, 0xBB, 0xAB, 0xCB, 0x9F, 0x93, 0x9E, 0x9C, 0x9B, 0x1A,
-                0x3A, 0x5A, 0x7A, 0xDA, 0xFA, 0x80, 0x82, 0x89, 0xC2, 0xE2, 0x04, 0x44, 0x64, 0x14,
-                0x34, 0x54, 0x74, 0xD4, 0xF4, 0x0C, 0x1C, 0x3C, 0x5C, 0x7C, 0xDC, 0xFC, 0x02, 0x12,
-                0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2,
+                0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2,
             ];
-            let stem = path.file_stem().unwrap();
-            let base_filename = stem.to_string_lossy();
-            let opcode: u8 = u8::from_str_radix(&base_filename, 16).unwrap();
+            let opcode = file_opcode(&path).unwrap();
             if unimplemented_opcodes.contains(&opcode) {
                 return TestCaseSet {
                     opcode,
@@ -72,36 +110,81 @@ impl<I: Iterator<Item = PathBuf>> Iterator for TestCaseIterator<I> {
     }
 }
 
-pub fn load_json_tests(dir_path: &str) -> Result<TestCaseIterator<Skip<IntoIter<PathBuf>>>> {
+/// Opcode a test file covers, from its name (e.g. `a9.json`)
+fn file_opcode(path: &Path) -> Option<u8> {
+    let stem = path.file_stem()?.to_str()?;
+    u8::from_str_radix(stem, 16).ok()
+}
+
+/// Every opcode's test file in `dir_path` in order, minus the ones in `skip`
+pub fn load_json_tests(
+    dir_path: &str,
+    skip: &BTreeSet<u8>,
+) -> Result<TestCaseIterator<IntoIter<PathBuf>>> {
     let entries = fs::read_dir(dir_path)?;
     let mut paths: Vec<PathBuf> = entries
         .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| file_opcode(path).is_some_and(|opcode| !skip.contains(&opcode)))
         .collect::<Vec<_>>();
     paths.sort();
 
-    // Checkpointing b/c there's 1gb of tests in total to load
-    let target_index = if Path::new("/tmp/nemsys.ck").exists() {
-        let file = File::open("/tmp/nemsys.ck")?;
-        let mut reader = BufReader::new(file);
-        let mut line = String::new();
-        reader.read_line(&mut line)?;
-        let checkpoint_ins = line.trim();
-        println!("Picking up from from {}", checkpoint_ins);
+    Ok(TestCaseIterator {
+        json_file_it: paths.into_iter(),
+    })
+}
+
+#[derive(Serialize, Deserialize, Clone, fmt::Debug, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum OpcodeStatus {
+    Passed {
+        cases: usize,
+    },
+    /// `case` is the index of the first failing case in the opcode's file
+    Failed {
+        case: usize,
+        name: String,
+    },
+}
+
+/// Where a single-step run has got to, one entry per opcode tried so far (keyed by hex opcode).
+/// Saved after every opcode so a run over the whole suite (~1GB of JSON) can be picked up again later.
+#[derive(Serialize, Deserialize, Default, fmt::Debug)]
+pub struct Progress {
+    pub opcodes: BTreeMap<String, OpcodeStatus>,
+}
+
+impl Progress {
+    /// An empty progress if nothing has been saved at `path` yet
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("{} is not a progress file", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, opcode: u8, status: OpcodeStatus) {
+        self.opcodes.insert(format!("{:02x}", opcode), status);
+    }
 
-        paths
+    pub fn passed(&self) -> BTreeSet<u8> {
+        self.opcodes
             .iter()
-            .position(|p| {
-                p.file_name()
-                    .and_then(|name| name.to_str())
-                    .map(|name| name == format!("{checkpoint_ins}.json"))
-                    .unwrap_or(false)
-            })
-            .unwrap()
-    } else {
-        0
-    };
+            .filter(|(_, status)| matches!(status, OpcodeStatus::Passed { .. }))
+            .filter_map(|(opcode, _)| u8::from_str_radix(opcode, 16).ok())
+            .collect()
+    }
 
-    Ok(TestCaseIterator {
-        json_file_it: paths.into_iter().skip(target_index),
-    })
+    pub fn failed(&self) -> Vec<(&String, &OpcodeStatus)> {
+        self.opcodes
+            .iter()
+            .filter(|(_, status)| matches!(status, OpcodeStatus::Failed { .. }))
+            .collect()
+    }
 }
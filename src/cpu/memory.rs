@@ -1,7 +1,8 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use log::info;
 use ppu::memory::VRAM;
 use sdl2::keyboard::Keycode;
+use serde::{Deserialize, Serialize};
 use std::{
     cell::RefCell,
     fs::File,
@@ -11,8 +12,11 @@ use std::{
 };
 
 use crate::{
+    access_stats::AccessStats,
+    apu::APU,
     cpu::jsontest::DatabusLog,
-    ppu::{self, PPU},
+    mappers::SharedMapper,
+    ppu::{self, PpuPosition, PPU},
     utils::{set_bit, unset_bit},
 };
 
@@ -26,13 +30,18 @@ pub struct MemoryAccessLog {
     pub value: u8,
 }
 
+/// Every bus access in order, while `enabled`, to compare against the single-step tests' `cycles`
 pub struct DatabusLogger {
     pub log: Vec<DatabusLog>,
+    pub enabled: bool,
 }
 
 impl DatabusLogger {
     pub fn new() -> Self {
-        Self { log: vec![] }
+        Self {
+            log: vec![],
+            enabled: false,
+        }
     }
 
     pub fn log_read(&mut self, address: u16, value: u8) {
@@ -63,6 +72,38 @@ pub struct Memory {
     pub databus_logger: DatabusLogger,
     pub ppu: Rc<RefCell<PPU>>,
     pub input: KeyboardController,
+    pub apu: APU,
+    /// Handles $4020-$FFFF once a cartridge is inserted. Without one that range is plain RAM in `buffer`,
+    /// which is what the single-step CPU tests expect.
+    pub mapper: Option<SharedMapper>,
+    /// Per-page access counts, only kept while set
+    pub access_stats: Option<AccessStats>,
+    /// Set by a $4014 write: the CPU is halted while the DMA unit copies the page into OAM
+    pub oam_dma_pending: bool,
+    /// Coin slots and DIP switches, only on Vs. System boards
+    pub vs_system: Option<VsSystemInputs>,
+    /// Set by the indexed reads (absolute,X/Y and (indirect),Y) when adding the index carried into the high
+    /// byte. The CPU reads from the wrong page first and has to read again, see Opcode::page_cross_penalty.
+    pub page_crossed: bool,
+    /// Set whenever $4016 or $4017 is read, for lag frame detection (see Emulator::lagged)
+    pub input_polled: bool,
+    /// Note writes that the cartridge ignores (see Mapper::ignores_write), such as a homebrew game storing
+    /// into NROM's ROM, in `ignored_write` for the CPU to warn about
+    pub warn_ignored_writes: bool,
+    /// The last such write (address and value) since the CPU looked
+    pub ignored_write: Option<(u16, u8)>,
+}
+
+/// The bus' part of a save state: RAM and the devices on it, except the PPU and the cartridge
+#[derive(Serialize, Deserialize)]
+pub struct MemoryState {
+    /// All 64 KB, of which only the internal RAM matters once a cartridge is inserted
+    pub buffer: Vec<u8>,
+    pub apu: APU,
+    pub input: KeyboardController,
+    pub vs_system: Option<VsSystemInputs>,
+    pub oam_dma_pending: bool,
+    pub input_polled: bool,
 }
 
 impl Memory {
@@ -71,51 +112,217 @@ impl Memory {
             buffer: vec![0; 0xFFFF + 1],
             databus_logger: DatabusLogger::new(),
             input: KeyboardController::new(),
+            apu: APU::new(),
+            mapper: None,
+            access_stats: None,
+            oam_dma_pending: false,
+            vs_system: None,
+            page_crossed: false,
+            input_polled: false,
+            warn_ignored_writes: false,
+            ignored_write: None,
             ppu,
         }
     }
 
+    pub fn save_state(&self) -> MemoryState {
+        MemoryState {
+            buffer: self.buffer.clone(),
+            apu: self.apu.save_state(),
+            input: self.input.clone(),
+            vs_system: self.vs_system,
+            oam_dma_pending: self.oam_dma_pending,
+            input_polled: self.input_polled,
+        }
+    }
+
+    pub fn load_state(&mut self, state: &MemoryState) -> Result<()> {
+        if state.buffer.len() != self.buffer.len() {
+            bail!(
+                "saved memory is {} bytes, the bus has {}",
+                state.buffer.len(),
+                self.buffer.len()
+            );
+        }
+        self.buffer.copy_from_slice(&state.buffer);
+        self.apu.load_state(&state.apu);
+        self.input = state.input.clone();
+        self.vs_system = state.vs_system;
+        self.oam_dma_pending = state.oam_dma_pending;
+        self.input_polled = state.input_polled;
+        Ok(())
+    }
+
+    /// The address that actually responds to `address`: the 2 KB of internal RAM repeats through $1FFF
+    /// and the eight PPU registers through $3FFF, since those chips only decode the low address lines.
+    /// Like $4020-$FFFF this only applies with a cartridge inserted, the single-step tests want flat RAM.
+    pub fn mirrored(&self, address: u16) -> u16 {
+        if self.mapper.is_none() {
+            return address;
+        }
+        match address {
+            0x0800..=0x1FFF => address & 0x07FF,
+            0x2008..=0x3FFF => 0x2000 | (address & 0x0007),
+            _ => address,
+        }
+    }
+
+    /// Where the PPU is right now. It only catches up with the CPU between instructions, so this is where it was
+    /// when the current one started.
+    pub fn ppu_position(&self) -> PpuPosition {
+        self.ppu.borrow().position()
+    }
+
     pub fn fetch_absolute(&mut self, address: u16) -> u8 {
+        if let Some(stats) = &mut self.access_stats {
+            stats.record_read(address);
+        }
+        let address = self.mirrored(address);
+        let value = self.buffer[address as usize];
+        let value = match address {
+            // without a cartridge the write-only registers read back like RAM, as the single-step tests expect
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 if self.mapper.is_none() => value,
+            0x2000..=0x2007 => self.ppu.borrow_mut().read_register(address),
+            0x4015 => self.apu.read_status(),
+            0x4016 => {
+                self.input_polled = true;
+                let controller = self.input.read_controller_one();
+                match &self.vs_system {
+                    Some(vs) => (controller & 1) | vs.read_4016(),
+                    None => controller,
+                }
+            }
+            0x4017 => {
+                self.input_polled = true;
+                match &self.vs_system {
+                    Some(vs) => vs.read_4017(),
+                    None => value,
+                }
+            }
+            0x4020..=0xFFFF => match &self.mapper {
+                Some(mapper) => mapper.borrow_mut().cpu_read(address),
+                None => value,
+            },
+            _ => value,
+        };
+        if self.databus_logger.enabled {
+            self.databus_logger.log_read(address, value);
+        }
+        value
+    }
+
+    /// What `fetch_absolute` would return, for debuggers and tracing: nothing on the bus or in the devices on it
+    /// changes, so $2002 doesn't clear VBlank, $2007 doesn't move the VRAM address, $4015 doesn't acknowledge the
+    /// frame IRQ and $4016 doesn't shift the controller. Isn't counted in the access stats or the bus log either.
+    pub fn peek(&self, address: u16) -> u8 {
+        let address = self.mirrored(address);
         let value = self.buffer[address as usize];
-        // self.databus_logger.log_read(address, value);
         match address {
-            0x2002 => self.ppu.borrow_mut().ppu_status(),
-            0x2004 => self.ppu.borrow_mut().oam_data_read(),
-            0x2007 => self.ppu.borrow_mut().ppu_data_read(),
-            0x4016 => self.input.read_controller_one(),
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 if self.mapper.is_none() => value,
+            0x2000..=0x2007 => self.ppu.borrow().peek_register(address),
+            0x4015 => self.apu.peek_status(),
+            0x4016 => {
+                let controller = self.input.peek_controller_one();
+                match &self.vs_system {
+                    Some(vs) => (controller & 1) | vs.read_4016(),
+                    None => controller,
+                }
+            }
+            0x4017 => match &self.vs_system {
+                Some(vs) => vs.read_4017(),
+                None => value,
+            },
+            0x4020..=0xFFFF => match &self.mapper {
+                Some(mapper) => mapper.borrow().cpu_peek(address),
+                None => value,
+            },
             _ => value,
         }
     }
 
+    /// `len` bytes from `address` on as `peek` sees them, wrapping around from $FFFF to $0000
+    pub fn peek_range(&self, address: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|offset| self.peek(address.wrapping_add(offset as u16)))
+            .collect()
+    }
+
     pub fn store_absolute(&mut self, address: u16, value: u8) {
-        // self.databus_logger.log_write(address, value);
+        if self.databus_logger.enabled {
+            self.databus_logger.log_write(address, value);
+        }
+        if let Some(stats) = &mut self.access_stats {
+            stats.record_write(address);
+        }
+        let address = self.mirrored(address);
         match address {
-            0x2000 => self.ppu.borrow_mut().ppu_ctrl(value),
-            0x2001 => self.ppu.borrow_mut().ppu_mask(value),
-            0x2003 => self.ppu.borrow_mut().oam_addr(value),
-            0x2004 => self.ppu.borrow_mut().oam_data_write(value),
-            0x2005 => self.ppu.borrow_mut().ppu_scroll(value),
-            0x2006 => self.ppu.borrow_mut().ppu_addr(value),
-            0x2007 => self.ppu.borrow_mut().ppu_data_write(value),
-            0x4014 => self.ppu.borrow_mut().oam_dma(
-                &self.buffer
-                    [(((value as u16) << 8) as usize)..=((((value as u16) << 8) | 0xFF) as usize)],
-            ),
+            0x2000..=0x2007 => self.ppu.borrow_mut().write_register(address, value),
+            0x4014 => {
+                let page = (value as u16) << 8;
+                let bytes: Vec<u8> = (0..=0xFF)
+                    .map(|offset| self.fetch_absolute(page | offset))
+                    .collect();
+                self.ppu.borrow_mut().oam_dma(value, &bytes);
+                self.oam_dma_pending = true;
+            }
             0x4016 => self.input.write_register(value),
+            0x4008..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(address, value),
+            0x4020..=0xFFFF => {
+                if let Some(mapper) = &self.mapper {
+                    let mut mapper = mapper.borrow_mut();
+                    if self.warn_ignored_writes && mapper.ignores_write(address) {
+                        self.ignored_write = Some((address, value));
+                    }
+                    mapper.cpu_write(address, value);
+                    return;
+                }
+            }
             _ => {}
         };
         self.buffer[address as usize] = value;
     }
 
+    /// Clock the APU for `cycles` CPU cycles, serving the DMC's sample fetches from this bus.
+    /// Returns the extra cycles the CPU was stalled for by those fetches.
+    pub fn clock_apu(&mut self, cycles: usize) -> usize {
+        let mut stall = 0;
+        let mut remaining = cycles;
+        while remaining > 0 {
+            remaining -= 1;
+            self.apu.clock();
+            if let Some(address) = self.apu.dmc.pending_fetch() {
+                let sample = self.fetch_absolute(address);
+                self.apu.dmc.load_sample(sample);
+                // the APU keeps running while the DMA holds the CPU
+                stall += 4;
+                remaining += 4;
+            }
+        }
+        stall
+    }
+
+    /// `base` plus `index`, the way the CPU gets there: it adds the index to the low byte first and reads
+    /// from that address while carrying into the high byte. Writes and read-modify-writes always pay for that
+    /// read, reads only when the carry changed the page (otherwise the read already got the right byte).
+    pub fn indexed_address(&mut self, base: u16, index: u8, access: IndexedAccess) -> u16 {
+        let address = base.wrapping_add(index as u16);
+        let crossed = (base ^ address) & 0xFF00 != 0;
+        self.page_crossed |= crossed;
+        if crossed || access == IndexedAccess::Write {
+            self.fetch_absolute((base & 0xFF00) | (address & 0x00FF));
+        }
+        address
+    }
+
     // also called for absolute_y
     pub fn fetch_absolute_x(&mut self, address: u16, index_x: u8) -> u8 {
-        let address = address.wrapping_add(index_x as u16);
+        let address = self.indexed_address(address, index_x, IndexedAccess::Read);
         self.fetch_absolute(address)
     }
 
     // also called for absolute_y
     pub fn store_absolute_x(&mut self, address: u16, index_x: u8, value: u8) {
-        let address = address.wrapping_add(index_x as u16);
+        let address = self.indexed_address(address, index_x, IndexedAccess::Write);
         self.store_absolute(address, value)
     }
 
@@ -129,14 +336,31 @@ impl Memory {
         self.store_absolute(address, value)
     }
 
-    // also called for zero_page_y
+    /// `addr_lower_byte` plus `index`, wrapping within the zero page. The CPU reads the unindexed address
+    /// while it adds, for every access.
+    pub fn zero_page_indexed_address(&mut self, addr_lower_byte: u8, index: u8) -> u8 {
+        self.fetch_zero_page(addr_lower_byte);
+        addr_lower_byte.wrapping_add(index)
+    }
+
     pub fn fetch_zero_page_x(&mut self, addr_lower_byte: u8, index_x: u8) -> u8 {
-        let address = addr_lower_byte.wrapping_add(index_x);
+        let address = self.zero_page_indexed_address(addr_lower_byte, index_x);
         self.fetch_zero_page(address)
     }
 
-    pub fn store_zero_page_x(&mut self, addr_lower_byte: u8, x: u8, value: u8) {
-        let address = addr_lower_byte.wrapping_add(x);
+    pub fn store_zero_page_x(&mut self, addr_lower_byte: u8, index_x: u8, value: u8) {
+        let address = self.zero_page_indexed_address(addr_lower_byte, index_x);
+        self.store_absolute(address as u16, value);
+    }
+
+    // LDX, STX, LAX and SAX are the only instructions with this mode
+    pub fn fetch_zero_page_y(&mut self, addr_lower_byte: u8, index_y: u8) -> u8 {
+        let address = self.zero_page_indexed_address(addr_lower_byte, index_y);
+        self.fetch_zero_page(address)
+    }
+
+    pub fn store_zero_page_y(&mut self, addr_lower_byte: u8, index_y: u8, value: u8) {
+        let address = self.zero_page_indexed_address(addr_lower_byte, index_y);
         self.store_absolute(address as u16, value);
     }
 
@@ -145,39 +369,89 @@ impl Memory {
         self.fetch_absolute(address) as u16 + (self.fetch_absolute(next_address) as u16) * 256
     }
 
+    /// The pointer at zero page `addr_lower_byte + index_x`, wrapping within the zero page
+    pub fn indirect_x_address(&mut self, addr_lower_byte: u8, index_x: u8) -> u16 {
+        let pointer = addr_lower_byte.wrapping_add(index_x);
+        self.fetch_zero_page(pointer) as u16
+            + self.fetch_zero_page(pointer.wrapping_add(1)) as u16 * 256
+    }
+
+    /// The pointer at zero page `addr_lower_byte`, its high byte wrapping around to $00 from $FF
+    pub fn zero_page_pointer(&mut self, addr_lower_byte: u8) -> u16 {
+        self.fetch_zero_page(addr_lower_byte) as u16
+            + self.fetch_zero_page(addr_lower_byte.wrapping_add(1)) as u16 * 256
+    }
+
+    /// The pointer at zero page `addr_lower_byte`, plus `index_y` (see `indexed_address`)
+    pub fn indirect_y_address(
+        &mut self,
+        addr_lower_byte: u8,
+        index_y: u8,
+        access: IndexedAccess,
+    ) -> u16 {
+        let base = self.zero_page_pointer(addr_lower_byte);
+        self.indexed_address(base, index_y, access)
+    }
+
     pub fn fetch_indirect_x(&mut self, addr_lower_byte: u8, index_x: u8) -> u8 {
         // val = PEEK(PEEK((arg + X) % 256) + PEEK((arg + X + 1) % 256) * 256)
-        let addr = self.fetch_zero_page(addr_lower_byte.wrapping_add(index_x)) as u16
-            + self.fetch_zero_page(addr_lower_byte.wrapping_add((index_x).wrapping_add(1))) as u16
-                * 256;
+        let addr = self.indirect_x_address(addr_lower_byte, index_x);
         self.fetch_absolute(addr)
     }
 
     pub fn store_indirect_x(&mut self, addr_lower_byte: u8, index_x: u8, value: u8) {
-        let addr = self.fetch_zero_page(addr_lower_byte.wrapping_add(index_x)) as u16
-            + self.fetch_zero_page(addr_lower_byte.wrapping_add(index_x).wrapping_add(1)) as u16
-                * 256;
+        let addr = self.indirect_x_address(addr_lower_byte, index_x);
         self.store_absolute(addr, value)
     }
 
     pub fn store_indirect_y(&mut self, addr_lower_byte: u8, index_y: u8, value: u8) {
-        let addr = self.fetch_zero_page(addr_lower_byte) as u16;
-        let addr =
-            addr.wrapping_add(self.fetch_zero_page(addr_lower_byte.wrapping_add(1)) as u16 * 256);
-        let addr = addr.wrapping_add(index_y as u16);
+        let addr = self.indirect_y_address(addr_lower_byte, index_y, IndexedAccess::Write);
         self.store_absolute(addr, value);
     }
 
     pub fn fetch_indirect_y(&mut self, addr_lower_byte: u8, index_y: u8) -> u8 {
         // val = PEEK(PEEK(arg) + PEEK((arg + 1) % 256) * 256 + Y)
-        let addr = self.fetch_zero_page(addr_lower_byte) as u16;
-        let addr =
-            addr.wrapping_add(self.fetch_zero_page(addr_lower_byte.wrapping_add(1)) as u16 * 256);
-        let addr = addr.wrapping_add(index_y as u16);
+        let addr = self.indirect_y_address(addr_lower_byte, index_y, IndexedAccess::Read);
         self.fetch_absolute(addr)
     }
 }
 
+/// What an indexed address is for, which decides whether the CPU's read from the not yet carried address
+/// always happens (see Memory::indexed_address). Read-modify-writes count as writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexedAccess {
+    Read,
+    Write,
+}
+
+/// The cabinet inputs a Vs. System board reads alongside the controllers
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct VsSystemInputs {
+    /// Switches 1-8 in bits 0-7, set when on
+    pub dip_switches: u8,
+    pub coins: [bool; 2],
+    pub service: bool,
+}
+
+impl VsSystemInputs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bit 2 service button, bits 3-4 DIP switches 1-2, bits 5-6 coin slots 1-2
+    fn read_4016(&self) -> u8 {
+        ((self.service as u8) << 2)
+            | ((self.dip_switches & 0b11) << 3)
+            | ((self.coins[0] as u8) << 5)
+            | ((self.coins[1] as u8) << 6)
+    }
+
+    /// Bits 2-7 DIP switches 3-8, controller 2 isn't connected
+    fn read_4017(&self) -> u8 {
+        self.dip_switches & 0b1111_1100
+    }
+}
+
 // ---- INPUT ----
 // 0 - A - a
 // 1 - B - s
@@ -191,6 +465,7 @@ impl Memory {
 // Input ($4016 write)
 // Output ($4016/$4017 read)
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct KeyboardController {
     pub strobe_activated: bool,
     button_register: u8,
@@ -258,6 +533,12 @@ impl KeyboardController {
         // );
     }
 
+    /// Hold exactly `buttons` (bit 0 A through bit 7 Right), whatever the keyboard says. For movie playback.
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.button_latch = !buttons;
+        self.latch();
+    }
+
     pub fn latch(&mut self) {
         if self.strobe_activated {
             self.button_register = self.button_latch;
@@ -281,15 +562,23 @@ impl KeyboardController {
         //     "Read: {:#010b} count {}, strobe {}",
         //     self.button_register, self.read_count, self.strobe_activated
         // );
+        let curr_bit = self.peek_controller_one();
         if self.read_count >= 8 {
-            return 0b1111_1111;
+            return curr_bit;
         }
 
         self.read_count += 1;
-
-        let curr_bit = self.button_register & 1;
         self.button_register = self.button_register >> 1;
 
         curr_bit
     }
+
+    /// What the next $4016 read would return, without shifting the register
+    pub fn peek_controller_one(&self) -> u8 {
+        if self.read_count >= 8 {
+            0b1111_1111
+        } else {
+            self.button_register & 1
+        }
+    }
 }
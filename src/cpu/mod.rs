@@ -4,20 +4,77 @@ use std::{
     sync::mpsc::{Receiver, Sender},
 };
 
-use log::{error, info};
-use memory::MemoryAccessLog;
+use anyhow::Result;
+use log::{error, info, warn};
+use memory::{IndexedAccess, MemoryState};
+use opcodes::Handler;
+use serde::{Deserialize, Serialize};
 
-use crate::ppu::PPU;
+use crate::ppu::{PpuPosition, PPU};
 
 pub mod jsontest;
 pub mod memory;
+pub mod opcodes;
 pub mod registers;
+pub mod trace;
+
+// The "magic" constant ANE and LXA OR into A, see ane_immediate
+const UNSTABLE_CONST: u8 = 0xEE;
+
+/// Devices that can pull the shared /IRQ line low. The line stays asserted while any of them holds it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IrqSource {
+    ApuFrameCounter = 0b001,
+    ApuDmc = 0b010,
+    Mapper = 0b100,
+}
+
+/// One executed instruction, as passed to the trace hook
+#[derive(Clone, Debug)]
+pub struct TracedInstruction {
+    pub pc: u16,
+    /// The opcode and the two bytes after it, whether or not the addressing mode uses them as operands
+    pub bytes: [u8; 3],
+    /// As nestest.log shows it, with the operand's address and value from before it ran (see trace::disassemble)
+    pub disassembly: String,
+    pub before: registers::Registers,
+    pub after: registers::Registers,
+    /// Value of num_cycles when the instruction started
+    pub start_cycle: usize,
+    /// Where the PPU was when the instruction started (see Memory::ppu_position)
+    pub ppu: PpuPosition,
+    pub cycles: usize,
+}
+
+pub type TraceHook = Box<dyn FnMut(&TracedInstruction)>;
+
+/// Called with the address and opcode of the JAM that halted the CPU
+pub type JamHook = Box<dyn FnMut(u16, u8)>;
+
+/// The CPU's part of a save state, along with the bus it drives. Hooks aren't part of it.
+#[derive(Serialize, Deserialize)]
+pub struct CpuState {
+    pub registers: registers::Registers,
+    pub num_cycles: usize,
+    pub num_instructions: usize,
+    pub irq_sources: u8,
+    pub halted: bool,
+    pub memory: MemoryState,
+}
 
 pub struct Cpu {
     pub memory: memory::Memory,
     pub registers: registers::Registers,
 
-    pub num_cycles: usize, // elapsed # of cycles
+    pub num_cycles: usize,       // elapsed # of cycles
+    pub num_instructions: usize, // executed # of instructions, interrupts not included
+
+    // level-triggered /IRQ input: one bit per IrqSource currently asserting it
+    irq_sources: u8,
+    trace_hook: Option<TraceHook>,
+    // stopped by a JAM until the next reset, see halted
+    halted: bool,
+    jam_hook: Option<JamHook>,
 }
 
 impl Cpu {
@@ -26,14 +83,113 @@ impl Cpu {
             memory: memory::Memory::new(ppu),
             registers: registers::Registers::new(),
             num_cycles: 0,
+            num_instructions: 0,
+            irq_sources: 0,
+            trace_hook: None,
+            halted: false,
+            jam_hook: None,
+        }
+    }
+
+    pub fn save_state(&self) -> CpuState {
+        CpuState {
+            registers: self.registers,
+            num_cycles: self.num_cycles,
+            num_instructions: self.num_instructions,
+            irq_sources: self.irq_sources,
+            halted: self.halted,
+            memory: self.memory.save_state(),
+        }
+    }
+
+    pub fn load_state(&mut self, state: &CpuState) -> Result<()> {
+        self.memory.load_state(&state.memory)?;
+        self.registers = state.registers;
+        self.num_cycles = state.num_cycles;
+        self.num_instructions = state.num_instructions;
+        self.irq_sources = state.irq_sources;
+        self.halted = state.halted;
+        Ok(())
+    }
+
+    /// Call `hook` after every instruction with its registers before and after, so tools (coverage, profilers, ...)
+    /// can follow execution without parsing the log. Interrupts taken afterwards aren't part of the instruction.
+    pub fn set_trace_hook(&mut self, hook: impl FnMut(&TracedInstruction) + 'static) {
+        self.trace_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_trace_hook(&mut self) {
+        self.trace_hook = None;
+    }
+
+    /// Call `hook` when a JAM opcode halts the CPU, so runaway code can be caught where it crashed
+    pub fn set_jam_hook(&mut self, hook: impl FnMut(u16, u8) + 'static) {
+        self.jam_hook = Some(Box::new(hook));
+    }
+
+    /// Whether a JAM opcode stopped the CPU. Only a reset (or power cycle) gets it going again; until then
+    /// time passes but no instructions run and interrupts are ignored.
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Hold the IRQ line on behalf of `source` until it's released again
+    pub fn assert_irq(&mut self, source: IrqSource) {
+        self.irq_sources |= source as u8;
+    }
+
+    pub fn release_irq(&mut self, source: IrqSource) {
+        self.irq_sources &= !(source as u8);
+    }
+
+    pub fn set_irq(&mut self, source: IrqSource, asserted: bool) {
+        if asserted {
+            self.assert_irq(source);
+        } else {
+            self.release_irq(source);
         }
     }
 
+    /// Whether any source is holding the IRQ line, regardless of the I flag
+    pub fn irq_asserted(&self) -> bool {
+        self.irq_sources != 0
+    }
+
     pub fn init_pc(&mut self) {
         self.registers.program_counter = self.fetch_u16(0xFFFC);
         info!("Initialize PC = {:x}", self.registers.program_counter);
     }
 
+    /// The RESET sequence: an interrupt whose three pushes are turned into reads, so SP still moves down by 3
+    /// but nothing is written. Sets I, takes PC from $FFFC and silences the APU. Returns the 7 cycles it takes.
+    pub fn reset(&mut self) -> u8 {
+        self.halted = false;
+        self.registers.stack_pointer = self.registers.stack_pointer.wrapping_sub(3);
+        self.registers.set_interrupt_disable();
+        self.init_pc();
+        self.memory.apu.reset();
+
+        7
+    }
+
+    /// Power-on state, then the RESET sequence: A, X and Y cleared and SP at 0, which the reset leaves at $FD
+    /// with I set. The 2 KB of internal RAM isn't initialized on hardware; it's filled with `ram_fill` here.
+    pub fn power_on(&mut self, ram_fill: u8) -> u8 {
+        self.registers = registers::Registers::new();
+        self.registers.stack_pointer = 0;
+        self.memory.buffer[..0x800].fill(ram_fill);
+        self.memory.apu.power_on();
+        self.reset()
+    }
+
+    /// True when the next instruction is a `JMP abs` back to itself, which only an interrupt can leave. Looks with
+    /// `Memory::peek`, so checking doesn't show up in the access stats or bus log.
+    pub fn at_jmp_to_self(&self) -> bool {
+        let pc = self.registers.program_counter;
+        let byte = |offset: u16| self.memory.peek(pc.wrapping_add(offset));
+        byte(0) == 0x4C && u16::from_le_bytes([byte(1), byte(2)]) == pc
+    }
+
     // Helper method
     fn update_zero_negative_flags(&mut self, value: u8) {
         if value == 0 {
@@ -48,20 +204,67 @@ impl Cpu {
         }
     }
 
+    // Read-modify-write: the CPU writes the value it read straight back while `op` works on it, then writes
+    // the result. Both writes reach the bus, which registers like a mapper's serial port or $2007 notice.
+    fn modify(&mut self, address: u16, op: fn(&mut Self, u8) -> u8) -> u8 {
+        let value = self.memory.fetch_absolute(address);
+        self.memory.store_absolute(address, value);
+        let value = op(self, value);
+        self.memory.store_absolute(address, value);
+        value
+    }
+
     /*
      * Stack abstraction methods
      */
 
     fn stack_push(&mut self, val: u8) {
         let stack_addr: u16 = ((0x01_u16) << 8) | self.registers.stack_pointer as u16;
-        self.memory.buffer[stack_addr as usize] = val;
+        self.memory.store_absolute(stack_addr, val);
         self.registers.stack_pointer = self.registers.stack_pointer.wrapping_sub(1);
     }
 
     fn stack_pop(&mut self) -> u8 {
         self.registers.stack_pointer = self.registers.stack_pointer.wrapping_add(1);
         let stack_addr: u16 = ((0x01_u16) << 8) | self.registers.stack_pointer as u16;
-        self.memory.buffer[stack_addr as usize]
+        self.memory.fetch_absolute(stack_addr)
+    }
+
+    // PLA and PLP read the top of the stack before moving the pointer, and ignore what they read
+    fn stack_dummy_read(&mut self) {
+        self.memory
+            .fetch_absolute(0x0100 | self.registers.stack_pointer as u16);
+    }
+
+    // High byte first, so the low byte ends up at the lower address
+    fn stack_push_u16(&mut self, val: u16) {
+        self.stack_push((val >> 8) as u8);
+        self.stack_push((val & 0xFF) as u8);
+    }
+
+    fn stack_pop_u16(&mut self) -> u16 {
+        let low = self.stack_pop() as u16;
+        let high = self.stack_pop() as u16;
+        (high << 8) | low
+    }
+
+    /*
+     * Return address helpers
+     * JSR pushes the address of its own last operand byte (return point - 1), RTS pulls it and adds 1.
+     * BRK is followed by a padding byte, so the address it pushes skips over it.
+     * NMI/IRQ are taken between instructions, so they push the address of the next instruction as is.
+     */
+
+    fn jsr_return_address(&self) -> u16 {
+        self.registers.program_counter.wrapping_add(2)
+    }
+
+    fn rts_target(pulled: u16) -> u16 {
+        pulled.wrapping_add(1)
+    }
+
+    fn brk_return_address(&self) -> u16 {
+        self.registers.program_counter.wrapping_add(2)
     }
 
     /*
@@ -71,7 +274,7 @@ impl Cpu {
 
     // Opcode: $69
     // 2 cycles
-    fn adc_immediate(&mut self, value: u8) -> u8 {
+    fn adc_immediate(&mut self, value: u8) {
         // check if both are positive or if both are negative
         let same_sign = (value & 0b1000_0000) == (self.registers.accumulator & 0b1000_0000);
 
@@ -96,80 +299,65 @@ impl Cpu {
         }
 
         self.update_zero_negative_flags(self.registers.accumulator);
-        2
     }
 
     // Opcode: $65
     // 3 cycles
-    fn adc_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn adc_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.memory.fetch_zero_page(addr_lower_byte);
         self.adc_immediate(value);
-
-        3
     }
 
     // Opcode: $75
     // 4 cycles
-    fn adc_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn adc_zero_page_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
         self.adc_immediate(value);
-
-        4
     }
 
     // Opcode: $6D
     // 4 cycles
-    fn adc_absolute(&mut self, address: u16) -> u8 {
+    fn adc_absolute(&mut self, address: u16) {
         let value = self.memory.fetch_absolute(address);
         self.adc_immediate(value);
-
-        4
     }
 
     // Opcode: $7D
     // 4 (+1 if page crossed) cycles
-    fn adc_absolute_x(&mut self, address: u16) -> u8 {
+    fn adc_absolute_x(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_x);
         self.adc_immediate(value);
-
-        4
     }
 
     // Opcode: $79
     // 4 (+1 if page crossed) cycles
-    fn adc_absolute_y(&mut self, address: u16) -> u8 {
+    fn adc_absolute_y(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_y);
         self.adc_immediate(value);
-
-        4
     }
 
     // Opcode: $61
     // 6 cycles
-    fn adc_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn adc_indirect_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_x(addr_lower_byte, self.registers.index_x);
         self.adc_immediate(value);
-
-        6
     }
 
     // Opcode: $71
     // 5 (+1 if page crossed) cycles
-    fn adc_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
+    fn adc_indirect_y(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_y(addr_lower_byte, self.registers.index_y);
         self.adc_immediate(value);
-
-        5
     }
 
     /*
@@ -179,81 +367,67 @@ impl Cpu {
 
     // Opcode: $E9
     // 2 cycles
-    fn sbc_immediate(&mut self, value: u8) -> u8 {
+    fn sbc_immediate(&mut self, value: u8) {
         self.adc_immediate(!value); // twos complement
-
-        2
     }
 
     // Opcode: $E5
     // 3 cycles
-    fn sbc_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sbc_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.memory.fetch_zero_page(addr_lower_byte);
         self.sbc_immediate(value);
-
-        3
     }
 
     // Opcode: $F5
     // 4 cycles
-    fn sbc_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sbc_zero_page_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
         self.sbc_immediate(value);
-
-        4
     }
 
     // Opcode: $ED
     // 4 cycles
-    fn sbc_absolute(&mut self, address: u16) -> u8 {
+    fn sbc_absolute(&mut self, address: u16) {
         let value = self.memory.fetch_absolute(address);
         self.sbc_immediate(value);
-
-        4
     }
 
     // Opcode: $FD
     // 4 (+1 if page crossed) cycles
-    fn sbc_absolute_x(&mut self, address: u16) -> u8 {
+    fn sbc_absolute_x(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_x);
-        self.sbc_immediate(value)
+        self.sbc_immediate(value);
     }
 
     // Opcode: $F9
     // 4 (+1 if page crossed) cycles
-    fn sbc_absolute_y(&mut self, address: u16) -> u8 {
+    fn sbc_absolute_y(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_y);
         self.sbc_immediate(value);
-
-        4
     }
 
     // Opcode: $E1
     // 6 cycles
-    fn sbc_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sbc_indirect_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_x(addr_lower_byte, self.registers.index_x);
         self.sbc_immediate(value);
-
-        6
     }
 
     // Opcode: $F1
     // 5 (+1 if page crossed) cycles
-    fn sbc_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sbc_indirect_y(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_y(addr_lower_byte, self.registers.index_y);
         self.sbc_immediate(value);
-
-        5
     }
 
     /*
@@ -263,7 +437,7 @@ impl Cpu {
 
     // Opcode: $C9
     // 2 cycles
-    fn cmp_immediate(&mut self, value: u8) -> u8 {
+    fn cmp_immediate(&mut self, value: u8) {
         if self.registers.accumulator == value {
             self.registers.set_zero();
         } else {
@@ -282,81 +456,65 @@ impl Cpu {
         } else {
             self.registers.unset_neg();
         }
-
-        2
     }
 
     // Opcode: $E5
     // 3 cycles
-    fn cmp_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn cmp_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.memory.fetch_zero_page(addr_lower_byte);
         self.cmp_immediate(value);
-
-        3
     }
 
     // Opcode: $D5
     // 4 cycles
-    fn cmp_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn cmp_zero_page_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
         self.cmp_immediate(value);
-
-        4
     }
 
     // Opcode: $CD
     // 4 cycles
-    fn cmp_absolute(&mut self, address: u16) -> u8 {
+    fn cmp_absolute(&mut self, address: u16) {
         let value = self.memory.fetch_absolute(address);
         self.cmp_immediate(value);
-
-        4
     }
 
     // Opcode: $DD
     // 4 (+1 if page crossed) cycles
-    fn cmp_absolute_x(&mut self, address: u16) -> u8 {
+    fn cmp_absolute_x(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_x);
         self.cmp_immediate(value);
-
-        4
     }
 
     // Opcode: $D9
     // 4 (+1 if page crossed) cycles
-    fn cmp_absolute_y(&mut self, address: u16) -> u8 {
+    fn cmp_absolute_y(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_y);
         self.cmp_immediate(value);
-
-        4
     }
 
     // Opcode: $C1
     // 6 cycles
-    fn cmp_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn cmp_indirect_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_x(addr_lower_byte, self.registers.index_x);
         self.cmp_immediate(value);
-
-        6
     }
 
     // Opcode: $D1
     // 5 (+1 if page crossed) cycles
-    fn cmp_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
+    fn cmp_indirect_y(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_y(addr_lower_byte, self.registers.index_y);
         self.cmp_immediate(value);
-
-        5
     }
 
     /*
@@ -366,7 +524,7 @@ impl Cpu {
 
     // Opcode: $E0
     // 2 cycles
-    fn cpx_immediate(&mut self, value: u8) -> u8 {
+    fn cpx_immediate(&mut self, value: u8) {
         if self.registers.index_x == value {
             self.registers.set_zero()
         } else {
@@ -387,26 +545,20 @@ impl Cpu {
         } else {
             self.registers.unset_neg()
         }
-
-        2
     }
 
     // Opcode: $E4
     // 3 cycles
-    fn cpx_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn cpx_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.memory.fetch_zero_page(addr_lower_byte);
         self.cpx_immediate(value);
-
-        3
     }
 
     // Opcode: $EC
     // 4 cycles
-    fn cpx_absolute(&mut self, address: u16) -> u8 {
+    fn cpx_absolute(&mut self, address: u16) {
         let value = self.memory.fetch_absolute(address);
         self.cpx_immediate(value);
-
-        4
     }
 
     /*
@@ -416,7 +568,7 @@ impl Cpu {
 
     // Opcode: $C0
     // 2 cycles
-    fn cpy_immediate(&mut self, value: u8) -> u8 {
+    fn cpy_immediate(&mut self, value: u8) {
         if self.registers.index_y == value {
             self.registers.set_zero()
         } else {
@@ -436,26 +588,20 @@ impl Cpu {
         } else {
             self.registers.unset_neg()
         }
-
-        2
     }
 
     // Opcode: $C4
     // 3 cycles
-    fn cpy_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn cpy_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.memory.fetch_zero_page(addr_lower_byte);
         self.cpy_immediate(value);
-
-        3
     }
 
     // Opcode: $CC
     // 4 cycles
-    fn cpy_absolute(&mut self, address: u16) -> u8 {
+    fn cpy_absolute(&mut self, address: u16) {
         let value = self.memory.fetch_absolute(address);
         self.cpy_immediate(value);
-
-        4
     }
 
     /*
@@ -482,84 +628,39 @@ impl Cpu {
 
     // Opcode: $0A
     // 2 cycles
-    fn asl_accumulator(&mut self) -> u8 {
+    fn asl_accumulator(&mut self) {
         let new_accum = self.asl_immediate(self.registers.accumulator);
         self.registers.accumulator = new_accum;
-
-        2
     }
 
     // Opcode: $06
     // 5 cycles
-    fn asl_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self.memory.fetch_zero_page(addr_lower_byte);
-        let value = self.asl_immediate(value);
-        self.memory.store_zero_page(addr_lower_byte, value);
-
-        5
+    fn asl_zero_page(&mut self, addr_lower_byte: u8) {
+        self.modify(addr_lower_byte as u16, Self::asl_immediate);
     }
 
     // Opcode: $16
     // 6 cycles
-    fn asl_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self
+    fn asl_zero_page_x(&mut self, addr_lower_byte: u8) {
+        let address = self
             .memory
-            .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
-        let value = self.asl_immediate(value);
-        self.memory
-            .store_zero_page_x(addr_lower_byte, self.registers.index_x, value);
-
-        6
+            .zero_page_indexed_address(addr_lower_byte, self.registers.index_x);
+        self.modify(address as u16, Self::asl_immediate);
     }
 
     // Opcode: $0E
     // 6 cycles
-    fn asl_absolute(&mut self, address: u16) -> u8 {
-        let value = self.memory.fetch_absolute(address);
-        let value = self.asl_immediate(value);
-        self.memory.store_absolute(address, value);
-
-        6
+    fn asl_absolute(&mut self, address: u16) {
+        self.modify(address, Self::asl_immediate);
     }
 
     // Opcode: $1E
     // 7 cycles
-    fn asl_absolute_x(&mut self, address: u16) -> u8 {
-        let value = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_x);
-        let value = self.asl_immediate(value);
-        self.memory
-            .store_absolute_x(address, self.registers.index_x, value);
-
-        7
-    }
-
-    fn asl_absolute_y(&mut self, address: u16) {
-        let value = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_y);
-        let value = self.asl_immediate(value);
-        self.memory
-            .store_absolute_x(address, self.registers.index_y, value);
-    }
-
-    fn asl_indirect_x(&mut self, addr_lower_byte: u8) {
-        let value = self
-            .memory
-            .fetch_indirect_x(addr_lower_byte, self.registers.index_x);
-        let value = self.asl_immediate(value);
-        self.memory
-            .store_indirect_x(addr_lower_byte, self.registers.index_x, value);
-    }
-
-    fn asl_indirect_y(&mut self, addr_lower_byte: u8) {
-        let value = self
-            .memory
-            .fetch_indirect_y(addr_lower_byte, self.registers.index_y);
-        let value = self.asl_immediate(value);
-        self.memory
-            .store_indirect_y(addr_lower_byte, self.registers.index_y, value);
+    fn asl_absolute_x(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_x, IndexedAccess::Write);
+        self.modify(address, Self::asl_immediate);
     }
 
     /*
@@ -593,84 +694,39 @@ impl Cpu {
 
     // Opcode: $4A
     // 2 cycles
-    fn lsr_accumulator(&mut self) -> u8 {
+    fn lsr_accumulator(&mut self) {
         let new_accum = self.lsr_immediate(self.registers.accumulator);
         self.registers.accumulator = new_accum;
-
-        2
     }
 
     // Opcode: $46
     // 5 cycles
-    fn lsr_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self.memory.fetch_zero_page(addr_lower_byte);
-        let value = self.lsr_immediate(value);
-        self.memory.store_zero_page(addr_lower_byte, value);
-
-        5
+    fn lsr_zero_page(&mut self, addr_lower_byte: u8) {
+        self.modify(addr_lower_byte as u16, Self::lsr_immediate);
     }
 
     // Opcode: $56
     // 6 cycles
-    fn lsr_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self
+    fn lsr_zero_page_x(&mut self, addr_lower_byte: u8) {
+        let address = self
             .memory
-            .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
-        let value = self.lsr_immediate(value);
-        self.memory
-            .store_zero_page_x(addr_lower_byte, self.registers.index_x, value);
-
-        6
+            .zero_page_indexed_address(addr_lower_byte, self.registers.index_x);
+        self.modify(address as u16, Self::lsr_immediate);
     }
 
     // Opcode: $4E
     // 6 cycles
-    fn lsr_absolute(&mut self, address: u16) -> u8 {
-        let value = self.memory.fetch_absolute(address);
-        let value = self.lsr_immediate(value);
-        self.memory.store_absolute(address, value);
-
-        6
+    fn lsr_absolute(&mut self, address: u16) {
+        self.modify(address, Self::lsr_immediate);
     }
 
     // Opcode: $5E
     // 7 cycles
-    fn lsr_absolute_x(&mut self, address: u16) -> u8 {
-        let value = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_x);
-        let value = self.lsr_immediate(value);
-        self.memory
-            .store_absolute_x(address, self.registers.index_x, value);
-
-        7
-    }
-
-    fn lsr_absolute_y(&mut self, address: u16) {
-        let value = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_y);
-        let value = self.lsr_immediate(value);
-        self.memory
-            .store_absolute_x(address, self.registers.index_y, value);
-    }
-
-    fn lsr_indirect_y(&mut self, address: u8) {
-        let value = self
-            .memory
-            .fetch_indirect_y(address, self.registers.index_y);
-        let value = self.lsr_immediate(value);
-        self.memory
-            .store_indirect_y(address, self.registers.index_y, value);
-    }
-
-    fn lsr_indirect_x(&mut self, address: u8) {
-        let value = self
-            .memory
-            .fetch_indirect_x(address, self.registers.index_x);
-        let value = self.lsr_immediate(value);
-        self.memory
-            .store_indirect_x(address, self.registers.index_x, value);
+    fn lsr_absolute_x(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_x, IndexedAccess::Write);
+        self.modify(address, Self::lsr_immediate);
     }
 
     /*
@@ -697,84 +753,38 @@ impl Cpu {
 
     // Opcode: $2A
     // 2 cycles
-    fn rol_accumulator(&mut self) -> u8 {
+    fn rol_accumulator(&mut self) {
         self.registers.accumulator = self.rol_immediate(self.registers.accumulator);
-
-        2
     }
 
     // Opcode: $26
     // 5 cycles
-    fn rol_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self.memory.fetch_zero_page(addr_lower_byte);
-        let value = self.rol_immediate(value);
-        self.memory.store_zero_page(addr_lower_byte, value);
-
-        5
+    fn rol_zero_page(&mut self, addr_lower_byte: u8) {
+        self.modify(addr_lower_byte as u16, Self::rol_immediate);
     }
 
     // Opcode: $36
     // 6 cycles
-    fn rol_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self
+    fn rol_zero_page_x(&mut self, addr_lower_byte: u8) {
+        let address = self
             .memory
-            .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
-        let value = self.rol_immediate(value);
-        self.memory
-            .store_zero_page_x(addr_lower_byte, self.registers.index_x, value);
-
-        6
+            .zero_page_indexed_address(addr_lower_byte, self.registers.index_x);
+        self.modify(address as u16, Self::rol_immediate);
     }
 
     // Opcode: $2E
     // 6 cycles
-    fn rol_absolute(&mut self, address: u16) -> u8 {
-        let value = self.memory.fetch_absolute(address);
-        let value = self.rol_immediate(value);
-        self.memory.store_absolute(address, value);
-
-        6
+    fn rol_absolute(&mut self, address: u16) {
+        self.modify(address, Self::rol_immediate);
     }
 
     // Opcode: $3E
     // 7 cycles
-    fn rol_absolute_x(&mut self, address: u16) -> u8 {
-        let value = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_x);
-        let value = self.rol_immediate(value);
-        self.memory
-            .store_absolute_x(address, self.registers.index_x, value);
-
-        7
-    }
-
-    fn rol_absolute_y(&mut self, address: u16) {
-        let value = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_y);
-        let value = self.rol_immediate(value);
-        self.memory
-            .store_absolute_x(address, self.registers.index_y, value);
-    }
-
-    fn rol_indirect_x(&mut self, addr_lower_byte: u8) {
-        let value = self
-            .memory
-            .fetch_indirect_x(addr_lower_byte, self.registers.index_x);
-        let value = self.rol_immediate(value);
-        self.memory
-            .store_indirect_x(addr_lower_byte, self.registers.index_x, value);
-    }
-
-    fn rol_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self
-            .memory
-            .fetch_indirect_y(addr_lower_byte, self.registers.index_y);
-        let value_after_rol = self.rol_immediate(value);
-        self.memory
-            .store_indirect_y(addr_lower_byte, self.registers.index_y, value_after_rol);
-        value_after_rol
+    fn rol_absolute_x(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_x, IndexedAccess::Write);
+        self.modify(address, Self::rol_immediate);
     }
 
     /*
@@ -804,92 +814,38 @@ impl Cpu {
 
     // Opcode: $6A
     // 2 cycles
-    fn ror_accumulator(&mut self) -> u8 {
+    fn ror_accumulator(&mut self) {
         self.registers.accumulator = self.ror_immediate(self.registers.accumulator);
-
-        2
     }
 
     // Opcode: $66
     // 5 cycles
-    fn ror_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self.memory.fetch_zero_page(addr_lower_byte);
-        let value = self.ror_immediate(value);
-        self.memory.store_zero_page(addr_lower_byte, value);
-
-        5
+    fn ror_zero_page(&mut self, addr_lower_byte: u8) {
+        self.modify(addr_lower_byte as u16, Self::ror_immediate);
     }
 
     // Opcode: $76
     // 6 cycles
-    fn ror_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self
+    fn ror_zero_page_x(&mut self, addr_lower_byte: u8) {
+        let address = self
             .memory
-            .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
-        let value = self.ror_immediate(value);
-        self.memory
-            .store_zero_page_x(addr_lower_byte, self.registers.index_x, value);
-
-        6
+            .zero_page_indexed_address(addr_lower_byte, self.registers.index_x);
+        self.modify(address as u16, Self::ror_immediate);
     }
 
     // Opcode: $6E
     // 6 cycles
-    fn ror_absolute(&mut self, address: u16) -> u8 {
-        let value = self.memory.fetch_absolute(address);
-        let value = self.ror_immediate(value);
-        self.memory.store_absolute(address, value);
-
-        6
+    fn ror_absolute(&mut self, address: u16) {
+        self.modify(address, Self::ror_immediate);
     }
 
     // Opcode: $7E
     // 7 cycles
-    fn ror_absolute_x(&mut self, address: u16) -> u8 {
-        let value = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_x);
-        let value = self.ror_immediate(value);
-        self.memory
-            .store_absolute_x(address, self.registers.index_x, value);
-
-        7
-    }
-
-    // Helper
-    fn ror_absolute_y(&mut self, address: u16) -> u8 {
-        let value = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_y);
-        let value = self.ror_immediate(value);
-        self.memory
-            .store_absolute_x(address, self.registers.index_y, value);
-
-        7
-    }
-
-    // Helper
-    fn ror_indirect_x(&mut self, address: u8) -> u8 {
-        let value = self
-            .memory
-            .fetch_indirect_x(address, self.registers.index_x);
-        let value = self.ror_immediate(value);
-        self.memory
-            .store_indirect_x(address, self.registers.index_x, value);
-
-        7
-    }
-
-    // Helper
-    fn ror_indirect_y(&mut self, address: u8) -> u8 {
-        let value = self
-            .memory
-            .fetch_indirect_y(address, self.registers.index_y);
-        let value = self.ror_immediate(value);
-        self.memory
-            .store_indirect_y(address, self.registers.index_y, value);
-
-        7
+    fn ror_absolute_x(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_x, IndexedAccess::Write);
+        self.modify(address, Self::ror_immediate);
     }
 
     /*
@@ -899,84 +855,68 @@ impl Cpu {
 
     // Opcode: $A9
     // 2 cycles
-    fn lda_immediate(&mut self, value: u8) -> u8 {
+    fn lda_immediate(&mut self, value: u8) {
         self.registers.accumulator = value;
         self.update_zero_negative_flags(self.registers.accumulator);
-
-        2
     }
 
     // Opcode: $AD
     // 4 cycles
-    fn lda_absolute(&mut self, address: u16) -> u8 {
+    fn lda_absolute(&mut self, address: u16) {
         let value = self.memory.fetch_absolute(address);
         self.lda_immediate(value);
-
-        4
     }
 
     // Opcode: $A5
     // 3 cycles
-    fn lda_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn lda_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.memory.fetch_zero_page(addr_lower_byte);
         self.lda_immediate(value);
-
-        3
     }
 
     // Opcode: $B5
     // 4 cycles
-    fn lda_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn lda_zero_page_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
         self.lda_immediate(value);
-
-        4
     }
 
     // Opcode: $BD
     // 4 (+1 if page crossed) cycles
-    fn lda_absolute_x(&mut self, address: u16) -> u8 {
+    fn lda_absolute_x(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_x);
         self.lda_immediate(value);
-
-        4
     }
 
     // Opcode: $B9
     // 4 (+1 if page crossed) cycles
-    fn lda_absolute_y(&mut self, address: u16) -> u8 {
+    fn lda_absolute_y(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_y);
         self.lda_immediate(value);
-
-        4
     }
 
     // Opcode: $A1
     // 6 cycles
-    fn lda_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn lda_indirect_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_x(addr_lower_byte, self.registers.index_x);
         self.lda_immediate(value);
-
-        6
     }
 
     // Opcode: $B1
     // 5 (+1 if page crossed) cycles
-    fn lda_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
+    fn lda_indirect_y(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_y(addr_lower_byte, self.registers.index_y);
         self.lda_immediate(value);
-
-        5
     }
 
     /*
@@ -988,51 +928,41 @@ impl Cpu {
 
     // Opcode: $A2
     // 2 cycles
-    fn ldx_immediate(&mut self, value: u8) -> u8 {
+    fn ldx_immediate(&mut self, value: u8) {
         self.registers.index_x = value;
         self.update_zero_negative_flags(self.registers.index_x);
-
-        2
     }
 
     // Opcode: $AE
     // 4 cycles
-    fn ldx_absolute(&mut self, address: u16) -> u8 {
+    fn ldx_absolute(&mut self, address: u16) {
         let value = self.memory.fetch_absolute(address);
         self.ldx_immediate(value);
-
-        4
     }
 
     // Opcode: $BE
     // 4 (+1 if page crossed) cycles
-    fn ldx_absolute_y(&mut self, address: u16) -> u8 {
+    fn ldx_absolute_y(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_y);
         self.ldx_immediate(value);
-
-        4
     }
 
     // Opcode: $A6
     // 3 cycles
-    fn ldx_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn ldx_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.memory.fetch_zero_page(addr_lower_byte);
         self.ldx_immediate(value);
-
-        3
     }
 
     // Opcode: $B6
     // 4 cycles
-    fn ldx_zero_page_y(&mut self, addr_lower_byte: u8) -> u8 {
+    fn ldx_zero_page_y(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
-            .fetch_zero_page_x(addr_lower_byte, self.registers.index_y);
+            .fetch_zero_page_y(addr_lower_byte, self.registers.index_y);
         self.ldx_immediate(value);
-
-        4
     }
 
     /*
@@ -1044,51 +974,41 @@ impl Cpu {
 
     // Opcode: $A0
     // 2 cycles
-    fn ldy_immediate(&mut self, value: u8) -> u8 {
+    fn ldy_immediate(&mut self, value: u8) {
         self.registers.index_y = value;
         self.update_zero_negative_flags(self.registers.index_y);
-
-        2
     }
 
     // Opcode: $AC
     // 4 cycles
-    fn ldy_absolute(&mut self, address: u16) -> u8 {
+    fn ldy_absolute(&mut self, address: u16) {
         let value = self.memory.fetch_absolute(address);
         self.ldy_immediate(value);
-
-        4
     }
 
     // Opcode: $BC
     // 4 (+1 if page crossed) cycles
-    fn ldy_absolute_x(&mut self, address: u16) -> u8 {
+    fn ldy_absolute_x(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_x);
         self.ldy_immediate(value);
-
-        4
     }
 
     // Opcode: $A4
     // 3 cycles
-    fn ldy_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn ldy_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.memory.fetch_zero_page(addr_lower_byte);
         self.ldy_immediate(value);
-
-        3
     }
 
     // Opcode: $B4
     // 4 cycles
-    fn ldy_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn ldy_zero_page_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
         self.ldy_immediate(value);
-
-        4
     }
 
     /*
@@ -1098,10 +1018,8 @@ impl Cpu {
      *   Opcode: $AA
      *   Cycles: 2
      */
-    fn tax(&mut self) -> u8 {
+    fn tax(&mut self) {
         self.ldx_immediate(self.registers.accumulator);
-
-        2
     }
 
     /*
@@ -1111,10 +1029,8 @@ impl Cpu {
      *   Opcode: $A8
      *   Cycles: 2
      */
-    fn tay(&mut self) -> u8 {
+    fn tay(&mut self) {
         self.ldy_immediate(self.registers.accumulator);
-
-        2
     }
 
     /*
@@ -1124,10 +1040,8 @@ impl Cpu {
      *   Opcode: $BA
      *   Cycles: 2
      */
-    fn tsx(&mut self) -> u8 {
+    fn tsx(&mut self) {
         self.ldx_immediate(self.registers.stack_pointer);
-
-        2
     }
 
     /*
@@ -1137,10 +1051,8 @@ impl Cpu {
      *   Opcode: $8A
      *   Cycles: 2
      */
-    fn txa(&mut self) -> u8 {
+    fn txa(&mut self) {
         self.lda_immediate(self.registers.index_x);
-
-        2
     }
 
     /*
@@ -1150,10 +1062,8 @@ impl Cpu {
      *   Opcode: $9A
      *   Cycles: 2
      */
-    fn txs(&mut self) -> u8 {
+    fn txs(&mut self) {
         self.registers.stack_pointer = self.registers.index_x;
-
-        2
     }
 
     /*
@@ -1163,10 +1073,8 @@ impl Cpu {
      *   Opcode: $98
      *   Cycles: 2
      */
-    fn tya(&mut self) -> u8 {
+    fn tya(&mut self) {
         self.lda_immediate(self.registers.index_y);
-
-        2
     }
 
     /*
@@ -1176,92 +1084,76 @@ impl Cpu {
 
     // Opcode: $29
     // Cycles: 2
-    fn and_immediate(&mut self, value: u8) -> u8 {
+    fn and_immediate(&mut self, value: u8) {
         self.registers.accumulator &= value;
 
         self.update_zero_negative_flags(self.registers.accumulator);
-
-        2
     }
 
     // Opcode: $25
     // Cycles: 3
-    fn and_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn and_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.memory.fetch_zero_page(addr_lower_byte);
 
         self.and_immediate(value);
-
-        3
     }
 
     // Opcode: $35
     // Cycles: 4
-    fn and_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn and_zero_page_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
 
         self.and_immediate(value);
-
-        4
     }
 
     // Opcode: $2D
     // Cycles: 4
-    fn and_absolute(&mut self, address: u16) -> u8 {
+    fn and_absolute(&mut self, address: u16) {
         let value = self.memory.fetch_absolute(address);
 
         self.and_immediate(value);
-
-        4
     }
 
     // Opcode: $3D
     // Cycles: 4 (+1 if page crossed)
-    fn and_absolute_x(&mut self, address: u16) -> u8 {
+    fn and_absolute_x(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_x);
 
         self.and_immediate(value);
-
-        4
     }
 
     // Opcode: $39
     // Cycles: 4 (+1 if page crossed)
-    fn and_absolute_y(&mut self, address: u16) -> u8 {
+    fn and_absolute_y(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_y);
 
         self.and_immediate(value);
-
-        4
     }
 
     // Opcode: $21
     // Cycles: 6
-    fn and_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn and_indirect_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_x(addr_lower_byte, self.registers.index_x);
 
         self.and_immediate(value);
-
-        6
     }
 
     // Opcode: $31
     // Cycles: 5 (+1 if page crossed)
-    fn and_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
+    fn and_indirect_y(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_y(addr_lower_byte, self.registers.index_y);
 
         self.and_immediate(value);
-
-        5
     }
 
     /*
@@ -1271,92 +1163,76 @@ impl Cpu {
 
     // Opcode: $49
     // Cycles: 2
-    fn eor_immediate(&mut self, value: u8) -> u8 {
+    fn eor_immediate(&mut self, value: u8) {
         self.registers.accumulator ^= value;
 
         self.update_zero_negative_flags(self.registers.accumulator);
-
-        2
     }
 
     // Opcode: $45
     // Cycles: 3
-    fn eor_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn eor_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.memory.fetch_zero_page(addr_lower_byte);
 
         self.eor_immediate(value);
-
-        3
     }
 
     // Opcode: $55
     // Cycles: 4
-    fn eor_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn eor_zero_page_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
 
         self.eor_immediate(value);
-
-        4
     }
 
     // Opcode: $4D
     // Cycles: 4
-    fn eor_absolute(&mut self, address: u16) -> u8 {
+    fn eor_absolute(&mut self, address: u16) {
         let value = self.memory.fetch_absolute(address);
 
         self.eor_immediate(value);
-
-        4
     }
 
     // Opcode: $5D
     // Cycles: 4 (+1 if page crossed)
-    fn eor_absolute_x(&mut self, address: u16) -> u8 {
+    fn eor_absolute_x(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_x);
 
         self.eor_immediate(value);
-
-        4
     }
 
     // Opcode: $59
     // Cycles: 4 (+1 if page crossed)
-    fn eor_absolute_y(&mut self, address: u16) -> u8 {
+    fn eor_absolute_y(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_y);
 
         self.eor_immediate(value);
-
-        4
     }
 
     // Opcode: $41
     // Cycles: 6
-    fn eor_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn eor_indirect_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_x(addr_lower_byte, self.registers.index_x);
 
         self.eor_immediate(value);
-
-        6
     }
 
     // Opcode: $51
     // Cycles: 5 (+1 if page crossed)
-    fn eor_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
+    fn eor_indirect_y(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_y(addr_lower_byte, self.registers.index_y);
 
         self.eor_immediate(value);
-
-        5
     }
 
     /*
@@ -1366,92 +1242,76 @@ impl Cpu {
 
     // Opcode: $09
     // Cycles: 2
-    fn ora_immediate(&mut self, value: u8) -> u8 {
+    fn ora_immediate(&mut self, value: u8) {
         self.registers.accumulator |= value;
 
         self.update_zero_negative_flags(self.registers.accumulator);
-
-        2
     }
 
     // Opcode: $05
     // Cycles: 3
-    fn ora_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn ora_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.memory.fetch_zero_page(addr_lower_byte);
 
         self.ora_immediate(value);
-
-        3
     }
 
     // Opcode: $15
     // Cycles: 4
-    fn ora_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn ora_zero_page_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
 
         self.ora_immediate(value);
-
-        4
     }
 
     // Opcode: $0D
     // Cycles: 4
-    fn ora_absolute(&mut self, address: u16) -> u8 {
+    fn ora_absolute(&mut self, address: u16) {
         let value = self.memory.fetch_absolute(address);
 
         self.ora_immediate(value);
-
-        4
     }
 
     // Opcode: $1D
     // Cycles: 4 (+1 if page crossed)
-    fn ora_absolute_x(&mut self, address: u16) -> u8 {
+    fn ora_absolute_x(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_x);
 
         self.ora_immediate(value);
-
-        4
     }
 
     // Opcode: $19
     // Cycles: 4 (+1 if page crossed)
-    fn ora_absolute_y(&mut self, address: u16) -> u8 {
+    fn ora_absolute_y(&mut self, address: u16) {
         let value = self
             .memory
             .fetch_absolute_x(address, self.registers.index_y);
 
         self.ora_immediate(value);
-
-        4
     }
 
     // Opcode: $01
     // Cycles: 6
-    fn ora_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn ora_indirect_x(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_x(addr_lower_byte, self.registers.index_x);
 
         self.ora_immediate(value);
-
-        6
     }
 
     // Opcode: $11
     // Cycles: 5 (+1 if page crossed)
-    fn ora_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
+    fn ora_indirect_y(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
             .fetch_indirect_y(addr_lower_byte, self.registers.index_y);
 
         self.ora_immediate(value);
-
-        5
     }
 
     /*
@@ -1461,7 +1321,7 @@ impl Cpu {
 
     // Opcode: $24
     // Cycles: 3
-    fn bit_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn bit_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.memory.fetch_zero_page(addr_lower_byte);
         let result = self.registers.accumulator & value;
 
@@ -1482,13 +1342,11 @@ impl Cpu {
         } else {
             self.registers.unset_overflow();
         }
-
-        3
     }
 
     // Opcode: $2C
     // Cycles: 4
-    fn bit_absolute(&mut self, address: u16) -> u8 {
+    fn bit_absolute(&mut self, address: u16) {
         let value = self.memory.fetch_absolute(address);
         let result = self.registers.accumulator & value;
 
@@ -1509,8 +1367,6 @@ impl Cpu {
         } else {
             self.registers.unset_overflow();
         }
-
-        4
     }
 
     /*
@@ -1520,10 +1376,8 @@ impl Cpu {
      *   Opcode: $18
      *   Cycles: 2
      */
-    fn clc(&mut self) -> u8 {
+    fn clc(&mut self) {
         self.registers.unset_carry();
-
-        2
     }
 
     /*
@@ -1534,10 +1388,8 @@ impl Cpu {
      *   Opcode: $D8
      *   Cycles: 2
      */
-    fn cld(&mut self) -> u8 {
+    fn cld(&mut self) {
         self.registers.unset_decimal();
-
-        2
     }
 
     /*
@@ -1547,10 +1399,8 @@ impl Cpu {
      *   Opcode: $58
      *   Cycles: 2
      */
-    fn cli(&mut self) -> u8 {
+    fn cli(&mut self) {
         self.registers.unset_interrupt_disable();
-
-        2
     }
 
     /*
@@ -1560,10 +1410,8 @@ impl Cpu {
      *   Opcode: $B8
      *   Cycles: 2
      */
-    fn clv(&mut self) -> u8 {
+    fn clv(&mut self) {
         self.registers.unset_overflow();
-
-        2
     }
 
     /*
@@ -1573,10 +1421,8 @@ impl Cpu {
      *   Opcode: $38
      *   Cycles: 2
      */
-    fn sec(&mut self) -> u8 {
+    fn sec(&mut self) {
         self.registers.set_carry();
-
-        2
     }
 
     /*
@@ -1588,9 +1434,8 @@ impl Cpu {
      *   Opcode: $F8
      *   Cycles: 2
      */
-    fn sed(&mut self) -> u8 {
+    fn sed(&mut self) {
         self.registers.set_decimal();
-        2
     }
 
     /*
@@ -1600,10 +1445,8 @@ impl Cpu {
      *   Opcode: $78
      *   Cycles: 2
      */
-    fn sei(&mut self) -> u8 {
+    fn sei(&mut self) {
         self.registers.set_interrupt_disable();
-
-        2
     }
 
     /*
@@ -1613,10 +1456,8 @@ impl Cpu {
      *   Opcode: $48
      *   Cycles: 3
      */
-    fn pha(&mut self) -> u8 {
+    fn pha(&mut self) {
         self.stack_push(self.registers.accumulator);
-
-        3
     }
 
     /*
@@ -1626,10 +1467,8 @@ impl Cpu {
      *   Opcode: $08
      *   Cycles: 3
      */
-    fn php(&mut self) -> u8 {
+    fn php(&mut self) {
         self.stack_push(self.registers.processor_status | 0x10);
-
-        3
     }
 
     /*
@@ -1639,11 +1478,10 @@ impl Cpu {
      *   Opcode: $68
      *   Cycles: 4
      */
-    fn pla(&mut self) -> u8 {
+    fn pla(&mut self) {
+        self.stack_dummy_read();
         let val = self.stack_pop();
         self.lda_immediate(val);
-
-        4
     }
 
     /*
@@ -1653,12 +1491,11 @@ impl Cpu {
      *   Opcode: $28
      *   Cycles: 4
      */
-    fn plp(&mut self) -> u8 {
+    fn plp(&mut self) {
+        self.stack_dummy_read();
         let val = self.stack_pop();
         self.registers.processor_status = val | 0b100000;
         self.registers.unset_break();
-
-        4
     }
 
     /*
@@ -1669,19 +1506,15 @@ impl Cpu {
 
     // Opcode: $4C
     // Cycles: 3
-    fn jmp_absolute(&mut self, address: u16) -> u8 {
+    fn jmp_absolute(&mut self, address: u16) {
         self.registers.program_counter = address;
-
-        3
     }
 
     // Opcode: $6C
     // Cycles: 5
-    fn jmp_indirect(&mut self, address: u16) -> u8 {
+    fn jmp_indirect(&mut self, address: u16) {
         let value = self.memory.fetch_indirect_quirk(address);
         self.registers.program_counter = value;
-
-        5
     }
 
     /*
@@ -1691,15 +1524,35 @@ impl Cpu {
      *   Opcode: $20
      *   Cycles: 6
      */
-    fn jsr(&mut self, address: u16) -> u8 {
-        let pc_high = ((self.registers.program_counter + 2) >> 8) as u8;
-        let pc_low = ((self.registers.program_counter + 2) & 0xFF) as u8;
-        self.stack_push(pc_high);
-        self.stack_push(pc_low);
+    fn jsr(&mut self, address: u16) {
+        self.stack_push_u16(self.jsr_return_address());
 
         self.registers.program_counter = address;
+    }
 
-        6
+    /*
+     *   Shared by the eight branches. The offset is relative to the instruction after the branch, and a taken
+     *   branch takes a cycle more than the base 2, or two more when the target is on a different page than that
+     *   instruction. Returns those extra cycles.
+     *   PC still points at the opcode here, decode_execute moves it past the operand afterwards.
+     */
+    fn branch(&mut self, taken: bool, offset: u8) -> u8 {
+        if !taken {
+            return 0;
+        }
+        let next = self.registers.program_counter.wrapping_add(2);
+        let target = next.wrapping_add_signed(offset as i8 as i16);
+        self.registers.program_counter = target.wrapping_sub(2);
+
+        // the extra cycles read the next opcode, then with a page crossing the target on the old page
+        self.memory.fetch_absolute(next);
+        if next & 0xFF00 == target & 0xFF00 {
+            1
+        } else {
+            self.memory
+                .fetch_absolute((next & 0xFF00) | (target & 0x00FF));
+            2
+        }
     }
 
     /*
@@ -1710,15 +1563,8 @@ impl Cpu {
      *   Cycles: 2 (+1 if branch succeeds +2 if to a new page)
      */
     fn bcc(&mut self, offset: u8) -> u8 {
-        if self.registers.get_carry() == 0 {
-            self.registers.program_counter = self
-                .registers
-                .program_counter
-                .wrapping_add_signed(offset as i8 as i16);
-            3
-        } else {
-            2
-        }
+        let taken = self.registers.get_carry() == 0;
+        self.branch(taken, offset)
     }
 
     /*
@@ -1729,15 +1575,8 @@ impl Cpu {
      *   Cycles: 2 (+1 if branch succeeds +2 if to a new page)
      */
     fn bcs(&mut self, offset: u8) -> u8 {
-        if self.registers.get_carry() > 0 {
-            self.registers.program_counter = self
-                .registers
-                .program_counter
-                .wrapping_add_signed(offset as i8 as i16);
-            3
-        } else {
-            2
-        }
+        let taken = self.registers.get_carry() > 0;
+        self.branch(taken, offset)
     }
 
     /*
@@ -1748,16 +1587,8 @@ impl Cpu {
      *   Cycles: 2 (+1 if branch succeeds +2 if to a new page)
      */
     fn beq(&mut self, offset: u8) -> u8 {
-        if self.registers.get_zero() > 0 {
-            self.registers.program_counter = self
-                .registers
-                .program_counter
-                .wrapping_add_signed(offset as i8 as i16);
-
-            3
-        } else {
-            2
-        }
+        let taken = self.registers.get_zero() > 0;
+        self.branch(taken, offset)
     }
 
     /*
@@ -1768,15 +1599,8 @@ impl Cpu {
      *   Cycles: 2 (+1 if branch succeeds +2 if to a new page)
      */
     fn bmi(&mut self, offset: u8) -> u8 {
-        if self.registers.get_neg() > 0 {
-            self.registers.program_counter = self
-                .registers
-                .program_counter
-                .wrapping_add_signed(offset as i8 as i16);
-            3
-        } else {
-            2
-        }
+        let taken = self.registers.get_neg() > 0;
+        self.branch(taken, offset)
     }
 
     /*
@@ -1787,15 +1611,8 @@ impl Cpu {
      *   Cycles: 2 (+1 if branch succeeds +2 if to a new page)
      */
     fn bne(&mut self, offset: u8) -> u8 {
-        if self.registers.get_zero() == 0 {
-            self.registers.program_counter = self
-                .registers
-                .program_counter
-                .wrapping_add_signed(offset as i8 as i16);
-            3
-        } else {
-            2
-        }
+        let taken = self.registers.get_zero() == 0;
+        self.branch(taken, offset)
     }
 
     /*
@@ -1806,15 +1623,8 @@ impl Cpu {
      *   Cycles: 2 (+1 if branch succeeds +2 if to a new page)
      */
     fn bpl(&mut self, offset: u8) -> u8 {
-        if self.registers.get_neg() == 0 {
-            self.registers.program_counter = self
-                .registers
-                .program_counter
-                .wrapping_add_signed(offset as i8 as i16);
-            3
-        } else {
-            2
-        }
+        let taken = self.registers.get_neg() == 0;
+        self.branch(taken, offset)
     }
 
     /*
@@ -1825,15 +1635,8 @@ impl Cpu {
      *   Cycles: 2 (+1 if branch succeeds +2 if to a new page)
      */
     fn bvc(&mut self, offset: u8) -> u8 {
-        if self.registers.get_overflow() == 0 {
-            self.registers.program_counter = self
-                .registers
-                .program_counter
-                .wrapping_add_signed(offset as i8 as i16);
-            3
-        } else {
-            2
-        }
+        let taken = self.registers.get_overflow() == 0;
+        self.branch(taken, offset)
     }
 
     /*
@@ -1844,15 +1647,8 @@ impl Cpu {
      *   Cycles: 2 (+1 if branch succeeds +2 if to a new page)
      */
     fn bvs(&mut self, offset: u8) -> u8 {
-        if self.registers.get_overflow() > 0 {
-            self.registers.program_counter = self
-                .registers
-                .program_counter
-                .wrapping_add_signed(offset as i8 as i16);
-            3
-        } else {
-            2
-        }
+        let taken = self.registers.get_overflow() > 0;
+        self.branch(taken, offset)
     }
 
     /*
@@ -1862,74 +1658,60 @@ impl Cpu {
 
     // Opcode: $85
     // Cycles: 3
-    fn sta_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sta_zero_page(&mut self, addr_lower_byte: u8) {
         self.memory
             .store_zero_page(addr_lower_byte, self.registers.accumulator);
-
-        3
     }
 
     // Opcode: $95
     // Cycles: 4
-    fn sta_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sta_zero_page_x(&mut self, addr_lower_byte: u8) {
         self.memory.store_zero_page_x(
             addr_lower_byte,
             self.registers.index_x,
             self.registers.accumulator,
         );
-
-        4
     }
 
     // Opcode: $8D
     // Cycles: 4
-    fn sta_absolute(&mut self, address: u16) -> u8 {
+    fn sta_absolute(&mut self, address: u16) {
         self.memory
             .store_absolute(address, self.registers.accumulator);
-
-        4
     }
 
     // Opcode: $9D
     // Cycles: 5
-    fn sta_absolute_x(&mut self, address: u16) -> u8 {
+    fn sta_absolute_x(&mut self, address: u16) {
         self.memory
             .store_absolute_x(address, self.registers.index_x, self.registers.accumulator);
-
-        5
     }
 
     // Opcode: $99
     // Cycles: 5
-    fn sta_absolute_y(&mut self, address: u16) -> u8 {
+    fn sta_absolute_y(&mut self, address: u16) {
         self.memory
             .store_absolute_x(address, self.registers.index_y, self.registers.accumulator);
-
-        5
     }
 
     // Opcode: $81
     // Cycles: 6
-    fn sta_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sta_indirect_x(&mut self, addr_lower_byte: u8) {
         self.memory.store_indirect_x(
             addr_lower_byte,
             self.registers.index_x,
             self.registers.accumulator,
         );
-
-        6
     }
 
     // Opcode: $91
     // Cycles: 6
-    fn sta_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sta_indirect_y(&mut self, addr_lower_byte: u8) {
         self.memory.store_indirect_y(
             addr_lower_byte,
             self.registers.index_y,
             self.registers.accumulator,
         );
-
-        6
     }
 
     /*
@@ -1939,31 +1721,25 @@ impl Cpu {
 
     // Opcode: $86
     // Cycles: 3
-    fn stx_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn stx_zero_page(&mut self, addr_lower_byte: u8) {
         self.memory
             .store_zero_page(addr_lower_byte, self.registers.index_x);
-
-        3
     }
 
     // Opcode: $96
     // Cycles: 4
-    fn stx_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        self.memory.store_zero_page_x(
+    fn stx_zero_page_y(&mut self, addr_lower_byte: u8) {
+        self.memory.store_zero_page_y(
             addr_lower_byte,
             self.registers.index_y,
             self.registers.index_x,
         );
-
-        4
     }
 
     // Opcode: $8E
     // Cycles: 4
-    fn stx_absolute(&mut self, address: u16) -> u8 {
+    fn stx_absolute(&mut self, address: u16) {
         self.memory.store_absolute(address, self.registers.index_x);
-
-        4
     }
 
     /*
@@ -1973,31 +1749,25 @@ impl Cpu {
 
     // Opcode: $84
     // Cycles: 3
-    fn sty_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sty_zero_page(&mut self, addr_lower_byte: u8) {
         self.memory
             .store_zero_page(addr_lower_byte, self.registers.index_y);
-
-        3
     }
 
     // Opcode: $94
     // Cycles: 4
-    fn sty_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sty_zero_page_x(&mut self, addr_lower_byte: u8) {
         self.memory.store_zero_page_x(
             addr_lower_byte,
             self.registers.index_x,
             self.registers.index_y,
         );
-
-        4
     }
 
     // Opcode: $8C
     // Cycles: 4
-    fn sty_absolute(&mut self, address: u16) -> u8 {
+    fn sty_absolute(&mut self, address: u16) {
         self.memory.store_absolute(address, self.registers.index_y);
-
-        4
     }
 
     /*
@@ -2005,56 +1775,41 @@ impl Cpu {
      *   Increment the value at a specified memory location
      */
 
-    // Opcode: $E6
-    // Cycles: 5
-    fn inc_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        let new_val = self.memory.fetch_zero_page(addr_lower_byte).wrapping_add(1);
-
-        self.memory.store_zero_page(addr_lower_byte, new_val);
+    // Helper
+    fn inc_immediate(&mut self, value: u8) -> u8 {
+        let new_val = value.wrapping_add(1);
         self.update_zero_negative_flags(new_val);
+        new_val
+    }
 
-        5
+    // Opcode: $E6
+    // Cycles: 5
+    fn inc_zero_page(&mut self, addr_lower_byte: u8) {
+        self.modify(addr_lower_byte as u16, Self::inc_immediate);
     }
 
     // Opcode: $F6
     // Cycles: 6
-    fn inc_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        let new_val = self
+    fn inc_zero_page_x(&mut self, addr_lower_byte: u8) {
+        let address = self
             .memory
-            .fetch_zero_page_x(addr_lower_byte, self.registers.index_x)
-            .wrapping_add(1);
-
-        self.memory
-            .store_zero_page_x(addr_lower_byte, self.registers.index_x, new_val);
-        self.update_zero_negative_flags(new_val);
-
-        6
+            .zero_page_indexed_address(addr_lower_byte, self.registers.index_x);
+        self.modify(address as u16, Self::inc_immediate);
     }
 
     // Opcode: $EE
     // Cycles: 6
-    fn inc_absolute(&mut self, address: u16) -> u8 {
-        let new_val = self.memory.fetch_absolute(address).wrapping_add(1);
-
-        self.memory.store_absolute(address, new_val);
-        self.update_zero_negative_flags(new_val);
-
-        6
+    fn inc_absolute(&mut self, address: u16) {
+        self.modify(address, Self::inc_immediate);
     }
 
     // Opcode: $FE
     // Cycles: 7
-    fn inc_absolute_x(&mut self, address: u16) -> u8 {
-        let new_val = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_x)
-            .wrapping_add(1);
-
-        self.memory
-            .store_absolute_x(address, self.registers.index_x, new_val);
-        self.update_zero_negative_flags(new_val);
-
-        7
+    fn inc_absolute_x(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_x, IndexedAccess::Write);
+        self.modify(address, Self::inc_immediate);
     }
 
     /*
@@ -2065,11 +1820,9 @@ impl Cpu {
      *   Cycles: 2
      */
 
-    fn inx_implied(&mut self) -> u8 {
+    fn inx_implied(&mut self) {
         self.registers.index_x = self.registers.index_x.wrapping_add(1);
         self.update_zero_negative_flags(self.registers.index_x);
-
-        2
     }
 
     /*
@@ -2080,11 +1833,9 @@ impl Cpu {
      *   Cycles: 2
      */
 
-    fn iny_implied(&mut self) -> u8 {
+    fn iny_implied(&mut self) {
         self.registers.index_y = self.registers.index_y.wrapping_add(1);
         self.update_zero_negative_flags(self.registers.index_y);
-
-        2
     }
 
     /*
@@ -2092,56 +1843,41 @@ impl Cpu {
      *   Decrement the value at a specified memory location
      */
 
-    // Opcode: $C6
-    // Cycles: 5
-    fn dec_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        let new_val = self.memory.fetch_zero_page(addr_lower_byte).wrapping_sub(1);
-
-        self.memory.store_zero_page(addr_lower_byte, new_val);
+    // Helper
+    fn dec_immediate(&mut self, value: u8) -> u8 {
+        let new_val = value.wrapping_sub(1);
         self.update_zero_negative_flags(new_val);
+        new_val
+    }
 
-        5
+    // Opcode: $C6
+    // Cycles: 5
+    fn dec_zero_page(&mut self, addr_lower_byte: u8) {
+        self.modify(addr_lower_byte as u16, Self::dec_immediate);
     }
 
     // Opcode: $D6
     // Cycles: 6
-    fn dnc_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        let new_val = self
+    fn dnc_zero_page_x(&mut self, addr_lower_byte: u8) {
+        let address = self
             .memory
-            .fetch_zero_page_x(addr_lower_byte, self.registers.index_x)
-            .wrapping_sub(1);
-
-        self.memory
-            .store_zero_page_x(addr_lower_byte, self.registers.index_x, new_val);
-        self.update_zero_negative_flags(new_val);
-
-        6
+            .zero_page_indexed_address(addr_lower_byte, self.registers.index_x);
+        self.modify(address as u16, Self::dec_immediate);
     }
 
     // Opcode: $CE
     // Cycles: 6
-    fn dec_absolute(&mut self, address: u16) -> u8 {
-        let new_val = self.memory.fetch_absolute(address).wrapping_sub(1);
-
-        self.memory.store_absolute(address, new_val);
-        self.update_zero_negative_flags(new_val);
-
-        6
+    fn dec_absolute(&mut self, address: u16) {
+        self.modify(address, Self::dec_immediate);
     }
 
     // Opcode: $DE
     // Cycles: 7
-    fn dec_absolute_x(&mut self, address: u16) -> u8 {
-        let new_val = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_x)
-            .wrapping_sub(1);
-
-        self.memory
-            .store_absolute_x(address, self.registers.index_x, new_val);
-        self.update_zero_negative_flags(new_val);
-
-        7
+    fn dec_absolute_x(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_x, IndexedAccess::Write);
+        self.modify(address, Self::dec_immediate);
     }
 
     /*
@@ -2152,11 +1888,9 @@ impl Cpu {
      *   Cycles: 2
      */
 
-    fn dex_implied(&mut self) -> u8 {
+    fn dex_implied(&mut self) {
         self.registers.index_x = self.registers.index_x.wrapping_sub(1);
         self.update_zero_negative_flags(self.registers.index_x);
-
-        2
     }
 
     /*
@@ -2167,11 +1901,9 @@ impl Cpu {
      *   Cycles: 2
      */
 
-    fn dey_implied(&mut self) -> u8 {
+    fn dey_implied(&mut self) {
         self.registers.index_y = self.registers.index_y.wrapping_sub(1);
         self.update_zero_negative_flags(self.registers.index_y);
-
-        2
     }
 
     /*
@@ -2182,14 +1914,8 @@ impl Cpu {
      *   Cycles: 7
      */
 
-    pub fn brk_implied(&mut self) -> u8 {
-        let pc_high = ((self.registers.program_counter + 2) >> 8) as u8;
-        self.stack_push(pc_high);
-
-        // Push low byte
-        let pc_low = ((self.registers.program_counter + 2) & 0xFF) as u8;
-        self.stack_push(pc_low);
-
+    pub fn brk_implied(&mut self) {
+        self.stack_push_u16(self.brk_return_address());
         self.stack_push(self.registers.processor_status | 0x10);
 
         let irq_vector_low = self.memory.fetch_absolute(0xFFFE) as u16;
@@ -2199,17 +1925,33 @@ impl Cpu {
 
         // self.registers.set_break();
         self.registers.set_interrupt_disable();
+    }
+
+    /*
+     *   IRQ - Maskable interrupt
+     *   Same sequence as NMI but through the $FFFE vector, and only taken while the interrupt disable flag is clear.
+     *   Pushed status has the break flag clear so handlers can tell it apart from BRK.
+     *
+     *   Cycles: 7
+     */
+    pub fn generate_irq(&mut self) -> u8 {
+        if self.halted {
+            return 0;
+        }
+        self.stack_push_u16(self.registers.program_counter);
+        self.stack_push((self.registers.processor_status & !0x10) | 0x20);
+
+        self.registers.program_counter = self.fetch_u16(0xFFFE);
+        self.registers.set_interrupt_disable();
 
         7
     }
 
     pub fn generate_nmi(&mut self) -> u8 {
-        let pc_high = ((self.registers.program_counter) >> 8) as u8;
-        self.stack_push(pc_high);
-
-        let pc_low = ((self.registers.program_counter) & 0xFF) as u8;
-        self.stack_push(pc_low);
-
+        if self.halted {
+            return 0;
+        }
+        self.stack_push_u16(self.registers.program_counter);
         self.stack_push(self.registers.processor_status);
 
         let nmi_vector_low = self.memory.fetch_absolute(0xFFFA) as u16;
@@ -2231,9 +1973,7 @@ impl Cpu {
      *   Cycles: 2
      */
 
-    fn nop_implied(&mut self) -> u8 {
-        2
-    }
+    fn nop_implied(&mut self) {}
 
     /*
      *   RTI - Return from Interrupt
@@ -2243,19 +1983,13 @@ impl Cpu {
      *   Cycles: 6
      */
 
-    fn rti_implied(&mut self) -> u8 {
+    fn rti_implied(&mut self) {
         let status = self.stack_pop();
         self.registers.processor_status = status | 0b100000;
 
-        let pc_low = self.stack_pop() as u16;
-        let pc_high = self.stack_pop() as u16;
-
-        let pc = (pc_high << 8) | pc_low;
-        self.registers.program_counter = pc;
+        self.registers.program_counter = self.stack_pop_u16();
 
         self.registers.unset_break();
-
-        6
     }
 
     /*
@@ -2266,15 +2000,9 @@ impl Cpu {
      *   Cycles: 6
      */
 
-    fn rts(&mut self) -> u8 {
-        let pc_low = self.stack_pop() as u16;
-        let pc_high = self.stack_pop() as u16;
-
-        let pc = (pc_high << 8) | pc_low;
-
-        self.registers.program_counter = pc;
-
-        6
+    fn rts(&mut self) {
+        let pulled = self.stack_pop_u16();
+        self.registers.program_counter = Self::rts_target(pulled);
     }
 
     // ILLEGAL OPCODES
@@ -2287,9 +2015,8 @@ impl Cpu {
      *   Opcode: $EB
      *   Cycles: 2
      */
-    fn usbc(&mut self, val: u8) -> u8 {
+    fn usbc(&mut self, val: u8) {
         self.sbc_immediate(val);
-        2
     }
 
     /*
@@ -2299,66 +2026,68 @@ impl Cpu {
 
     // Opcode: $27
     // Cycles: 5
-    fn rla_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        self.rol_zero_page(addr_lower_byte);
-        self.and_zero_page(addr_lower_byte);
-
-        5
+    fn rla_zero_page(&mut self, addr_lower_byte: u8) {
+        let value = self.modify(addr_lower_byte as u16, Self::rol_immediate);
+        self.and_immediate(value);
     }
 
     // Opcode: $37
     // Cycles: 6
-    fn rla_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        self.rol_zero_page_x(addr_lower_byte);
-        self.and_zero_page_x(addr_lower_byte);
-
-        6
+    fn rla_zero_page_x(&mut self, addr_lower_byte: u8) {
+        let address = self
+            .memory
+            .zero_page_indexed_address(addr_lower_byte, self.registers.index_x);
+        let value = self.modify(address as u16, Self::rol_immediate);
+        self.and_immediate(value);
     }
 
     // Opcode: $2F
     // Cycles: 6
-    fn rla_absolute(&mut self, address: u16) -> u8 {
-        self.rol_absolute(address);
-        self.and_absolute(address);
-
-        6
+    fn rla_absolute(&mut self, address: u16) {
+        let value = self.modify(address, Self::rol_immediate);
+        self.and_immediate(value);
     }
 
     // Opcode: $3F
     // Cycles: 7
-    fn rla_absolute_x(&mut self, address: u16) -> u8 {
-        self.rol_absolute_x(address);
-        self.and_absolute_x(address);
-
-        7
+    fn rla_absolute_x(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_x, IndexedAccess::Write);
+        let value = self.modify(address, Self::rol_immediate);
+        self.and_immediate(value);
     }
 
     // Opcode: $3B
     // Cycles: 7
-    fn rla_absolute_y(&mut self, address: u16) -> u8 {
-        self.rol_absolute_y(address);
-        self.and_absolute_y(address);
-
-        7
+    fn rla_absolute_y(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_y, IndexedAccess::Write);
+        let value = self.modify(address, Self::rol_immediate);
+        self.and_immediate(value);
     }
 
     // Opcode: $23
     // Cycles: 8
-    fn rla_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
-        self.rol_indirect_x(addr_lower_byte);
-        self.and_indirect_x(addr_lower_byte);
-
-        8
+    fn rla_indirect_x(&mut self, addr_lower_byte: u8) {
+        let address = self
+            .memory
+            .indirect_x_address(addr_lower_byte, self.registers.index_x);
+        let value = self.modify(address, Self::rol_immediate);
+        self.and_immediate(value);
     }
 
     // Opcode: $33
     // Cycles: 8
-    fn rla_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self.rol_indirect_y(addr_lower_byte);
-        // TODO: (BUG) In some cases, you cannot read the same address after store_indirect_{x,y} if the addr_lower_byte is modified itself
+    fn rla_indirect_y(&mut self, addr_lower_byte: u8) {
+        let address = self.memory.indirect_y_address(
+            addr_lower_byte,
+            self.registers.index_y,
+            IndexedAccess::Write,
+        );
+        let value = self.modify(address, Self::rol_immediate);
         self.and_immediate(value);
-
-        8
     }
 
     /*
@@ -2368,65 +2097,68 @@ impl Cpu {
 
     // Opcode: $67
     // Cycles: 5
-    fn rra_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        self.ror_zero_page(addr_lower_byte);
-        self.adc_zero_page(addr_lower_byte);
-
-        5
+    fn rra_zero_page(&mut self, addr_lower_byte: u8) {
+        let value = self.modify(addr_lower_byte as u16, Self::ror_immediate);
+        self.adc_immediate(value);
     }
 
     // Opcode: $77
     // Cycles: 6
-    fn rra_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        self.ror_zero_page_x(addr_lower_byte);
-        self.adc_zero_page_x(addr_lower_byte);
-
-        6
+    fn rra_zero_page_x(&mut self, addr_lower_byte: u8) {
+        let address = self
+            .memory
+            .zero_page_indexed_address(addr_lower_byte, self.registers.index_x);
+        let value = self.modify(address as u16, Self::ror_immediate);
+        self.adc_immediate(value);
     }
 
     // Opcode: $6F
     // Cycles: 6
-    fn rra_absolute(&mut self, address: u16) -> u8 {
-        self.ror_absolute(address);
-        self.adc_absolute(address);
-
-        6
+    fn rra_absolute(&mut self, address: u16) {
+        let value = self.modify(address, Self::ror_immediate);
+        self.adc_immediate(value);
     }
 
     // Opcode: $7F
     // Cycles: 7
-    fn rra_absolute_x(&mut self, address: u16) -> u8 {
-        self.ror_absolute_x(address);
-        self.adc_absolute_x(address);
-
-        7
+    fn rra_absolute_x(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_x, IndexedAccess::Write);
+        let value = self.modify(address, Self::ror_immediate);
+        self.adc_immediate(value);
     }
 
     // Opcode: $7B
     // Cycles: 7
-    fn rra_absolute_y(&mut self, address: u16) -> u8 {
-        self.ror_absolute_y(address);
-        self.adc_absolute_y(address);
-
-        7
+    fn rra_absolute_y(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_y, IndexedAccess::Write);
+        let value = self.modify(address, Self::ror_immediate);
+        self.adc_immediate(value);
     }
 
     // Opcode: $63
     // Cycles: 8
-    fn rra_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
-        self.ror_indirect_x(addr_lower_byte);
-        self.adc_indirect_x(addr_lower_byte);
-
-        8
+    fn rra_indirect_x(&mut self, addr_lower_byte: u8) {
+        let address = self
+            .memory
+            .indirect_x_address(addr_lower_byte, self.registers.index_x);
+        let value = self.modify(address, Self::ror_immediate);
+        self.adc_immediate(value);
     }
 
     // Opcode: $73
     // Cycles: 8
-    fn rra_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
-        self.ror_indirect_y(addr_lower_byte);
-        self.adc_indirect_y(addr_lower_byte);
-
-        8
+    fn rra_indirect_y(&mut self, addr_lower_byte: u8) {
+        let address = self.memory.indirect_y_address(
+            addr_lower_byte,
+            self.registers.index_y,
+            IndexedAccess::Write,
+        );
+        let value = self.modify(address, Self::ror_immediate);
+        self.adc_immediate(value);
     }
 
     /*
@@ -2436,65 +2168,68 @@ impl Cpu {
 
     // Opcode: $07
     // Cycles: 5
-    fn slo_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        self.asl_zero_page(addr_lower_byte);
-        self.ora_zero_page(addr_lower_byte);
-
-        5
+    fn slo_zero_page(&mut self, addr_lower_byte: u8) {
+        let value = self.modify(addr_lower_byte as u16, Self::asl_immediate);
+        self.ora_immediate(value);
     }
 
     // Opcode: $17
     // Cycles: 6
-    fn slo_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        self.asl_zero_page_x(addr_lower_byte);
-        self.ora_zero_page_x(addr_lower_byte);
-
-        6
+    fn slo_zero_page_x(&mut self, addr_lower_byte: u8) {
+        let address = self
+            .memory
+            .zero_page_indexed_address(addr_lower_byte, self.registers.index_x);
+        let value = self.modify(address as u16, Self::asl_immediate);
+        self.ora_immediate(value);
     }
 
     // Opcode: $0F
     // Cycles: 6
-    fn slo_absolute(&mut self, address: u16) -> u8 {
-        self.asl_absolute(address);
-        self.ora_absolute(address);
-
-        6
+    fn slo_absolute(&mut self, address: u16) {
+        let value = self.modify(address, Self::asl_immediate);
+        self.ora_immediate(value);
     }
 
     // Opcode: $1F
     // Cycles: 7
-    fn slo_absolute_x(&mut self, address: u16) -> u8 {
-        self.asl_absolute_x(address);
-        self.ora_absolute_x(address);
-
-        7
+    fn slo_absolute_x(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_x, IndexedAccess::Write);
+        let value = self.modify(address, Self::asl_immediate);
+        self.ora_immediate(value);
     }
 
     // Opcode: $1B
     // Cycles: 7
-    fn slo_absolute_y(&mut self, address: u16) -> u8 {
-        self.asl_absolute_y(address);
-        self.ora_absolute_y(address);
-
-        7
+    fn slo_absolute_y(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_y, IndexedAccess::Write);
+        let value = self.modify(address, Self::asl_immediate);
+        self.ora_immediate(value);
     }
 
     // Opcode: $03
     // Cycles: 8
-    fn slo_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
-        self.asl_indirect_x(addr_lower_byte);
-        self.ora_indirect_x(addr_lower_byte);
-
-        8
+    fn slo_indirect_x(&mut self, addr_lower_byte: u8) {
+        let address = self
+            .memory
+            .indirect_x_address(addr_lower_byte, self.registers.index_x);
+        let value = self.modify(address, Self::asl_immediate);
+        self.ora_immediate(value);
     }
 
     // Opcode: $13
     // Cycles: 8
-    fn slo_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
-        self.asl_indirect_y(addr_lower_byte);
-        self.ora_indirect_y(addr_lower_byte);
-
-        8
+    fn slo_indirect_y(&mut self, addr_lower_byte: u8) {
+        let address = self.memory.indirect_y_address(
+            addr_lower_byte,
+            self.registers.index_y,
+            IndexedAccess::Write,
+        );
+        let value = self.modify(address, Self::asl_immediate);
+        self.ora_immediate(value);
     }
 
     /*
@@ -2504,65 +2239,68 @@ impl Cpu {
 
     // Opcode: $47
     // Cycles: 5
-    fn sre_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        self.lsr_zero_page(addr_lower_byte);
-        self.eor_zero_page(addr_lower_byte);
-
-        5
+    fn sre_zero_page(&mut self, addr_lower_byte: u8) {
+        let value = self.modify(addr_lower_byte as u16, Self::lsr_immediate);
+        self.eor_immediate(value);
     }
 
     // Opcode: $57
     // Cycles: 6
-    fn sre_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        self.lsr_zero_page_x(addr_lower_byte);
-        self.eor_zero_page_x(addr_lower_byte);
-
-        6
+    fn sre_zero_page_x(&mut self, addr_lower_byte: u8) {
+        let address = self
+            .memory
+            .zero_page_indexed_address(addr_lower_byte, self.registers.index_x);
+        let value = self.modify(address as u16, Self::lsr_immediate);
+        self.eor_immediate(value);
     }
 
     // Opcode: $4F
     // Cycles: 6
-    fn sre_absolute(&mut self, address: u16) -> u8 {
-        self.lsr_absolute(address);
-        self.eor_absolute(address);
-
-        6
+    fn sre_absolute(&mut self, address: u16) {
+        let value = self.modify(address, Self::lsr_immediate);
+        self.eor_immediate(value);
     }
 
     // Opcode: $5F
     // Cycles: 7
-    fn sre_absolute_x(&mut self, address: u16) -> u8 {
-        self.lsr_absolute_x(address);
-        self.eor_absolute_x(address);
-
-        7
+    fn sre_absolute_x(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_x, IndexedAccess::Write);
+        let value = self.modify(address, Self::lsr_immediate);
+        self.eor_immediate(value);
     }
 
     // Opcode: $5B
     // Cycles: 7
-    fn sre_absolute_y(&mut self, address: u16) -> u8 {
-        self.lsr_absolute_y(address);
-        self.eor_absolute_y(address);
-
-        7
+    fn sre_absolute_y(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_y, IndexedAccess::Write);
+        let value = self.modify(address, Self::lsr_immediate);
+        self.eor_immediate(value);
     }
 
     // Opcode: $43
     // Cycles: 8
-    fn sre_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
-        self.lsr_indirect_x(addr_lower_byte);
-        self.eor_indirect_x(addr_lower_byte);
-
-        8
+    fn sre_indirect_x(&mut self, addr_lower_byte: u8) {
+        let address = self
+            .memory
+            .indirect_x_address(addr_lower_byte, self.registers.index_x);
+        let value = self.modify(address, Self::lsr_immediate);
+        self.eor_immediate(value);
     }
 
     // Opcode: $53
     // Cycles: 8
-    fn sre_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
-        self.lsr_indirect_y(addr_lower_byte);
-        self.eor_indirect_y(addr_lower_byte);
-
-        8
+    fn sre_indirect_y(&mut self, addr_lower_byte: u8) {
+        let address = self.memory.indirect_y_address(
+            addr_lower_byte,
+            self.registers.index_y,
+            IndexedAccess::Write,
+        );
+        let value = self.modify(address, Self::lsr_immediate);
+        self.eor_immediate(value);
     }
 
     /*
@@ -2571,39 +2309,32 @@ impl Cpu {
 
     // Opcode: $83
     // Cycles: 6
-    fn sax_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sax_indirect_x(&mut self, addr_lower_byte: u8) {
         let value = self.registers.index_x & self.registers.accumulator;
         self.memory
             .store_indirect_x(addr_lower_byte, self.registers.index_x, value);
-
-        6
     }
 
     // Opcode: $87
     // Cycles: 3
-    fn sax_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sax_zero_page(&mut self, addr_lower_byte: u8) {
         let value = self.registers.index_x & self.registers.accumulator;
         self.memory.store_zero_page(addr_lower_byte, value);
-
-        3
     }
 
     // Opcode: $8F
     // Cycles: 4
-    fn sax_absolute(&mut self, address: u16) -> u8 {
+    fn sax_absolute(&mut self, address: u16) {
         let value = self.registers.index_x & self.registers.accumulator;
         self.memory.store_absolute(address, value);
-
-        4
     }
 
     // Opcode: $97
     // Cycles: 4
-    fn sax_zero_page_y(&mut self, addr_lower_byte: u8) -> u8 {
+    fn sax_zero_page_y(&mut self, addr_lower_byte: u8) {
         let value = self.registers.index_x & self.registers.accumulator;
         self.memory
-            .store_zero_page_x(addr_lower_byte, self.registers.index_y, value);
-        4
+            .store_zero_page_y(addr_lower_byte, self.registers.index_y, value);
     }
 
     /*
@@ -2612,99 +2343,68 @@ impl Cpu {
 
     // Opcde: $C3
     // Cycles: 8
-    fn dcp_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self
+    fn dcp_indirect_x(&mut self, addr_lower_byte: u8) {
+        let address = self
             .memory
-            .fetch_indirect_x(addr_lower_byte, self.registers.index_x)
-            .wrapping_sub(1);
-        self.memory
-            .store_indirect_x(addr_lower_byte, self.registers.index_x, value);
-
-        self.cmp_indirect_x(addr_lower_byte);
-
-        8
+            .indirect_x_address(addr_lower_byte, self.registers.index_x);
+        let value = self.modify(address, |_, value| value.wrapping_sub(1));
+        self.cmp_immediate(value);
     }
 
     // Opcde: $D3
     // Cycles: 8
-    fn dcp_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self
-            .memory
-            .fetch_indirect_y(addr_lower_byte, self.registers.index_y)
-            .wrapping_sub(1);
-        self.memory
-            .store_indirect_y(addr_lower_byte, self.registers.index_y, value);
-
-        self.cmp_indirect_y(addr_lower_byte);
-
-        8
-    }
+    fn dcp_indirect_y(&mut self, addr_lower_byte: u8) {
+        let address = self.memory.indirect_y_address(
+            addr_lower_byte,
+            self.registers.index_y,
+            IndexedAccess::Write,
+        );
+        let value = self.modify(address, |_, value| value.wrapping_sub(1));
+        self.cmp_immediate(value);
+    }
 
     // Opcde: $C7
     // Cycles: 5
-    fn dcp_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self.memory.fetch_zero_page(addr_lower_byte).wrapping_sub(1);
-        self.memory.store_zero_page(addr_lower_byte, value);
-
-        self.cmp_zero_page(addr_lower_byte);
-
-        5
+    fn dcp_zero_page(&mut self, addr_lower_byte: u8) {
+        let value = self.modify(addr_lower_byte as u16, |_, value| value.wrapping_sub(1));
+        self.cmp_immediate(value);
     }
 
     // Opcde: $D7
     // Cycles: 6
-    fn dcp_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self
+    fn dcp_zero_page_x(&mut self, addr_lower_byte: u8) {
+        let address = self
             .memory
-            .fetch_zero_page_x(addr_lower_byte, self.registers.index_x)
-            .wrapping_sub(1);
-        self.memory
-            .store_zero_page_x(addr_lower_byte, self.registers.index_x, value);
-
-        self.cmp_zero_page_x(addr_lower_byte);
-
-        6
+            .zero_page_indexed_address(addr_lower_byte, self.registers.index_x);
+        let value = self.modify(address as u16, |_, value| value.wrapping_sub(1));
+        self.cmp_immediate(value);
     }
 
     // Opcde: $CF
     // Cycles: 6
-    fn dcp_absolute(&mut self, address: u16) -> u8 {
-        let value = self.memory.fetch_absolute(address).wrapping_sub(1);
-        self.memory.store_absolute(address, value);
-
-        self.cmp_absolute(address);
-
-        6
+    fn dcp_absolute(&mut self, address: u16) {
+        let value = self.modify(address, |_, value| value.wrapping_sub(1));
+        self.cmp_immediate(value);
     }
 
     // Opcde: $DF
     // Cycles: 7
-    fn dcp_absolute_x(&mut self, address: u16) -> u8 {
-        let value = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_x)
-            .wrapping_sub(1);
-        self.memory
-            .store_absolute_x(address, self.registers.index_x, value);
-
-        self.cmp_absolute_x(address);
-
-        7
+    fn dcp_absolute_x(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_x, IndexedAccess::Write);
+        let value = self.modify(address, |_, value| value.wrapping_sub(1));
+        self.cmp_immediate(value);
     }
 
     // Opcde: $DB
     // Cycles: 7
-    fn dcp_absolute_y(&mut self, address: u16) -> u8 {
-        let value = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_y)
-            .wrapping_sub(1);
-        self.memory
-            .store_absolute_x(address, self.registers.index_y, value);
-
-        self.cmp_absolute_y(address);
-
-        7
+    fn dcp_absolute_y(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_y, IndexedAccess::Write);
+        let value = self.modify(address, |_, value| value.wrapping_sub(1));
+        self.cmp_immediate(value);
     }
 
     /*
@@ -2713,101 +2413,68 @@ impl Cpu {
 
     // Opcode: $E3
     // Cycles: 8
-    fn isb_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self
+    fn isb_indirect_x(&mut self, addr_lower_byte: u8) {
+        let address = self
             .memory
-            .fetch_indirect_x(addr_lower_byte, self.registers.index_x)
-            .wrapping_add(1);
-
-        // TODO: (BUG) In some cases, you cannot read the same address after store_indirect_{x,y} if the addr_lower_byte is modified itself
-        self.memory
-            .store_indirect_x(addr_lower_byte, self.registers.index_x, value);
-
+            .indirect_x_address(addr_lower_byte, self.registers.index_x);
+        let value = self.modify(address, |_, value| value.wrapping_add(1));
         self.sbc_immediate(value);
-
-        8
     }
 
     // Opcode: $F3
     // Cycles: 8
-    fn isb_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self
-            .memory
-            .fetch_indirect_y(addr_lower_byte, self.registers.index_y)
-            .wrapping_add(1);
-        self.memory
-            .store_indirect_y(addr_lower_byte, self.registers.index_y, value);
-
-        self.sbc_indirect_y(addr_lower_byte);
-
-        8
+    fn isb_indirect_y(&mut self, addr_lower_byte: u8) {
+        let address = self.memory.indirect_y_address(
+            addr_lower_byte,
+            self.registers.index_y,
+            IndexedAccess::Write,
+        );
+        let value = self.modify(address, |_, value| value.wrapping_add(1));
+        self.sbc_immediate(value);
     }
 
     // Opcode: $E7
     // Cycles: 5
-    fn isb_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self.memory.fetch_zero_page(addr_lower_byte).wrapping_add(1);
-        self.memory.store_zero_page(addr_lower_byte, value);
-
-        self.sbc_zero_page(addr_lower_byte);
-
-        5
+    fn isb_zero_page(&mut self, addr_lower_byte: u8) {
+        let value = self.modify(addr_lower_byte as u16, |_, value| value.wrapping_add(1));
+        self.sbc_immediate(value);
     }
 
     // Opcode: $F7
     // Cycles: 6
-    fn isb_zero_page_x(&mut self, addr_lower_byte: u8) -> u8 {
-        let value = self
+    fn isb_zero_page_x(&mut self, addr_lower_byte: u8) {
+        let address = self
             .memory
-            .fetch_zero_page_x(addr_lower_byte, self.registers.index_x)
-            .wrapping_add(1);
-        self.memory
-            .store_zero_page_x(addr_lower_byte, self.registers.index_x, value);
-
-        self.sbc_zero_page_x(addr_lower_byte);
-
-        6
+            .zero_page_indexed_address(addr_lower_byte, self.registers.index_x);
+        let value = self.modify(address as u16, |_, value| value.wrapping_add(1));
+        self.sbc_immediate(value);
     }
 
     // Opcode: $EF
     // Cycles: 6
-    fn isb_absolute(&mut self, address: u16) -> u8 {
-        let value = self.memory.fetch_absolute(address).wrapping_add(1);
-        self.memory.store_absolute(address, value);
-
-        self.sbc_absolute(address);
-
-        6
+    fn isb_absolute(&mut self, address: u16) {
+        let value = self.modify(address, |_, value| value.wrapping_add(1));
+        self.sbc_immediate(value);
     }
 
     // Opcode: $FB
     // Cycles: 7
-    fn isb_absolute_y(&mut self, address: u16) -> u8 {
-        let value = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_y)
-            .wrapping_add(1);
-        self.memory
-            .store_absolute_x(address, self.registers.index_y, value);
-
-        self.sbc_absolute_y(address);
-
-        7
+    fn isb_absolute_y(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_y, IndexedAccess::Write);
+        let value = self.modify(address, |_, value| value.wrapping_add(1));
+        self.sbc_immediate(value);
     }
 
     // Opcode: $FF
     // Cycles: 7
-    fn isb_absolute_x(&mut self, address: u16) -> u8 {
-        let value = self
-            .memory
-            .fetch_absolute_x(address, self.registers.index_x)
-            .wrapping_add(1);
-        self.memory
-            .store_absolute_x(address, self.registers.index_x, value);
-
-        self.sbc_absolute_x(address);
-
-        7
+    fn isb_absolute_x(&mut self, address: u16) {
+        let address =
+            self.memory
+                .indexed_address(address, self.registers.index_x, IndexedAccess::Write);
+        let value = self.modify(address, |_, value| value.wrapping_add(1));
+        self.sbc_immediate(value);
     }
 
     /*
@@ -2816,382 +2483,424 @@ impl Cpu {
 
     // Opcode: $A3
     // Cycles: 6
-    fn lax_indirect_x(&mut self, addr_lower_byte: u8) -> u8 {
-        self.lda_indirect_x(addr_lower_byte);
-        let val = self
+    fn lax_indirect_x(&mut self, addr_lower_byte: u8) {
+        let value = self
             .memory
             .fetch_indirect_x(addr_lower_byte, self.registers.index_x);
-        self.ldx_immediate(val);
-        6
+        self.lda_immediate(value);
+        self.ldx_immediate(value);
     }
 
     // Opcode: $B3
     // Cycles: 5 (+1 if page boundary is crossed)
-    fn lax_indirect_y(&mut self, addr_lower_byte: u8) -> u8 {
-        self.lda_indirect_y(addr_lower_byte);
-        let val = self
+    fn lax_indirect_y(&mut self, addr_lower_byte: u8) {
+        let value = self
             .memory
             .fetch_indirect_y(addr_lower_byte, self.registers.index_y);
-        self.ldx_immediate(val);
-        5
+        self.lda_immediate(value);
+        self.ldx_immediate(value);
     }
 
     // Opcode: $A7
     // Cycles: 3
-    fn lax_zero_page(&mut self, addr_lower_byte: u8) -> u8 {
-        self.lda_zero_page(addr_lower_byte);
-        self.ldx_zero_page(addr_lower_byte);
-        3
+    fn lax_zero_page(&mut self, addr_lower_byte: u8) {
+        let value = self.memory.fetch_zero_page(addr_lower_byte);
+        self.lda_immediate(value);
+        self.ldx_immediate(value);
     }
 
     // Opcode: $B7
     // Cycles: 4
-    fn lax_zero_page_y(&mut self, addr_lower_byte: u8) -> u8 {
+    fn lax_zero_page_y(&mut self, addr_lower_byte: u8) {
         let value = self
             .memory
-            .fetch_zero_page_x(addr_lower_byte, self.registers.index_y);
+            .fetch_zero_page_y(addr_lower_byte, self.registers.index_y);
         self.lda_immediate(value);
-        self.ldx_zero_page_y(addr_lower_byte);
-        4
+        self.ldx_immediate(value);
     }
 
     // Opcode: $AF
     // Cycles: 4
-    fn lax_absolute(&mut self, address: u16) -> u8 {
-        self.lda_absolute(address);
-        self.ldx_absolute(address);
-        4
+    fn lax_absolute(&mut self, address: u16) {
+        let value = self.memory.fetch_absolute(address);
+        self.lda_immediate(value);
+        self.ldx_immediate(value);
     }
 
     // Opcode: $BF
     // Cycles: 4 (+1 if page boundary crossed)
-    fn lax_absolute_y(&mut self, address: u16) -> u8 {
-        self.lda_absolute_y(address);
-        self.ldx_absolute_y(address);
-        4
+    fn lax_absolute_y(&mut self, address: u16) {
+        let value = self
+            .memory
+            .fetch_absolute_x(address, self.registers.index_y);
+        self.lda_immediate(value);
+        self.ldx_immediate(value);
     }
 
-    fn fetch_u16(&mut self, addr: u16) -> u16 {
-        (self.memory.fetch_absolute(addr) as u16)
-            + (self.memory.fetch_absolute(addr.wrapping_add(1)) as u16 * 256)
+    /*
+     *   ANC: AND oper + set C as ASL
+     *   A AND M -> A, N -> C
+     *
+     *   Opcodes: $0B, $2B
+     *   Cycles: 2
+     */
+    fn anc_immediate(&mut self, value: u8) {
+        self.and_immediate(value);
+        if self.registers.get_neg() != 0 {
+            self.registers.set_carry();
+        } else {
+            self.registers.unset_carry();
+        }
     }
 
     /*
-     * Maps opcodes to methods and is responsible for decoding & executing
+     *   ALR: AND oper + LSR
+     *   A AND M -> A, 0 -> [76543210] -> C
+     *
+     *   Opcode: $4B
+     *   Cycles: 2
      */
-    fn decode_execute(&mut self, opcode: u8) -> (u8, u8) {
-        macro_rules! handle_opcode_zerobyte {
-            ($self:ident, $method:ident) => {{
-                ($self.$method(), 0)
-            }};
-        }
+    fn alr_immediate(&mut self, value: u8) {
+        self.and_immediate(value);
+        self.lsr_accumulator();
+    }
 
-        macro_rules! handle_opcode_onebyte {
-            ($self:ident, $method:ident) => {{
-                ($self.$method(), 1)
-            }};
+    /*
+     *   ARR: AND oper + ROR
+     *   A AND M -> A, C -> [76543210] -> A. C comes from bit 6 of the result and V from bit 6 XOR bit 5,
+     *   the adder being involved; the CPU's binary mode is the only one the 2A03 has.
+     *
+     *   Opcode: $6B
+     *   Cycles: 2
+     */
+    fn arr_immediate(&mut self, value: u8) {
+        let anded = self.registers.accumulator & value;
+        let result = self.ror_immediate(anded);
+        self.registers.accumulator = result;
+        if result & 0b0100_0000 != 0 {
+            self.registers.set_carry();
+        } else {
+            self.registers.unset_carry();
         }
-
-        macro_rules! handle_opcode_twobytes {
-            ($self:ident, $method:ident) => {{
-                let value = $self
-                    .memory
-                    .fetch_absolute($self.registers.program_counter.wrapping_add(1));
-                ($self.$method(value), 2)
-            }};
+        if ((result >> 6) ^ (result >> 5)) & 1 != 0 {
+            self.registers.set_overflow();
+        } else {
+            self.registers.unset_overflow();
         }
+    }
 
-        macro_rules! handle_opcode_threebytes {
-            ($self:ident, $method:ident) => {{
-                let value = self.fetch_u16($self.registers.program_counter.wrapping_add(1));
-                ($self.$method(value), 3)
-            }};
-        }
+    /*
+     *   ANE (XAA): (A OR CONST) AND X AND oper -> A
+     *   CONST depends on the chip and its temperature; $EE is what the single-step tests use.
+     *
+     *   Opcode: $8B
+     *   Cycles: 2
+     */
+    fn ane_immediate(&mut self, value: u8) {
+        let result = (self.registers.accumulator | UNSTABLE_CONST) & self.registers.index_x & value;
+        self.registers.accumulator = result;
+        self.update_zero_negative_flags(result);
+    }
 
-        macro_rules! handle_opcode_jump {
-            ($self:ident, $method:ident) => {{
-                let value = self.fetch_u16($self.registers.program_counter.wrapping_add(1));
-                ($self.$method(value.into()), 0)
-            }};
+    /*
+     *   LXA (LAX immediate): (A OR CONST) AND oper -> A -> X, CONST as for ANE
+     *
+     *   Opcode: $AB
+     *   Cycles: 2
+     */
+    fn lxa_immediate(&mut self, value: u8) {
+        let result = (self.registers.accumulator | UNSTABLE_CONST) & value;
+        self.registers.accumulator = result;
+        self.registers.index_x = result;
+        self.update_zero_negative_flags(result);
+    }
+
+    /*
+     *   LAS: M AND SP -> A, X, SP
+     *
+     *   Opcode: $BB
+     *   Cycles: 4 (+1 if page boundary crossed)
+     */
+    fn las_absolute_y(&mut self, address: u16) {
+        let value = self
+            .memory
+            .fetch_absolute_x(address, self.registers.index_y)
+            & self.registers.stack_pointer;
+        self.registers.accumulator = value;
+        self.registers.index_x = value;
+        self.registers.stack_pointer = value;
+        self.update_zero_negative_flags(value);
+    }
+
+    /*
+     *   SBX (AXS): (A AND X) - oper -> X, setting C like CMP and leaving V alone
+     *
+     *   Opcode: $CB
+     *   Cycles: 2
+     */
+    fn sbx_immediate(&mut self, value: u8) {
+        let anded = self.registers.accumulator & self.registers.index_x;
+        if anded >= value {
+            self.registers.set_carry();
+        } else {
+            self.registers.unset_carry();
         }
+        let result = anded.wrapping_sub(value);
+        self.registers.index_x = result;
+        self.update_zero_negative_flags(result);
+    }
+
+    /*
+     *   SHA, SHX, SHY and TAS store a register ANDed with the high byte of the base address plus one.
+     *   When indexing carries into the high byte, that same ANDed value replaces the high byte of the address.
+     */
+    fn store_and_high_byte(&mut self, base: u16, index: u8, value: u8) {
+        let address = self
+            .memory
+            .indexed_address(base, index, IndexedAccess::Write);
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        let address = if (base ^ address) & 0xFF00 != 0 {
+            (address & 0x00FF) | (value as u16) << 8
+        } else {
+            address
+        };
+        self.memory.store_absolute(address, value);
+    }
+
+    // SHA: A AND X AND (H + 1) -> M
+    // Opcode: $9F
+    // Cycles: 5
+    fn sha_absolute_y(&mut self, address: u16) {
+        let value = self.registers.accumulator & self.registers.index_x;
+        self.store_and_high_byte(address, self.registers.index_y, value);
+    }
+
+    // Opcode: $93
+    // Cycles: 6
+    fn sha_indirect_y(&mut self, addr_lower_byte: u8) {
+        let base = self.memory.zero_page_pointer(addr_lower_byte);
+        let value = self.registers.accumulator & self.registers.index_x;
+        self.store_and_high_byte(base, self.registers.index_y, value);
+    }
+
+    // SHX: X AND (H + 1) -> M
+    // Opcode: $9E
+    // Cycles: 5
+    fn shx_absolute_y(&mut self, address: u16) {
+        self.store_and_high_byte(address, self.registers.index_y, self.registers.index_x);
+    }
+
+    // SHY: Y AND (H + 1) -> M
+    // Opcode: $9C
+    // Cycles: 5
+    fn shy_absolute_x(&mut self, address: u16) {
+        self.store_and_high_byte(address, self.registers.index_x, self.registers.index_y);
+    }
+
+    // TAS: A AND X -> SP, SP AND (H + 1) -> M
+    // Opcode: $9B
+    // Cycles: 5
+    fn tas_absolute_y(&mut self, address: u16) {
+        self.registers.stack_pointer = self.registers.accumulator & self.registers.index_x;
+        self.store_and_high_byte(
+            address,
+            self.registers.index_y,
+            self.registers.stack_pointer,
+        );
+    }
 
-        match opcode {
-            0x00 => handle_opcode_zerobyte!(self, brk_implied),
-            0x01 => handle_opcode_twobytes!(self, ora_indirect_x),
-            0x05 => handle_opcode_twobytes!(self, ora_zero_page),
-            0x06 => handle_opcode_twobytes!(self, asl_zero_page),
-            0x08 => handle_opcode_onebyte!(self, php),
-            0x09 => handle_opcode_twobytes!(self, ora_immediate),
-            0x0A => handle_opcode_onebyte!(self, asl_accumulator),
-            0x0D => handle_opcode_threebytes!(self, ora_absolute),
-            0x0E => handle_opcode_threebytes!(self, asl_absolute),
-            0x10 => handle_opcode_twobytes!(self, bpl),
-            0x11 => handle_opcode_twobytes!(self, ora_indirect_y),
-            0x15 => handle_opcode_twobytes!(self, ora_zero_page_x),
-            0x16 => handle_opcode_twobytes!(self, asl_zero_page_x),
-            0x18 => handle_opcode_onebyte!(self, clc),
-            0x19 => handle_opcode_threebytes!(self, ora_absolute_y),
-            0x1D => handle_opcode_threebytes!(self, ora_absolute_x),
-            0x1E => handle_opcode_threebytes!(self, asl_absolute_x),
-            0x20 => handle_opcode_jump!(self, jsr),
-            0x21 => handle_opcode_twobytes!(self, and_indirect_x),
-            0x24 => handle_opcode_twobytes!(self, bit_zero_page),
-            0x25 => handle_opcode_twobytes!(self, and_zero_page),
-            0x26 => handle_opcode_twobytes!(self, rol_zero_page),
-            0x28 => handle_opcode_onebyte!(self, plp),
-            0x29 => handle_opcode_twobytes!(self, and_immediate),
-            0x2A => handle_opcode_onebyte!(self, rol_accumulator),
-            0x2C => handle_opcode_threebytes!(self, bit_absolute),
-            0x2D => handle_opcode_threebytes!(self, and_absolute),
-            0x2E => handle_opcode_threebytes!(self, rol_absolute),
-            0x30 => handle_opcode_twobytes!(self, bmi),
-            0x31 => handle_opcode_twobytes!(self, and_indirect_y),
-            0x35 => handle_opcode_twobytes!(self, and_zero_page_x),
-            0x36 => handle_opcode_twobytes!(self, rol_zero_page_x),
-            0x38 => handle_opcode_onebyte!(self, sec),
-            0x39 => handle_opcode_threebytes!(self, and_absolute_y),
-            0x3D => handle_opcode_threebytes!(self, and_absolute_x),
-            0x3E => handle_opcode_threebytes!(self, rol_absolute_x),
-            0x40 => handle_opcode_zerobyte!(self, rti_implied),
-            0x41 => handle_opcode_twobytes!(self, eor_indirect_x),
-            0x45 => handle_opcode_twobytes!(self, eor_zero_page),
-            0x46 => handle_opcode_twobytes!(self, lsr_zero_page),
-            0x48 => handle_opcode_onebyte!(self, pha),
-            0x49 => handle_opcode_twobytes!(self, eor_immediate),
-            0x4A => handle_opcode_onebyte!(self, lsr_accumulator),
-            0x4C => handle_opcode_jump!(self, jmp_absolute),
-            0x4D => handle_opcode_threebytes!(self, eor_absolute),
-            0x4E => handle_opcode_threebytes!(self, lsr_absolute),
-            0x50 => handle_opcode_twobytes!(self, bvc),
-            0x51 => handle_opcode_twobytes!(self, eor_indirect_y),
-            0x55 => handle_opcode_twobytes!(self, eor_zero_page_x),
-            0x56 => handle_opcode_twobytes!(self, lsr_zero_page_x),
-            0x58 => handle_opcode_onebyte!(self, cli),
-            0x59 => handle_opcode_threebytes!(self, eor_absolute_y),
-            0x5D => handle_opcode_threebytes!(self, eor_absolute_x),
-            0x5E => handle_opcode_threebytes!(self, lsr_absolute_x),
-            0x60 => handle_opcode_onebyte!(self, rts),
-            0x61 => handle_opcode_twobytes!(self, adc_indirect_x),
-            0x65 => handle_opcode_twobytes!(self, adc_zero_page),
-            0x66 => handle_opcode_twobytes!(self, ror_zero_page),
-            0x68 => handle_opcode_onebyte!(self, pla),
-            0x69 => handle_opcode_twobytes!(self, adc_immediate),
-            0x6A => handle_opcode_onebyte!(self, ror_accumulator),
-            0x6C => handle_opcode_jump!(self, jmp_indirect),
-            0x6D => handle_opcode_threebytes!(self, adc_absolute),
-            0x6E => handle_opcode_threebytes!(self, ror_absolute),
-            0x70 => handle_opcode_twobytes!(self, bvs),
-            0x71 => handle_opcode_twobytes!(self, adc_indirect_y),
-            0x75 => handle_opcode_twobytes!(self, adc_zero_page_x),
-            0x76 => handle_opcode_twobytes!(self, ror_zero_page_x),
-            0x78 => handle_opcode_onebyte!(self, sei),
-            0x79 => handle_opcode_threebytes!(self, adc_absolute_y),
-            0x7D => handle_opcode_threebytes!(self, adc_absolute_x),
-            0x7E => handle_opcode_threebytes!(self, ror_absolute_x),
-            0x81 => handle_opcode_twobytes!(self, sta_indirect_x),
-            0x84 => handle_opcode_twobytes!(self, sty_zero_page),
-            0x85 => handle_opcode_twobytes!(self, sta_zero_page),
-            0x86 => handle_opcode_twobytes!(self, stx_zero_page),
-            0x88 => handle_opcode_onebyte!(self, dey_implied),
-            0x8A => handle_opcode_onebyte!(self, txa),
-            0x8C => handle_opcode_threebytes!(self, sty_absolute),
-            0x8D => handle_opcode_threebytes!(self, sta_absolute),
-            0x8E => handle_opcode_threebytes!(self, stx_absolute),
-            0x90 => handle_opcode_twobytes!(self, bcc),
-            0x91 => handle_opcode_twobytes!(self, sta_indirect_y),
-            0x94 => handle_opcode_twobytes!(self, sty_zero_page_x),
-            0x95 => handle_opcode_twobytes!(self, sta_zero_page_x),
-            0x96 => handle_opcode_twobytes!(self, stx_zero_page_x),
-            0x98 => handle_opcode_onebyte!(self, tya),
-            0x99 => handle_opcode_threebytes!(self, sta_absolute_y),
-            0x9A => handle_opcode_onebyte!(self, txs),
-            0x9D => handle_opcode_threebytes!(self, sta_absolute_x),
-            0xA0 => handle_opcode_twobytes!(self, ldy_immediate),
-            0xA1 => handle_opcode_twobytes!(self, lda_indirect_x),
-            0xA2 => handle_opcode_twobytes!(self, ldx_immediate),
-            0xA4 => handle_opcode_twobytes!(self, ldy_zero_page),
-            0xA5 => handle_opcode_twobytes!(self, lda_zero_page),
-            0xA6 => handle_opcode_twobytes!(self, ldx_zero_page),
-            0xA8 => handle_opcode_onebyte!(self, tay),
-            0xA9 => handle_opcode_twobytes!(self, lda_immediate),
-            0xAA => handle_opcode_onebyte!(self, tax),
-            0xAC => handle_opcode_threebytes!(self, ldy_absolute),
-            0xAD => handle_opcode_threebytes!(self, lda_absolute),
-            0xAE => handle_opcode_threebytes!(self, ldx_absolute),
-            0xB0 => handle_opcode_twobytes!(self, bcs),
-            0xB1 => handle_opcode_twobytes!(self, lda_indirect_y),
-            0xB4 => handle_opcode_twobytes!(self, ldy_zero_page_x),
-            0xB5 => handle_opcode_twobytes!(self, lda_zero_page_x),
-            0xB6 => handle_opcode_twobytes!(self, ldx_zero_page_y),
-            0xB8 => handle_opcode_onebyte!(self, clv),
-            0xB9 => handle_opcode_threebytes!(self, lda_absolute_y),
-            0xBA => handle_opcode_onebyte!(self, tsx),
-            0xBC => handle_opcode_threebytes!(self, ldy_absolute_x),
-            0xBD => handle_opcode_threebytes!(self, lda_absolute_x),
-            0xBE => handle_opcode_threebytes!(self, ldx_absolute_y),
-            0xC0 => handle_opcode_twobytes!(self, cpy_immediate),
-            0xC1 => handle_opcode_twobytes!(self, cmp_indirect_x),
-            0xC4 => handle_opcode_twobytes!(self, cpy_zero_page),
-            0xC5 => handle_opcode_twobytes!(self, cmp_zero_page),
-            0xC6 => handle_opcode_twobytes!(self, dec_zero_page),
-            0xC8 => handle_opcode_onebyte!(self, iny_implied),
-            0xC9 => handle_opcode_twobytes!(self, cmp_immediate),
-            0xCA => handle_opcode_onebyte!(self, dex_implied),
-            0xCC => handle_opcode_threebytes!(self, cpy_absolute),
-            0xCD => handle_opcode_threebytes!(self, cmp_absolute),
-            0xCE => handle_opcode_threebytes!(self, dec_absolute),
-            0xD0 => handle_opcode_twobytes!(self, bne),
-            0xD1 => handle_opcode_twobytes!(self, cmp_indirect_y),
-            0xD5 => handle_opcode_twobytes!(self, cmp_zero_page_x),
-            0xD6 => handle_opcode_twobytes!(self, dnc_zero_page_x),
-            0xD8 => handle_opcode_onebyte!(self, cld),
-            0xD9 => handle_opcode_threebytes!(self, cmp_absolute_y),
-            0xDD => handle_opcode_threebytes!(self, cmp_absolute_x),
-            0xDE => handle_opcode_threebytes!(self, dec_absolute_x),
-            0xE0 => handle_opcode_twobytes!(self, cpx_immediate),
-            0xE1 => handle_opcode_twobytes!(self, sbc_indirect_x),
-            0xE4 => handle_opcode_twobytes!(self, cpx_zero_page),
-            0xE5 => handle_opcode_twobytes!(self, sbc_zero_page),
-            0xE6 => handle_opcode_twobytes!(self, inc_zero_page),
-            0xE8 => handle_opcode_onebyte!(self, inx_implied),
-            0xE9 => handle_opcode_twobytes!(self, sbc_immediate),
-            0xEA => handle_opcode_onebyte!(self, nop_implied),
-            0xEC => handle_opcode_threebytes!(self, cpx_absolute),
-            0xED => handle_opcode_threebytes!(self, sbc_absolute),
-            0xEE => handle_opcode_threebytes!(self, inc_absolute),
-            0xF0 => handle_opcode_twobytes!(self, beq),
-            0xF1 => handle_opcode_twobytes!(self, sbc_indirect_y),
-            0xF5 => handle_opcode_twobytes!(self, sbc_zero_page_x),
-            0xF6 => handle_opcode_twobytes!(self, inc_zero_page_x),
-            0xF8 => handle_opcode_onebyte!(self, sed),
-            0xF9 => handle_opcode_threebytes!(self, sbc_absolute_y),
-            0xFD => handle_opcode_threebytes!(self, sbc_absolute_x),
-            0xFE => handle_opcode_threebytes!(self, inc_absolute_x),
-            0x4B => (0, 2),
-            0x0B => (0, 2),
-            0x2B => (0, 2),
-            0x8B => (0, 2),
-            0x6B => (0, 2),
-            0xC7 => handle_opcode_twobytes!(self, dcp_zero_page),
-            0xD7 => handle_opcode_twobytes!(self, dcp_zero_page_x),
-            0xCF => handle_opcode_threebytes!(self, dcp_absolute),
-            0xDF => handle_opcode_threebytes!(self, dcp_absolute_x),
-            0xDB => handle_opcode_threebytes!(self, dcp_absolute_y),
-            0xC3 => handle_opcode_twobytes!(self, dcp_indirect_x),
-            0xD3 => handle_opcode_twobytes!(self, dcp_indirect_y),
-            0xE7 => handle_opcode_twobytes!(self, isb_zero_page),
-            0xF7 => handle_opcode_twobytes!(self, isb_zero_page_x),
-            0xEF => handle_opcode_threebytes!(self, isb_absolute),
-            0xFF => handle_opcode_threebytes!(self, isb_absolute_x),
-            0xFB => handle_opcode_threebytes!(self, isb_absolute_y),
-            0xE3 => handle_opcode_twobytes!(self, isb_indirect_x),
-            0xF3 => handle_opcode_twobytes!(self, isb_indirect_y),
-            0xBB => (0, 3),
-            0xA7 => handle_opcode_twobytes!(self, lax_zero_page),
-            0xB7 => handle_opcode_twobytes!(self, lax_zero_page_y),
-            0xAF => handle_opcode_threebytes!(self, lax_absolute),
-            0xBF => handle_opcode_threebytes!(self, lax_absolute_y),
-            0xA3 => handle_opcode_twobytes!(self, lax_indirect_x),
-            0xB3 => handle_opcode_twobytes!(self, lax_indirect_y),
-            0xAB => (0, 2),
-            0x27 => handle_opcode_twobytes!(self, rla_zero_page),
-            0x37 => handle_opcode_twobytes!(self, rla_zero_page_x),
-            0x2F => handle_opcode_threebytes!(self, rla_absolute),
-            0x3F => handle_opcode_threebytes!(self, rla_absolute_x),
-            0x3B => handle_opcode_threebytes!(self, rla_absolute_y),
-            0x23 => handle_opcode_twobytes!(self, rla_indirect_x),
-            0x33 => handle_opcode_twobytes!(self, rla_indirect_y),
-            0x67 => handle_opcode_twobytes!(self, rra_zero_page),
-            0x77 => handle_opcode_twobytes!(self, rra_zero_page_x),
-            0x6F => handle_opcode_threebytes!(self, rra_absolute),
-            0x7F => handle_opcode_threebytes!(self, rra_absolute_x),
-            0x7B => handle_opcode_threebytes!(self, rra_absolute_y),
-            0x63 => handle_opcode_twobytes!(self, rra_indirect_x),
-            0x73 => handle_opcode_twobytes!(self, rra_indirect_y),
-            0x87 => handle_opcode_twobytes!(self, sax_zero_page),
-            0x97 => handle_opcode_twobytes!(self, sax_zero_page_y),
-            0x8F => handle_opcode_threebytes!(self, sax_absolute),
-            0x83 => handle_opcode_twobytes!(self, sax_indirect_x),
-            0xCB => (0, 2),
-            0x9F => (0, 3),
-            0x93 => (0, 2),
-            0x9E => (0, 3),
-            0x9C => (0, 3),
-            0x07 => handle_opcode_twobytes!(self, slo_zero_page),
-            0x17 => handle_opcode_twobytes!(self, slo_zero_page_x),
-            0x0F => handle_opcode_threebytes!(self, slo_absolute),
-            0x1F => handle_opcode_threebytes!(self, slo_absolute_x),
-            0x1B => handle_opcode_threebytes!(self, slo_absolute_y),
-            0x03 => handle_opcode_twobytes!(self, slo_indirect_x),
-            0x13 => handle_opcode_twobytes!(self, slo_indirect_y),
-            0x47 => handle_opcode_twobytes!(self, sre_zero_page),
-            0x57 => handle_opcode_twobytes!(self, sre_zero_page_x),
-            0x4F => handle_opcode_threebytes!(self, sre_absolute),
-            0x5F => handle_opcode_threebytes!(self, sre_absolute_x),
-            0x5B => handle_opcode_threebytes!(self, sre_absolute_y),
-            0x43 => handle_opcode_twobytes!(self, sre_indirect_x),
-            0x53 => handle_opcode_twobytes!(self, sre_indirect_y),
-            0x9B => (0, 3),
-            0xEB => handle_opcode_twobytes!(self, usbc),
-            0x1A => (0, 1),
-            0x3A => (0, 1),
-            0x5A => (0, 1),
-            0x7A => (0, 1),
-            0xDA => (0, 1),
-            0xFA => (0, 1),
-            0x80 => (0, 2),
-            0x82 => (0, 2),
-            0x89 => (0, 2),
-            0xC2 => (0, 2),
-            0xE2 => (0, 2),
-            0x04 => (0, 2),
-            0x44 => (0, 2),
-            0x64 => (0, 2),
-            0x14 => (0, 2),
-            0x34 => (0, 2),
-            0x54 => (0, 2),
-            0x74 => (0, 2),
-            0xD4 => (0, 2),
-            0xF4 => (0, 2),
-            0x0C => (0, 3),
-            0x1C => (0, 3),
-            0x3C => (0, 3),
-            0x5C => (0, 3),
-            0x7C => (0, 3),
-            0xDC => (0, 3),
-            0xFC => (0, 3),
-            0x02 => (0, 1),
-            0x12 => (0, 1),
-            0x22 => (0, 1),
-            0x32 => (0, 1),
-            0x42 => (0, 1),
-            0x52 => (0, 1),
-            0x62 => (0, 1),
-            0x72 => (0, 1),
-            0x92 => (0, 1),
-            0xB2 => (0, 1),
-            0xD2 => (0, 1),
-            0xF2 => (0, 1),
+    /*
+     *   NOP: the unofficial ones with operands still fetch them, so they take as long as a load would
+     *   (and read from wherever it would have loaded from, side effects included).
+     *
+     *   Opcodes: $1A, $3A, $5A, $7A, $DA, $FA are implied and behave like $EA
+     */
+
+    // Opcodes: $80, $82, $89, $C2, $E2
+    // Cycles: 2
+    fn nop_immediate(&mut self, _value: u8) {}
+
+    // Opcodes: $04, $44, $64
+    // Cycles: 3
+    fn nop_zero_page(&mut self, addr_lower_byte: u8) {
+        self.memory.fetch_zero_page(addr_lower_byte);
+    }
+
+    // Opcodes: $14, $34, $54, $74, $D4, $F4
+    // Cycles: 4
+    fn nop_zero_page_x(&mut self, addr_lower_byte: u8) {
+        self.memory
+            .fetch_zero_page_x(addr_lower_byte, self.registers.index_x);
+    }
+
+    // Opcode: $0C
+    // Cycles: 4
+    fn nop_absolute(&mut self, address: u16) {
+        self.memory.fetch_absolute(address);
+    }
+
+    // Opcodes: $1C, $3C, $5C, $7C, $DC, $FC
+    // Cycles: 4 (+1 if page boundary crossed)
+    fn nop_absolute_x(&mut self, address: u16) {
+        self.memory
+            .fetch_absolute_x(address, self.registers.index_x);
+    }
+
+    /*
+     *   JAM (KIL): the CPU locks up with the data bus at $FF, and stays that way until reset.
+     *   PC is left on the JAM so it shows where execution went astray.
+     *
+     *   Opcodes: $02, $12, $22, $32, $42, $52, $62, $72, $92, $B2, $D2, $F2
+     *   Cycles: 2, then none of its own while halted
+     */
+    fn jam(&mut self) {
+        self.halted = true;
+    }
+
+    fn fetch_u16(&mut self, addr: u16) -> u16 {
+        (self.memory.fetch_absolute(addr) as u16)
+            + (self.memory.fetch_absolute(addr.wrapping_add(1)) as u16 * 256)
+    }
+
+    /*
+     * Runs the opcode's method from OPCODES and returns the cycles it took and how far to move PC past it
+     */
+    fn decode_execute(&mut self, opcode: u8) -> (u8, u8) {
+        let op = &opcodes::OPCODES[opcode as usize];
+        let operand_addr = self.registers.program_counter.wrapping_add(1);
+
+        self.memory.page_crossed = false;
+        let mut cycles = op.cycles;
+        let mut bytes = op.bytes();
+        match op.handler {
+            Handler::NoOperand(method) => {
+                // the CPU reads the byte after the opcode anyway and ignores it
+                self.memory.fetch_absolute(operand_addr);
+                method(self)
+            }
+            Handler::Byte(method) => {
+                let value = self.memory.fetch_absolute(operand_addr);
+                method(self, value);
+            }
+            Handler::Word(method) => {
+                let value = self.fetch_u16(operand_addr);
+                method(self, value);
+            }
+            Handler::Branch(method) => {
+                let offset = self.memory.fetch_absolute(operand_addr);
+                cycles += method(self, offset);
+            }
+            Handler::Jump(method) => {
+                let address = self.fetch_u16(operand_addr);
+                method(self, address);
+                bytes = 0;
+            }
+            Handler::Control(method) => {
+                method(self);
+                bytes = 0;
+            }
         }
+        if self.memory.page_crossed && op.page_cross_penalty() {
+            cycles += 1;
+        }
+        (cycles, bytes)
     }
 
     pub fn tick_ins(&mut self) {
+        if self.halted {
+            // nothing runs, but the rest of the system keeps going a cycle at a time
+            self.num_cycles += 1;
+            self.num_cycles += self.memory.clock_apu(1);
+            return;
+        }
         let opcode = self.memory.fetch_absolute(self.registers.program_counter);
         let old_pc = self.registers.program_counter;
-        info!(
-            "{:02X}  {:04X}\t\t\tA:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:  0, 0 CYC:{}",
-            old_pc,
-            opcode,
-            self.registers.accumulator,
-            self.registers.index_x,
-            self.registers.index_y,
-            self.registers.processor_status,
-            self.registers.stack_pointer,
-            self.num_cycles
-        );
+        let irq_disabled_before = self.registers.get_interrupt_disable() != 0;
+        let before = self.registers;
+        let start_cycle = self.num_cycles;
+        let traced = self.trace_hook.is_some().then(|| {
+            (
+                trace::instruction_bytes(&self.memory, old_pc),
+                trace::disassemble(&self.memory, &self.registers, old_pc),
+                self.memory.ppu_position(),
+            )
+        });
         let (cycles, bytes) = self.decode_execute(opcode);
+        self.num_instructions += 1;
+        if let Some((address, value)) = self.memory.ignored_write.take() {
+            warn!(
+                "${:04X} wrote ${:02X} to ${:04X}, which the cartridge ignores",
+                old_pc, value, address
+            );
+        }
+        if self.halted {
+            error!("CPU jammed by ${:02X} at ${:04X}", opcode, old_pc);
+            if let Some(hook) = &mut self.jam_hook {
+                hook(old_pc, opcode);
+            }
+        }
         self.num_cycles += cycles as usize;
         self.registers.program_counter = self.registers.program_counter.wrapping_add(bytes as u16);
+        self.num_cycles += self.memory.clock_apu(cycles as usize);
+        if std::mem::take(&mut self.memory.oam_dma_pending) {
+            let stall = Self::oam_dma_cycles(self.num_cycles);
+            self.num_cycles += stall;
+            self.num_cycles += self.memory.clock_apu(stall);
+        }
+
+        if let (Some(mut hook), Some((bytes, disassembly, ppu))) = (self.trace_hook.take(), traced)
+        {
+            let traced = TracedInstruction {
+                pc: old_pc,
+                bytes,
+                disassembly,
+                ppu,
+                before,
+                after: self.registers,
+                start_cycle,
+                cycles: self.num_cycles - start_cycle,
+            };
+            hook(&traced);
+            self.trace_hook = Some(hook);
+        }
+
+        let apu = &self.memory.apu;
+        let (frame_irq, dmc_irq) = (apu.frame_counter.irq, apu.dmc.irq);
+        self.set_irq(IrqSource::ApuFrameCounter, frame_irq);
+        self.set_irq(IrqSource::ApuDmc, dmc_irq);
+        if let Some(mapper) = &self.memory.mapper {
+            let mapper_irq = mapper.borrow().irq();
+            self.set_irq(IrqSource::Mapper, mapper_irq);
+        }
+
+        if self.irq_asserted() && Self::polls_interrupts(opcode, cycles) {
+            // CLI, SEI and PLP only change I on their last cycle, after the poll already saw the old value
+            let irq_disabled = match opcode {
+                0x58 | 0x78 | 0x28 => irq_disabled_before,
+                _ => self.registers.get_interrupt_disable() != 0,
+            };
+            if !irq_disabled {
+                self.num_cycles += self.generate_irq() as usize;
+            }
+        }
+    }
+
+    /*
+     * Interrupts are polled on the second-to-last cycle of an instruction, so they're taken once it completes.
+     * A taken branch that stays on the same page skips the poll on its extra cycle, delaying the interrupt by
+     * one more instruction.
+     */
+    fn polls_interrupts(opcode: u8, cycles: u8) -> bool {
+        let is_branch = opcode & 0x1F == 0x10;
+        !(is_branch && cycles == 3)
+    }
+
+    /*
+     * OAM DMA halts the CPU for 256 read/write pairs plus one cycle waiting for the $4014 write to finish,
+     * and one more to get onto a read cycle if the write ended on an odd one.
+     */
+    fn oam_dma_cycles(cycle: usize) -> usize {
+        513 + cycle % 2
     }
 
     pub fn tick(&mut self, dur_cycles: usize) {
@@ -3201,3 +2910,553 @@ impl Cpu {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::jsontest::{DatabusLog, InstructionTestCase};
+
+    // Cases use the SingleStepTests (nes6502/v1) JSON format
+    fn run_case(json: &str) {
+        let case: InstructionTestCase = serde_json::from_str(json).unwrap();
+        let ppu = Rc::new(RefCell::new(PPU::new(Rc::new(RefCell::new(vec![])))));
+        let mut cpu = Cpu::new(ppu);
+
+        case.initial.load_into(&mut cpu);
+        cpu.tick_ins();
+        case.r#final.assert_matches(&cpu);
+    }
+
+    // Also checks the case's per-cycle bus accesses
+    fn run_case_with_bus(json: &str) {
+        let case: InstructionTestCase = serde_json::from_str(json).unwrap();
+        let ppu = Rc::new(RefCell::new(PPU::new(Rc::new(RefCell::new(vec![])))));
+        let mut cpu = Cpu::new(ppu);
+
+        case.initial.load_into(&mut cpu);
+        cpu.memory.databus_logger.enabled = true;
+        cpu.tick_ins();
+        case.r#final.assert_matches(&cpu);
+        case.assert_cycle_count(&cpu);
+        case.assert_bus_activity(&cpu);
+    }
+
+    #[test]
+    fn jsr_pushes_address_of_last_operand_byte() {
+        run_case(
+            r#"{
+                "name": "20 78 56",
+                "initial": { "pc": 4660, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+                             "ram": [[4660, 32], [4661, 120], [4662, 86]] },
+                "final":   { "pc": 22136, "s": 251, "a": 0, "x": 0, "y": 0, "p": 36,
+                             "ram": [[509, 18], [508, 54]] }
+            }"#,
+        );
+    }
+
+    #[test]
+    fn jsr_return_address_wraps_at_end_of_memory() {
+        run_case(
+            r#"{
+                "name": "20 00 90",
+                "initial": { "pc": 65534, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+                             "ram": [[65534, 32], [65535, 0], [0, 144]] },
+                "final":   { "pc": 36864, "s": 251, "a": 0, "x": 0, "y": 0, "p": 36,
+                             "ram": [[509, 0], [508, 0]] }
+            }"#,
+        );
+    }
+
+    #[test]
+    fn rts_adds_one_to_pulled_address() {
+        run_case(
+            r#"{
+                "name": "60",
+                "initial": { "pc": 22136, "s": 251, "a": 0, "x": 0, "y": 0, "p": 36,
+                             "ram": [[22136, 96], [508, 54], [509, 18]] },
+                "final":   { "pc": 4663, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+                             "ram": [] }
+            }"#,
+        );
+    }
+
+    #[test]
+    fn brk_skips_padding_byte_and_sets_break_on_pushed_status() {
+        run_case(
+            r#"{
+                "name": "00",
+                "initial": { "pc": 4660, "s": 253, "a": 0, "x": 0, "y": 0, "p": 32,
+                             "ram": [[4660, 0], [65534, 0], [65535, 144]] },
+                "final":   { "pc": 36864, "s": 250, "a": 0, "x": 0, "y": 0, "p": 36,
+                             "ram": [[509, 18], [508, 54], [507, 48]] }
+            }"#,
+        );
+    }
+
+    #[test]
+    fn php_pushes_status_with_break_and_bit_5_set() {
+        run_case(
+            r#"{
+                "name": "08",
+                "initial": { "pc": 4660, "s": 253, "a": 0, "x": 0, "y": 0, "p": 227,
+                             "ram": [[4660, 8]] },
+                "final":   { "pc": 4661, "s": 252, "a": 0, "x": 0, "y": 0, "p": 227,
+                             "ram": [[509, 243]] }
+            }"#,
+        );
+    }
+
+    #[test]
+    fn plp_ignores_break_and_bit_5_of_pulled_status() {
+        for (pulled, status) in [(255, 239), (0, 32), (16, 32)] {
+            run_case(&format!(
+                r#"{{
+                    "name": "28",
+                    "initial": {{ "pc": 4660, "s": 252, "a": 0, "x": 0, "y": 0, "p": 36,
+                                 "ram": [[4660, 40], [509, {}]] }},
+                    "final":   {{ "pc": 4661, "s": 253, "a": 0, "x": 0, "y": 0, "p": {},
+                                 "ram": [] }}
+                }}"#,
+                pulled, status
+            ));
+        }
+    }
+
+    // X and Y differ in the zero page,Y cases below, and $F0 + Y wraps around to $10
+
+    #[test]
+    fn stx_zero_page_y_indexes_with_y() {
+        run_case_with_bus(
+            r#"{
+                "name": "96 f0",
+                "initial": { "pc": 4660, "s": 253, "a": 0, "x": 66, "y": 32, "p": 36,
+                             "ram": [[4660, 150], [4661, 240], [240, 7], [16, 0]] },
+                "final":   { "pc": 4662, "s": 253, "a": 0, "x": 66, "y": 32, "p": 36,
+                             "ram": [[16, 66]] },
+                "cycles": [[4660, 150, "read"], [4661, 240, "read"], [240, 7, "read"], [16, 66, "write"]]
+            }"#,
+        );
+    }
+
+    #[test]
+    fn ldx_zero_page_y_indexes_with_y() {
+        run_case_with_bus(
+            r#"{
+                "name": "b6 f0",
+                "initial": { "pc": 4660, "s": 253, "a": 0, "x": 66, "y": 32, "p": 36,
+                             "ram": [[4660, 182], [4661, 240], [240, 7], [16, 153]] },
+                "final":   { "pc": 4662, "s": 253, "a": 0, "x": 153, "y": 32, "p": 164,
+                             "ram": [] },
+                "cycles": [[4660, 182, "read"], [4661, 240, "read"], [240, 7, "read"], [16, 153, "read"]]
+            }"#,
+        );
+    }
+
+    #[test]
+    fn sax_zero_page_y_indexes_with_y() {
+        run_case_with_bus(
+            r#"{
+                "name": "97 f0",
+                "initial": { "pc": 4660, "s": 253, "a": 15, "x": 60, "y": 32, "p": 36,
+                             "ram": [[4660, 151], [4661, 240], [240, 7], [16, 0]] },
+                "final":   { "pc": 4662, "s": 253, "a": 15, "x": 60, "y": 32, "p": 36,
+                             "ram": [[16, 12]] },
+                "cycles": [[4660, 151, "read"], [4661, 240, "read"], [240, 7, "read"], [16, 12, "write"]]
+            }"#,
+        );
+    }
+
+    #[test]
+    fn implied_ops_read_the_byte_after_the_opcode() {
+        run_case_with_bus(
+            r#"{
+                "name": "e8 9f f9",
+                "initial": { "pc": 35594, "s": 199, "a": 205, "x": 144, "y": 179, "p": 162,
+                             "ram": [[35594, 232], [35595, 159], [35596, 249]] },
+                "final":   { "pc": 35595, "s": 199, "a": 205, "x": 145, "y": 179, "p": 160,
+                             "ram": [] },
+                "cycles": [[35594, 232, "read"], [35595, 159, "read"]]
+            }"#,
+        );
+    }
+
+    #[test]
+    fn taken_branches_read_the_next_opcode() {
+        run_case_with_bus(
+            r#"{
+                "name": "d0 db 70",
+                "initial": { "pc": 54820, "s": 239, "a": 190, "x": 1, "y": 21, "p": 236,
+                             "ram": [[54820, 208], [54821, 219], [54822, 112], [54785, 6]] },
+                "final":   { "pc": 54785, "s": 239, "a": 190, "x": 1, "y": 21, "p": 236,
+                             "ram": [] },
+                "cycles": [[54820, 208, "read"], [54821, 219, "read"], [54822, 112, "read"]]
+            }"#,
+        );
+    }
+
+    #[test]
+    fn stack_accesses_are_on_the_bus() {
+        // PHA, then PLP with its dummy read of the top of the stack
+        run_case_with_bus(
+            r#"{
+                "name": "48 06 3b",
+                "initial": { "pc": 24483, "s": 95, "a": 152, "x": 169, "y": 110, "p": 229,
+                             "ram": [[24483, 72], [24484, 6], [24485, 59]] },
+                "final":   { "pc": 24484, "s": 94, "a": 152, "x": 169, "y": 110, "p": 229,
+                             "ram": [[351, 152]] },
+                "cycles": [[24483, 72, "read"], [24484, 6, "read"], [351, 152, "write"]]
+            }"#,
+        );
+        run_case_with_bus(
+            r#"{
+                "name": "28 87 5a",
+                "initial": { "pc": 50529, "s": 58, "a": 28, "x": 193, "y": 77, "p": 229,
+                             "ram": [[50529, 40], [50530, 135], [50531, 90], [314, 173], [315, 136]] },
+                "final":   { "pc": 50530, "s": 59, "a": 28, "x": 193, "y": 77, "p": 168,
+                             "ram": [] },
+                "cycles": [[50529, 40, "read"], [50530, 135, "read"], [314, 173, "read"], [315, 136, "read"]]
+            }"#,
+        );
+    }
+
+    #[test]
+    fn lax_zero_page_reads_once() {
+        run_case_with_bus(
+            r#"{
+                "name": "a7 45 35",
+                "initial": { "pc": 49957, "s": 240, "a": 171, "x": 72, "y": 207, "p": 96,
+                             "ram": [[49957, 167], [49958, 69], [49959, 53], [69, 206]] },
+                "final":   { "pc": 49959, "s": 240, "a": 206, "x": 206, "y": 207, "p": 224,
+                             "ram": [] },
+                "cycles": [[49957, 167, "read"], [49958, 69, "read"], [69, 206, "read"]]
+            }"#,
+        );
+    }
+
+    #[test]
+    fn lax_zero_page_y_reads_once() {
+        run_case_with_bus(
+            r#"{
+                "name": "b7 f0",
+                "initial": { "pc": 4660, "s": 253, "a": 0, "x": 66, "y": 32, "p": 36,
+                             "ram": [[4660, 183], [4661, 240], [240, 7], [16, 153]] },
+                "final":   { "pc": 4662, "s": 253, "a": 153, "x": 153, "y": 32, "p": 164,
+                             "ram": [] },
+                "cycles": [[4660, 183, "read"], [4661, 240, "read"], [240, 7, "read"], [16, 153, "read"]]
+            }"#,
+        );
+    }
+
+    #[test]
+    fn rti_returns_to_pulled_address_unmodified() {
+        run_case(
+            r#"{
+                "name": "40",
+                "initial": { "pc": 36864, "s": 250, "a": 0, "x": 0, "y": 0, "p": 36,
+                             "ram": [[36864, 64], [507, 195], [508, 54], [509, 18]] },
+                "final":   { "pc": 4662, "s": 253, "a": 0, "x": 0, "y": 0, "p": 227,
+                             "ram": [] }
+            }"#,
+        );
+    }
+
+    fn cpu_with_program(program: &[u8]) -> Cpu {
+        let ppu = Rc::new(RefCell::new(PPU::new(Rc::new(RefCell::new(vec![])))));
+        let mut cpu = Cpu::new(ppu);
+        cpu.memory.buffer[0x8000..(0x8000 + program.len())].copy_from_slice(program);
+        cpu.memory.buffer[0xFFFE] = 0x00;
+        cpu.memory.buffer[0xFFFF] = 0x90;
+        cpu.registers.program_counter = 0x8000;
+        cpu
+    }
+
+    #[test]
+    fn reset_keeps_registers_and_ram_but_power_on_starts_over() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.memory.buffer[0xFFFC..0xFFFE].copy_from_slice(&[0x34, 0x12]);
+        cpu.registers.accumulator = 0x42;
+        cpu.registers.stack_pointer = 0xF0;
+        cpu.memory.buffer[0x10] = 0x99;
+        // noise on, with its length counter loaded
+        cpu.memory.apu.write_register(0x4015, 0b1000);
+        cpu.memory.apu.write_register(0x400F, 0x08);
+        assert_eq!(cpu.memory.apu.read_status() & 0b1000, 0b1000);
+
+        assert_eq!(cpu.reset(), 7);
+        let registers = &mut cpu.registers;
+        assert_eq!(
+            (registers.accumulator, registers.stack_pointer),
+            (0x42, 0xED)
+        );
+        assert_eq!(
+            (
+                registers.program_counter,
+                registers.get_interrupt_disable() != 0
+            ),
+            (0x1234, true)
+        );
+        assert_eq!(cpu.memory.buffer[0x10], 0x99);
+        assert_eq!(cpu.memory.apu.read_status() & 0b1000, 0);
+
+        cpu.power_on(0xFF);
+        assert_eq!(
+            (cpu.registers.accumulator, cpu.registers.stack_pointer),
+            (0, 0xFD)
+        );
+        assert_eq!(cpu.registers.program_counter, 0x1234);
+        assert_eq!(cpu.memory.buffer[0x10], 0xFF);
+    }
+
+    #[test]
+    fn irq_after_cli_waits_one_instruction() {
+        // CLI, NOP, NOP
+        let mut cpu = cpu_with_program(&[0x58, 0xEA, 0xEA]);
+        cpu.assert_irq(IrqSource::Mapper);
+
+        cpu.tick_ins();
+        assert_eq!(cpu.registers.program_counter, 0x8001);
+        cpu.tick_ins();
+        assert_eq!(cpu.registers.program_counter, 0x9000);
+        cpu.stack_pop();
+        assert_eq!(cpu.stack_pop_u16(), 0x8002);
+    }
+
+    #[test]
+    fn irq_is_taken_right_after_sei() {
+        // SEI, NOP
+        let mut cpu = cpu_with_program(&[0x78, 0xEA]);
+        cpu.registers.unset_interrupt_disable();
+        cpu.assert_irq(IrqSource::Mapper);
+
+        cpu.tick_ins();
+        assert_eq!(cpu.registers.program_counter, 0x9000);
+        let pushed_status = cpu.stack_pop();
+        assert_ne!(pushed_status & 0b0000_0100, 0);
+        assert_eq!(pushed_status & 0b0001_0000, 0);
+        assert_eq!(cpu.stack_pop_u16(), 0x8001);
+    }
+
+    #[test]
+    fn detects_jmp_to_self() {
+        // JMP $8003, JMP $8003
+        let mut cpu = cpu_with_program(&[0x4C, 0x03, 0x80, 0x4C, 0x03, 0x80]);
+        assert!(!cpu.at_jmp_to_self());
+        cpu.tick_ins();
+        cpu.memory.access_stats = Some(crate::access_stats::AccessStats::new(0x10000));
+        assert!(cpu.at_jmp_to_self());
+        // looking isn't an access
+        let stats = cpu.memory.access_stats.take().unwrap();
+        assert!(stats.reads.iter().all(|&count| count == 0));
+        cpu.tick_ins();
+        assert_eq!(cpu.registers.program_counter, 0x8003);
+    }
+
+    #[test]
+    fn irq_line_is_held_until_every_source_releases_it() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.assert_irq(IrqSource::Mapper);
+        cpu.assert_irq(IrqSource::ApuFrameCounter);
+        cpu.release_irq(IrqSource::Mapper);
+        assert!(cpu.irq_asserted());
+        cpu.release_irq(IrqSource::ApuFrameCounter);
+        assert!(!cpu.irq_asserted());
+    }
+
+    #[test]
+    fn trace_hook_sees_each_instruction() {
+        // LDA #$42, LDX #$07
+        let mut cpu = cpu_with_program(&[0xA9, 0x42, 0xA2, 0x07]);
+        let traced = Rc::new(RefCell::new(vec![]));
+        let sink = Rc::clone(&traced);
+        cpu.set_trace_hook(move |ins| sink.borrow_mut().push(ins.clone()));
+        cpu.tick_ins();
+        cpu.tick_ins();
+
+        let traced = traced.borrow();
+        assert_eq!(traced.len(), 2);
+        assert_eq!(traced[0].pc, 0x8000);
+        assert_eq!(traced[0].bytes[..2], [0xA9, 0x42]);
+        assert_eq!(
+            (traced[0].before.accumulator, traced[0].after.accumulator),
+            (0, 0x42)
+        );
+        assert_eq!(traced[0].cycles, 2);
+        assert_eq!(traced[1].start_cycle, traced[0].start_cycle + 2);
+        assert_eq!(traced[1].after.index_x, 7);
+    }
+
+    #[test]
+    fn ram_and_ppu_registers_are_mirrored() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.memory.mapper = Some(
+            crate::mappers::Cartridge::from_ines_bytes(&crate::mappers::test_image(0, 1, 1))
+                .and_then(crate::mappers::Cartridge::into_mapper)
+                .unwrap(),
+        );
+
+        cpu.memory.store_absolute(0x1805, 0x1E);
+        assert_eq!(cpu.memory.fetch_absolute(0x0005), 0x1E);
+        assert_eq!(cpu.memory.fetch_absolute(0x0805), 0x1E);
+
+        // PPUMASK through its mirror at $3FF9
+        cpu.memory.store_absolute(0x3FF9, 0x08);
+        assert!(cpu.memory.ppu.borrow().snapshot().show_background);
+    }
+
+    #[test]
+    fn indexed_reads_crossing_a_page_take_a_cycle_longer() {
+        // LDX #$10, LDA $20F0,X, LDA $2000,X, STA $20F0,X, INC $20F0,X
+        let mut cpu = cpu_with_program(&[
+            0xA2, 0x10, 0xBD, 0xF0, 0x20, 0xBD, 0x00, 0x20, 0x9D, 0xF0, 0x20, 0xFE, 0xF0, 0x20,
+        ]);
+        let mut cycles = vec![];
+        for _ in 0..5 {
+            let start = cpu.num_cycles;
+            cpu.tick_ins();
+            cycles.push(cpu.num_cycles - start);
+        }
+        assert_eq!(cycles, [2, 5, 4, 5, 7]);
+    }
+
+    #[test]
+    fn read_modify_writes_and_indexed_stores_touch_the_bus_again() {
+        // INC $0200, then STA $02F0,X with X = $20
+        let mut cpu = cpu_with_program(&[0xEE, 0x00, 0x02, 0x9D, 0xF0, 0x02]);
+        cpu.memory.buffer[0x0200] = 5;
+        cpu.registers.index_x = 0x20;
+        cpu.registers.accumulator = 0xAA;
+        cpu.memory.databus_logger.enabled = true;
+        cpu.tick_ins();
+        cpu.tick_ins();
+
+        let accesses: Vec<_> = cpu
+            .memory
+            .databus_logger
+            .log
+            .iter()
+            .map(|DatabusLog(address, value, kind)| (*address, *value, kind.as_str()))
+            .collect();
+        assert_eq!(
+            accesses,
+            [
+                (0x8000, 0xEE, "read"),
+                (0x8001, 0x00, "read"),
+                (0x8002, 0x02, "read"),
+                (0x0200, 5, "read"),
+                // the unmodified value goes back first
+                (0x0200, 5, "write"),
+                (0x0200, 6, "write"),
+                (0x8003, 0x9D, "read"),
+                (0x8004, 0xF0, "read"),
+                (0x8005, 0x02, "read"),
+                // before the carry reaches the high byte
+                (0x0210, 0, "read"),
+                (0x0310, 0xAA, "write"),
+            ]
+        );
+    }
+
+    #[test]
+    fn shx_ands_with_the_high_byte_and_corrupts_it_across_a_page() {
+        // SHX $1200,Y then SHX $12F0,Y, with Y = $10 and then $20
+        let mut cpu = cpu_with_program(&[0x9E, 0x00, 0x12, 0x9E, 0xF0, 0x12]);
+        cpu.registers.index_x = 0xFF;
+        cpu.registers.index_y = 0x10;
+        cpu.tick_ins();
+        assert_eq!(cpu.memory.buffer[0x1210], 0x13);
+
+        // $05 AND $13 = $01, which also becomes the high byte of $1310
+        cpu.registers.index_x = 0x05;
+        cpu.registers.index_y = 0x20;
+        cpu.tick_ins();
+        assert_eq!(cpu.memory.buffer[0x0110], 0x01);
+        assert_eq!(cpu.memory.buffer[0x1310], 0);
+    }
+
+    #[test]
+    fn unofficial_nops_fetch_their_operands() {
+        // NOP $10, NOP $12F0,X with X = $08 and then $10, NOP #$FF
+        let mut cpu =
+            cpu_with_program(&[0x04, 0x10, 0x1C, 0xF0, 0x12, 0x1C, 0xF0, 0x12, 0x80, 0xFF]);
+        let mut results = vec![];
+        for index_x in [0, 0x08, 0x10, 0] {
+            cpu.registers.index_x = index_x;
+            let (cycles, pc) = (cpu.num_cycles, cpu.registers.program_counter);
+            cpu.tick_ins();
+            results.push((cpu.num_cycles - cycles, cpu.registers.program_counter - pc));
+        }
+        assert_eq!(results, [(3, 2), (4, 3), (5, 3), (2, 2)]);
+    }
+
+    #[test]
+    fn jam_halts_until_reset() {
+        // INX, JAM, INX
+        let mut cpu = cpu_with_program(&[0xE8, 0x02, 0xE8]);
+        cpu.memory.buffer[0xFFFC..0xFFFE].copy_from_slice(&[0x00, 0x80]);
+        let jams = Rc::new(RefCell::new(vec![]));
+        let seen = Rc::clone(&jams);
+        cpu.set_jam_hook(move |pc, opcode| seen.borrow_mut().push((pc, opcode)));
+        cpu.tick_ins();
+        cpu.tick_ins();
+        assert!(cpu.halted());
+
+        let cycles = cpu.num_cycles;
+        cpu.tick_ins();
+        assert_eq!(cpu.generate_nmi(), 0);
+        assert_eq!(
+            (cpu.registers.program_counter, cpu.registers.index_x),
+            (0x8001, 1)
+        );
+        assert!(cpu.num_cycles > cycles);
+        assert_eq!(*jams.borrow(), [(0x8001, 0x02)]);
+
+        cpu.reset();
+        assert!(!cpu.halted());
+        cpu.tick_ins();
+        assert_eq!(cpu.registers.index_x, 2);
+    }
+
+    #[test]
+    fn taken_branches_cost_a_cycle_more_across_a_page() {
+        // BEQ +$10 at $8000, not taken and taken, then BEQ -$10 at $8100 which lands on $80F2
+        let mut results = vec![];
+        for (pc, zero) in [(0x8000, false), (0x8000, true), (0x8100, true)] {
+            let mut cpu = cpu_with_program(&[0xF0, 0x10]);
+            cpu.memory.buffer[0x8100..0x8102].copy_from_slice(&[0xF0, 0xF0]);
+            cpu.registers.program_counter = pc;
+            if zero {
+                cpu.registers.set_zero();
+            }
+            cpu.tick_ins();
+            results.push((cpu.num_cycles, cpu.registers.program_counter));
+        }
+        assert_eq!(results, [(2, 0x8002), (3, 0x8012), (4, 0x80F2)]);
+    }
+
+    #[test]
+    fn oam_dma_halts_the_cpu() {
+        // LDA #$02, STA $4014
+        let mut cpu = cpu_with_program(&[0xA9, 0x02, 0x8D, 0x14, 0x40]);
+        cpu.tick_ins();
+        cpu.tick_ins();
+        // the STA ends on cycle 6, so the DMA doesn't need the extra alignment cycle
+        assert_eq!(cpu.num_cycles, 2 + 4 + 513);
+
+        cpu.registers.program_counter = 0x8002;
+        cpu.tick_ins();
+        assert_eq!(cpu.num_cycles, 519 + 4 + 514);
+    }
+
+    #[test]
+    fn vs_system_inputs_share_controller_ports() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.memory.vs_system = Some(memory::VsSystemInputs {
+            dip_switches: 0b1010_0110,
+            coins: [true, false],
+            service: false,
+        });
+
+        assert_eq!(cpu.memory.fetch_absolute(0x4016) & !1, 0b0011_0000);
+        assert_eq!(cpu.memory.fetch_absolute(0x4017), 0b1010_0100);
+    }
+}
@@ -0,0 +1,418 @@
+// The 256 opcodes as data: what each is called, how it addresses memory, its base cycle count and the Cpu
+// method that carries it out. decode_execute dispatches through OPCODES, and anything that needs to know how
+// long an instruction is (a disassembler, a tracer) can read it from here instead.
+
+use super::Cpu;
+
+use AddressingMode::*;
+use Handler::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl AddressingMode {
+    /// Bytes after the opcode
+    pub fn operand_bytes(self) -> u8 {
+        match self {
+            Implied | Accumulator => 0,
+            Immediate | ZeroPage | ZeroPageX | ZeroPageY | IndirectX | IndirectY | Relative => 1,
+            Absolute | AbsoluteX | AbsoluteY | Indirect => 2,
+        }
+    }
+}
+
+/// How decode_execute calls an opcode's method: which operand it fetches first, and whether PC is moved past the
+/// instruction afterwards or the method has already set it
+#[derive(Clone, Copy)]
+pub(super) enum Handler {
+    NoOperand(fn(&mut Cpu)),
+    Byte(fn(&mut Cpu, u8)),
+    Word(fn(&mut Cpu, u16)),
+    // returns the cycles a taken branch adds
+    Branch(fn(&mut Cpu, u8) -> u8),
+    // JMP and JSR
+    Jump(fn(&mut Cpu, u16)),
+    // BRK, RTI, RTS and JAM
+    Control(fn(&mut Cpu)),
+}
+
+#[derive(Clone, Copy)]
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    /// Without the page crossing penalty or the extra cycles of a taken branch
+    pub cycles: u8,
+    pub(super) handler: Handler,
+}
+
+impl Opcode {
+    const fn new(
+        mnemonic: &'static str,
+        mode: AddressingMode,
+        cycles: u8,
+        handler: Handler,
+    ) -> Self {
+        Self {
+            mnemonic,
+            mode,
+            cycles,
+            handler,
+        }
+    }
+
+    /// The opcode and its operands
+    pub fn bytes(&self) -> u8 {
+        1 + self.mode.operand_bytes()
+    }
+
+    /*
+     * Instructions that only read through absolute,X/Y or (indirect),Y take a cycle longer when the index
+     * carries into the high byte: the first read is from the wrong page and has to be done again.
+     * Writes and read-modify-writes always spend that cycle, it's part of their base count.
+     */
+    pub fn page_cross_penalty(&self) -> bool {
+        matches!(self.mode, AbsoluteX | AbsoluteY | IndirectY)
+            && matches!(
+                self.mnemonic,
+                "ORA"
+                    | "AND"
+                    | "EOR"
+                    | "ADC"
+                    | "LDA"
+                    | "CMP"
+                    | "SBC"
+                    | "LDX"
+                    | "LDY"
+                    | "LAX"
+                    | "LAS"
+                    | "NOP"
+            )
+    }
+}
+
+/// Whether `opcode` is outside the documented instruction set, like $EB (SBC) and every NOP but $EA
+pub fn is_unofficial(opcode: u8) -> bool {
+    let op = &OPCODES[opcode as usize];
+    match op.mnemonic {
+        "NOP" => opcode != 0xEA,
+        "SBC" => opcode == 0xEB,
+        "SLO" | "RLA" | "SRE" | "RRA" | "SAX" | "LAX" | "DCP" | "ISB" | "ANC" | "ALR" | "ARR"
+        | "ANE" | "LXA" | "SBX" | "LAS" | "SHA" | "SHX" | "SHY" | "TAS" | "JAM" => true,
+        _ => false,
+    }
+}
+
+#[rustfmt::skip]
+pub static OPCODES: [Opcode; 256] = [
+    /* $00 */ Opcode::new("BRK", Implied, 7, Control(Cpu::brk_implied)),
+    /* $01 */ Opcode::new("ORA", IndirectX, 6, Byte(Cpu::ora_indirect_x)),
+    /* $02 */ Opcode::new("JAM", Implied, 2, Control(Cpu::jam)),
+    /* $03 */ Opcode::new("SLO", IndirectX, 8, Byte(Cpu::slo_indirect_x)),
+    /* $04 */ Opcode::new("NOP", ZeroPage, 3, Byte(Cpu::nop_zero_page)),
+    /* $05 */ Opcode::new("ORA", ZeroPage, 3, Byte(Cpu::ora_zero_page)),
+    /* $06 */ Opcode::new("ASL", ZeroPage, 5, Byte(Cpu::asl_zero_page)),
+    /* $07 */ Opcode::new("SLO", ZeroPage, 5, Byte(Cpu::slo_zero_page)),
+    /* $08 */ Opcode::new("PHP", Implied, 3, NoOperand(Cpu::php)),
+    /* $09 */ Opcode::new("ORA", Immediate, 2, Byte(Cpu::ora_immediate)),
+    /* $0A */ Opcode::new("ASL", Accumulator, 2, NoOperand(Cpu::asl_accumulator)),
+    /* $0B */ Opcode::new("ANC", Immediate, 2, Byte(Cpu::anc_immediate)),
+    /* $0C */ Opcode::new("NOP", Absolute, 4, Word(Cpu::nop_absolute)),
+    /* $0D */ Opcode::new("ORA", Absolute, 4, Word(Cpu::ora_absolute)),
+    /* $0E */ Opcode::new("ASL", Absolute, 6, Word(Cpu::asl_absolute)),
+    /* $0F */ Opcode::new("SLO", Absolute, 6, Word(Cpu::slo_absolute)),
+    /* $10 */ Opcode::new("BPL", Relative, 2, Branch(Cpu::bpl)),
+    /* $11 */ Opcode::new("ORA", IndirectY, 5, Byte(Cpu::ora_indirect_y)),
+    /* $12 */ Opcode::new("JAM", Implied, 2, Control(Cpu::jam)),
+    /* $13 */ Opcode::new("SLO", IndirectY, 8, Byte(Cpu::slo_indirect_y)),
+    /* $14 */ Opcode::new("NOP", ZeroPageX, 4, Byte(Cpu::nop_zero_page_x)),
+    /* $15 */ Opcode::new("ORA", ZeroPageX, 4, Byte(Cpu::ora_zero_page_x)),
+    /* $16 */ Opcode::new("ASL", ZeroPageX, 6, Byte(Cpu::asl_zero_page_x)),
+    /* $17 */ Opcode::new("SLO", ZeroPageX, 6, Byte(Cpu::slo_zero_page_x)),
+    /* $18 */ Opcode::new("CLC", Implied, 2, NoOperand(Cpu::clc)),
+    /* $19 */ Opcode::new("ORA", AbsoluteY, 4, Word(Cpu::ora_absolute_y)),
+    /* $1A */ Opcode::new("NOP", Implied, 2, NoOperand(Cpu::nop_implied)),
+    /* $1B */ Opcode::new("SLO", AbsoluteY, 7, Word(Cpu::slo_absolute_y)),
+    /* $1C */ Opcode::new("NOP", AbsoluteX, 4, Word(Cpu::nop_absolute_x)),
+    /* $1D */ Opcode::new("ORA", AbsoluteX, 4, Word(Cpu::ora_absolute_x)),
+    /* $1E */ Opcode::new("ASL", AbsoluteX, 7, Word(Cpu::asl_absolute_x)),
+    /* $1F */ Opcode::new("SLO", AbsoluteX, 7, Word(Cpu::slo_absolute_x)),
+    /* $20 */ Opcode::new("JSR", Absolute, 6, Jump(Cpu::jsr)),
+    /* $21 */ Opcode::new("AND", IndirectX, 6, Byte(Cpu::and_indirect_x)),
+    /* $22 */ Opcode::new("JAM", Implied, 2, Control(Cpu::jam)),
+    /* $23 */ Opcode::new("RLA", IndirectX, 8, Byte(Cpu::rla_indirect_x)),
+    /* $24 */ Opcode::new("BIT", ZeroPage, 3, Byte(Cpu::bit_zero_page)),
+    /* $25 */ Opcode::new("AND", ZeroPage, 3, Byte(Cpu::and_zero_page)),
+    /* $26 */ Opcode::new("ROL", ZeroPage, 5, Byte(Cpu::rol_zero_page)),
+    /* $27 */ Opcode::new("RLA", ZeroPage, 5, Byte(Cpu::rla_zero_page)),
+    /* $28 */ Opcode::new("PLP", Implied, 4, NoOperand(Cpu::plp)),
+    /* $29 */ Opcode::new("AND", Immediate, 2, Byte(Cpu::and_immediate)),
+    /* $2A */ Opcode::new("ROL", Accumulator, 2, NoOperand(Cpu::rol_accumulator)),
+    /* $2B */ Opcode::new("ANC", Immediate, 2, Byte(Cpu::anc_immediate)),
+    /* $2C */ Opcode::new("BIT", Absolute, 4, Word(Cpu::bit_absolute)),
+    /* $2D */ Opcode::new("AND", Absolute, 4, Word(Cpu::and_absolute)),
+    /* $2E */ Opcode::new("ROL", Absolute, 6, Word(Cpu::rol_absolute)),
+    /* $2F */ Opcode::new("RLA", Absolute, 6, Word(Cpu::rla_absolute)),
+    /* $30 */ Opcode::new("BMI", Relative, 2, Branch(Cpu::bmi)),
+    /* $31 */ Opcode::new("AND", IndirectY, 5, Byte(Cpu::and_indirect_y)),
+    /* $32 */ Opcode::new("JAM", Implied, 2, Control(Cpu::jam)),
+    /* $33 */ Opcode::new("RLA", IndirectY, 8, Byte(Cpu::rla_indirect_y)),
+    /* $34 */ Opcode::new("NOP", ZeroPageX, 4, Byte(Cpu::nop_zero_page_x)),
+    /* $35 */ Opcode::new("AND", ZeroPageX, 4, Byte(Cpu::and_zero_page_x)),
+    /* $36 */ Opcode::new("ROL", ZeroPageX, 6, Byte(Cpu::rol_zero_page_x)),
+    /* $37 */ Opcode::new("RLA", ZeroPageX, 6, Byte(Cpu::rla_zero_page_x)),
+    /* $38 */ Opcode::new("SEC", Implied, 2, NoOperand(Cpu::sec)),
+    /* $39 */ Opcode::new("AND", AbsoluteY, 4, Word(Cpu::and_absolute_y)),
+    /* $3A */ Opcode::new("NOP", Implied, 2, NoOperand(Cpu::nop_implied)),
+    /* $3B */ Opcode::new("RLA", AbsoluteY, 7, Word(Cpu::rla_absolute_y)),
+    /* $3C */ Opcode::new("NOP", AbsoluteX, 4, Word(Cpu::nop_absolute_x)),
+    /* $3D */ Opcode::new("AND", AbsoluteX, 4, Word(Cpu::and_absolute_x)),
+    /* $3E */ Opcode::new("ROL", AbsoluteX, 7, Word(Cpu::rol_absolute_x)),
+    /* $3F */ Opcode::new("RLA", AbsoluteX, 7, Word(Cpu::rla_absolute_x)),
+    /* $40 */ Opcode::new("RTI", Implied, 6, Control(Cpu::rti_implied)),
+    /* $41 */ Opcode::new("EOR", IndirectX, 6, Byte(Cpu::eor_indirect_x)),
+    /* $42 */ Opcode::new("JAM", Implied, 2, Control(Cpu::jam)),
+    /* $43 */ Opcode::new("SRE", IndirectX, 8, Byte(Cpu::sre_indirect_x)),
+    /* $44 */ Opcode::new("NOP", ZeroPage, 3, Byte(Cpu::nop_zero_page)),
+    /* $45 */ Opcode::new("EOR", ZeroPage, 3, Byte(Cpu::eor_zero_page)),
+    /* $46 */ Opcode::new("LSR", ZeroPage, 5, Byte(Cpu::lsr_zero_page)),
+    /* $47 */ Opcode::new("SRE", ZeroPage, 5, Byte(Cpu::sre_zero_page)),
+    /* $48 */ Opcode::new("PHA", Implied, 3, NoOperand(Cpu::pha)),
+    /* $49 */ Opcode::new("EOR", Immediate, 2, Byte(Cpu::eor_immediate)),
+    /* $4A */ Opcode::new("LSR", Accumulator, 2, NoOperand(Cpu::lsr_accumulator)),
+    /* $4B */ Opcode::new("ALR", Immediate, 2, Byte(Cpu::alr_immediate)),
+    /* $4C */ Opcode::new("JMP", Absolute, 3, Jump(Cpu::jmp_absolute)),
+    /* $4D */ Opcode::new("EOR", Absolute, 4, Word(Cpu::eor_absolute)),
+    /* $4E */ Opcode::new("LSR", Absolute, 6, Word(Cpu::lsr_absolute)),
+    /* $4F */ Opcode::new("SRE", Absolute, 6, Word(Cpu::sre_absolute)),
+    /* $50 */ Opcode::new("BVC", Relative, 2, Branch(Cpu::bvc)),
+    /* $51 */ Opcode::new("EOR", IndirectY, 5, Byte(Cpu::eor_indirect_y)),
+    /* $52 */ Opcode::new("JAM", Implied, 2, Control(Cpu::jam)),
+    /* $53 */ Opcode::new("SRE", IndirectY, 8, Byte(Cpu::sre_indirect_y)),
+    /* $54 */ Opcode::new("NOP", ZeroPageX, 4, Byte(Cpu::nop_zero_page_x)),
+    /* $55 */ Opcode::new("EOR", ZeroPageX, 4, Byte(Cpu::eor_zero_page_x)),
+    /* $56 */ Opcode::new("LSR", ZeroPageX, 6, Byte(Cpu::lsr_zero_page_x)),
+    /* $57 */ Opcode::new("SRE", ZeroPageX, 6, Byte(Cpu::sre_zero_page_x)),
+    /* $58 */ Opcode::new("CLI", Implied, 2, NoOperand(Cpu::cli)),
+    /* $59 */ Opcode::new("EOR", AbsoluteY, 4, Word(Cpu::eor_absolute_y)),
+    /* $5A */ Opcode::new("NOP", Implied, 2, NoOperand(Cpu::nop_implied)),
+    /* $5B */ Opcode::new("SRE", AbsoluteY, 7, Word(Cpu::sre_absolute_y)),
+    /* $5C */ Opcode::new("NOP", AbsoluteX, 4, Word(Cpu::nop_absolute_x)),
+    /* $5D */ Opcode::new("EOR", AbsoluteX, 4, Word(Cpu::eor_absolute_x)),
+    /* $5E */ Opcode::new("LSR", AbsoluteX, 7, Word(Cpu::lsr_absolute_x)),
+    /* $5F */ Opcode::new("SRE", AbsoluteX, 7, Word(Cpu::sre_absolute_x)),
+    /* $60 */ Opcode::new("RTS", Implied, 6, Control(Cpu::rts)),
+    /* $61 */ Opcode::new("ADC", IndirectX, 6, Byte(Cpu::adc_indirect_x)),
+    /* $62 */ Opcode::new("JAM", Implied, 2, Control(Cpu::jam)),
+    /* $63 */ Opcode::new("RRA", IndirectX, 8, Byte(Cpu::rra_indirect_x)),
+    /* $64 */ Opcode::new("NOP", ZeroPage, 3, Byte(Cpu::nop_zero_page)),
+    /* $65 */ Opcode::new("ADC", ZeroPage, 3, Byte(Cpu::adc_zero_page)),
+    /* $66 */ Opcode::new("ROR", ZeroPage, 5, Byte(Cpu::ror_zero_page)),
+    /* $67 */ Opcode::new("RRA", ZeroPage, 5, Byte(Cpu::rra_zero_page)),
+    /* $68 */ Opcode::new("PLA", Implied, 4, NoOperand(Cpu::pla)),
+    /* $69 */ Opcode::new("ADC", Immediate, 2, Byte(Cpu::adc_immediate)),
+    /* $6A */ Opcode::new("ROR", Accumulator, 2, NoOperand(Cpu::ror_accumulator)),
+    /* $6B */ Opcode::new("ARR", Immediate, 2, Byte(Cpu::arr_immediate)),
+    /* $6C */ Opcode::new("JMP", Indirect, 5, Jump(Cpu::jmp_indirect)),
+    /* $6D */ Opcode::new("ADC", Absolute, 4, Word(Cpu::adc_absolute)),
+    /* $6E */ Opcode::new("ROR", Absolute, 6, Word(Cpu::ror_absolute)),
+    /* $6F */ Opcode::new("RRA", Absolute, 6, Word(Cpu::rra_absolute)),
+    /* $70 */ Opcode::new("BVS", Relative, 2, Branch(Cpu::bvs)),
+    /* $71 */ Opcode::new("ADC", IndirectY, 5, Byte(Cpu::adc_indirect_y)),
+    /* $72 */ Opcode::new("JAM", Implied, 2, Control(Cpu::jam)),
+    /* $73 */ Opcode::new("RRA", IndirectY, 8, Byte(Cpu::rra_indirect_y)),
+    /* $74 */ Opcode::new("NOP", ZeroPageX, 4, Byte(Cpu::nop_zero_page_x)),
+    /* $75 */ Opcode::new("ADC", ZeroPageX, 4, Byte(Cpu::adc_zero_page_x)),
+    /* $76 */ Opcode::new("ROR", ZeroPageX, 6, Byte(Cpu::ror_zero_page_x)),
+    /* $77 */ Opcode::new("RRA", ZeroPageX, 6, Byte(Cpu::rra_zero_page_x)),
+    /* $78 */ Opcode::new("SEI", Implied, 2, NoOperand(Cpu::sei)),
+    /* $79 */ Opcode::new("ADC", AbsoluteY, 4, Word(Cpu::adc_absolute_y)),
+    /* $7A */ Opcode::new("NOP", Implied, 2, NoOperand(Cpu::nop_implied)),
+    /* $7B */ Opcode::new("RRA", AbsoluteY, 7, Word(Cpu::rra_absolute_y)),
+    /* $7C */ Opcode::new("NOP", AbsoluteX, 4, Word(Cpu::nop_absolute_x)),
+    /* $7D */ Opcode::new("ADC", AbsoluteX, 4, Word(Cpu::adc_absolute_x)),
+    /* $7E */ Opcode::new("ROR", AbsoluteX, 7, Word(Cpu::ror_absolute_x)),
+    /* $7F */ Opcode::new("RRA", AbsoluteX, 7, Word(Cpu::rra_absolute_x)),
+    /* $80 */ Opcode::new("NOP", Immediate, 2, Byte(Cpu::nop_immediate)),
+    /* $81 */ Opcode::new("STA", IndirectX, 6, Byte(Cpu::sta_indirect_x)),
+    /* $82 */ Opcode::new("NOP", Immediate, 2, Byte(Cpu::nop_immediate)),
+    /* $83 */ Opcode::new("SAX", IndirectX, 6, Byte(Cpu::sax_indirect_x)),
+    /* $84 */ Opcode::new("STY", ZeroPage, 3, Byte(Cpu::sty_zero_page)),
+    /* $85 */ Opcode::new("STA", ZeroPage, 3, Byte(Cpu::sta_zero_page)),
+    /* $86 */ Opcode::new("STX", ZeroPage, 3, Byte(Cpu::stx_zero_page)),
+    /* $87 */ Opcode::new("SAX", ZeroPage, 3, Byte(Cpu::sax_zero_page)),
+    /* $88 */ Opcode::new("DEY", Implied, 2, NoOperand(Cpu::dey_implied)),
+    /* $89 */ Opcode::new("NOP", Immediate, 2, Byte(Cpu::nop_immediate)),
+    /* $8A */ Opcode::new("TXA", Implied, 2, NoOperand(Cpu::txa)),
+    /* $8B */ Opcode::new("ANE", Immediate, 2, Byte(Cpu::ane_immediate)),
+    /* $8C */ Opcode::new("STY", Absolute, 4, Word(Cpu::sty_absolute)),
+    /* $8D */ Opcode::new("STA", Absolute, 4, Word(Cpu::sta_absolute)),
+    /* $8E */ Opcode::new("STX", Absolute, 4, Word(Cpu::stx_absolute)),
+    /* $8F */ Opcode::new("SAX", Absolute, 4, Word(Cpu::sax_absolute)),
+    /* $90 */ Opcode::new("BCC", Relative, 2, Branch(Cpu::bcc)),
+    /* $91 */ Opcode::new("STA", IndirectY, 6, Byte(Cpu::sta_indirect_y)),
+    /* $92 */ Opcode::new("JAM", Implied, 2, Control(Cpu::jam)),
+    /* $93 */ Opcode::new("SHA", IndirectY, 6, Byte(Cpu::sha_indirect_y)),
+    /* $94 */ Opcode::new("STY", ZeroPageX, 4, Byte(Cpu::sty_zero_page_x)),
+    /* $95 */ Opcode::new("STA", ZeroPageX, 4, Byte(Cpu::sta_zero_page_x)),
+    /* $96 */ Opcode::new("STX", ZeroPageY, 4, Byte(Cpu::stx_zero_page_y)),
+    /* $97 */ Opcode::new("SAX", ZeroPageY, 4, Byte(Cpu::sax_zero_page_y)),
+    /* $98 */ Opcode::new("TYA", Implied, 2, NoOperand(Cpu::tya)),
+    /* $99 */ Opcode::new("STA", AbsoluteY, 5, Word(Cpu::sta_absolute_y)),
+    /* $9A */ Opcode::new("TXS", Implied, 2, NoOperand(Cpu::txs)),
+    /* $9B */ Opcode::new("TAS", AbsoluteY, 5, Word(Cpu::tas_absolute_y)),
+    /* $9C */ Opcode::new("SHY", AbsoluteX, 5, Word(Cpu::shy_absolute_x)),
+    /* $9D */ Opcode::new("STA", AbsoluteX, 5, Word(Cpu::sta_absolute_x)),
+    /* $9E */ Opcode::new("SHX", AbsoluteY, 5, Word(Cpu::shx_absolute_y)),
+    /* $9F */ Opcode::new("SHA", AbsoluteY, 5, Word(Cpu::sha_absolute_y)),
+    /* $A0 */ Opcode::new("LDY", Immediate, 2, Byte(Cpu::ldy_immediate)),
+    /* $A1 */ Opcode::new("LDA", IndirectX, 6, Byte(Cpu::lda_indirect_x)),
+    /* $A2 */ Opcode::new("LDX", Immediate, 2, Byte(Cpu::ldx_immediate)),
+    /* $A3 */ Opcode::new("LAX", IndirectX, 6, Byte(Cpu::lax_indirect_x)),
+    /* $A4 */ Opcode::new("LDY", ZeroPage, 3, Byte(Cpu::ldy_zero_page)),
+    /* $A5 */ Opcode::new("LDA", ZeroPage, 3, Byte(Cpu::lda_zero_page)),
+    /* $A6 */ Opcode::new("LDX", ZeroPage, 3, Byte(Cpu::ldx_zero_page)),
+    /* $A7 */ Opcode::new("LAX", ZeroPage, 3, Byte(Cpu::lax_zero_page)),
+    /* $A8 */ Opcode::new("TAY", Implied, 2, NoOperand(Cpu::tay)),
+    /* $A9 */ Opcode::new("LDA", Immediate, 2, Byte(Cpu::lda_immediate)),
+    /* $AA */ Opcode::new("TAX", Implied, 2, NoOperand(Cpu::tax)),
+    /* $AB */ Opcode::new("LXA", Immediate, 2, Byte(Cpu::lxa_immediate)),
+    /* $AC */ Opcode::new("LDY", Absolute, 4, Word(Cpu::ldy_absolute)),
+    /* $AD */ Opcode::new("LDA", Absolute, 4, Word(Cpu::lda_absolute)),
+    /* $AE */ Opcode::new("LDX", Absolute, 4, Word(Cpu::ldx_absolute)),
+    /* $AF */ Opcode::new("LAX", Absolute, 4, Word(Cpu::lax_absolute)),
+    /* $B0 */ Opcode::new("BCS", Relative, 2, Branch(Cpu::bcs)),
+    /* $B1 */ Opcode::new("LDA", IndirectY, 5, Byte(Cpu::lda_indirect_y)),
+    /* $B2 */ Opcode::new("JAM", Implied, 2, Control(Cpu::jam)),
+    /* $B3 */ Opcode::new("LAX", IndirectY, 5, Byte(Cpu::lax_indirect_y)),
+    /* $B4 */ Opcode::new("LDY", ZeroPageX, 4, Byte(Cpu::ldy_zero_page_x)),
+    /* $B5 */ Opcode::new("LDA", ZeroPageX, 4, Byte(Cpu::lda_zero_page_x)),
+    /* $B6 */ Opcode::new("LDX", ZeroPageY, 4, Byte(Cpu::ldx_zero_page_y)),
+    /* $B7 */ Opcode::new("LAX", ZeroPageY, 4, Byte(Cpu::lax_zero_page_y)),
+    /* $B8 */ Opcode::new("CLV", Implied, 2, NoOperand(Cpu::clv)),
+    /* $B9 */ Opcode::new("LDA", AbsoluteY, 4, Word(Cpu::lda_absolute_y)),
+    /* $BA */ Opcode::new("TSX", Implied, 2, NoOperand(Cpu::tsx)),
+    /* $BB */ Opcode::new("LAS", AbsoluteY, 4, Word(Cpu::las_absolute_y)),
+    /* $BC */ Opcode::new("LDY", AbsoluteX, 4, Word(Cpu::ldy_absolute_x)),
+    /* $BD */ Opcode::new("LDA", AbsoluteX, 4, Word(Cpu::lda_absolute_x)),
+    /* $BE */ Opcode::new("LDX", AbsoluteY, 4, Word(Cpu::ldx_absolute_y)),
+    /* $BF */ Opcode::new("LAX", AbsoluteY, 4, Word(Cpu::lax_absolute_y)),
+    /* $C0 */ Opcode::new("CPY", Immediate, 2, Byte(Cpu::cpy_immediate)),
+    /* $C1 */ Opcode::new("CMP", IndirectX, 6, Byte(Cpu::cmp_indirect_x)),
+    /* $C2 */ Opcode::new("NOP", Immediate, 2, Byte(Cpu::nop_immediate)),
+    /* $C3 */ Opcode::new("DCP", IndirectX, 8, Byte(Cpu::dcp_indirect_x)),
+    /* $C4 */ Opcode::new("CPY", ZeroPage, 3, Byte(Cpu::cpy_zero_page)),
+    /* $C5 */ Opcode::new("CMP", ZeroPage, 3, Byte(Cpu::cmp_zero_page)),
+    /* $C6 */ Opcode::new("DEC", ZeroPage, 5, Byte(Cpu::dec_zero_page)),
+    /* $C7 */ Opcode::new("DCP", ZeroPage, 5, Byte(Cpu::dcp_zero_page)),
+    /* $C8 */ Opcode::new("INY", Implied, 2, NoOperand(Cpu::iny_implied)),
+    /* $C9 */ Opcode::new("CMP", Immediate, 2, Byte(Cpu::cmp_immediate)),
+    /* $CA */ Opcode::new("DEX", Implied, 2, NoOperand(Cpu::dex_implied)),
+    /* $CB */ Opcode::new("SBX", Immediate, 2, Byte(Cpu::sbx_immediate)),
+    /* $CC */ Opcode::new("CPY", Absolute, 4, Word(Cpu::cpy_absolute)),
+    /* $CD */ Opcode::new("CMP", Absolute, 4, Word(Cpu::cmp_absolute)),
+    /* $CE */ Opcode::new("DEC", Absolute, 6, Word(Cpu::dec_absolute)),
+    /* $CF */ Opcode::new("DCP", Absolute, 6, Word(Cpu::dcp_absolute)),
+    /* $D0 */ Opcode::new("BNE", Relative, 2, Branch(Cpu::bne)),
+    /* $D1 */ Opcode::new("CMP", IndirectY, 5, Byte(Cpu::cmp_indirect_y)),
+    /* $D2 */ Opcode::new("JAM", Implied, 2, Control(Cpu::jam)),
+    /* $D3 */ Opcode::new("DCP", IndirectY, 8, Byte(Cpu::dcp_indirect_y)),
+    /* $D4 */ Opcode::new("NOP", ZeroPageX, 4, Byte(Cpu::nop_zero_page_x)),
+    /* $D5 */ Opcode::new("CMP", ZeroPageX, 4, Byte(Cpu::cmp_zero_page_x)),
+    /* $D6 */ Opcode::new("DEC", ZeroPageX, 6, Byte(Cpu::dnc_zero_page_x)),
+    /* $D7 */ Opcode::new("DCP", ZeroPageX, 6, Byte(Cpu::dcp_zero_page_x)),
+    /* $D8 */ Opcode::new("CLD", Implied, 2, NoOperand(Cpu::cld)),
+    /* $D9 */ Opcode::new("CMP", AbsoluteY, 4, Word(Cpu::cmp_absolute_y)),
+    /* $DA */ Opcode::new("NOP", Implied, 2, NoOperand(Cpu::nop_implied)),
+    /* $DB */ Opcode::new("DCP", AbsoluteY, 7, Word(Cpu::dcp_absolute_y)),
+    /* $DC */ Opcode::new("NOP", AbsoluteX, 4, Word(Cpu::nop_absolute_x)),
+    /* $DD */ Opcode::new("CMP", AbsoluteX, 4, Word(Cpu::cmp_absolute_x)),
+    /* $DE */ Opcode::new("DEC", AbsoluteX, 7, Word(Cpu::dec_absolute_x)),
+    /* $DF */ Opcode::new("DCP", AbsoluteX, 7, Word(Cpu::dcp_absolute_x)),
+    /* $E0 */ Opcode::new("CPX", Immediate, 2, Byte(Cpu::cpx_immediate)),
+    /* $E1 */ Opcode::new("SBC", IndirectX, 6, Byte(Cpu::sbc_indirect_x)),
+    /* $E2 */ Opcode::new("NOP", Immediate, 2, Byte(Cpu::nop_immediate)),
+    /* $E3 */ Opcode::new("ISB", IndirectX, 8, Byte(Cpu::isb_indirect_x)),
+    /* $E4 */ Opcode::new("CPX", ZeroPage, 3, Byte(Cpu::cpx_zero_page)),
+    /* $E5 */ Opcode::new("SBC", ZeroPage, 3, Byte(Cpu::sbc_zero_page)),
+    /* $E6 */ Opcode::new("INC", ZeroPage, 5, Byte(Cpu::inc_zero_page)),
+    /* $E7 */ Opcode::new("ISB", ZeroPage, 5, Byte(Cpu::isb_zero_page)),
+    /* $E8 */ Opcode::new("INX", Implied, 2, NoOperand(Cpu::inx_implied)),
+    /* $E9 */ Opcode::new("SBC", Immediate, 2, Byte(Cpu::sbc_immediate)),
+    /* $EA */ Opcode::new("NOP", Implied, 2, NoOperand(Cpu::nop_implied)),
+    /* $EB */ Opcode::new("SBC", Immediate, 2, Byte(Cpu::usbc)),
+    /* $EC */ Opcode::new("CPX", Absolute, 4, Word(Cpu::cpx_absolute)),
+    /* $ED */ Opcode::new("SBC", Absolute, 4, Word(Cpu::sbc_absolute)),
+    /* $EE */ Opcode::new("INC", Absolute, 6, Word(Cpu::inc_absolute)),
+    /* $EF */ Opcode::new("ISB", Absolute, 6, Word(Cpu::isb_absolute)),
+    /* $F0 */ Opcode::new("BEQ", Relative, 2, Branch(Cpu::beq)),
+    /* $F1 */ Opcode::new("SBC", IndirectY, 5, Byte(Cpu::sbc_indirect_y)),
+    /* $F2 */ Opcode::new("JAM", Implied, 2, Control(Cpu::jam)),
+    /* $F3 */ Opcode::new("ISB", IndirectY, 8, Byte(Cpu::isb_indirect_y)),
+    /* $F4 */ Opcode::new("NOP", ZeroPageX, 4, Byte(Cpu::nop_zero_page_x)),
+    /* $F5 */ Opcode::new("SBC", ZeroPageX, 4, Byte(Cpu::sbc_zero_page_x)),
+    /* $F6 */ Opcode::new("INC", ZeroPageX, 6, Byte(Cpu::inc_zero_page_x)),
+    /* $F7 */ Opcode::new("ISB", ZeroPageX, 6, Byte(Cpu::isb_zero_page_x)),
+    /* $F8 */ Opcode::new("SED", Implied, 2, NoOperand(Cpu::sed)),
+    /* $F9 */ Opcode::new("SBC", AbsoluteY, 4, Word(Cpu::sbc_absolute_y)),
+    /* $FA */ Opcode::new("NOP", Implied, 2, NoOperand(Cpu::nop_implied)),
+    /* $FB */ Opcode::new("ISB", AbsoluteY, 7, Word(Cpu::isb_absolute_y)),
+    /* $FC */ Opcode::new("NOP", AbsoluteX, 4, Word(Cpu::nop_absolute_x)),
+    /* $FD */ Opcode::new("SBC", AbsoluteX, 4, Word(Cpu::sbc_absolute_x)),
+    /* $FE */ Opcode::new("INC", AbsoluteX, 7, Word(Cpu::inc_absolute_x)),
+    /* $FF */ Opcode::new("ISB", AbsoluteX, 7, Word(Cpu::isb_absolute_x)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handlers_fetch_the_operands_their_mode_has() {
+        for (opcode, op) in OPCODES.iter().enumerate() {
+            let fetched = match op.handler {
+                NoOperand(_) | Control(_) => 0,
+                Byte(_) | Branch(_) => 1,
+                Word(_) | Jump(_) => 2,
+            };
+            assert_eq!(
+                fetched,
+                op.mode.operand_bytes(),
+                "${:02X} {}",
+                opcode,
+                op.mnemonic
+            );
+        }
+    }
+
+    #[test]
+    fn only_indexed_reads_pay_for_crossing_a_page() {
+        let penalized: Vec<usize> = (0..256)
+            .filter(|&i| OPCODES[i].page_cross_penalty())
+            .collect();
+        assert_eq!(
+            penalized,
+            [
+                0x11, 0x19, 0x1C, 0x1D, 0x31, 0x39, 0x3C, 0x3D, 0x51, 0x59, 0x5C, 0x5D, 0x71, 0x79,
+                0x7C, 0x7D, 0xB1, 0xB3, 0xB9, 0xBB, 0xBC, 0xBD, 0xBE, 0xBF, 0xD1, 0xD9, 0xDC, 0xDD,
+                0xF1, 0xF9, 0xFC, 0xFD
+            ]
+        );
+        // stores and read-modify-writes through the same modes don't
+        assert!(!OPCODES[0x9D].page_cross_penalty());
+        assert!(!OPCODES[0x1E].page_cross_penalty());
+    }
+}
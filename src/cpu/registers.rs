@@ -1,3 +1,6 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Registers {
     // points to the next instruction to be executed
     pub program_counter: u16,
@@ -143,3 +146,105 @@ impl Registers {
         self.get_nth_status_bit(7)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Flag = (
+        &'static str,
+        u8,
+        fn(&mut Registers),
+        fn(&mut Registers),
+        fn(&mut Registers) -> u8,
+    );
+
+    const FLAGS: [Flag; 7] = [
+        (
+            "carry",
+            0,
+            Registers::set_carry,
+            Registers::unset_carry,
+            Registers::get_carry,
+        ),
+        (
+            "zero",
+            1,
+            Registers::set_zero,
+            Registers::unset_zero,
+            Registers::get_zero,
+        ),
+        (
+            "interrupt disable",
+            2,
+            Registers::set_interrupt_disable,
+            Registers::unset_interrupt_disable,
+            Registers::get_interrupt_disable,
+        ),
+        (
+            "decimal",
+            3,
+            Registers::set_decimal,
+            Registers::unset_decimal,
+            Registers::get_decimal,
+        ),
+        (
+            "break",
+            4,
+            Registers::set_break,
+            Registers::unset_break,
+            Registers::get_break,
+        ),
+        (
+            "overflow",
+            6,
+            Registers::set_overflow,
+            Registers::unset_overflow,
+            Registers::get_overflow,
+        ),
+        (
+            "negative",
+            7,
+            Registers::set_neg,
+            Registers::unset_neg,
+            Registers::get_neg,
+        ),
+    ];
+
+    #[test]
+    fn each_flag_owns_its_bit() {
+        for (name, bit, set, unset, get) in FLAGS {
+            for others in [0x00, 0xFF] {
+                let mut registers = Registers::new();
+                registers.processor_status = others;
+
+                set(&mut registers);
+                assert_eq!(
+                    registers.processor_status,
+                    others | (1 << bit),
+                    "set {}",
+                    name
+                );
+                assert_eq!(get(&mut registers), 1 << bit, "get {}", name);
+
+                unset(&mut registers);
+                assert_eq!(
+                    registers.processor_status,
+                    others & !(1 << bit),
+                    "unset {}",
+                    name
+                );
+                assert_eq!(get(&mut registers), 0, "get {}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn power_on_status_has_only_interrupt_disable_and_bit_5() {
+        let mut registers = Registers::new();
+        assert_eq!(registers.processor_status, 0b0010_0100);
+        for (name, bit, _, _, get) in FLAGS {
+            assert_eq!(get(&mut registers) != 0, bit == 2, "{}", name);
+        }
+    }
+}
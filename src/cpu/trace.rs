@@ -0,0 +1,254 @@
+// Execution traces in the format of nestest.log (from Nintendulator), so a run of nestest.nes can be compared with
+// it line by line.
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+
+use super::memory::Memory;
+use super::opcodes::{self, AddressingMode::*, OPCODES};
+use super::registers::Registers;
+use super::TracedInstruction;
+use crate::ppu::PpuPosition;
+
+/// A read for the trace that changes nothing (see Memory::peek). PPU, APU and controller registers show as $FF
+/// like they do in nestest.log.
+fn peek(memory: &Memory, address: u16) -> u8 {
+    match memory.mirrored(address) {
+        0x2000..=0x401F => 0xFF,
+        address => memory.peek(address),
+    }
+}
+
+/// The opcode at `pc` and the two bytes after it, whether or not it uses them
+pub fn instruction_bytes(memory: &Memory, pc: u16) -> [u8; 3] {
+    [0, 1, 2].map(|offset| peek(memory, pc.wrapping_add(offset)))
+}
+
+/// The instruction at `pc` as nestest.log shows it, about to run with `registers`: the mnemonic, marked with a *
+/// if it's unofficial, its operand and where that points, e.g. ` LDA ($89),Y = 0300 @ 0300 = 89`
+pub fn disassemble(memory: &Memory, registers: &Registers, pc: u16) -> String {
+    let [opcode, low, high] = instruction_bytes(memory, pc);
+    let op = &OPCODES[opcode as usize];
+    let word = u16::from_le_bytes([low, high]);
+    let (x, y) = (registers.index_x, registers.index_y);
+
+    let operand = match op.mode {
+        Implied => String::new(),
+        Accumulator => "A".to_string(),
+        Immediate => format!("#${:02X}", low),
+        ZeroPage => format!("${:02X} = {:02X}", low, peek(memory, low as u16)),
+        ZeroPageX | ZeroPageY => {
+            let (register, index) = if op.mode == ZeroPageX {
+                ('X', x)
+            } else {
+                ('Y', y)
+            };
+            let address = low.wrapping_add(index);
+            format!(
+                "${:02X},{} @ {:02X} = {:02X}",
+                low,
+                register,
+                address,
+                peek(memory, address as u16)
+            )
+        }
+        // JMP and JSR don't read their target
+        Absolute if op.mnemonic == "JMP" || op.mnemonic == "JSR" => format!("${:04X}", word),
+        Absolute => format!("${:04X} = {:02X}", word, peek(memory, word)),
+        AbsoluteX | AbsoluteY => {
+            let (register, index) = if op.mode == AbsoluteX {
+                ('X', x)
+            } else {
+                ('Y', y)
+            };
+            let address = word.wrapping_add(index as u16);
+            format!(
+                "${:04X},{} @ {:04X} = {:02X}",
+                word,
+                register,
+                address,
+                peek(memory, address)
+            )
+        }
+        Indirect => {
+            // the high byte comes from the start of the same page, see Memory::fetch_indirect_quirk
+            let high_address = (word & 0xFF00) | (word as u8).wrapping_add(1) as u16;
+            let target = peek(memory, word) as u16 | (peek(memory, high_address) as u16) << 8;
+            format!("(${:04X}) = {:04X}", word, target)
+        }
+        IndirectX => {
+            let pointer = low.wrapping_add(x);
+            let address = peek(memory, pointer as u16) as u16
+                | (peek(memory, pointer.wrapping_add(1) as u16) as u16) << 8;
+            format!(
+                "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                low,
+                pointer,
+                address,
+                peek(memory, address)
+            )
+        }
+        IndirectY => {
+            let base = peek(memory, low as u16) as u16
+                | (peek(memory, low.wrapping_add(1) as u16) as u16) << 8;
+            let address = base.wrapping_add(y as u16);
+            format!(
+                "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                low,
+                base,
+                address,
+                peek(memory, address)
+            )
+        }
+        Relative => format!(
+            "${:04X}",
+            pc.wrapping_add(2).wrapping_add_signed(low as i8 as i16)
+        ),
+    };
+
+    let marker = if opcodes::is_unofficial(opcode) {
+        '*'
+    } else {
+        ' '
+    };
+    if operand.is_empty() {
+        format!("{}{}", marker, op.mnemonic)
+    } else {
+        format!("{}{} {}", marker, op.mnemonic, operand)
+    }
+}
+
+/// One line of nestest.log for an instruction. `ppu` is usually `traced.ppu`, see nestest_ppu_position for when
+/// the PPU isn't running.
+pub fn nestest_line(traced: &TracedInstruction, ppu: PpuPosition) -> String {
+    let len = OPCODES[traced.bytes[0] as usize].bytes() as usize;
+    let bytes: Vec<String> = traced.bytes[..len]
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    let registers = &traced.before;
+    format!(
+        "{:04X}  {:<8} {:<33}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        traced.pc,
+        bytes.join(" "),
+        traced.disassembly,
+        registers.accumulator,
+        registers.index_x,
+        registers.index_y,
+        registers.processor_status,
+        registers.stack_pointer,
+        ppu.scanline,
+        ppu.dot,
+        traced.start_cycle
+    )
+}
+
+/// Where nestest.log has the PPU for a CPU cycle: it counts from dot 0 of scanline 0 at power on, with the PPU
+/// running 3 dots per cycle and every frame a whole 262 lines
+pub fn nestest_ppu_position(cycle: usize) -> PpuPosition {
+    let dots = cycle * 3;
+    let line = dots / 341;
+    PpuPosition {
+        frame: line / 262,
+        scanline: (line % 262) as i32,
+        dot: dots % 341,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::ppu::PPU;
+
+    // Runs the instruction at `pc` with a trace hook and returns its nestest.log line
+    fn trace_line(setup: impl FnOnce(&mut Cpu), pc: u16, start_cycle: usize) -> String {
+        let ppu = Rc::new(RefCell::new(PPU::new(Rc::new(RefCell::new(vec![])))));
+        let mut cpu = Cpu::new(ppu);
+        setup(&mut cpu);
+        cpu.registers.program_counter = pc;
+        cpu.num_cycles = start_cycle;
+
+        let line = Rc::new(RefCell::new(String::new()));
+        let sink = Rc::clone(&line);
+        cpu.set_trace_hook(move |traced| {
+            *sink.borrow_mut() = nestest_line(traced, nestest_ppu_position(traced.start_cycle))
+        });
+        cpu.tick_ins();
+        let line = line.borrow().clone();
+        line
+    }
+
+    #[test]
+    fn matches_nestest_log_lines() {
+        let line = trace_line(
+            |cpu| {
+                cpu.memory.buffer[0xD922..0xD924].copy_from_slice(&[0xB1, 0x89]);
+                cpu.memory.buffer[0x89..0x8B].copy_from_slice(&[0x00, 0x03]);
+                cpu.memory.buffer[0x0300] = 0x89;
+                cpu.registers.index_x = 0x65;
+                cpu.registers.processor_status = 0x27;
+                cpu.registers.stack_pointer = 0xFB;
+            },
+            0xD922,
+            8760,
+        );
+        assert_eq!(
+            line,
+            "D922  B1 89     LDA ($89),Y = 0300 @ 0300 = 89  A:00 X:65 Y:00 P:27 SP:FB PPU: 77, 23 CYC:8760"
+        );
+
+        let line = trace_line(
+            |cpu| {
+                cpu.memory.buffer[0xE545..0xE547].copy_from_slice(&[0xA3, 0x40]);
+                cpu.memory.buffer[0x43..0x45].copy_from_slice(&[0x80, 0x05]);
+                cpu.memory.buffer[0x0580] = 0x55;
+                cpu.registers.index_x = 0x03;
+                cpu.registers.index_y = 0x77;
+                cpu.registers.processor_status = 0x67;
+                cpu.registers.stack_pointer = 0xFB;
+            },
+            0xE545,
+            15276,
+        );
+        assert_eq!(
+            line,
+            "E545  A3 40    *LAX ($40,X) @ 43 = 0580 = 55    A:00 X:03 Y:77 P:67 SP:FB PPU:134,134 CYC:15276"
+        );
+
+        let line = trace_line(
+            |cpu| {
+                cpu.memory.buffer[0xDBB5..0xDBB8].copy_from_slice(&[0x6C, 0xFF, 0x02]);
+                // the pointer's high byte wraps around to $0200
+                cpu.memory.buffer[0x02FF] = 0x00;
+                cpu.memory.buffer[0x0200] = 0x03;
+                cpu.registers.accumulator = 0x60;
+                cpu.registers.index_x = 0x07;
+                cpu.registers.processor_status = 0x65;
+                cpu.registers.stack_pointer = 0xF9;
+            },
+            0xDBB5,
+            9615,
+        );
+        assert_eq!(
+            line,
+            "DBB5  6C FF 02  JMP ($02FF) = 0300              A:60 X:07 Y:00 P:65 SP:F9 PPU: 84,201 CYC:9615"
+        );
+    }
+
+    #[test]
+    fn io_registers_show_as_ff() {
+        let ppu = Rc::new(RefCell::new(PPU::new(Rc::new(RefCell::new(vec![])))));
+        let mut cpu = Cpu::new(ppu);
+        cpu.memory.buffer[0x8000..0x8003].copy_from_slice(&[0x8D, 0x15, 0x40]);
+        cpu.memory.buffer[0x8003..0x8005].copy_from_slice(&[0x4A, 0xEA]);
+        let registers = cpu.registers;
+
+        assert_eq!(
+            disassemble(&cpu.memory, &registers, 0x8000),
+            " STA $4015 = FF"
+        );
+        assert_eq!(disassemble(&cpu.memory, &registers, 0x8003), " LSR A");
+        assert_eq!(disassemble(&cpu.memory, &registers, 0x8004), " NOP");
+    }
+}
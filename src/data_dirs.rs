@@ -0,0 +1,76 @@
+// Where nemsys keeps what it writes: battery saves and save states, screenshots, logs and config.
+// Follows XDG (data under $XDG_DATA_HOME/nemsys, config under $XDG_CONFIG_HOME/nemsys) unless running
+// portable, in which case everything goes in a nemsys-data directory beside the executable.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+pub struct DataDirs {
+    data: PathBuf,
+    config: PathBuf,
+}
+
+impl DataDirs {
+    pub fn new(portable: bool) -> Self {
+        if portable {
+            let beside_exe = env::current_exe()
+                .ok()
+                .and_then(|exe| exe.parent().map(Path::to_path_buf))
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("nemsys-data");
+            return Self::in_dir(beside_exe);
+        }
+
+        let home = env::var_os("HOME").map(PathBuf::from);
+        let xdg = |var: &str, fallback: &str| {
+            env::var_os(var)
+                .map(PathBuf::from)
+                .or_else(|| home.as_ref().map(|home| home.join(fallback)))
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("nemsys")
+        };
+        Self {
+            data: xdg("XDG_DATA_HOME", ".local/share"),
+            config: xdg("XDG_CONFIG_HOME", ".config"),
+        }
+    }
+
+    /// Everything under `root`, config included
+    pub fn in_dir(root: PathBuf) -> Self {
+        Self {
+            config: root.join("config"),
+            data: root,
+        }
+    }
+
+    /// Battery saves and save states, which go through the same storage (see Emulator::set_save_dir)
+    pub fn saves(&self) -> Result<PathBuf> {
+        create(self.data.join("saves"))
+    }
+
+    pub fn screenshots(&self) -> Result<PathBuf> {
+        create(self.data.join("screenshots"))
+    }
+
+    pub fn logs(&self) -> Result<PathBuf> {
+        create(self.data.join("logs"))
+    }
+
+    pub fn config(&self) -> Result<PathBuf> {
+        create(self.config.clone())
+    }
+
+    /// Scratch files of the test harnesses, like single-step progress
+    pub fn test_runs(&self) -> Result<PathBuf> {
+        create(self.data.join("test-runs"))
+    }
+}
+
+fn create(dir: PathBuf) -> Result<PathBuf> {
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
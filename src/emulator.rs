@@ -0,0 +1,1025 @@
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    cheats::Cheat,
+    cpu::memory::VsSystemInputs,
+    cpu::Cpu,
+    game_genie::{GameGenie, GameGenieCode},
+    mappers::{Cartridge, CartridgeId, ConsoleType, SharedMapper},
+    movie::Movie,
+    ppu::PPU,
+    rewind::RewindBuffer,
+    sav,
+    save_state::{self, SaveState},
+    storage::{FileStorage, StorageBackend},
+    utils::checksum,
+};
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+pub type ScanlineHook = Box<dyn FnMut(&mut PPU)>;
+
+// the first dot of a scanline's hblank, where scanline hooks run
+const HBLANK_DOT: usize = 257;
+
+/// Ties the CPU and PPU together and steps them in lockstep, one scanline at a time
+pub struct Emulator {
+    pub cpu: Cpu,
+    pub ppu: Rc<RefCell<PPU>>,
+    pub framebuffer: Rc<RefCell<Vec<u32>>>,
+    pub frame_count: usize,
+    /// Frames in which the game never read the controllers (lag frames), see `lagged`
+    pub lag_frame_count: usize,
+    last_frame_lagged: bool,
+
+    // PPU dots the CPU clock is ahead by at power-on (0-3)
+    alignment: usize,
+    // dots of it the PPU has still to be held back by when stepping by dots, see set_alignment
+    alignment_pending: usize,
+    // sync the CPU and PPU once per scanline instead of after every instruction
+    fast_stepping: bool,
+    // the game loaded by load_rom or load_rom_bytes, which save states are checked against
+    cartridge: Option<CartridgeId>,
+    // storage key of the battery-backed PRG-RAM, for cartridges that have a battery
+    save_key: Option<String>,
+    // storage key of the save state slot, for ROMs loaded from a file
+    state_key: Option<String>,
+    // where saves are kept, see set_storage
+    storage: Option<Box<dyn StorageBackend>>,
+    // without one, files next to the loaded ROM
+    rom_dir_storage: FileStorage,
+    game_genie_codes: Vec<GameGenieCode>,
+    cheats: Vec<Cheat>,
+    scanline_hooks: Vec<(i32, ScanlineHook)>,
+    // the movie feeding controller 1 and the frame of it being played, see play_movie
+    movie: Option<(Movie, usize)>,
+    rewind: Option<RewindBuffer>,
+}
+
+impl Emulator {
+    pub fn new(framebuffer: Rc<RefCell<Vec<u32>>>) -> Self {
+        let ppu = Rc::new(RefCell::new(PPU::new(Rc::clone(&framebuffer))));
+        let cpu = Cpu::new(Rc::clone(&ppu));
+
+        Self {
+            cpu,
+            ppu,
+            framebuffer,
+            frame_count: 0,
+            lag_frame_count: 0,
+            last_frame_lagged: false,
+            alignment: 0,
+            alignment_pending: 0,
+            fast_stepping: false,
+            cartridge: None,
+            save_key: None,
+            state_key: None,
+            storage: None,
+            rom_dir_storage: FileStorage::new(PathBuf::from(".")),
+            game_genie_codes: vec![],
+            cheats: vec![],
+            scanline_hooks: vec![],
+            movie: None,
+            rewind: None,
+        }
+    }
+
+    /// An emulator with its own framebuffer, for runs that never display anything
+    pub fn headless() -> Self {
+        Self::new(Rc::new(RefCell::new(vec![0; SCREEN_WIDTH * SCREEN_HEIGHT])))
+    }
+
+    /// Keep `.sav` and `.state` files as `<rom name>.sav`/`.state` in `dir` instead of next to the ROM. Applies to
+    /// ROMs loaded afterwards.
+    pub fn set_save_dir(&mut self, dir: PathBuf) {
+        self.set_storage(Box::new(FileStorage::new(dir)));
+    }
+
+    /// Keep `.sav` and `.state` files somewhere other than the local filesystem. Applies to ROMs loaded afterwards.
+    pub fn set_storage(&mut self, storage: Box<dyn StorageBackend>) {
+        self.storage = Some(storage);
+    }
+
+    /// Put a Game Genie with these codes between the CPU and the cartridge. Applies to ROMs loaded afterwards.
+    pub fn set_game_genie_codes(&mut self, codes: Vec<GameGenieCode>) {
+        self.game_genie_codes = codes;
+    }
+
+    /// Poke these at the end of every frame, the enabled ones anyway (see `toggle_cheat`)
+    pub fn set_cheats(&mut self, cheats: Vec<Cheat>) {
+        self.cheats = cheats;
+    }
+
+    /// Switch cheat `index` on or off, returning it, or None if there's no such cheat
+    pub fn toggle_cheat(&mut self, index: usize) -> Option<Cheat> {
+        let cheat = self.cheats.get_mut(index)?;
+        cheat.enabled = !cheat.enabled;
+        Some(*cheat)
+    }
+
+    /// Load an iNES file. A cartridge with a battery gets its PRG-RAM from its `.sav` file (see `set_save_dir`),
+    /// if there is one, and `save_battery_ram` writes it back there.
+    pub fn load_rom(&mut self, path: &str) -> Result<()> {
+        let cartridge = Cartridge::from_ines_rom(path)?;
+        let battery = cartridge.battery;
+        self.install_cartridge(cartridge)?;
+
+        let rom = Path::new(path);
+        self.rom_dir_storage.dir = rom.parent().unwrap_or(Path::new(".")).to_path_buf();
+        self.state_key = Some(save_state::key_for(rom));
+        if battery {
+            let key = sav::key_for(rom);
+            self.load_battery_ram(&key)?;
+            self.save_key = Some(key);
+        }
+        Ok(())
+    }
+
+    /// Load an iNES image from memory. It has no `.sav` file, so `save_battery_ram` does nothing for it.
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<()> {
+        self.install_cartridge(Cartridge::from_ines_bytes(rom)?)
+    }
+
+    /// What loading any ROM does: build its mapper, behind the Game Genie if there are codes, and insert it.
+    /// The previous game's `.sav`, state slot and rewind snapshots are forgotten, the loaders set up the new one's.
+    fn install_cartridge(&mut self, cartridge: Cartridge) -> Result<()> {
+        let console = cartridge.console;
+        let id = cartridge.id();
+        let mut mapper = cartridge.build_mapper()?;
+        if !self.game_genie_codes.is_empty() {
+            mapper = Box::new(GameGenie::new(mapper, self.game_genie_codes.clone()));
+        }
+        self.set_console(console);
+        self.insert_cartridge(Rc::new(RefCell::new(mapper)));
+        self.cartridge = Some(id);
+        self.save_key = None;
+        self.state_key = None;
+        self.clear_rewind();
+        Ok(())
+    }
+
+    /// Vs. System boards get their coin slots and DIP switches on $4016/$4017
+    fn set_console(&mut self, console: ConsoleType) {
+        self.cpu.memory.vs_system = (console == ConsoleType::VsSystem).then(VsSystemInputs::new);
+    }
+
+    fn storage(&self) -> &dyn StorageBackend {
+        self.storage.as_deref().unwrap_or(&self.rom_dir_storage)
+    }
+
+    fn load_battery_ram(&mut self, key: &str) -> Result<()> {
+        let Some(save) = self.storage().load(key)? else {
+            return Ok(());
+        };
+
+        let mapper = self.cpu.memory.mapper.as_ref().unwrap();
+        let mut mapper = mapper.borrow_mut();
+        let Some(ram) = mapper.prg_ram_mut() else {
+            return Ok(());
+        };
+        if save.len() != ram.len() {
+            bail!(
+                "{} is {} bytes, the cartridge has {} bytes of PRG-RAM",
+                self.storage().describe(key),
+                save.len(),
+                ram.len()
+            );
+        }
+        ram.copy_from_slice(&save);
+        Ok(())
+    }
+
+    /// Put battery-backed PRG-RAM back the way the ROM's `.sav` file has it, throwing away what was written since.
+    /// Does nothing for cartridges without a battery or a save.
+    pub fn reload_battery_ram(&mut self) -> Result<()> {
+        match self.save_key.clone() {
+            Some(key) => self.load_battery_ram(&key),
+            None => Ok(()),
+        }
+    }
+
+    /// Write battery-backed PRG-RAM to the ROM's `.sav` file. Does nothing for cartridges without a battery.
+    pub fn save_battery_ram(&self) -> Result<()> {
+        let (Some(key), Some(mapper)) = (&self.save_key, &self.cpu.memory.mapper) else {
+            return Ok(());
+        };
+        if let Some(ram) = mapper.borrow().prg_ram() {
+            self.storage().store(key, ram)?;
+        }
+        Ok(())
+    }
+
+    /// Where `save_battery_ram` writes to, if the loaded cartridge has a battery
+    pub fn save_location(&self) -> Option<String> {
+        let key = self.save_key.as_ref()?;
+        Some(self.storage().describe(key))
+    }
+
+    /// Put the mapper on both the CPU and PPU buses and take the reset vector from it
+    pub fn insert_cartridge(&mut self, mapper: SharedMapper) {
+        self.ppu.borrow_mut().vram.mapper = Some(Rc::clone(&mapper));
+        self.cpu.memory.mapper = Some(mapper);
+        self.cpu.init_pc();
+        self.cartridge = None;
+    }
+
+    /// Run the CPU a scanline's worth of cycles at a time, then the PPU for that whole scanline.
+    /// Faster than the default of interleaving them after every instruction, but register writes only take
+    /// effect between scanlines, so mid-scanline scroll splits and the like come out wrong.
+    pub fn set_fast_stepping(&mut self, fast: bool) {
+        self.fast_stepping = fast;
+    }
+
+    /// Pick which of the power-on CPU/PPU clock alignments (0-3 PPU dots) to emulate, i.e. which dot of a CPU
+    /// cycle the PPU starts on. Stepping by dots, the PPU starts that many dots behind the CPU, from now and
+    /// after every `power_cycle`. With fast stepping (see `set_fast_stepping`) it moves where CPU instruction
+    /// boundaries fall relative to the end of each scanline.
+    pub fn set_alignment(&mut self, alignment: u8) {
+        assert!(
+            alignment < 4,
+            "CPU/PPU alignment must be 0-3, got {}",
+            alignment
+        );
+        self.alignment = alignment as usize;
+        self.alignment_pending = self.alignment;
+    }
+
+    /// Call `hook` every frame when the PPU reaches the hblank of `scanline` (-1 to 260), with the PPU available
+    /// for register writes. Lets raster effects be prototyped without a mapper IRQ.
+    /// Stepping by dots, it runs at dot 257, in the middle of whichever instruction the PPU got there during,
+    /// so a scroll write lands before the horizontal scroll is copied for the next line. With fast stepping
+    /// (see `set_fast_stepping`) it runs once the whole scanline is done.
+    pub fn add_scanline_hook(&mut self, scanline: i32, hook: impl FnMut(&mut PPU) + 'static) {
+        self.scanline_hooks.push((scanline, Box::new(hook)));
+    }
+
+    /// Hold the buttons of `movie`'s frames on controller 1, one per frame starting with the next one. The keyboard
+    /// still reaches the controller but is overridden every frame. Movies are recorded from power-on, so this
+    /// usually follows a `power_cycle`. There's no rewinding while it plays, see `rewind`.
+    pub fn play_movie(&mut self, movie: Movie) {
+        self.clear_rewind();
+        self.movie = Some((movie, 0));
+        self.next_movie_frame();
+    }
+
+    /// Let go of the movie's buttons, whether it's done or not
+    pub fn stop_movie(&mut self) {
+        if self.movie.take().is_some() {
+            self.cpu.memory.input.set_buttons(0);
+        }
+    }
+
+    /// Whether a movie is still being played, see `play_movie`
+    pub fn playing_movie(&self) -> bool {
+        self.movie.is_some()
+    }
+
+    fn next_movie_frame(&mut self) {
+        let Some((movie, frame)) = &mut self.movie else {
+            return;
+        };
+        match movie.frames.get(*frame) {
+            Some(&buttons) => {
+                self.cpu.memory.input.set_buttons(buttons);
+                *frame += 1;
+            }
+            None => self.stop_movie(),
+        }
+    }
+
+    /// Snapshot the machine every `interval` frames, keeping the last `capacity` snapshots for `rewind`
+    pub fn enable_rewind(&mut self, interval: usize, capacity: usize) {
+        self.rewind = Some(RewindBuffer::new(interval, capacity));
+    }
+
+    /// Go back to the last rewind snapshot and forget it, so the next call goes further back. False once there
+    /// are none left (or rewinding isn't enabled), leaving the machine where it is.
+    /// Refused while a movie plays: its frame isn't part of the snapshots, so it would go on from the wrong one.
+    pub fn rewind(&mut self) -> Result<bool> {
+        if self.playing_movie() {
+            bail!("can't rewind while a movie is playing");
+        }
+        let Some(state) = self.rewind.as_mut().and_then(RewindBuffer::pop) else {
+            return Ok(false);
+        };
+        self.load_state(&SaveState::from_bytes(&state)?)?;
+        Ok(true)
+    }
+
+    /// Snapshots `rewind` can still go back through, and the bytes they take up
+    pub fn rewind_depth(&self) -> (usize, usize) {
+        self.rewind
+            .as_ref()
+            .map_or((0, 0), |rewind| (rewind.len(), rewind.size()))
+    }
+
+    // snapshots from before a power cycle or of another cartridge would bring back that machine's PRG-RAM
+    fn clear_rewind(&mut self) {
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+    }
+
+    fn record_rewind_snapshot(&mut self) {
+        let Some(interval) = self.rewind.as_ref().map(|rewind| rewind.interval) else {
+            return;
+        };
+        if self.playing_movie() {
+            return;
+        }
+        if self.frame_count.is_multiple_of(interval) {
+            let state = self
+                .save_state()
+                .to_bytes()
+                .expect("save states always serialize");
+            self.rewind.as_mut().unwrap().push(state);
+        }
+    }
+
+    /// Write a byte through the CPU bus exactly as a store instruction would, register side effects included
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.cpu.memory.store_absolute(address, value);
+    }
+
+    // what `poke` does, for each enabled cheat
+    fn apply_cheats(&mut self) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            self.cpu.memory.store_absolute(cheat.address, cheat.value);
+        }
+    }
+
+    /// The PC of a `JMP` to itself that nothing can break out of: NMIs are off in PPUCTRL and no unmasked IRQ is pending.
+    /// Test ROMs commonly end this way once they're done.
+    pub fn stuck_at(&mut self) -> Option<u16> {
+        if !self.cpu.at_jmp_to_self() || self.ppu.borrow().generate_nmi {
+            return None;
+        }
+        if self.cpu.irq_asserted() && self.cpu.registers.get_interrupt_disable() == 0 {
+            return None;
+        }
+        Some(self.cpu.registers.program_counter)
+    }
+
+    /// The reset button: the CPU runs its RESET sequence and silences the APU (see Cpu::reset), the PPU clears
+    /// its control registers and ignores some writes for a frame (see PPU::reset), memory contents are left as they were
+    pub fn reset(&mut self) {
+        self.cpu.num_cycles += self.cpu.reset() as usize;
+        self.ppu.borrow_mut().reset();
+    }
+
+    /// Switch the console off and on again with the same cartridge: the CPU, APU, PPU and the cartridge's registers
+    /// (see Cpu::power_on, PPU::power_on and Mapper::power_on) start over, internal RAM comes up zeroed.
+    /// PRG-RAM keeps what was in it. Rewind snapshots from before are dropped.
+    pub fn power_cycle(&mut self) {
+        self.clear_rewind();
+        self.alignment_pending = self.alignment;
+        if let Some(mapper) = &self.cpu.memory.mapper {
+            mapper.borrow_mut().power_on();
+        }
+        self.ppu.borrow_mut().power_on();
+        self.cpu.num_cycles += self.cpu.power_on(0) as usize;
+    }
+
+    /// Runs the CPU and PPU until the PPU finishes its current scanline.
+    /// Returns true when that scanline started vblank, i.e. a frame is ready to be displayed.
+    pub fn step_scanline(&mut self) -> bool {
+        let scanline = self.ppu.borrow().curr_scanline;
+        if self.fast_stepping {
+            // a scanline is 113 2/3 CPU cycles: catch the CPU up to the end of this one and let the remainder carry over
+            let ppu_dots = self.ppu.borrow().num_cycles + 341 + self.alignment;
+            self.cpu
+                .tick((ppu_dots / 3).saturating_sub(self.cpu.num_cycles));
+            self.ppu.borrow_mut().tick(); // runs ppu for 1 scanline
+            if self.ppu.borrow_mut().take_nmi() {
+                self.cpu.num_cycles += self.cpu.generate_nmi() as usize;
+            }
+            self.run_scanline_hooks(scanline);
+        } else {
+            self.run_scanline_by_dots(scanline);
+        }
+
+        if scanline != 241 {
+            return false;
+        }
+
+        self.frame_count += 1;
+        self.apply_cheats();
+        self.next_movie_frame();
+        self.last_frame_lagged = !std::mem::take(&mut self.cpu.memory.input_polled);
+        if self.last_frame_lagged {
+            self.lag_frame_count += 1;
+        }
+        self.record_rewind_snapshot();
+        true
+    }
+
+    /// Whether the frame that just finished was a lag frame: no read of $4016 or $4017 since the one before,
+    /// so whatever was held on the controllers during it had no effect
+    pub fn lagged(&self) -> bool {
+        self.last_frame_lagged
+    }
+
+    /// One CPU instruction at a time, each followed by the 3 PPU dots per cycle it took, so register writes
+    /// reach the PPU within an instruction of the dot they happen on.
+    /// An NMI the PPU raised while catching up with one instruction is taken after the next, the way the CPU
+    /// polls for it before an instruction's last cycle. A $2002 read in between can still suppress it.
+    /// The first dots after power-on are held back by the alignment (see `set_alignment`).
+    /// The scanline's hooks run when the PPU gets to its hblank, partway through an instruction's dots.
+    fn run_scanline_by_dots(&mut self, scanline: i32) {
+        let mut hooks_run = false;
+        while self.ppu.borrow().curr_scanline == scanline {
+            let start = self.cpu.num_cycles;
+            self.cpu.tick_ins();
+            if self.ppu.borrow_mut().take_nmi() {
+                self.cpu.num_cycles += self.cpu.generate_nmi() as usize;
+            }
+            let dots = (self.cpu.num_cycles - start) * 3;
+            let held_back = dots.min(self.alignment_pending);
+            self.alignment_pending -= held_back;
+            let mut dots = dots - held_back;
+
+            let to_hblank = HBLANK_DOT.saturating_sub(self.ppu.borrow().position().dot);
+            if !hooks_run && to_hblank <= dots {
+                self.ppu.borrow_mut().step(to_hblank);
+                self.run_scanline_hooks(scanline);
+                hooks_run = true;
+                dots -= to_hblank;
+            }
+            self.ppu.borrow_mut().step(dots);
+        }
+    }
+
+    fn run_scanline_hooks(&mut self, scanline: i32) {
+        for (hook_scanline, hook) in self.scanline_hooks.iter_mut() {
+            if *hook_scanline == scanline {
+                hook(&mut self.ppu.borrow_mut());
+            }
+        }
+    }
+
+    /// Checksum of the CPU registers and cycle count, internal RAM and the PPU's state (see PPU::state_bytes).
+    /// Logging it every frame from two builds shows the first frame where they stopped agreeing.
+    pub fn state_checksum(&self) -> u64 {
+        let registers = &self.cpu.registers;
+        let mut bytes = vec![
+            registers.accumulator,
+            registers.index_x,
+            registers.index_y,
+            registers.stack_pointer,
+            registers.processor_status,
+        ];
+        bytes.extend(registers.program_counter.to_le_bytes());
+        bytes.extend((self.cpu.num_cycles as u64).to_le_bytes());
+        bytes.extend(&self.cpu.memory.buffer[..0x800]);
+        bytes.extend(self.ppu.borrow().state_bytes());
+        checksum(&bytes)
+    }
+
+    /// The whole machine, to be put back with `load_state`. Settings and hooks aren't part of it.
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            cpu: self.cpu.save_state(),
+            ppu: self.ppu.borrow().save_state(),
+            cartridge: self.cartridge,
+            mapper: self
+                .cpu
+                .memory
+                .mapper
+                .as_ref()
+                .map(|mapper| mapper.borrow().save_state()),
+            frame_count: self.frame_count,
+            lag_frame_count: self.lag_frame_count,
+            last_frame_lagged: self.last_frame_lagged,
+        }
+    }
+
+    /// Go back to where `save_state` was called, with the same cartridge inserted. A state that doesn't fit
+    /// leaves the machine as it was.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<()> {
+        let current = self.save_state();
+        if let Err(err) = self.restore(state) {
+            self.restore(&current)
+                .expect("the machine's own state always fits");
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Keep `save_state` as the ROM's `<rom name>.state` in the same storage as battery saves (see `set_storage`),
+    /// returning where it went
+    pub fn store_state(&self) -> Result<String> {
+        let Some(key) = &self.state_key else {
+            bail!("only ROMs loaded from a file have a state slot");
+        };
+        self.storage().store(key, &self.save_state().to_bytes()?)?;
+        Ok(self.storage().describe(key))
+    }
+
+    /// `load_state` with what `store_state` kept, returning where it came from
+    pub fn restore_stored_state(&mut self) -> Result<String> {
+        let Some(key) = &self.state_key else {
+            bail!("only ROMs loaded from a file have a state slot");
+        };
+        let location = self.storage().describe(key);
+        let Some(bytes) = self.storage().load(key)? else {
+            bail!("no state saved at {}", location);
+        };
+        let state =
+            SaveState::from_bytes(&bytes).map_err(|err| anyhow!("{}: {}", location, err))?;
+        self.load_state(&state)?;
+        Ok(location)
+    }
+
+    fn restore(&mut self, state: &SaveState) -> Result<()> {
+        if let (Some(saved), Some(inserted)) = (state.cartridge, self.cartridge) {
+            if saved.mapper_number != inserted.mapper_number {
+                bail!(
+                    "save state was made on mapper {}, the cartridge is mapper {}",
+                    saved.mapper_number,
+                    inserted.mapper_number
+                );
+            }
+            if saved.rom_checksum != inserted.rom_checksum {
+                bail!("save state was made with a different game");
+            }
+        }
+        match (&self.cpu.memory.mapper, &state.mapper) {
+            (Some(mapper), Some(saved)) => mapper.borrow_mut().load_state(saved)?,
+            (None, None) => {}
+            (Some(_), None) => bail!("save state was made without a cartridge"),
+            (None, Some(_)) => bail!("save state needs a cartridge"),
+        }
+        self.ppu.borrow_mut().load_state(&state.ppu)?;
+        self.cpu.load_state(&state.cpu)?;
+        self.frame_count = state.frame_count;
+        self.lag_frame_count = state.lag_frame_count;
+        self.last_frame_lagged = state.last_frame_lagged;
+        Ok(())
+    }
+
+    pub fn run_frame(&mut self) {
+        while !self.step_scanline() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, env, fs};
+
+    use super::*;
+    use crate::mappers::test_image;
+
+    // NROM with `program` at $8000, which is also the reset vector
+    fn nrom(flags6: u8, program: &[u8]) -> Vec<u8> {
+        let mut image = test_image(flags6 >> 4, 1, 1);
+        image[6] |= flags6 & 0x0F;
+        let prg = &mut image[16..16 + 0x4000];
+        prg.fill(0xEA);
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        image
+    }
+
+    #[test]
+    fn battery_ram_survives_reload() {
+        let image = nrom(0b10, &[]);
+
+        let dir = env::temp_dir().join(format!("nemsys-battery-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.nes");
+        fs::write(&rom, &image).unwrap();
+
+        let mut emulator = Emulator::headless();
+        emulator.load_rom(rom.to_str().unwrap()).unwrap();
+        emulator.poke(0x6123, 0x42);
+        emulator.save_battery_ram().unwrap();
+
+        let mut reloaded = Emulator::headless();
+        reloaded.load_rom(rom.to_str().unwrap()).unwrap();
+        let value = reloaded.cpu.memory.fetch_absolute(0x6123);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(value, 0x42);
+    }
+
+    // keeps saves in a map the test can look into
+    struct MemoryStorage(Rc<RefCell<HashMap<String, Vec<u8>>>>);
+
+    impl StorageBackend for MemoryStorage {
+        fn load(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            Ok(self.0.borrow().get(key).cloned())
+        }
+
+        fn store(&self, key: &str, data: &[u8]) -> Result<()> {
+            self.0.borrow_mut().insert(key.to_string(), data.to_vec());
+            Ok(())
+        }
+
+        fn describe(&self, key: &str) -> String {
+            format!("memory:{}", key)
+        }
+    }
+
+    #[test]
+    fn battery_ram_goes_through_the_storage_backend() {
+        let dir = env::temp_dir().join(format!("nemsys-storage-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.nes");
+        fs::write(&rom, nrom(0b10, &[])).unwrap();
+
+        let saves = Rc::new(RefCell::new(HashMap::new()));
+        saves
+            .borrow_mut()
+            .insert("game.sav".to_string(), vec![0x42; 0x2000]);
+        let mut emulator = Emulator::headless();
+        emulator.set_storage(Box::new(MemoryStorage(Rc::clone(&saves))));
+        let loaded = emulator.load_rom(rom.to_str().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        loaded.unwrap();
+        assert_eq!(emulator.cpu.memory.fetch_absolute(0x6123), 0x42);
+
+        emulator.poke(0x6123, 7);
+        emulator.save_battery_ram().unwrap();
+        assert_eq!(saves.borrow()["game.sav"][0x123], 7);
+        assert_eq!(emulator.save_location().as_deref(), Some("memory:game.sav"));
+
+        // writes since then (an attract mode demo's) are thrown away, a power cycle keeps them
+        emulator.poke(0x6123, 8);
+        emulator.power_cycle();
+        assert_eq!(emulator.cpu.memory.fetch_absolute(0x6123), 8);
+        emulator.reload_battery_ram().unwrap();
+        assert_eq!(emulator.cpu.memory.fetch_absolute(0x6123), 7);
+
+        // a ROM from memory has no save, and mustn't write over the last one's
+        emulator.load_rom_bytes(&nrom(0b10, &[])).unwrap();
+        emulator.poke(0x6123, 0x99);
+        emulator.save_battery_ram().unwrap();
+        assert_eq!(saves.borrow()["game.sav"][0x123], 7);
+        assert_eq!(emulator.save_location(), None);
+    }
+
+    #[test]
+    fn states_go_through_the_storage_backend() {
+        let dir = env::temp_dir().join(format!("nemsys-state-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.nes");
+        fs::write(&rom, nrom(0, &[])).unwrap();
+
+        let saves = Rc::new(RefCell::new(HashMap::new()));
+        let mut emulator = Emulator::headless();
+        emulator.set_storage(Box::new(MemoryStorage(Rc::clone(&saves))));
+        let loaded = emulator.load_rom(rom.to_str().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        loaded.unwrap();
+
+        assert!(emulator.restore_stored_state().is_err());
+        emulator.poke(0x0010, 0x42);
+        assert_eq!(emulator.store_state().unwrap(), "memory:game.state");
+        assert!(saves.borrow().contains_key("game.state"));
+        emulator.poke(0x0010, 0);
+        emulator.restore_stored_state().unwrap();
+        assert_eq!(emulator.cpu.memory.fetch_absolute(0x0010), 0x42);
+
+        // a ROM from memory has no slot
+        emulator.load_rom_bytes(&nrom(0, &[])).unwrap();
+        assert!(emulator.store_state().is_err());
+    }
+
+    #[test]
+    fn game_genie_codes_apply_to_roms_from_memory() {
+        let mut emulator = Emulator::headless();
+        // $91D9 reads $AD instead
+        emulator.set_game_genie_codes(vec![GameGenieCode::parse("SXIOPO").unwrap()]);
+        emulator.load_rom_bytes(&nrom(0, &[])).unwrap();
+        assert_eq!(emulator.cpu.memory.fetch_absolute(0x91D9), 0xAD);
+        assert_eq!(emulator.cpu.memory.fetch_absolute(0x91DA), 0xEA);
+    }
+
+    #[test]
+    fn loading_a_state_replays_the_same_frames() {
+        // INC $10 / LDA $10 / STA $2005 / STA $6000 / JMP $8000: RAM, PPU and PRG-RAM all keep changing
+        let program = [
+            0xE6, 0x10, 0xA5, 0x10, 0x8D, 0x05, 0x20, 0x8D, 0x00, 0x60, 0x4C, 0x00, 0x80,
+        ];
+        let mut emulator = Emulator::headless();
+        emulator.load_rom_bytes(&nrom(0, &program)).unwrap();
+        emulator.run_frame();
+
+        let state = SaveState::from_bytes(&emulator.save_state().to_bytes().unwrap()).unwrap();
+        let checksums = |emulator: &mut Emulator| -> Vec<u64> {
+            (0..3)
+                .map(|_| {
+                    emulator.run_frame();
+                    emulator.state_checksum()
+                })
+                .collect()
+        };
+        let first = checksums(&mut emulator);
+
+        // into the same emulator and into a fresh one with the cartridge inserted
+        emulator.load_state(&state).unwrap();
+        assert_eq!(checksums(&mut emulator), first);
+        let mut fresh = Emulator::headless();
+        fresh.load_rom_bytes(&nrom(0, &program)).unwrap();
+        fresh.load_state(&state).unwrap();
+        assert_eq!(checksums(&mut fresh), first);
+        assert_eq!(fresh.frame_count, 4);
+        assert_eq!(
+            fresh.cpu.memory.fetch_absolute(0x6000),
+            fresh.cpu.memory.buffer[0x10]
+        );
+    }
+
+    #[test]
+    fn states_that_dont_fit_are_refused() {
+        let state = Emulator::headless().save_state();
+        let mut emulator = Emulator::headless();
+        emulator.load_rom_bytes(&nrom(0, &[])).unwrap();
+        emulator.run_frame();
+        let before = emulator.state_checksum();
+
+        assert!(emulator.load_state(&state).is_err());
+        assert_eq!(emulator.state_checksum(), before);
+    }
+
+    #[test]
+    fn states_only_load_into_their_game() {
+        // CNROM and UxROM both keep one bank register, so only the mapper number tells their states apart
+        let mut cnrom = Emulator::headless();
+        cnrom.load_rom_bytes(&nrom(0x30, &[])).unwrap();
+        let state = cnrom.save_state();
+
+        let mut uxrom = Emulator::headless();
+        uxrom.load_rom_bytes(&nrom(0x20, &[])).unwrap();
+        let err = uxrom.load_state(&state).err().unwrap();
+        assert!(err.to_string().contains("mapper 3"), "{}", err);
+
+        let mut other_game = Emulator::headless();
+        other_game.load_rom_bytes(&nrom(0x30, &[0xE8])).unwrap();
+        let err = other_game.load_state(&state).err().unwrap();
+        assert!(err.to_string().contains("different game"), "{}", err);
+    }
+
+    #[test]
+    fn writes_to_nrom_rom_are_noted_and_dropped() {
+        let mut emulator = Emulator::headless();
+        emulator.load_rom_bytes(&nrom(0, &[])).unwrap();
+        let memory = &mut emulator.cpu.memory;
+
+        memory.store_absolute(0xC005, 0x12);
+        assert_eq!(memory.ignored_write, None);
+
+        memory.warn_ignored_writes = true;
+        memory.store_absolute(0x6000, 0x34);
+        assert_eq!(memory.ignored_write, None);
+        memory.store_absolute(0xC005, 0x12);
+        assert_eq!(memory.ignored_write, Some((0xC005, 0x12)));
+        // the 16 KB of PRG-ROM is mirrored at $8000 and $C000, neither copy changes
+        assert_eq!(memory.fetch_absolute(0x8005), 0xEA);
+        assert_eq!(memory.fetch_absolute(0xC005), 0xEA);
+    }
+
+    #[test]
+    fn pokes_reach_ram_and_registers() {
+        // UxROM with $EA in bank 0 and $11 in bank 1
+        let mut image = test_image(2, 2, 0);
+        image[16..16 + 0x4000].fill(0xEA);
+        image[16 + 0x4000..].fill(0x11);
+        let mut emulator = Emulator::headless();
+        emulator.load_rom_bytes(&image).unwrap();
+
+        // RAM through its mirrors
+        emulator.poke(0x0812, 0x42);
+        assert_eq!(emulator.cpu.memory.peek(0x0012), 0x42);
+        // the PPU's registers
+        emulator.poke(0x2000, 0x80);
+        assert!(emulator.ppu.borrow().generate_nmi);
+        // and the mapper's
+        assert_eq!(emulator.cpu.memory.peek(0x8000), 0xEA);
+        emulator.poke(0x8000, 1);
+        assert_eq!(emulator.cpu.memory.peek(0x8000), 0x11);
+    }
+
+    #[test]
+    fn cheats_are_poked_every_frame_until_switched_off() {
+        // INC $10 / JMP $8000
+        let program = [0xE6, 0x10, 0x4C, 0x00, 0x80];
+        let mut emulator = Emulator::headless();
+        emulator.load_rom_bytes(&nrom(0, &program)).unwrap();
+        emulator.set_cheats(vec![Cheat::parse("0010:05").unwrap()]);
+
+        emulator.run_frame();
+        assert_eq!(emulator.cpu.memory.buffer[0x10], 5);
+        emulator.run_frame();
+        assert_eq!(emulator.cpu.memory.buffer[0x10], 5);
+
+        assert!(!emulator.toggle_cheat(0).unwrap().enabled);
+        emulator.run_frame();
+        assert_ne!(emulator.cpu.memory.buffer[0x10], 5);
+        assert_eq!(emulator.toggle_cheat(1), None);
+    }
+
+    #[test]
+    fn peeking_leaves_registers_alone() {
+        let mut emulator = Emulator::headless();
+        emulator.load_rom_bytes(&nrom(0, &[])).unwrap();
+        emulator.poke(0x0001, 0x42);
+        let memory = &mut emulator.cpu.memory;
+
+        memory.ppu.borrow_mut().is_vblank = true;
+        assert_eq!(memory.peek(0x2002) & 0x80, 0x80);
+        assert_eq!(memory.peek(0x3FFA) & 0x80, 0x80);
+        assert_eq!(memory.fetch_absolute(0x2002) & 0x80, 0x80);
+        assert_eq!(memory.peek(0x2002) & 0x80, 0);
+
+        // $21 into the first palette entry, then v back to it
+        for (address, value) in [(0x2006, 0x3F), (0x2006, 0x00), (0x2007, 0x21)] {
+            memory.store_absolute(address, value);
+        }
+        memory.store_absolute(0x2006, 0x3F);
+        memory.store_absolute(0x2006, 0x00);
+        assert_eq!(memory.peek(0x2007), 0x21);
+        assert_eq!(memory.peek(0x2007), 0x21);
+        assert_eq!(memory.fetch_absolute(0x2007), 0x21);
+        assert_ne!(memory.peek(0x2007), 0x21);
+
+        memory.input.set_buttons(0b0000_0101);
+        for _ in 0..10 {
+            let next = memory.peek(0x4016);
+            assert_eq!(memory.peek(0x4016), next);
+            assert_eq!(memory.fetch_absolute(0x4016), next);
+        }
+
+        // the IRQ vector, then around to RAM
+        assert_eq!(
+            memory.peek_range(0xFFFC, 6),
+            [0x00, 0x80, 0xEA, 0xEA, 0x00, 0x42]
+        );
+    }
+
+    #[test]
+    fn movies_hold_one_frame_of_buttons_per_frame() {
+        // LDA #1 / STA $4016 / LSR A / STA $4016 / LDA $4016 / STA $10 / JMP $8000: A button into $10
+        let program = [
+            0xA9, 0x01, 0x8D, 0x16, 0x40, 0x4A, 0x8D, 0x16, 0x40, 0xAD, 0x16, 0x40, 0x85, 0x10,
+            0x4C, 0x00, 0x80,
+        ];
+        let mut emulator = Emulator::headless();
+        emulator.load_rom_bytes(&nrom(0, &program)).unwrap();
+        emulator.play_movie(Movie {
+            frames: vec![0x01, 0x00, 0x81],
+        });
+
+        // the controller reads a held button as 0
+        let mut held = vec![];
+        while emulator.playing_movie() {
+            emulator.run_frame();
+            held.push(emulator.cpu.memory.buffer[0x10] & 1 == 0);
+        }
+        // the last frame is played out, then the buttons are let go
+        emulator.run_frame();
+        held.push(emulator.cpu.memory.buffer[0x10] & 1 == 0);
+        assert_eq!(held, [true, false, true, false]);
+    }
+
+    #[test]
+    fn rewinding_steps_back_through_snapshots() {
+        // INC $10 / JMP $8000
+        let mut emulator = Emulator::headless();
+        emulator
+            .load_rom_bytes(&nrom(0, &[0xE6, 0x10, 0x4C, 0x00, 0x80]))
+            .unwrap();
+        emulator.enable_rewind(2, 3);
+
+        let mut checksums = HashMap::new();
+        for _ in 0..10 {
+            emulator.run_frame();
+            checksums.insert(emulator.frame_count, emulator.state_checksum());
+        }
+        // one whole state, the other two as small deltas
+        let (depth, size) = emulator.rewind_depth();
+        let whole = emulator.save_state().to_bytes().unwrap().len();
+        assert_eq!(depth, 3);
+        assert!(size < whole + 1024, "{} bytes for {}", size, whole);
+
+        // only the last 3 snapshots, frames 10, 8 and 6, are kept
+        for frame in [10, 8, 6] {
+            assert!(emulator.rewind().unwrap());
+            assert_eq!(emulator.frame_count, frame);
+            assert_eq!(emulator.state_checksum(), checksums[&frame]);
+        }
+        assert!(!emulator.rewind().unwrap());
+        assert_eq!(emulator.frame_count, 6);
+
+        // and they're taken again from there
+        emulator.run_frame();
+        emulator.run_frame();
+        assert_eq!(emulator.state_checksum(), checksums[&8]);
+        assert!(emulator.rewind().unwrap());
+        assert_eq!(emulator.frame_count, 8);
+    }
+
+    #[test]
+    fn rewinding_never_crosses_a_power_cycle() {
+        // INC $6000 / JMP $8000: PRG-RAM changes every frame
+        let program = [0xEE, 0x00, 0x60, 0x4C, 0x00, 0x80];
+        let mut emulator = Emulator::headless();
+        emulator.load_rom_bytes(&nrom(0b10, &program)).unwrap();
+        emulator.enable_rewind(1, 10);
+        for _ in 0..5 {
+            emulator.run_frame();
+        }
+
+        // an attract mode demo ends with one, and its PRG-RAM must not come back
+        emulator.power_cycle();
+        emulator.poke(0x6000, 0x42);
+        assert!(!emulator.rewind().unwrap());
+        assert_eq!(emulator.cpu.memory.fetch_absolute(0x6000), 0x42);
+
+        // nor the last cartridge's after loading another
+        emulator.run_frame();
+        emulator.load_rom_bytes(&nrom(0b10, &program)).unwrap();
+        assert!(!emulator.rewind().unwrap());
+
+        // and no rewinding a movie out of sync with its frames
+        emulator.play_movie(Movie { frames: vec![0; 5] });
+        emulator.run_frame();
+        emulator.run_frame();
+        assert!(emulator.rewind().is_err());
+        assert_eq!(emulator.rewind_depth(), (0, 0));
+    }
+
+    #[test]
+    fn alignment_holds_the_ppu_back_when_stepping_by_dots() {
+        // how far the PPU falls behind 3 dots per CPU cycle in a frame, from the start and after a power cycle
+        let lag = |alignment: u8| -> Vec<usize> {
+            let mut emulator = Emulator::headless();
+            emulator.set_alignment(alignment);
+            emulator.load_rom_bytes(&nrom(0, &[])).unwrap();
+            let mut lags = vec![];
+            for _ in 0..2 {
+                let start = (emulator.cpu.num_cycles, emulator.ppu.borrow().num_cycles);
+                emulator.run_frame();
+                let cycles = emulator.cpu.num_cycles - start.0;
+                let dots = emulator.ppu.borrow().num_cycles - start.1;
+                lags.push(cycles * 3 - dots);
+                emulator.power_cycle();
+            }
+            lags
+        };
+        assert_eq!(lag(0), [0, 0]);
+        assert_eq!(lag(2), [2, 2]);
+        assert_eq!(lag(3), [3, 3]);
+    }
+
+    #[test]
+    fn scanline_hooks_run_once_a_frame_in_hblank() {
+        for fast in [false, true] {
+            let mut emulator = Emulator::headless();
+            emulator.set_fast_stepping(fast);
+            emulator.load_rom_bytes(&nrom(0, &[])).unwrap();
+            let calls = Rc::new(RefCell::new(vec![]));
+            let seen = Rc::clone(&calls);
+            emulator.add_scanline_hook(100, move |ppu| seen.borrow_mut().push(ppu.position()));
+            for _ in 0..3 {
+                emulator.run_frame();
+            }
+
+            let calls = calls.borrow();
+            assert_eq!(calls.len(), 3);
+            for (frame, position) in calls.iter().enumerate() {
+                assert_eq!(position.frame, calls[0].frame + frame);
+                if fast {
+                    // the line is done and the next one under way
+                    assert_eq!(position.scanline, 101);
+                } else {
+                    assert_eq!((position.scanline, position.dot), (100, HBLANK_DOT));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frames_without_a_controller_read_are_lag_frames() {
+        let mut results = vec![];
+        // JMP $8000, then LDA $4016 / JMP $8000
+        for program in [
+            &[0x4C, 0x00, 0x80][..],
+            &[0xAD, 0x16, 0x40, 0x4C, 0x00, 0x80],
+        ] {
+            let mut emulator = Emulator::headless();
+            emulator.load_rom_bytes(&nrom(0, program)).unwrap();
+            for _ in 0..3 {
+                emulator.run_frame();
+            }
+            results.push((emulator.lag_frame_count, emulator.lagged()));
+        }
+        assert_eq!(results, [(3, true), (0, false)]);
+    }
+}
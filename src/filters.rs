@@ -0,0 +1,540 @@
+// Postprocessing for finished frames, applied by frontends right before presenting.
+// A filter gets the 256x240 frame both as displayed (0xRRGGBBAA, see ppu::to_rgba8888) and as NES color indices
+// (see PPU::index_frame), and writes a scaled RGB image of whatever size it says it produces.
+
+use crate::{
+    emulator::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    ppu::to_rgba8888,
+};
+
+/// One 256x240 frame, in both forms
+#[derive(Clone, Copy)]
+pub struct Frame<'a> {
+    pub rgb: &'a [u32],
+    pub indices: &'a [u16],
+}
+
+pub trait FrameFilter {
+    fn name(&self) -> &'static str;
+    /// Width and height of the image `apply` writes
+    fn output_size(&self) -> (usize, usize);
+    /// Fill `out` (output width * height pixels, row by row) from `frame`
+    fn apply(&self, frame: Frame, out: &mut [u32]);
+}
+
+/// Names `by_name` accepts, in the order frontends cycle through them
+pub const FILTER_NAMES: &[&str] = &["nearest", "scanlines", "scale2x", "xbr", "ntsc"];
+
+pub fn by_name(name: &str) -> Option<Box<dyn FrameFilter>> {
+    let filter: Box<dyn FrameFilter> = match name {
+        "nearest" => Box::new(Nearest { scale: 2 }),
+        "scanlines" => Box::new(Scanlines),
+        "scale2x" => Box::new(Scale2x),
+        "xbr" => Box::new(Xbr),
+        "ntsc" => Box::new(Ntsc),
+        _ => return None,
+    };
+    Some(filter)
+}
+
+fn rgb(pixel: u32) -> (f32, f32, f32) {
+    let [r, g, b, _] = pixel.to_be_bytes();
+    (r as f32, g as f32, b as f32)
+}
+
+fn pack((r, g, b): (f32, f32, f32)) -> u32 {
+    let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+    to_rgba8888((channel(r), channel(g), channel(b)))
+}
+
+/// Every pixel becomes a `scale` x `scale` block
+pub struct Nearest {
+    pub scale: usize,
+}
+
+impl FrameFilter for Nearest {
+    fn name(&self) -> &'static str {
+        "nearest"
+    }
+
+    fn output_size(&self) -> (usize, usize) {
+        (SCREEN_WIDTH * self.scale, SCREEN_HEIGHT * self.scale)
+    }
+
+    fn apply(&self, frame: Frame, out: &mut [u32]) {
+        let width = SCREEN_WIDTH * self.scale;
+        for (i, pixel) in out.iter_mut().enumerate() {
+            let (x, y) = (i % width / self.scale, i / width / self.scale);
+            *pixel = frame.rgb[y * SCREEN_WIDTH + x];
+        }
+    }
+}
+
+/// Doubled pixels with every second row darkened, like the gaps between a CRT's scanlines
+pub struct Scanlines;
+
+impl FrameFilter for Scanlines {
+    fn name(&self) -> &'static str {
+        "scanlines"
+    }
+
+    fn output_size(&self) -> (usize, usize) {
+        (SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2)
+    }
+
+    fn apply(&self, frame: Frame, out: &mut [u32]) {
+        Nearest { scale: 2 }.apply(frame, out);
+        for row in out.chunks_mut(SCREEN_WIDTH * 2).skip(1).step_by(2) {
+            for pixel in row {
+                let (r, g, b) = rgb(*pixel);
+                *pixel = pack((r * 0.6, g * 0.6, b * 0.6));
+            }
+        }
+    }
+}
+
+/// Scale2x (EPX): edge-directed 2x scaling that rounds off staircase diagonals without blending colors.
+/// The same idea hqx and xBR refine with larger neighbourhoods.
+pub struct Scale2x;
+
+impl FrameFilter for Scale2x {
+    fn name(&self) -> &'static str {
+        "scale2x"
+    }
+
+    fn output_size(&self) -> (usize, usize) {
+        (SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2)
+    }
+
+    fn apply(&self, frame: Frame, out: &mut [u32]) {
+        let at = |x: usize, y: usize| frame.rgb[y * SCREEN_WIDTH + x];
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let center = at(x, y);
+                let up = at(x, y.saturating_sub(1));
+                let down = at(x, (y + 1).min(SCREEN_HEIGHT - 1));
+                let left = at(x.saturating_sub(1), y);
+                let right = at((x + 1).min(SCREEN_WIDTH - 1), y);
+
+                let mut block = [center; 4];
+                if up != down && left != right {
+                    if up == left {
+                        block[0] = up;
+                    }
+                    if up == right {
+                        block[1] = up;
+                    }
+                    if down == left {
+                        block[2] = down;
+                    }
+                    if down == right {
+                        block[3] = down;
+                    }
+                }
+
+                let top = 2 * y * SCREEN_WIDTH * 2 + 2 * x;
+                let bottom = top + SCREEN_WIDTH * 2;
+                out[top..top + 2].copy_from_slice(&block[..2]);
+                out[bottom..bottom + 2].copy_from_slice(&block[2..]);
+            }
+        }
+    }
+}
+
+/// 2xBR, Hyllian's xBR at 2x. Each corner of a pixel looks at a 5x5 neighbourhood for an edge running across it,
+/// by weighing the color differences along one diagonal against those along the other, and blends towards the
+/// neighbour on the far side of the edge: a quarter-pixel at 45 degrees, more of the block along shallow slopes.
+pub struct Xbr;
+
+// corner blends, out of 256
+const XBR_BLEND_DIAGONAL: u32 = 128;
+const XBR_BLEND_SLOPE: u32 = 192;
+const XBR_BLEND_SLOPE_BOTH: u32 = 224;
+const XBR_BLEND_SIDE: u32 = 64;
+// color distance below which two pixels count as the same
+const XBR_SAME: i32 = 155;
+
+impl Xbr {
+    fn yuv(pixel: u32) -> [i32; 3] {
+        let (r, g, b) = rgb(pixel);
+        [
+            0.299 * r + 0.587 * g + 0.114 * b,
+            -0.169 * r - 0.331 * g + 0.5 * b,
+            0.5 * r - 0.419 * g - 0.081 * b,
+        ]
+        .map(|channel| channel as i32)
+    }
+
+    /// Brightness differences count the most
+    fn distance(a: [i32; 3], b: [i32; 3]) -> i32 {
+        48 * (a[0] - b[0]).abs() + 7 * (a[1] - b[1]).abs() + 6 * (a[2] - b[2]).abs()
+    }
+
+    fn blend(dst: u32, src: u32, amount: u32) -> u32 {
+        let amount = amount as f32 / 256.0;
+        let ((dr, dg, db), (sr, sg, sb)) = (rgb(dst), rgb(src));
+        pack((
+            dr + (sr - dr) * amount,
+            dg + (sg - dg) * amount,
+            db + (sb - db) * amount,
+        ))
+    }
+
+    /// The bottom right corner of the block for the pixel at `at(0, 0)`, with `at` mirrored so that any corner
+    /// can be treated as that one. `corner` is the block's pixel there, `vertical` and `horizontal` its
+    /// neighbours above and to the left.
+    fn corner(
+        frame: &[u32],
+        yuv: &[[i32; 3]],
+        at: impl Fn(isize, isize) -> usize,
+        block: &mut [u32; 4],
+        (corner, vertical, horizontal): (usize, usize, usize),
+    ) {
+        //    B
+        // D  E  F  F4
+        // G  H  I  I4
+        //    H5 I5
+        let [b, d, e, f, g, h, i, c] = [
+            at(0, -1),
+            at(-1, 0),
+            at(0, 0),
+            at(1, 0),
+            at(-1, 1),
+            at(0, 1),
+            at(1, 1),
+            at(1, -1),
+        ];
+        let [f4, i4, h5, i5] = [at(2, 0), at(2, 1), at(0, 2), at(1, 2)];
+        if frame[e] == frame[f] || frame[e] == frame[h] {
+            return;
+        }
+        let diff = |a: usize, b: usize| Self::distance(yuv[a], yuv[b]);
+        let same = |a: usize, b: usize| diff(a, b) < XBR_SAME;
+
+        // differences across the E-I diagonal against those along it
+        let across = diff(e, c) + diff(e, g) + diff(i, h5) + diff(i, f4) + 4 * diff(h, f);
+        let along = diff(h, d) + diff(h, i5) + diff(f, i4) + diff(f, b) + 4 * diff(e, i);
+        let edge = across < along
+            && (!same(f, b) && !same(h, d)
+                || same(e, i) && !same(f, i4) && !same(h, i5)
+                || same(e, g)
+                || same(e, c));
+        if !edge {
+            return;
+        }
+
+        let pixel = if diff(e, f) <= diff(e, h) {
+            frame[f]
+        } else {
+            frame[h]
+        };
+        // how flat the edge runs: along F-G it's shallow and reaches the left neighbour, along H-C the one above
+        let (flat, steep) = (diff(f, g), diff(h, c));
+        let shallow = 2 * flat <= steep && frame[e] != frame[g] && frame[d] != frame[g];
+        let tall = flat >= 2 * steep && frame[e] != frame[c] && frame[b] != frame[c];
+        match (shallow, tall) {
+            (true, true) => {
+                block[corner] = Self::blend(block[corner], pixel, XBR_BLEND_SLOPE_BOTH);
+                block[horizontal] = Self::blend(block[horizontal], pixel, XBR_BLEND_SIDE);
+                block[vertical] = block[horizontal];
+            }
+            (true, false) => {
+                block[corner] = Self::blend(block[corner], pixel, XBR_BLEND_SLOPE);
+                block[horizontal] = Self::blend(block[horizontal], pixel, XBR_BLEND_SIDE);
+            }
+            (false, true) => {
+                block[corner] = Self::blend(block[corner], pixel, XBR_BLEND_SLOPE);
+                block[vertical] = Self::blend(block[vertical], pixel, XBR_BLEND_SIDE);
+            }
+            (false, false) => block[corner] = Self::blend(block[corner], pixel, XBR_BLEND_DIAGONAL),
+        }
+    }
+}
+
+impl FrameFilter for Xbr {
+    fn name(&self) -> &'static str {
+        "xbr"
+    }
+
+    fn output_size(&self) -> (usize, usize) {
+        (SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2)
+    }
+
+    fn apply(&self, frame: Frame, out: &mut [u32]) {
+        let frame = frame.rgb;
+        let yuv: Vec<_> = frame.iter().map(|&pixel| Self::yuv(pixel)).collect();
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                // top left, top right, bottom left, bottom right
+                let mut block = [frame[y * SCREEN_WIDTH + x]; 4];
+                for (sx, sy) in [(1, 1), (1, -1), (-1, -1), (-1, 1)] {
+                    let at = |dx: isize, dy: isize| {
+                        let x = (x as isize + sx * dx).clamp(0, SCREEN_WIDTH as isize - 1);
+                        let y = (y as isize + sy * dy).clamp(0, SCREEN_HEIGHT as isize - 1);
+                        y as usize * SCREEN_WIDTH + x as usize
+                    };
+                    let subpixel = |sx: isize, sy: isize| (sy > 0) as usize * 2 + (sx > 0) as usize;
+                    let corners = (subpixel(sx, sy), subpixel(sx, -sy), subpixel(-sx, sy));
+                    Self::corner(frame, &yuv, at, &mut block, corners);
+                }
+
+                let top = 2 * y * SCREEN_WIDTH * 2 + 2 * x;
+                let bottom = top + SCREEN_WIDTH * 2;
+                out[top..top + 2].copy_from_slice(&block[..2]);
+                out[bottom..bottom + 2].copy_from_slice(&block[2..]);
+            }
+        }
+    }
+}
+
+/// Composite video the way the NES puts it out and a TV takes it apart. Every pixel's NES color (see
+/// PPU::index_frame) becomes 8 samples of the square wave the PPU generates for it, 12 samples to a cycle of the
+/// color subcarrier, and each output pixel is decoded to YIQ from the 12 samples around it. Colors bleed into
+/// their neighbours and fine patterns pick up fringes, as on a real set. Signal levels are nesdev's NTSC video
+/// measurements.
+pub struct Ntsc;
+
+// each of the 4 brightness levels' low and high voltages, relative to sync
+const NTSC_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const NTSC_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const NTSC_BLACK: f32 = 0.518;
+const NTSC_WHITE: f32 = 1.962;
+// what an emphasis bit scales the signal by, during the part of the cycle it covers
+const NTSC_EMPHASIS: f32 = 0.746;
+const NTSC_SAMPLES_PER_PIXEL: usize = 8;
+const NTSC_SAMPLES_PER_CYCLE: usize = 12;
+// a line is 341 dots long, so each starts 4 samples further into the subcarrier cycle than the one above
+const NTSC_SAMPLES_PER_LINE: usize = 341 * NTSC_SAMPLES_PER_PIXEL;
+// the decoder's phase against the PPU's, in samples, which puts color 6 at red
+const NTSC_HUE: f32 = 3.9;
+
+impl Ntsc {
+    /// The signal for NES color `index` (emphasis in bits 6-8) at `phase` (0-11) of the subcarrier cycle,
+    /// 0 at black and 1 at white
+    fn signal(index: u16, phase: usize) -> f32 {
+        let color = (index & 0x0F) as usize;
+        // $xE and $xF are black whatever the brightness
+        let level = if color > 13 {
+            1
+        } else {
+            (index >> 4 & 3) as usize
+        };
+        let emphasis = index >> 6;
+        let in_phase = |color: usize| (color + phase) % NTSC_SAMPLES_PER_CYCLE < 6;
+
+        // the greys ($x0) only have the high level, $xD-$xF only the low one
+        let low = if color == 0 {
+            NTSC_HIGH[level]
+        } else {
+            NTSC_LOW[level]
+        };
+        let high = if color > 12 { low } else { NTSC_HIGH[level] };
+        let mut signal = if in_phase(color) { high } else { low };
+        // red, green and blue emphasis each cover the half of the cycle around their hue
+        if (0..3).any(|bit| emphasis >> bit & 1 != 0 && in_phase(4 * bit)) {
+            signal *= NTSC_EMPHASIS;
+        }
+        (signal - NTSC_BLACK) / (NTSC_WHITE - NTSC_BLACK)
+    }
+
+    /// FCC YIQ to RGB (0-255), with the gamma of a CRT
+    fn to_rgb((y, i, q): (f32, f32, f32)) -> (f32, f32, f32) {
+        let gamma = |value: f32| value.max(0.0).powf(2.2 / 1.8) * 255.0;
+        (
+            gamma(y + 0.946_882 * i + 0.623_557 * q),
+            gamma(y - 0.274_788 * i - 0.635_691 * q),
+            gamma(y - 1.108_545 * i + 1.709_007 * q),
+        )
+    }
+}
+
+impl FrameFilter for Ntsc {
+    fn name(&self) -> &'static str {
+        "ntsc"
+    }
+
+    fn output_size(&self) -> (usize, usize) {
+        (SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2)
+    }
+
+    fn apply(&self, frame: Frame, out: &mut [u32]) {
+        let width = SCREEN_WIDTH * 2;
+        let half_cycle = NTSC_SAMPLES_PER_CYCLE / 2;
+        let carrier: Vec<_> = (0..NTSC_SAMPLES_PER_CYCLE)
+            .map(|phase| {
+                let angle = std::f32::consts::PI * (phase as f32 + NTSC_HUE) / 6.0;
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+
+        for (y, line) in frame.indices.chunks(SCREEN_WIDTH).enumerate() {
+            let line_phase = y * NTSC_SAMPLES_PER_LINE % NTSC_SAMPLES_PER_CYCLE;
+            // half a cycle of the edge pixels past either end, so the decoder has a whole cycle everywhere
+            let samples: Vec<_> = (0..SCREEN_WIDTH * NTSC_SAMPLES_PER_PIXEL + 2 * half_cycle)
+                .map(|sample| {
+                    let pixel = (sample.saturating_sub(half_cycle) / NTSC_SAMPLES_PER_PIXEL)
+                        .min(SCREEN_WIDTH - 1);
+                    let phase = (line_phase + sample + NTSC_SAMPLES_PER_CYCLE - half_cycle)
+                        % NTSC_SAMPLES_PER_CYCLE;
+                    (Self::signal(line[pixel], phase), phase)
+                })
+                .collect();
+
+            for x in 0..width {
+                // output pixels are half an NES pixel wide, the window is the cycle centered on this one
+                let center = x * NTSC_SAMPLES_PER_PIXEL / 2 + NTSC_SAMPLES_PER_PIXEL / 4;
+                let (mut luma, mut i, mut q) = (0.0, 0.0, 0.0);
+                for &(signal, phase) in &samples[center..center + NTSC_SAMPLES_PER_CYCLE] {
+                    let signal = signal / NTSC_SAMPLES_PER_CYCLE as f32;
+                    luma += signal;
+                    i += signal * carrier[phase].0;
+                    q += signal * carrier[phase].1;
+                }
+                let pixel = pack(Self::to_rgb((luma, i, q)));
+                out[2 * y * width + x] = pixel;
+                out[(2 * y + 1) * width + x] = pixel;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_frame(filter: &dyn FrameFilter, frame: Frame) -> Vec<u32> {
+        let (width, height) = filter.output_size();
+        let mut out = vec![0; width * height];
+        filter.apply(frame, &mut out);
+        out
+    }
+
+    // for the filters that only read the RGB frame
+    fn run(filter: &dyn FrameFilter, rgb: &[u32]) -> Vec<u32> {
+        let indices = vec![0x0F; SCREEN_WIDTH * SCREEN_HEIGHT];
+        run_frame(
+            filter,
+            Frame {
+                rgb,
+                indices: &indices,
+            },
+        )
+    }
+
+    // an Ntsc frame of one NES color
+    fn run_ntsc(index: u16) -> Vec<u32> {
+        let rgb = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let indices = vec![index; SCREEN_WIDTH * SCREEN_HEIGHT];
+        run_frame(
+            &Ntsc,
+            Frame {
+                rgb: &rgb,
+                indices: &indices,
+            },
+        )
+    }
+
+    #[test]
+    fn flat_frames_stay_flat() {
+        let gray = to_rgba8888((0x80, 0x80, 0x80));
+        let frame = vec![gray; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for name in ["nearest", "scale2x", "xbr"] {
+            let out = run(by_name(name).unwrap().as_ref(), &frame);
+            assert!(out.iter().all(|&pixel| pixel == gray), "{}", name);
+        }
+        for index in [0x00, 0x16, 0x2A, 0x0F] {
+            let out = run_ntsc(index);
+            assert!(out.iter().all(|&pixel| pixel == out[0]), "{:02x}", index);
+        }
+
+        let out = run(&Scanlines, &frame);
+        assert_eq!(out[0], gray);
+        assert_eq!(out[SCREEN_WIDTH * 2], to_rgba8888((0x4D, 0x4D, 0x4D)));
+    }
+
+    #[test]
+    fn scale2x_rounds_off_diagonals() {
+        // a black pixel with white above and to the left gets its top-left quarter filled in
+        let (white, black) = (to_rgba8888((255, 255, 255)), to_rgba8888((0, 0, 0)));
+        let mut frame = vec![black; SCREEN_WIDTH * SCREEN_HEIGHT];
+        frame[SCREEN_WIDTH] = white;
+        frame[1] = white;
+        let out = run(&Scale2x, &frame);
+        let width = SCREEN_WIDTH * 2;
+        let (x, y) = (2, 2);
+        assert_eq!(out[y * width + x], white);
+        assert_eq!(out[y * width + x + 1], black);
+        assert_eq!(out[(y + 1) * width + x], black);
+    }
+
+    #[test]
+    fn xbr_blends_along_staircases() {
+        // white above a 1-in-2 staircase, black below
+        let (white, black) = (to_rgba8888((255, 255, 255)), to_rgba8888((0, 0, 0)));
+        let frame: Vec<u32> = (0..SCREEN_WIDTH * SCREEN_HEIGHT)
+            .map(|i| {
+                let (x, y) = (i % SCREEN_WIDTH, i / SCREEN_WIDTH);
+                if x + 2 * y < 200 {
+                    white
+                } else {
+                    black
+                }
+            })
+            .collect();
+        let out = run(&Xbr, &frame);
+        let width = SCREEN_WIDTH * 2;
+
+        assert!(out.iter().any(|&pixel| pixel != white && pixel != black));
+        // away from the edge nothing changes
+        assert_eq!(out[0], white);
+        assert_eq!(out[out.len() - 1], black);
+        assert_eq!(out[100 * width + 400], black);
+        // and only pixels next to it are touched
+        for (i, &pixel) in out.iter().enumerate() {
+            let (x, y) = (i % width / 2, i / width / 2);
+            if (x + 2 * y).abs_diff(200) > 3 {
+                assert_eq!(pixel, frame[y * SCREEN_WIDTH + x], "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn ntsc_decodes_nes_colors() {
+        let channels = |index| {
+            let [r, g, b, _] = run_ntsc(index)[0].to_be_bytes();
+            (r, g, b)
+        };
+        let (r, g, b) = channels(0x16);
+        assert!(r > 2 * g && r > 2 * b, "red {:?}", (r, g, b));
+        let (r, g, b) = channels(0x1A);
+        assert!(g > 2 * r && g > 2 * b, "green {:?}", (r, g, b));
+        let (r, g, b) = channels(0x12);
+        assert!(b > 2 * r && b > 2 * g, "blue {:?}", (r, g, b));
+        assert_eq!(channels(0x0F), (0, 0, 0));
+        assert!(channels(0x30) >= (250, 250, 250));
+        // red emphasis darkens white everywhere but its own hue
+        let (r, g, b) = channels(0x30 | 1 << 6);
+        assert!(r > g + 30 && r > b + 30, "emphasized {:?}", (r, g, b));
+    }
+
+    #[test]
+    fn ntsc_bleeds_color_across_edges() {
+        // a white pixel between blacks spreads into its neighbours, with color fringes
+        let rgb = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut indices = vec![0x0F; SCREEN_WIDTH * SCREEN_HEIGHT];
+        indices[100] = 0x30;
+        let out = run_frame(
+            &Ntsc,
+            Frame {
+                rgb: &rgb,
+                indices: &indices,
+            },
+        );
+        let black = to_rgba8888((0, 0, 0));
+        assert_ne!(out[2 * 99 + 1], black);
+        assert_ne!(out[2 * 101], black);
+        assert_eq!(out[2 * 90], black);
+        let [r, g, b, _] = out[2 * 101].to_be_bytes();
+        assert!(r != g || g != b, "no fringe {:?}", (r, g, b));
+    }
+}
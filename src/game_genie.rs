@@ -0,0 +1,208 @@
+// Game Genie codes, applied by a mapper that sits between the CPU and the real cartridge.
+// The device itself only ever patches PRG reads: each code names a ROM address, the value to return instead,
+// and for 8-letter codes the value that has to be there for the patch to apply (so it survives bank switching).
+
+use std::fmt;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    mappers::{Mapper, MapperState},
+    memory_map::Region,
+    ppu::NametableArrangement,
+};
+
+const LETTERS: &str = "APZLGITYEOXUKSVN";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameGenieCode {
+    pub address: u16,
+    pub value: u8,
+    /// Only substitute when the ROM holds this value (8-letter codes)
+    pub compare: Option<u8>,
+}
+
+impl GameGenieCode {
+    /// Decode a 6 or 8 letter code such as `SXIOPO`
+    pub fn parse(code: &str) -> Result<Self> {
+        let n = code
+            .chars()
+            .map(|letter| LETTERS.find(letter.to_ascii_uppercase()).map(|i| i as u16))
+            .collect::<Option<Vec<u16>>>();
+        let Some(n) = n.filter(|n| n.len() == 6 || n.len() == 8) else {
+            bail!(
+                "{} is not a Game Genie code: 6 or 8 of the letters {}",
+                code,
+                LETTERS
+            );
+        };
+
+        // the bits of address and value are scattered over the letters
+        let address = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8)
+            | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4)
+            | ((n[1] & 8) << 4)
+            | (n[4] & 7)
+            | (n[3] & 8);
+        let value = |high: u16, low: u16, last: u16| {
+            (((high & 7) << 4) | ((low & 8) << 4) | (low & 7) | (last & 8)) as u8
+        };
+
+        Ok(if n.len() == 6 {
+            Self {
+                address,
+                value: value(n[1], n[0], n[5]),
+                compare: None,
+            }
+        } else {
+            Self {
+                address,
+                value: value(n[1], n[0], n[7]),
+                compare: Some(value(n[7], n[6], n[5])),
+            }
+        })
+    }
+}
+
+impl fmt::Display for GameGenieCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "${:04X}:{:02X}", self.address, self.value)?;
+        if let Some(compare) = self.compare {
+            write!(f, "?{:02X}", compare)?;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps the inserted cartridge and passes everything through to it, except CPU reads that a code patches
+pub struct GameGenie {
+    cartridge: Box<dyn Mapper>,
+    codes: Vec<GameGenieCode>,
+}
+
+impl GameGenie {
+    pub fn new(cartridge: Box<dyn Mapper>, codes: Vec<GameGenieCode>) -> Self {
+        Self { cartridge, codes }
+    }
+
+    /// `value`, read from the cartridge at `address`, as the codes replace it
+    fn patch(&self, address: u16, value: u8) -> u8 {
+        self.codes
+            .iter()
+            .find(|code| code.address == address && code.compare.is_none_or(|c| c == value))
+            .map_or(value, |code| code.value)
+    }
+}
+
+impl Mapper for GameGenie {
+    fn cpu_peek(&self, address: u16) -> u8 {
+        self.patch(address, self.cartridge.cpu_peek(address))
+    }
+
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        let value = self.cartridge.cpu_read(address);
+        self.patch(address, value)
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        self.cartridge.cpu_write(address, value)
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.cartridge.ppu_read(address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.cartridge.ppu_write(address, value)
+    }
+
+    fn chr_source(&self, address: u16) -> usize {
+        self.cartridge.chr_source(address)
+    }
+
+    fn nametable_arrangement(&self) -> NametableArrangement {
+        self.cartridge.nametable_arrangement()
+    }
+
+    fn clock_scanline(&mut self) {
+        self.cartridge.clock_scanline()
+    }
+
+    fn power_on(&mut self) {
+        self.cartridge.power_on()
+    }
+
+    fn ignores_write(&self, address: u16) -> bool {
+        self.cartridge.ignores_write(address)
+    }
+
+    fn irq(&self) -> bool {
+        self.cartridge.irq()
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        self.cartridge.prg_ram()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.cartridge.prg_ram_mut()
+    }
+
+    fn save_state(&self) -> MapperState {
+        self.cartridge.save_state()
+    }
+
+    fn load_state(&mut self, state: &MapperState) -> Result<()> {
+        self.cartridge.load_state(state)
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        self.cartridge.cpu_regions()
+    }
+
+    fn ppu_regions(&self) -> Vec<Region> {
+        self.cartridge.ppu_regions()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::{test_image, Cartridge};
+
+    #[test]
+    fn decodes_codes() {
+        let code = GameGenieCode::parse("SXIOPO").unwrap();
+        assert_eq!(
+            (code.address, code.value, code.compare),
+            (0x91D9, 0xAD, None)
+        );
+        let code = GameGenieCode::parse("zexpygla").unwrap();
+        assert_eq!(
+            (code.address, code.value, code.compare),
+            (0x94A7, 0x02, Some(0x03))
+        );
+        assert!(GameGenieCode::parse("SXIOP").is_err());
+        assert!(GameGenieCode::parse("SXIOPB").is_err());
+    }
+
+    #[test]
+    fn patches_prg_reads_that_match() {
+        let mut image = test_image(0, 2, 1);
+        image[16..16 + 0x8000].fill(0x03);
+        let cartridge = Cartridge::from_ines_bytes(&image)
+            .and_then(Cartridge::build_mapper)
+            .unwrap();
+        let codes =
+            ["ZEXPYGLA", "GOSSIP", "AAAAAAAA"].map(|code| GameGenieCode::parse(code).unwrap());
+        let mut mapper = GameGenie::new(cartridge, codes.to_vec());
+
+        assert_eq!(mapper.cpu_read(0x94A7), 0x02);
+        assert_eq!(mapper.cpu_read(0xD1DD), 0x14);
+        // compare value doesn't match what's in ROM
+        assert_eq!(mapper.cpu_read(codes[2].address), 0x03);
+        assert_eq!(mapper.cpu_read(0x8001), 0x03);
+    }
+}
@@ -0,0 +1,136 @@
+// Frontend shortcuts, looked up in one rebindable map instead of matched on keycodes in the event loop.
+// Bindings are read from hotkeys.json in the config directory (see DataDirs::config), an object from action
+// to SDL key name such as {"pause": "Space", "screenshot": "F9"}. Actions it leaves out keep their default key.
+
+use std::{collections::HashMap, fs, io::ErrorKind, path::Path};
+
+use anyhow::{anyhow, Result};
+use sdl2::keyboard::Keycode;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Hotkey {
+    Quit,
+    Pause,
+    /// The console's reset button
+    Reset,
+    Screenshot,
+    /// Save the whole machine to the ROM's save state file, see save_state
+    SaveState,
+    LoadState,
+    /// Held to step back through the rewind snapshots
+    Rewind,
+    /// Held for as long as the key is (Vs. System coin slot 1)
+    InsertCoin,
+    /// Debug view coloring pixels by the layer they came from
+    PixelSources,
+    /// Debug view of both pattern tables, marking what changed in the last frame
+    PatternTables,
+    CycleBgPatterns,
+    CycleSpritePatterns,
+    CycleFilter,
+    /// Switch the first four cheats (see cheats) on and off
+    Cheat1,
+    Cheat2,
+    Cheat3,
+    Cheat4,
+}
+
+impl Hotkey {
+    /// The cheat toggles, the nth one switching cheat n
+    pub const CHEATS: [Hotkey; 4] = [Self::Cheat1, Self::Cheat2, Self::Cheat3, Self::Cheat4];
+}
+
+const DEFAULTS: &[(Hotkey, Keycode)] = &[
+    (Hotkey::Quit, Keycode::Escape),
+    (Hotkey::Pause, Keycode::P),
+    (Hotkey::Reset, Keycode::R),
+    (Hotkey::Screenshot, Keycode::F12),
+    (Hotkey::SaveState, Keycode::F5),
+    (Hotkey::LoadState, Keycode::F7),
+    (Hotkey::Rewind, Keycode::Backspace),
+    (Hotkey::InsertCoin, Keycode::C),
+    (Hotkey::PixelSources, Keycode::V),
+    (Hotkey::PatternTables, Keycode::T),
+    (Hotkey::CycleBgPatterns, Keycode::B),
+    (Hotkey::CycleSpritePatterns, Keycode::N),
+    (Hotkey::CycleFilter, Keycode::F),
+    (Hotkey::Cheat1, Keycode::F1),
+    (Hotkey::Cheat2, Keycode::F2),
+    (Hotkey::Cheat3, Keycode::F3),
+    (Hotkey::Cheat4, Keycode::F4),
+];
+
+pub struct Hotkeys {
+    bindings: HashMap<Keycode, Hotkey>,
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        Self {
+            bindings: DEFAULTS
+                .iter()
+                .map(|&(action, key)| (key, action))
+                .collect(),
+        }
+    }
+}
+
+impl Hotkeys {
+    /// The defaults with the bindings from `path` on top, or just the defaults if there is no such file
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut hotkeys = Self::default();
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(hotkeys),
+            Err(err) => return Err(err.into()),
+        };
+
+        let overrides: HashMap<Hotkey, String> =
+            serde_json::from_str(&json).map_err(|err| anyhow!("{}: {}", path.display(), err))?;
+        for (action, name) in overrides {
+            let key = Keycode::from_name(&name)
+                .ok_or_else(|| anyhow!("{}: no key called {:?}", path.display(), name))?;
+            hotkeys.bind(action, key);
+        }
+        Ok(hotkeys)
+    }
+
+    /// Move `action` to `key`, taking the key away from whatever action had it
+    pub fn bind(&mut self, action: Hotkey, key: Keycode) {
+        self.bindings.retain(|_, bound| *bound != action);
+        self.bindings.insert(key, action);
+    }
+
+    pub fn action(&self, key: Keycode) -> Option<Hotkey> {
+        self.bindings.get(&key).copied()
+    }
+
+    pub fn key(&self, action: Hotkey) -> Option<Keycode> {
+        self.bindings
+            .iter()
+            .find(|(_, bound)| **bound == action)
+            .map(|(&key, _)| key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebinding_moves_the_action() {
+        let mut hotkeys = Hotkeys::default();
+        assert_eq!(hotkeys.action(Keycode::P), Some(Hotkey::Pause));
+
+        hotkeys.bind(Hotkey::Pause, Keycode::Space);
+        assert_eq!(hotkeys.action(Keycode::P), None);
+        assert_eq!(hotkeys.key(Hotkey::Pause), Some(Keycode::Space));
+
+        // a key only triggers one action
+        hotkeys.bind(Hotkey::Screenshot, Keycode::Space);
+        assert_eq!(hotkeys.action(Keycode::Space), Some(Hotkey::Screenshot));
+        assert_eq!(hotkeys.key(Hotkey::Pause), None);
+    }
+}
@@ -1,4 +1,21 @@
+pub mod access_stats;
+pub mod apu;
+pub mod capture;
+pub mod cheats;
 pub mod cpu;
+pub mod data_dirs;
+pub mod emulator;
+pub mod filters;
+pub mod game_genie;
+pub mod hotkeys;
 pub mod mappers;
+pub mod memory_map;
+pub mod movie;
 pub mod ppu;
+pub mod rewind;
+pub mod sav;
+pub mod save_state;
+pub mod storage;
+pub mod test_rom;
+pub mod test_suites;
 pub mod utils;
@@ -1,25 +1,182 @@
-use std::{fs::File, io::Read};
+use std::{cell::RefCell, fs::File, io::Read, rc::Rc};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use log::info;
+use serde::{Deserialize, Serialize};
 
-use crate::{
-    cpu::memory::Memory,
-    ppu::{memory::VRAM, NametableArrangement},
-};
+use crate::{memory_map::Region, ppu::NametableArrangement, utils::checksum};
 
+/// A cartridge's mapper, shared between the CPU bus ($4020-$FFFF) and the PPU bus ($0000-$1FFF)
+pub type SharedMapper = Rc<RefCell<Box<dyn Mapper>>>;
+
+/// The cartridge hardware that sits on both buses for as long as the ROM is loaded.
+/// Every CPU access to $4020-$FFFF and every PPU access to the pattern tables goes through it,
+/// so it can switch banks in response to writes.
 pub trait Mapper {
-    fn from_ines_rom(path: &str, vram: &mut VRAM, wram: &mut Memory) -> Result<Self>
-    where
-        Self: Sized;
+    /// What a CPU read of `address` returns, without any effect a read could have on the board
+    fn cpu_peek(&self, address: u16) -> u8;
+
+    /// A CPU read. None of the boards here react to reads, so by default it's just `cpu_peek`.
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        self.cpu_peek(address)
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8);
+
+    fn ppu_read(&mut self, address: u16) -> u8;
+
+    fn ppu_write(&mut self, address: u16, value: u8);
+
+    /// Where in CHR-ROM or CHR-RAM the pattern table `address` ($0000-$1FFF) currently reads from, which changes
+    /// with the CHR bank switched in there
+    fn chr_source(&self, address: u16) -> usize {
+        address as usize & 0x1FFF
+    }
+
+    /// How CIRAM is currently wired into $2000-$2FFF
+    fn nametable_arrangement(&self) -> NametableArrangement;
+
+    /// Called once per rendered scanline (pre-render and visible lines, rendering enabled), standing in for
+    /// the rise of PPU A12 when fetches move from the background to the sprite pattern table
+    fn clock_scanline(&mut self) {}
+
+    /// Put the registers back the way the board comes up, for a power cycle. RAM keeps its contents, like the
+    /// console's internal RAM the emulator doesn't scramble it.
+    fn power_on(&mut self) {}
+
+    /// Whether a CPU write to `address` lands nowhere, as it does in the ROM of boards without registers there
+    fn ignores_write(&self, _address: u16) -> bool {
+        false
+    }
+
+    /// Whether the cartridge is pulling the CPU's IRQ line low
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// The work RAM at $6000-$7FFF, for boards that have it
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    /// Everything on the board a save state has to keep, which is everything but the ROMs
+    fn save_state(&self) -> MapperState;
+
+    /// Put the board back the way `save_state` found it. The state has to come from the same kind of board.
+    fn load_state(&mut self, state: &MapperState) -> Result<()>;
+
+    /// What the cartridge decodes in $4020-$FFFF
+    fn cpu_regions(&self) -> Vec<Region>;
+
+    /// What the cartridge decodes in $0000-$2FFF (pattern tables and nametables)
+    fn ppu_regions(&self) -> Vec<Region>;
 }
 
-pub struct NROM {
-    nt_arrangement: NametableArrangement,
+/// A mapper's part of a save state (see save_state::SaveState)
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MapperState {
+    /// Bank selects, shift registers, IRQ counters and the like, in whatever order the board keeps them
+    pub registers: Vec<u8>,
+    /// For boards that can change the mirroring, or that take it from the header
+    pub nt_arrangement: Option<NametableArrangement>,
+    pub prg_ram: Vec<u8>,
+    /// Empty for boards with CHR-ROM
+    pub chr_ram: Vec<u8>,
 }
 
-impl Mapper for NROM {
-    fn from_ines_rom(path: &str, vram: &mut VRAM, wram: &mut Memory) -> Result<Self> {
+impl MapperState {
+    /// The saved registers, which have to be the `N` the board keeps
+    fn registers<const N: usize>(&self) -> Result<[u8; N]> {
+        match self.registers.as_slice().try_into() {
+            Ok(registers) => Ok(registers),
+            Err(_) => bail!(
+                "mapper state has {} registers, the board has {}",
+                self.registers.len(),
+                N
+            ),
+        }
+    }
+
+    fn nt_arrangement(&self) -> Result<NametableArrangement> {
+        match self.nt_arrangement {
+            Some(arrangement) => Ok(arrangement),
+            None => bail!("mapper state has no nametable arrangement"),
+        }
+    }
+
+    fn restore_prg_ram(&self, prg_ram: &mut [u8]) -> Result<()> {
+        restore_ram(prg_ram, &self.prg_ram, "PRG-RAM")
+    }
+
+    /// CHR-ROM stays as it is, there's nothing saved for it
+    fn restore_chr_ram(&self, chr: &mut [u8], chr_is_ram: bool) -> Result<()> {
+        if chr_is_ram {
+            restore_ram(chr, &self.chr_ram, "CHR-RAM")?;
+        }
+        Ok(())
+    }
+}
+
+fn saved_chr_ram(chr: &[u8], chr_is_ram: bool) -> Vec<u8> {
+    if chr_is_ram {
+        chr.to_vec()
+    } else {
+        vec![]
+    }
+}
+
+fn restore_ram(ram: &mut [u8], saved: &[u8], name: &str) -> Result<()> {
+    if saved.len() != ram.len() {
+        bail!(
+            "saved {} is {} bytes, the board has {} bytes",
+            name,
+            saved.len(),
+            ram.len()
+        );
+    }
+    ram.copy_from_slice(saved);
+    Ok(())
+}
+
+/// What the ROM was dumped from, header flags 7 bits 0-1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    /// Arcade board: coin slots and DIP switches are read through $4016/$4017
+    VsSystem,
+    PlayChoice10,
+}
+
+/// The contents of an iNES image, before a mapper is wrapped around them
+pub struct Cartridge {
+    pub mapper_number: u8,
+    pub prg_rom: Vec<u8>,
+    /// Empty when the board has CHR-RAM instead
+    pub chr_rom: Vec<u8>,
+    pub nt_arrangement: NametableArrangement,
+    /// The PRG-RAM keeps its contents with the power off, so it should be saved
+    pub battery: bool,
+    /// Bytes of battery-backed PRG-RAM the header declares, 0 without a battery. The boards here have 8 KB of
+    /// PRG-RAM whatever this says, and that is what a .sav holds (see sav).
+    pub save_size: usize,
+    /// 512 bytes that go into PRG-RAM at $7000-$71FF before the game starts
+    pub trainer: Option<Vec<u8>>,
+    pub console: ConsoleType,
+}
+
+/// Which game a cartridge holds, as far as save states are concerned: its mapper and a checksum of its ROMs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CartridgeId {
+    pub mapper_number: u8,
+    pub rom_checksum: u64,
+}
+
+impl Cartridge {
+    pub fn from_ines_rom(path: &str) -> Result<Self> {
         let mut file = File::open(path)?;
         let mut buffer = Vec::new();
 
@@ -27,34 +184,1585 @@ impl Mapper for NROM {
 
         info!("Loaded {} bytes from ROM", buffer.len());
 
-        let mapper_flags = buffer[7] >> 4;
-        info!("Mapper type: {}", mapper_flags);
+        Self::from_ines_bytes(&buffer)
+    }
+
+    /// Parse an iNES image that is already in memory (fetched over the network, embedded in a test, ...)
+    pub fn from_ines_bytes(buffer: &[u8]) -> Result<Self> {
+        if buffer.len() < 16 {
+            bail!("iNES header is 16 bytes, got {}", buffer.len());
+        }
+        if buffer[..4] != *b"NES\x1A" {
+            bail!("not an iNES image, it doesn't start with NES<EOF>");
+        }
+        if buffer[4] == 0 {
+            bail!("header says there is no PRG-ROM");
+        }
 
-        let prg_rom_size = buffer[4];
-        info!("Program ROM size: {} kb", prg_rom_size * 16);
+        let mapper_number = (buffer[7] & 0xF0) | (buffer[6] >> 4);
+        info!("Mapper type: {}", mapper_number);
 
-        let prg_rom_size: usize = prg_rom_size as usize * 16384;
-        info!("Copying {} bytes", prg_rom_size);
+        let prg_rom_size: usize = buffer[4] as usize * 16384;
+        info!("Program ROM size: {} kb", prg_rom_size / 1024);
 
-        let prg_rom = &buffer[16..(16 + prg_rom_size)];
+        let chr_rom_size: usize = buffer[5] as usize * 8192;
 
-        // implementing NROM mapper (mapper 0) for now
-        // copy prg-rom to 0x8000 and 0xC000
-        wram.buffer[0x8000..(0x8000 + prg_rom_size)].clone_from_slice(prg_rom);
-        wram.buffer[0xC000..(0xC000 + prg_rom_size)].clone_from_slice(prg_rom);
+        // the trainer, if there is one, sits between the header and PRG-ROM
+        let trainer_size = if buffer[6] & 0b100 != 0 { 512 } else { 0 };
+        let prg_start = 16 + trainer_size;
+        if buffer.len() < prg_start + prg_rom_size + chr_rom_size {
+            bail!(
+                "ROM is truncated: header promises {} bytes of trainer, PRG and CHR, only {} present",
+                trainer_size + prg_rom_size + chr_rom_size,
+                buffer.len() - 16
+            );
+        }
 
-        let nt_arrangement = if buffer[6] & 1 == 0 {
+        let nt_arrangement = if buffer[6] & 0b1000 != 0 {
+            NametableArrangement::FourScreen
+        } else if buffer[6] & 1 == 0 {
             NametableArrangement::HorizontalMirror
         } else {
             NametableArrangement::VerticalMirror
         };
 
-        let chr_rom_size = buffer[5];
-        let chr_rom_size: usize = chr_rom_size as usize * 8192;
+        let battery = buffer[6] & 0b10 != 0;
+        // NES 2.0 headers give the battery-backed RAM as a shift count (64 << n bytes); iNES 1.0 ones have no
+        // reliable field for it (byte 8 is often garbage from old dumping tools), and 8 KB is what boards carry
+        let nes2 = buffer[7] & 0x0C == 0x08;
+        let save_size = match (battery, nes2, buffer[10] >> 4) {
+            (false, _, _) => 0,
+            (true, true, shift) if shift > 0 => 64 << shift,
+            _ => 0x2000,
+        };
+        let console = match buffer[7] & 0b11 {
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::PlayChoice10,
+            _ => ConsoleType::Nes,
+        };
+        // NES 2.0 byte 13 names the Vs. PPU. RP2C04-0001 to -0004 put the 64 colors in a different order
+        // each, which isn't emulated, so their games would come out in the wrong colors
+        let vs_ppu = buffer[13] & 0x0F;
+        if console == ConsoleType::VsSystem && nes2 && (2..=5).contains(&vs_ppu) {
+            bail!(
+                "Vs. System image for an RP2C04-000{} PPU, whose palette isn't supported",
+                vs_ppu - 1
+            );
+        }
+
+        let trainer = (trainer_size > 0).then(|| buffer[16..prg_start].to_vec());
+        let chr_start = prg_start + prg_rom_size;
+        let prg_rom = buffer[prg_start..chr_start].to_vec();
+        let chr_rom = buffer[chr_start..(chr_start + chr_rom_size)].to_vec();
+
+        Ok(Self {
+            mapper_number,
+            prg_rom,
+            chr_rom,
+            nt_arrangement,
+            battery,
+            save_size,
+            trainer,
+            console,
+        })
+    }
+
+    pub fn id(&self) -> CartridgeId {
+        CartridgeId {
+            mapper_number: self.mapper_number,
+            rom_checksum: checksum(&[self.prg_rom.as_slice(), &self.chr_rom].concat()),
+        }
+    }
+
+    /// Build the mapper the header asks for, ready to be put on the buses
+    pub fn into_mapper(self) -> Result<SharedMapper> {
+        Ok(Rc::new(RefCell::new(self.build_mapper()?)))
+    }
+
+    /// Look the header's mapper number up in `SUPPORTED_MAPPERS` and wrap that implementation around the image
+    pub fn build_mapper(mut self) -> Result<Box<dyn Mapper>> {
+        let trainer = self.trainer.take();
+        let mapper_number = self.mapper_number;
+        let Some((_, _, constructor)) = SUPPORTED_MAPPERS
+            .iter()
+            .find(|(number, _, _)| *number == mapper_number)
+        else {
+            let supported = SUPPORTED_MAPPERS
+                .iter()
+                .map(|(number, name, _)| format!("{} ({})", number, name))
+                .collect::<Vec<_>>()
+                .join(", ");
+            bail!(
+                "mapper {} is not supported, supported mappers are {}",
+                mapper_number,
+                supported
+            );
+        };
+
+        let mut mapper = constructor(self)?;
+        if let Some(trainer) = trainer {
+            match mapper.prg_ram_mut() {
+                Some(ram) => ram[0x1000..0x1200].copy_from_slice(&trainer),
+                None => bail!(
+                    "ROM has a trainer but mapper {} has no PRG-RAM",
+                    mapper_number
+                ),
+            }
+        }
+        Ok(mapper)
+    }
+}
+
+type MapperConstructor = fn(Cartridge) -> Result<Box<dyn Mapper>>;
+
+/// Every mapper number that has an implementation, with the board name it's usually known by
+pub const SUPPORTED_MAPPERS: &[(u8, &str, MapperConstructor)] = &[
+    (0, "NROM", |cartridge| Ok(Box::new(NROM::new(cartridge)?))),
+    (1, "MMC1", |cartridge| Ok(Box::new(MMC1::new(cartridge)?))),
+    (2, "UxROM", |cartridge| Ok(Box::new(UxROM::new(cartridge)?))),
+    (3, "CNROM", |cartridge| Ok(Box::new(CNROM::new(cartridge)?))),
+    (4, "MMC3", |cartridge| Ok(Box::new(MMC3::new(cartridge)?))),
+    (7, "AxROM", |cartridge| Ok(Box::new(AxROM::new(cartridge)?))),
+];
+
+/// A bare iNES image for tests: the header for `mapper` with `prg_banks` 16 KB banks of PRG-ROM and `chr_banks`
+/// 8 KB banks of CHR-ROM (0 for CHR-RAM), then both ROMs zeroed. Tests set the flags and bytes they care about.
+/// Not `#[cfg(test)]`, so that the binaries' tests can build images too.
+pub fn test_image(mapper: u8, prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+    let mut image = vec![0; 16 + prg_banks as usize * 0x4000 + chr_banks as usize * 0x2000];
+    image[..4].copy_from_slice(b"NES\x1A");
+    image[4] = prg_banks;
+    image[5] = chr_banks;
+    image[6] = mapper << 4;
+    image[7] = mapper & 0xF0;
+    image
+}
+
+/// Read an iNES file and instantiate whichever mapper its header asks for
+pub fn load_rom(path: &str) -> Result<Box<dyn Mapper>> {
+    Cartridge::from_ines_rom(path)?.build_mapper()
+}
+
+/// Mapper 0: no bank switching. 16 or 32 KB of PRG-ROM (16 KB is mirrored into $C000),
+/// 8 KB of CHR-ROM or CHR-RAM, and 8 KB of PRG-RAM at $6000 which test ROMs report their results in.
+pub struct NROM {
+    nt_arrangement: NametableArrangement,
+    prg_rom: Vec<u8>,
+    prg_ram: [u8; 0x2000],
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+}
+
+impl NROM {
+    pub fn new(cartridge: Cartridge) -> Result<Self> {
+        let prg_rom_size = cartridge.prg_rom.len();
+        if prg_rom_size == 0 || prg_rom_size > 0x8000 {
+            bail!(
+                "NROM has 16 or 32 KB of PRG-ROM, header says {} KB",
+                prg_rom_size / 1024
+            );
+        }
+
+        let chr_is_ram = cartridge.chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            vec![0; 0x2000]
+        } else {
+            cartridge.chr_rom
+        };
+
+        Ok(Self {
+            nt_arrangement: cartridge.nt_arrangement,
+            prg_rom: cartridge.prg_rom,
+            prg_ram: [0; 0x2000],
+            chr,
+            chr_is_ram,
+        })
+    }
+}
+
+impl Mapper for NROM {
+    fn cpu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF => self.prg_ram[(address - 0x6000) as usize],
+            0x8000..=0xFFFF => self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if let 0x6000..=0x7FFF = address {
+            self.prg_ram[(address - 0x6000) as usize] = value;
+        }
+    }
+
+    fn ignores_write(&self, address: u16) -> bool {
+        address >= 0x8000
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr[address as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            self.chr[address as usize % 0x2000] = value;
+        }
+    }
+
+    fn nametable_arrangement(&self) -> NametableArrangement {
+        self.nt_arrangement
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn save_state(&self) -> MapperState {
+        MapperState {
+            registers: vec![],
+            nt_arrangement: Some(self.nt_arrangement),
+            prg_ram: self.prg_ram.to_vec(),
+            chr_ram: saved_chr_ram(&self.chr, self.chr_is_ram),
+        }
+    }
+
+    fn load_state(&mut self, state: &MapperState) -> Result<()> {
+        self.nt_arrangement = state.nt_arrangement()?;
+        state.restore_prg_ram(&mut self.prg_ram)?;
+        state.restore_chr_ram(&mut self.chr, self.chr_is_ram)
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        let prg_ram = Region::new(0x6000, 0x7FFF, "PRG-RAM (8 KB)");
+        if self.prg_rom.len() == 0x4000 {
+            vec![
+                prg_ram,
+                Region::new(0x8000, 0xBFFF, "PRG-ROM (16 KB)"),
+                Region::new(0xC000, 0xFFFF, "Mirror of $8000-$BFFF"),
+            ]
+        } else {
+            vec![prg_ram, Region::new(0x8000, 0xFFFF, "PRG-ROM (32 KB)")]
+        }
+    }
+
+    fn ppu_regions(&self) -> Vec<Region> {
+        let pattern_tables = if self.chr_is_ram {
+            "CHR-RAM"
+        } else {
+            "CHR-ROM"
+        };
+        let mut regions = vec![
+            Region::new(
+                0x0000,
+                0x0FFF,
+                &format!("Pattern table 0 ({})", pattern_tables),
+            ),
+            Region::new(
+                0x1000,
+                0x1FFF,
+                &format!("Pattern table 1 ({})", pattern_tables),
+            ),
+        ];
+        regions.extend(nametable_regions(self.nt_arrangement));
+        regions
+    }
+}
+
+/// Mapper 7. Writes to $8000-$FFFF select a 32 KB PRG bank (bits 0-2) and which half of CIRAM all four
+/// nametables show (bit 4). CHR is 8 KB of RAM.
+pub struct AxROM {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    // ...N .PPP: nametable page, PRG bank
+    bank_select: u8,
+}
+
+impl AxROM {
+    pub fn new(cartridge: Cartridge) -> Result<Self> {
+        let prg_rom_size = cartridge.prg_rom.len();
+        if prg_rom_size == 0 || !prg_rom_size.is_multiple_of(0x8000) {
+            bail!(
+                "AxROM switches 32 KB PRG-ROM banks, the image has {} bytes",
+                prg_rom_size
+            );
+        }
+
+        let chr_is_ram = cartridge.chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            vec![0; 0x2000]
+        } else {
+            cartridge.chr_rom
+        };
+
+        Ok(Self {
+            prg_rom: cartridge.prg_rom,
+            chr,
+            chr_is_ram,
+            bank_select: 0,
+        })
+    }
+
+    fn prg_bank(&self) -> usize {
+        (self.bank_select & 0b111) as usize % (self.prg_rom.len() / 0x8000)
+    }
+}
+
+impl Mapper for AxROM {
+    fn cpu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => {
+                let offset = self.prg_bank() * 0x8000 + (address as usize & 0x7FFF);
+                self.prg_rom[offset % self.prg_rom.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.bank_select = value;
+        }
+    }
+
+    fn power_on(&mut self) {
+        self.bank_select = 0;
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr[address as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            self.chr[address as usize % 0x2000] = value;
+        }
+    }
+
+    fn nametable_arrangement(&self) -> NametableArrangement {
+        if self.bank_select & 0x10 == 0 {
+            NametableArrangement::SingleScreenA
+        } else {
+            NametableArrangement::SingleScreenB
+        }
+    }
+
+    fn save_state(&self) -> MapperState {
+        MapperState {
+            registers: vec![self.bank_select],
+            chr_ram: saved_chr_ram(&self.chr, self.chr_is_ram),
+            ..Default::default()
+        }
+    }
+
+    fn load_state(&mut self, state: &MapperState) -> Result<()> {
+        [self.bank_select] = state.registers()?;
+        state.restore_chr_ram(&mut self.chr, self.chr_is_ram)
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        vec![Region::new(
+            0x8000,
+            0xFFFF,
+            &format!(
+                "PRG-ROM bank {} of {} (32 KB)",
+                self.prg_bank(),
+                (self.prg_rom.len() / 0x8000).max(1)
+            ),
+        )]
+    }
+
+    fn ppu_regions(&self) -> Vec<Region> {
+        let chr = if self.chr_is_ram {
+            "CHR-RAM"
+        } else {
+            "CHR-ROM"
+        };
+        let mut regions = vec![
+            Region::new(0x0000, 0x0FFF, &format!("Pattern table 0 ({})", chr)),
+            Region::new(0x1000, 0x1FFF, &format!("Pattern table 1 ({})", chr)),
+        ];
+        regions.extend(nametable_regions(self.nametable_arrangement()));
+        regions
+    }
+}
+
+fn nametable_regions(arrangement: NametableArrangement) -> [Region; 4] {
+    match arrangement {
+        NametableArrangement::HorizontalMirror => [
+            Region::new(0x2000, 0x23FF, "Nametable A (CIRAM $000)"),
+            Region::new(0x2400, 0x27FF, "Nametable A (mirror of $2000)"),
+            Region::new(0x2800, 0x2BFF, "Nametable B (CIRAM $400)"),
+            Region::new(0x2C00, 0x2FFF, "Nametable B (mirror of $2800)"),
+        ],
+        NametableArrangement::VerticalMirror => [
+            Region::new(0x2000, 0x23FF, "Nametable A (CIRAM $000)"),
+            Region::new(0x2400, 0x27FF, "Nametable B (CIRAM $400)"),
+            Region::new(0x2800, 0x2BFF, "Nametable A (mirror of $2000)"),
+            Region::new(0x2C00, 0x2FFF, "Nametable B (mirror of $2400)"),
+        ],
+        NametableArrangement::SingleScreenA => [
+            Region::new(0x2000, 0x23FF, "Nametable A (CIRAM $000)"),
+            Region::new(0x2400, 0x27FF, "Nametable A (mirror of $2000)"),
+            Region::new(0x2800, 0x2BFF, "Nametable A (mirror of $2000)"),
+            Region::new(0x2C00, 0x2FFF, "Nametable A (mirror of $2000)"),
+        ],
+        NametableArrangement::SingleScreenB => [
+            Region::new(0x2000, 0x23FF, "Nametable B (CIRAM $400)"),
+            Region::new(0x2400, 0x27FF, "Nametable B (mirror of $2000)"),
+            Region::new(0x2800, 0x2BFF, "Nametable B (mirror of $2000)"),
+            Region::new(0x2C00, 0x2FFF, "Nametable B (mirror of $2000)"),
+        ],
+        NametableArrangement::FourScreen => [
+            Region::new(0x2000, 0x23FF, "Nametable A (CIRAM $000)"),
+            Region::new(0x2400, 0x27FF, "Nametable B (CIRAM $400)"),
+            Region::new(0x2800, 0x2BFF, "Nametable C (cartridge RAM $000)"),
+            Region::new(0x2C00, 0x2FFF, "Nametable D (cartridge RAM $400)"),
+        ],
+    }
+}
+
+/// Mapper 1 (SxROM). Registers are loaded one bit at a time through a 5-bit shift register:
+/// writes to $8000-$FFFF shift bit 0 in, and the fifth write copies it into the register picked by
+/// address bits 13-14 ($8000 control, $A000 CHR bank 0, $C000 CHR bank 1, $E000 PRG bank).
+/// Writing a value with bit 7 set clears the shift register and locks the last PRG bank at $C000.
+pub struct MMC1 {
+    prg_rom: Vec<u8>,
+    prg_ram: [u8; 0x2000],
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+
+    shift: u8,
+    shift_count: u8,
+    // 43210
+    // CPPMM: CHR mode, PRG mode, mirroring
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    // bit 4 disables PRG-RAM
+    prg_bank: u8,
+}
+
+impl MMC1 {
+    pub fn new(cartridge: Cartridge) -> Result<Self> {
+        let prg_rom_size = cartridge.prg_rom.len();
+        if prg_rom_size == 0 || !prg_rom_size.is_multiple_of(0x4000) {
+            bail!(
+                "MMC1 switches 16 KB PRG-ROM banks, the image has {} bytes",
+                prg_rom_size
+            );
+        }
+
+        let chr_is_ram = cartridge.chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            vec![0; 0x2000]
+        } else {
+            cartridge.chr_rom
+        };
+
+        Ok(Self {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: [0; 0x2000],
+            chr,
+            chr_is_ram,
+            shift: 0,
+            shift_count: 0,
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        })
+    }
+
+    fn write_serial(&mut self, address: u16, value: u8) {
+        if value & 0x80 != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0x0C;
+            return;
+        }
+
+        self.shift |= (value & 1) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count < 5 {
+            return;
+        }
+
+        let register = self.shift;
+        self.shift = 0;
+        self.shift_count = 0;
+        match address {
+            0x8000..=0x9FFF => self.control = register,
+            0xA000..=0xBFFF => self.chr_bank_0 = register,
+            0xC000..=0xDFFF => self.chr_bank_1 = register,
+            _ => self.prg_bank = register,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0
+    }
+
+    /// Which 16 KB PRG bank is mapped at $8000 (`half` 0) or $C000 (`half` 1)
+    fn prg_bank_at(&self, half: usize) -> usize {
+        let last = self.prg_rom.len() / 0x4000 - 1;
+        let bank = (self.prg_bank & 0x0F) as usize;
+        match (self.control >> 2) & 0b11 {
+            // 32 KB mode ignores the low bit
+            0 | 1 => (bank & !1) + half,
+            2 => [0, bank][half],
+            _ => [bank, last][half],
+        }
+    }
+
+    /// Offset into CHR of the 4 KB bank mapped at $0000 (`half` 0) or $1000 (`half` 1)
+    fn chr_offset(&self, address: u16) -> usize {
+        let half = (address >> 12) as usize & 1;
+        let bank = if self.control & 0x10 == 0 {
+            // 8 KB mode ignores the low bit
+            (self.chr_bank_0 as usize & !1) + half
+        } else {
+            [self.chr_bank_0, self.chr_bank_1][half] as usize
+        };
+        (bank * 0x1000 + (address as usize & 0x0FFF)) % self.chr.len()
+    }
+}
+
+impl Mapper for MMC1 {
+    fn cpu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[(address - 0x6000) as usize],
+            0x8000..=0xFFFF => {
+                let bank = self.prg_bank_at(((address - 0x8000) / 0x4000) as usize);
+                self.prg_rom[(bank * 0x4000 + (address as usize & 0x3FFF)) % self.prg_rom.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram[(address - 0x6000) as usize] = value
+            }
+            0x8000..=0xFFFF => self.write_serial(address, value),
+            _ => {}
+        }
+    }
+
+    fn power_on(&mut self) {
+        self.shift = 0;
+        self.shift_count = 0;
+        self.control = 0x0C;
+        self.chr_bank_0 = 0;
+        self.chr_bank_1 = 0;
+        self.prg_bank = 0;
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn chr_source(&self, address: u16) -> usize {
+        self.chr_offset(address)
+    }
+
+    fn nametable_arrangement(&self) -> NametableArrangement {
+        match self.control & 0b11 {
+            0 => NametableArrangement::SingleScreenA,
+            1 => NametableArrangement::SingleScreenB,
+            2 => NametableArrangement::VerticalMirror,
+            _ => NametableArrangement::HorizontalMirror,
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn save_state(&self) -> MapperState {
+        MapperState {
+            registers: vec![
+                self.shift,
+                self.shift_count,
+                self.control,
+                self.chr_bank_0,
+                self.chr_bank_1,
+                self.prg_bank,
+            ],
+            nt_arrangement: None,
+            prg_ram: self.prg_ram.to_vec(),
+            chr_ram: saved_chr_ram(&self.chr, self.chr_is_ram),
+        }
+    }
+
+    fn load_state(&mut self, state: &MapperState) -> Result<()> {
+        [
+            self.shift,
+            self.shift_count,
+            self.control,
+            self.chr_bank_0,
+            self.chr_bank_1,
+            self.prg_bank,
+        ] = state.registers()?;
+        state.restore_prg_ram(&mut self.prg_ram)?;
+        state.restore_chr_ram(&mut self.chr, self.chr_is_ram)
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        let banks = self.prg_rom.len() / 0x4000;
+        let describe = |half: usize| {
+            format!(
+                "PRG-ROM bank {} of {} (16 KB)",
+                self.prg_bank_at(half) % banks,
+                banks
+            )
+        };
+        let prg_ram = if self.prg_ram_enabled() {
+            "PRG-RAM (8 KB)"
+        } else {
+            "PRG-RAM (8 KB, disabled)"
+        };
+        vec![
+            Region::new(0x6000, 0x7FFF, prg_ram),
+            Region::new(0x8000, 0xBFFF, &describe(0)),
+            Region::new(0xC000, 0xFFFF, &describe(1)),
+        ]
+    }
+
+    fn ppu_regions(&self) -> Vec<Region> {
+        let chr = if self.chr_is_ram {
+            "CHR-RAM"
+        } else {
+            "CHR-ROM"
+        };
+        let describe = |address: u16| {
+            format!(
+                "Pattern table {} ({} bank {}, 4 KB)",
+                address >> 12,
+                chr,
+                self.chr_offset(address) / 0x1000
+            )
+        };
+        let mut regions = vec![
+            Region::new(0x0000, 0x0FFF, &describe(0x0000)),
+            Region::new(0x1000, 0x1FFF, &describe(0x1000)),
+        ];
+        regions.extend(nametable_regions(self.nametable_arrangement()));
+        regions
+    }
+}
+
+/// Mapper 2. Any write to $8000-$FFFF selects the 16 KB PRG bank at $8000, the last bank is fixed at $C000.
+/// CHR is almost always 8 KB of RAM.
+pub struct UxROM {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    nt_arrangement: NametableArrangement,
+    prg_bank: u8,
+}
+
+impl UxROM {
+    pub fn new(cartridge: Cartridge) -> Result<Self> {
+        let prg_rom_size = cartridge.prg_rom.len();
+        if prg_rom_size == 0 || !prg_rom_size.is_multiple_of(0x4000) {
+            bail!(
+                "UxROM switches 16 KB PRG-ROM banks, the image has {} bytes",
+                prg_rom_size
+            );
+        }
+
+        let chr_is_ram = cartridge.chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            vec![0; 0x2000]
+        } else {
+            cartridge.chr_rom
+        };
+
+        Ok(Self {
+            prg_rom: cartridge.prg_rom,
+            chr,
+            chr_is_ram,
+            nt_arrangement: cartridge.nt_arrangement,
+            prg_bank: 0,
+        })
+    }
+
+    fn prg_bank_at(&self, address: u16) -> usize {
+        let banks = self.prg_rom.len() / 0x4000;
+        match address {
+            0x8000..=0xBFFF => self.prg_bank as usize % banks,
+            _ => banks - 1,
+        }
+    }
+}
+
+impl Mapper for UxROM {
+    fn cpu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => {
+                self.prg_rom[self.prg_bank_at(address) * 0x4000 + (address as usize & 0x3FFF)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.prg_bank = value;
+        }
+    }
+
+    fn power_on(&mut self) {
+        self.prg_bank = 0;
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr[address as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            self.chr[address as usize % 0x2000] = value;
+        }
+    }
+
+    fn nametable_arrangement(&self) -> NametableArrangement {
+        self.nt_arrangement
+    }
+
+    fn save_state(&self) -> MapperState {
+        MapperState {
+            registers: vec![self.prg_bank],
+            nt_arrangement: Some(self.nt_arrangement),
+            prg_ram: vec![],
+            chr_ram: saved_chr_ram(&self.chr, self.chr_is_ram),
+        }
+    }
+
+    fn load_state(&mut self, state: &MapperState) -> Result<()> {
+        [self.prg_bank] = state.registers()?;
+        self.nt_arrangement = state.nt_arrangement()?;
+        state.restore_chr_ram(&mut self.chr, self.chr_is_ram)
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        let banks = self.prg_rom.len() / 0x4000;
+        vec![
+            Region::new(
+                0x8000,
+                0xBFFF,
+                &format!(
+                    "PRG-ROM bank {} of {} (16 KB)",
+                    self.prg_bank_at(0x8000),
+                    banks
+                ),
+            ),
+            Region::new(
+                0xC000,
+                0xFFFF,
+                &format!("PRG-ROM bank {} of {} (16 KB, fixed)", banks - 1, banks),
+            ),
+        ]
+    }
+
+    fn ppu_regions(&self) -> Vec<Region> {
+        let chr = if self.chr_is_ram {
+            "CHR-RAM"
+        } else {
+            "CHR-ROM"
+        };
+        let mut regions = vec![
+            Region::new(0x0000, 0x0FFF, &format!("Pattern table 0 ({})", chr)),
+            Region::new(0x1000, 0x1FFF, &format!("Pattern table 1 ({})", chr)),
+        ];
+        regions.extend(nametable_regions(self.nt_arrangement));
+        regions
+    }
+}
+
+/// Mapper 3. PRG is laid out like NROM, any write to $8000-$FFFF selects the 8 KB CHR-ROM bank.
+pub struct CNROM {
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    nt_arrangement: NametableArrangement,
+    chr_bank: u8,
+}
+
+impl CNROM {
+    pub fn new(cartridge: Cartridge) -> Result<Self> {
+        if cartridge.chr_rom.is_empty() {
+            bail!("CNROM switches CHR-ROM banks, but the image has no CHR-ROM");
+        }
+
+        Ok(Self {
+            prg_rom: cartridge.prg_rom,
+            chr_rom: cartridge.chr_rom,
+            nt_arrangement: cartridge.nt_arrangement,
+            chr_bank: 0,
+        })
+    }
+
+    fn chr_bank(&self) -> usize {
+        self.chr_bank as usize % (self.chr_rom.len() / 0x2000)
+    }
+}
+
+impl Mapper for CNROM {
+    fn cpu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => self.prg_rom[(address - 0x8000) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.chr_bank = value;
+        }
+    }
+
+    fn power_on(&mut self) {
+        self.chr_bank = 0;
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr_rom[self.chr_source(address)]
+    }
+
+    fn ppu_write(&mut self, _address: u16, _value: u8) {}
+
+    fn chr_source(&self, address: u16) -> usize {
+        self.chr_bank() * 0x2000 + (address as usize & 0x1FFF)
+    }
+
+    fn nametable_arrangement(&self) -> NametableArrangement {
+        self.nt_arrangement
+    }
+
+    fn save_state(&self) -> MapperState {
+        MapperState {
+            registers: vec![self.chr_bank],
+            nt_arrangement: Some(self.nt_arrangement),
+            ..Default::default()
+        }
+    }
+
+    fn load_state(&mut self, state: &MapperState) -> Result<()> {
+        [self.chr_bank] = state.registers()?;
+        self.nt_arrangement = state.nt_arrangement()?;
+        Ok(())
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        if self.prg_rom.len() == 0x4000 {
+            vec![
+                Region::new(0x8000, 0xBFFF, "PRG-ROM (16 KB)"),
+                Region::new(0xC000, 0xFFFF, "Mirror of $8000-$BFFF"),
+            ]
+        } else {
+            vec![Region::new(0x8000, 0xFFFF, "PRG-ROM (32 KB)")]
+        }
+    }
+
+    fn ppu_regions(&self) -> Vec<Region> {
+        let banks = self.chr_rom.len() / 0x2000;
+        let mut regions = vec![Region::new(
+            0x0000,
+            0x1FFF,
+            &format!(
+                "Pattern tables (CHR-ROM bank {} of {}, 8 KB)",
+                self.chr_bank(),
+                banks
+            ),
+        )];
+        regions.extend(nametable_regions(self.nt_arrangement));
+        regions
+    }
+}
+
+/// Mapper 4 (TxROM). Eight bank registers are written in two steps: the even address in $8000-$9FFF picks
+/// which one (bits 0-2) along with the PRG and CHR layouts (bits 6 and 7), the odd address sets it.
+/// R0-R1 are 2 KB and R2-R5 1 KB CHR banks, R6-R7 8 KB PRG banks; the second-to-last and last PRG banks
+/// are fixed. A scanline counter reloaded from $C000 raises an IRQ when it reaches zero.
+pub struct MMC3 {
+    prg_rom: Vec<u8>,
+    prg_ram: [u8; 0x2000],
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    nt_arrangement: NametableArrangement,
+
+    bank_select: u8,
+    banks: [u8; 8],
+    // bit 7 enables, bit 6 write-protects
+    prg_ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl MMC3 {
+    pub fn new(cartridge: Cartridge) -> Result<Self> {
+        // the last two 8 KB banks are fixed, so there have to be at least two
+        let prg_rom_size = cartridge.prg_rom.len();
+        if prg_rom_size < 0x4000 || !prg_rom_size.is_multiple_of(0x2000) {
+            bail!(
+                "MMC3 needs at least two 8 KB PRG-ROM banks, the image has {} bytes",
+                prg_rom_size
+            );
+        }
+
+        let chr_is_ram = cartridge.chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            vec![0; 0x2000]
+        } else {
+            cartridge.chr_rom
+        };
+
+        Ok(Self {
+            prg_rom: cartridge.prg_rom,
+            prg_ram: [0; 0x2000],
+            chr,
+            chr_is_ram,
+            nt_arrangement: cartridge.nt_arrangement,
+            bank_select: 0,
+            banks: [0; 8],
+            prg_ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        })
+    }
+
+    /// Which 8 KB PRG bank is mapped at $8000 + `slot` * $2000
+    fn prg_bank_at(&self, slot: usize) -> usize {
+        let banks = self.prg_rom.len() / 0x2000;
+        let second_last = banks - 2;
+        let bank = match (self.bank_select & 0x40 != 0, slot) {
+            (false, 0) | (true, 2) => self.banks[6] as usize,
+            (_, 1) => self.banks[7] as usize,
+            (false, 2) | (true, 0) => second_last,
+            _ => banks - 1,
+        };
+        bank % banks
+    }
+
+    /// Which 1 KB CHR bank is mapped at `address`
+    fn chr_bank_at(&self, address: u16) -> usize {
+        let mut slot = (address >> 10) as usize & 7;
+        if self.bank_select & 0x80 != 0 {
+            slot ^= 4;
+        }
+        match slot {
+            // R0 and R1 are 2 KB banks, their low bit is ignored
+            0..=3 => (self.banks[slot / 2] as usize & !1) + slot % 2,
+            _ => self.banks[slot - 2] as usize,
+        }
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        (self.chr_bank_at(address) * 0x400 + (address as usize & 0x3FF)) % self.chr.len()
+    }
+}
+
+impl Mapper for MMC3 {
+    fn cpu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_protect & 0x80 != 0 => {
+                self.prg_ram[(address - 0x6000) as usize]
+            }
+            0x8000..=0xFFFF => {
+                let bank = self.prg_bank_at(((address - 0x8000) / 0x2000) as usize);
+                self.prg_rom[bank * 0x2000 + (address as usize & 0x1FFF)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        let even = address & 1 == 0;
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_protect & 0xC0 == 0x80 => {
+                self.prg_ram[(address - 0x6000) as usize] = value
+            }
+            0x8000..=0x9FFF if even => self.bank_select = value,
+            0x8000..=0x9FFF => self.banks[(self.bank_select & 7) as usize] = value,
+            // boards with four-screen VRAM don't wire up the mirroring register
+            0xA000..=0xBFFF if even && self.nt_arrangement == NametableArrangement::FourScreen => {}
+            0xA000..=0xBFFF if even => {
+                self.nt_arrangement = if value & 1 == 0 {
+                    NametableArrangement::VerticalMirror
+                } else {
+                    NametableArrangement::HorizontalMirror
+                }
+            }
+            0xA000..=0xBFFF => self.prg_ram_protect = value,
+            0xC000..=0xDFFF if even => self.irq_latch = value,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn power_on(&mut self) {
+        self.bank_select = 0;
+        self.banks = [0; 8];
+        self.prg_ram_protect = 0x80;
+        self.irq_latch = 0;
+        self.irq_counter = 0;
+        self.irq_reload = false;
+        self.irq_enabled = false;
+        self.irq_pending = false;
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address);
+            self.chr[offset] = value;
+        }
+    }
+
+    fn chr_source(&self, address: u16) -> usize {
+        self.chr_offset(address)
+    }
+
+    fn nametable_arrangement(&self) -> NametableArrangement {
+        self.nt_arrangement
+    }
+
+    fn clock_scanline(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn save_state(&self) -> MapperState {
+        let mut registers = vec![self.bank_select];
+        registers.extend(self.banks);
+        registers.extend([
+            self.prg_ram_protect,
+            self.irq_latch,
+            self.irq_counter,
+            self.irq_reload as u8,
+            self.irq_enabled as u8,
+            self.irq_pending as u8,
+        ]);
+        MapperState {
+            registers,
+            nt_arrangement: Some(self.nt_arrangement),
+            prg_ram: self.prg_ram.to_vec(),
+            chr_ram: saved_chr_ram(&self.chr, self.chr_is_ram),
+        }
+    }
+
+    fn load_state(&mut self, state: &MapperState) -> Result<()> {
+        let [bank_select, banks @ .., protect, latch, counter, reload, enabled, pending] =
+            state.registers::<15>()?;
+        self.bank_select = bank_select;
+        self.banks = banks;
+        self.prg_ram_protect = protect;
+        self.irq_latch = latch;
+        self.irq_counter = counter;
+        self.irq_reload = reload != 0;
+        self.irq_enabled = enabled != 0;
+        self.irq_pending = pending != 0;
+        self.nt_arrangement = state.nt_arrangement()?;
+        state.restore_prg_ram(&mut self.prg_ram)?;
+        state.restore_chr_ram(&mut self.chr, self.chr_is_ram)
+    }
+
+    fn cpu_regions(&self) -> Vec<Region> {
+        let banks = self.prg_rom.len() / 0x2000;
+        let mut regions = vec![Region::new(0x6000, 0x7FFF, "PRG-RAM (8 KB)")];
+        for slot in 0..4 {
+            let start = 0x8000 + slot as u16 * 0x2000;
+            regions.push(Region::new(
+                start,
+                start + 0x1FFF,
+                &format!(
+                    "PRG-ROM bank {} of {} (8 KB)",
+                    self.prg_bank_at(slot),
+                    banks
+                ),
+            ));
+        }
+        regions
+    }
+
+    fn ppu_regions(&self) -> Vec<Region> {
+        let chr = if self.chr_is_ram {
+            "CHR-RAM"
+        } else {
+            "CHR-ROM"
+        };
+        let mut regions = vec![];
+        for slot in 0..8 {
+            let start = slot * 0x400;
+            regions.push(Region::new(
+                start,
+                start + 0x3FF,
+                &format!(
+                    "Pattern table {} ({} bank {}, 1 KB)",
+                    start >> 12,
+                    chr,
+                    self.chr_offset(start) / 0x400
+                ),
+            ));
+        }
+        regions.extend(nametable_regions(self.nt_arrangement));
+        regions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(prg_banks: u8, prg: u8, chr_banks: u8, chr: u8) -> Vec<u8> {
+        mapper_image(0, prg_banks, prg, chr_banks, chr)
+    }
+
+    /// Every PRG and CHR byte is `prg`/`chr` plus the index of the 16 KB/4 KB bank it's in
+    fn mapper_image(mapper: u8, prg_banks: u8, prg: u8, chr_banks: u8, chr: u8) -> Vec<u8> {
+        let mut image = test_image(mapper, prg_banks, chr_banks);
+        let (prg_rom, chr_rom) = image[16..].split_at_mut(prg_banks as usize * 0x4000);
+        for (bank, bytes) in prg_rom.chunks_mut(0x4000).enumerate() {
+            bytes.fill(prg + bank as u8);
+        }
+        for (bank, bytes) in chr_rom.chunks_mut(0x1000).enumerate() {
+            bytes.fill(chr + bank as u8);
+        }
+        image
+    }
+
+    #[test]
+    fn loads_image_from_bytes() {
+        let mapper = Cartridge::from_ines_bytes(&image(1, 0xEA, 1, 0x55))
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        assert_eq!(mapper.cpu_read(0x8000), 0xEA);
+        assert_eq!(mapper.cpu_read(0xFFFF), 0xEA);
+        assert_eq!(mapper.ppu_read(0x0000), 0x55);
+        assert_eq!(mapper.ppu_read(0x1FFF), 0x56);
+    }
+
+    #[test]
+    fn rejects_truncated_image() {
+        let mut image = image(1, 0xEA, 1, 0x55);
+        image[4] = 2;
+
+        assert!(Cartridge::from_ines_bytes(&image).is_err());
+        assert!(Cartridge::from_ines_bytes(&image[..8]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let mut image = image(1, 0xEA, 1, 0x55);
+        image[3] = 0x00;
+        assert!(Cartridge::from_ines_bytes(&image).is_err());
+
+        // no PRG-ROM at all: the header check refuses it before any board sees the image
+        for mapper in [0, 1, 2, 4] {
+            let image = mapper_image(mapper, 0, 0, 1, 0);
+            assert!(Cartridge::from_ines_bytes(&image).is_err());
+        }
+    }
+
+    #[test]
+    fn unknown_mapper_lists_supported_ones() {
+        let err = Cartridge::from_ines_bytes(&mapper_image(5, 2, 0, 1, 0))
+            .and_then(Cartridge::into_mapper)
+            .err()
+            .unwrap();
+
+        assert!(err.to_string().starts_with("mapper 5 is not supported"));
+        assert!(err.to_string().contains("4 (MMC3)"), "{}", err);
+    }
+
+    #[test]
+    fn save_size_comes_from_the_header() {
+        let save_size = |flags6, flags7, byte10| {
+            let mut image = image(1, 0xEA, 1, 0x55);
+            (image[6], image[7], image[10]) = (flags6, flags7, byte10);
+            Cartridge::from_ines_bytes(&image).unwrap().save_size
+        };
+
+        assert_eq!(save_size(0, 0, 0), 0);
+        // iNES 1.0 ignores byte 10
+        assert_eq!(save_size(0b10, 0, 0x90), 0x2000);
+        // NES 2.0: 64 << 9 = 32 KB
+        assert_eq!(save_size(0b10, 0x08, 0x90), 0x8000);
+    }
+
+    #[test]
+    fn reads_console_type() {
+        let mut image = image(1, 0xEA, 1, 0x55);
+        assert_eq!(
+            Cartridge::from_ines_bytes(&image).unwrap().console,
+            ConsoleType::Nes
+        );
+        image[7] |= 0b01;
+        assert_eq!(
+            Cartridge::from_ines_bytes(&image).unwrap().console,
+            ConsoleType::VsSystem
+        );
+        image[7] ^= 0b11;
+        assert_eq!(
+            Cartridge::from_ines_bytes(&image).unwrap().console,
+            ConsoleType::PlayChoice10
+        );
+    }
+
+    #[test]
+    fn refuses_vs_system_rp2c04_palettes() {
+        let mut image = image(1, 0xEA, 1, 0x55);
+        image[7] |= 0x08 | 0b01;
+        // RP2C03B, then RP2C04-0001 and -0004
+        assert!(Cartridge::from_ines_bytes(&image).is_ok());
+        for (ppu, name) in [(2, "RP2C04-0001"), (5, "RP2C04-0004")] {
+            image[13] = ppu;
+            let err = Cartridge::from_ines_bytes(&image).err().unwrap();
+            assert!(err.to_string().contains(name), "{}", err);
+        }
+        // byte 13 means something else on an NES
+        image[7] &= !0b11;
+        assert!(Cartridge::from_ines_bytes(&image).is_ok());
+    }
+
+    #[test]
+    fn trainer_goes_to_prg_ram() {
+        let mut image = image(1, 0xEA, 1, 0x55);
+        image[6] |= 0b100;
+        image.splice(16..16, vec![0x77; 512]);
+        let mapper = Cartridge::from_ines_bytes(&image)
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        assert_eq!(mapper.cpu_read(0x6FFF), 0);
+        assert_eq!(mapper.cpu_read(0x7000), 0x77);
+        assert_eq!(mapper.cpu_read(0x71FF), 0x77);
+        assert_eq!(mapper.cpu_read(0x7200), 0);
+        assert_eq!(mapper.cpu_read(0x8000), 0xEA);
+        assert_eq!(mapper.ppu_read(0x0000), 0x55);
+    }
+
+    #[test]
+    fn only_ram_is_writable() {
+        let mapper = Cartridge::from_ines_bytes(&image(2, 0xEA, 0, 0))
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        mapper.cpu_write(0x8000, 0x12);
+        mapper.cpu_write(0x6000, 0x34);
+        mapper.ppu_write(0x0010, 0x56);
+        assert_eq!(mapper.cpu_read(0x8000), 0xEA);
+        assert_eq!(mapper.cpu_read(0x6000), 0x34);
+        assert_eq!(mapper.ppu_read(0x0010), 0x56);
+    }
+
+    /// Shift `value` into an MMC1 register one bit per write, the way games do it
+    fn mmc1_write(mapper: &mut Box<dyn Mapper>, address: u16, value: u8) {
+        for bit in 0..5 {
+            mapper.cpu_write(address, value >> bit);
+        }
+    }
+
+    #[test]
+    fn mmc1_needs_whole_prg_banks() {
+        let mut cartridge = Cartridge::from_ines_bytes(&mapper_image(1, 2, 0, 0, 0)).unwrap();
+        cartridge.prg_rom.clear();
+        assert!(MMC1::new(cartridge).is_err());
+    }
+
+    #[test]
+    fn mmc1_switches_prg_banks() {
+        let mapper = Cartridge::from_ines_bytes(&mapper_image(1, 8, 0x10, 0, 0))
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        // power-on: switchable bank at $8000, last bank fixed at $C000
+        assert_eq!(mapper.cpu_read(0x8000), 0x10);
+        assert_eq!(mapper.cpu_read(0xC000), 0x17);
+        mmc1_write(&mut mapper, 0xE000, 3);
+        assert_eq!(mapper.cpu_read(0x8000), 0x13);
+
+        // first bank fixed at $8000, switchable bank at $C000
+        mmc1_write(&mut mapper, 0x8000, 0b01000);
+        assert_eq!(mapper.cpu_read(0x8000), 0x10);
+        assert_eq!(mapper.cpu_read(0xC000), 0x13);
+
+        // 32 KB mode drops the low bit of the bank number
+        mmc1_write(&mut mapper, 0x8000, 0b00000);
+        assert_eq!(mapper.cpu_read(0x8000), 0x12);
+        assert_eq!(mapper.cpu_read(0xC000), 0x13);
+        assert_eq!(
+            mapper.nametable_arrangement(),
+            NametableArrangement::SingleScreenA
+        );
+    }
+
+    #[test]
+    fn mmc1_reset_bit_clears_shift_register() {
+        let mapper = Cartridge::from_ines_bytes(&mapper_image(1, 4, 0x10, 0, 0))
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        // two stray bits, then a reset: the next five writes are a whole register again
+        mapper.cpu_write(0xE000, 1);
+        mapper.cpu_write(0xE000, 1);
+        mapper.cpu_write(0xE000, 0x80);
+        mmc1_write(&mut mapper, 0xE000, 2);
+        assert_eq!(mapper.cpu_read(0x8000), 0x12);
+        assert_eq!(mapper.cpu_read(0xC000), 0x13);
+    }
+
+    #[test]
+    fn mmc1_switches_chr_banks() {
+        let mapper = Cartridge::from_ines_bytes(&mapper_image(1, 2, 0, 4, 0x20))
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        // 4 KB mode with separate banks, vertical mirroring
+        mmc1_write(&mut mapper, 0x8000, 0b11110);
+        mmc1_write(&mut mapper, 0xA000, 5);
+        mmc1_write(&mut mapper, 0xC000, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 0x25);
+        assert_eq!(mapper.ppu_read(0x1000), 0x22);
+        assert_eq!(
+            mapper.nametable_arrangement(),
+            NametableArrangement::VerticalMirror
+        );
+
+        // 8 KB mode uses CHR bank 0 without its low bit for both halves
+        mmc1_write(&mut mapper, 0x8000, 0b01110);
+        assert_eq!(mapper.ppu_read(0x0000), 0x24);
+        assert_eq!(mapper.ppu_read(0x1000), 0x25);
+    }
+
+    #[test]
+    fn uxrom_needs_whole_prg_banks() {
+        let mut cartridge = Cartridge::from_ines_bytes(&mapper_image(2, 2, 0, 0, 0)).unwrap();
+        cartridge.prg_rom.clear();
+        assert!(UxROM::new(cartridge).is_err());
+    }
+
+    #[test]
+    fn uxrom_switches_low_prg_bank() {
+        let mapper = Cartridge::from_ines_bytes(&mapper_image(2, 4, 0x10, 0, 0))
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        assert_eq!(mapper.cpu_read(0x8000), 0x10);
+        assert_eq!(mapper.cpu_read(0xC000), 0x13);
+        mapper.cpu_write(0x8000, 2);
+        assert_eq!(mapper.cpu_read(0xBFFF), 0x12);
+        assert_eq!(mapper.cpu_read(0xFFFF), 0x13);
+    }
+
+    #[test]
+    fn cnrom_switches_chr_bank() {
+        let mapper = Cartridge::from_ines_bytes(&mapper_image(3, 1, 0x10, 4, 0x20))
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        mapper.cpu_write(0xFFFF, 3);
+        // 8 KB bank 3 holds 4 KB halves 6 and 7
+        assert_eq!(mapper.ppu_read(0x0000), 0x26);
+        assert_eq!(mapper.ppu_read(0x1FFF), 0x27);
+        assert_eq!(mapper.cpu_read(0xC000), 0x10);
+    }
+
+    #[test]
+    fn mmc3_needs_two_prg_banks() {
+        let mut cartridge = Cartridge::from_ines_bytes(&mapper_image(4, 1, 0, 1, 0)).unwrap();
+        cartridge.prg_rom.truncate(0x2000);
+        assert!(MMC3::new(cartridge).is_err());
+    }
+
+    #[test]
+    fn mmc3_switches_banks() {
+        let mapper = Cartridge::from_ines_bytes(&mapper_image(4, 4, 0x10, 2, 0x20))
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        // R6 = 8 KB bank 1 at $8000, R0 = 2 KB at $0000, R5 = 1 KB at $1C00
+        for (register, bank) in [(6, 1), (0, 2), (5, 7)] {
+            mapper.cpu_write(0x8000, register);
+            mapper.cpu_write(0x8001, bank);
+        }
+        // the image is marked per 16 KB and 4 KB, so 8 KB PRG bank n reads 0x10 + n / 2 and 1 KB CHR bank n 0x20 + n / 4
+        assert_eq!(mapper.cpu_read(0x8000), 0x10);
+        assert_eq!(mapper.cpu_read(0xC000), 0x13);
+        assert_eq!(mapper.ppu_read(0x0000), 0x20);
+        assert_eq!(mapper.ppu_read(0x1C00), 0x21);
+
+        // PRG mode 1 swaps $8000 and $C000, CHR inversion swaps the pattern table halves
+        mapper.cpu_write(0x8000, 0xC0);
+        assert_eq!(mapper.cpu_read(0x8000), 0x13);
+        assert_eq!(mapper.cpu_read(0xC000), 0x10);
+        assert_eq!(mapper.ppu_read(0x1000), 0x20);
+
+        // all of that is gone after a power cycle
+        mapper.power_on();
+        assert_eq!(mapper.cpu_read(0x8000), 0x10);
+        assert_eq!(mapper.cpu_read(0xC000), 0x13);
+        assert_eq!(mapper.ppu_read(0x1C00), 0x20);
+    }
+
+    #[test]
+    fn mmc3_irq_fires_after_latch_scanlines() {
+        let mapper = Cartridge::from_ines_bytes(&mapper_image(4, 2, 0, 1, 0))
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut mapper = mapper.borrow_mut();
+
+        mapper.cpu_write(0xC000, 3);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+        // the first clock reloads the counter, then it counts 3 lines down to zero
+        for _ in 0..3 {
+            mapper.clock_scanline();
+            assert!(!mapper.irq());
+        }
+        mapper.clock_scanline();
+        assert!(mapper.irq());
+
+        mapper.cpu_write(0xE000, 0);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn mmc3_state_restores_banks_irq_and_ram() {
+        let mmc3 = mapper_image(4, 4, 0x10, 0, 0);
+        let saved = Cartridge::from_ines_bytes(&mmc3)
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut saved = saved.borrow_mut();
+        saved.cpu_write(0x8000, 0x46);
+        saved.cpu_write(0x8001, 1);
+        saved.cpu_write(0xA000, 1);
+        saved.cpu_write(0xC000, 2);
+        saved.cpu_write(0xC001, 0);
+        saved.cpu_write(0xE001, 0);
+        saved.clock_scanline();
+        saved.cpu_write(0x6000, 0x42);
+        saved.ppu_write(0x1234, 0x99);
+        let state = saved.save_state();
+
+        let restored = Cartridge::from_ines_bytes(&mmc3)
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut restored = restored.borrow_mut();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.cpu_read(0xC000), 0x10);
+        assert_eq!(restored.cpu_read(0x6000), 0x42);
+        assert_eq!(restored.ppu_read(0x1234), 0x99);
+        assert_eq!(
+            restored.nametable_arrangement(),
+            NametableArrangement::HorizontalMirror
+        );
+        // two more lines reach zero, as they would have on the saved board
+        restored.clock_scanline();
+        assert!(!restored.irq());
+        restored.clock_scanline();
+        assert!(restored.irq());
+
+        // a state from another kind of board doesn't fit
+        let nrom = Cartridge::from_ines_bytes(&image(1, 0, 1, 0))
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        assert!(restored.load_state(&nrom.borrow().save_state()).is_err());
+    }
+
+    #[test]
+    fn axrom_needs_whole_prg_banks() {
+        // one 16 KB bank, half of what AxROM switches in at once
+        let cartridge = Cartridge::from_ines_bytes(&mapper_image(7, 1, 0, 0, 0)).unwrap();
+        assert!(AxROM::new(cartridge).is_err());
+    }
+
+    #[test]
+    fn axrom_switches_prg_and_nametable_page() {
+        let mapper = Cartridge::from_ines_bytes(&mapper_image(7, 8, 0x10, 0, 0))
+            .and_then(Cartridge::into_mapper)
+            .unwrap();
+        let mut mapper = mapper.borrow_mut();
 
-        let chr_rom = &buffer[(16 + prg_rom_size)..((16 + prg_rom_size) + chr_rom_size)];
-        vram.buffer[0x0000..(0x0000 + chr_rom_size)].clone_from_slice(chr_rom);
+        assert_eq!(mapper.cpu_read(0x8000), 0x10);
+        assert_eq!(
+            mapper.nametable_arrangement(),
+            NametableArrangement::SingleScreenA
+        );
 
-        Ok(Self { nt_arrangement })
+        mapper.cpu_write(0x8000, 0x13);
+        // 32 KB bank 3 is 16 KB banks 6 and 7
+        assert_eq!(mapper.cpu_read(0x8000), 0x16);
+        assert_eq!(mapper.cpu_read(0xFFFF), 0x17);
+        assert_eq!(
+            mapper.nametable_arrangement(),
+            NametableArrangement::SingleScreenB
+        );
     }
 }
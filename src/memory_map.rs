@@ -0,0 +1,104 @@
+use std::fmt;
+
+use crate::mappers::Mapper;
+
+/// An inclusive range of an address space and what is wired to it
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    pub description: String,
+}
+
+impl Region {
+    pub fn new(start: u16, end: u16, description: &str) -> Self {
+        Self {
+            start,
+            end,
+            description: description.to_string(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        (self.end - self.start) as usize + 1
+    }
+}
+
+/// How the CPU and PPU address spaces are populated for a loaded cartridge.
+/// The console-internal regions are fixed, everything else comes from the mapper.
+pub struct MemoryMap {
+    pub cpu: Vec<Region>,
+    pub ppu: Vec<Region>,
+}
+
+impl MemoryMap {
+    pub fn new(mapper: &dyn Mapper) -> Self {
+        let mut cpu = vec![
+            Region::new(0x0000, 0x07FF, "Internal RAM"),
+            Region::new(0x0800, 0x1FFF, "Mirrors of $0000-$07FF"),
+            Region::new(0x2000, 0x2007, "PPU registers"),
+            Region::new(0x2008, 0x3FFF, "Mirrors of $2000-$2007"),
+            Region::new(0x4000, 0x4017, "APU and I/O registers"),
+            Region::new(0x4018, 0x401F, "APU and I/O test mode (disabled)"),
+        ];
+        cpu.extend(fill_gaps(0x4020, 0xFFFF, mapper.cpu_regions()));
+
+        let mut ppu = fill_gaps(0x0000, 0x2FFF, mapper.ppu_regions());
+        ppu.extend([
+            Region::new(0x3000, 0x3EFF, "Mirrors of $2000-$2EFF"),
+            Region::new(0x3F00, 0x3F1F, "Palette RAM"),
+            Region::new(0x3F20, 0x3FFF, "Mirrors of $3F00-$3F1F"),
+        ]);
+
+        Self { cpu, ppu }
+    }
+}
+
+/// Sorts the cartridge's regions and marks anything it leaves undecoded between `start` and `end` as open bus
+fn fill_gaps(start: u16, end: u16, mut regions: Vec<Region>) -> Vec<Region> {
+    regions.sort_by_key(|region| region.start);
+
+    let mut filled = vec![];
+    let mut next = start as u32;
+    for region in regions {
+        if (region.start as u32) > next {
+            filled.push(Region::new(
+                next as u16,
+                region.start - 1,
+                "Unmapped (open bus)",
+            ));
+        }
+        next = region.end as u32 + 1;
+        filled.push(region);
+    }
+    if next <= end as u32 {
+        filled.push(Region::new(next as u16, end, "Unmapped (open bus)"));
+    }
+
+    filled
+}
+
+fn write_regions(f: &mut fmt::Formatter, regions: &[Region]) -> fmt::Result {
+    for region in regions {
+        let size = if region.size() >= 1024 {
+            format!("{} KB", region.size() / 1024)
+        } else {
+            format!("{} B", region.size())
+        };
+        writeln!(
+            f,
+            "  ${:04X}-${:04X}  {:>6}  {}",
+            region.start, region.end, size, region.description
+        )?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for MemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "CPU address space")?;
+        write_regions(f, &self.cpu)?;
+        writeln!(f)?;
+        writeln!(f, "PPU address space")?;
+        write_regions(f, &self.ppu)
+    }
+}
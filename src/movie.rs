@@ -0,0 +1,68 @@
+// Input movies: what was held on controller 1 on every frame since power-on, played back by Emulator::play_movie.
+// Read from FCEUX's .fm2, whose input log has a line per frame such as `|0|R..U...A|||`: the commands field,
+// then one field per port with the buttons in RLDUTSBA order, anything but '.' or ' ' meaning held.
+// Header lines (`romFilename smb`, ...) and the other ports are skipped.
+
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Movie {
+    /// Buttons held on each frame, bit 0 A through bit 7 Right (the order the controller shifts them out in)
+    pub frames: Vec<u8>,
+}
+
+impl Movie {
+    pub fn read_fm2(path: &Path) -> Result<Self> {
+        Self::parse_fm2(&fs::read_to_string(path)?)
+            .map_err(|err| anyhow!("{}: {}", path.display(), err))
+    }
+
+    pub fn parse_fm2(text: &str) -> Result<Self> {
+        let mut frames = vec![];
+        for (number, line) in text.lines().enumerate() {
+            let Some(fields) = line.strip_prefix('|') else {
+                continue;
+            };
+            let Some(port) = fields.split('|').nth(1) else {
+                bail!("line {}: no controller 1 field", number + 1);
+            };
+            if port.is_empty() {
+                frames.push(0);
+                continue;
+            }
+            if port.len() != 8 {
+                bail!(
+                    "line {}: controller 1 has 8 buttons, got {:?}",
+                    number + 1,
+                    port
+                );
+            }
+            let buttons = port
+                .chars()
+                .enumerate()
+                .filter(|&(_, c)| c != '.' && c != ' ')
+                .fold(0, |buttons, (i, _)| buttons | 0x80 >> i);
+            frames.push(buttons);
+        }
+        Ok(Self { frames })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fm2_input_log_becomes_buttons_per_frame() {
+        let movie = Movie::parse_fm2(
+            "version 3\nromFilename smb\n|0|........|||\n|0|....T...|||\n|0|R......A|........||\n|1||||\n",
+        )
+        .unwrap();
+        // Start, then Right and A
+        assert_eq!(movie.frames, [0x00, 0x08, 0x81, 0x00]);
+
+        assert!(Movie::parse_fm2("|0|RLDU|||\n").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+// Background/sprite compositing: the pixel math of rendering, without any of its timing. Which layer wins a dot,
+// which palette RAM entry it shows and how PPUMASK changes that. PPU::render_pixel runs composite_pixel once per dot
+// on whatever its shifters and sprite units put out, so register writes still land mid-line.
+
+use super::{PixelSource, SpritePixel};
+
+/// The 2-bit pattern value and palette of the background at one dot, before PPUMASK hides or clips it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct BgPixel {
+    pub pixel: u8,
+    pub palette: u8,
+}
+
+/// What PPUMASK decides about a pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct MaskFlags {
+    pub show_background: bool,
+    pub show_sprites: bool,
+    /// Hide the layer in the leftmost 8 pixels
+    pub clip_background: bool,
+    pub clip_sprites: bool,
+    /// $30 in greyscale mode, which keeps only the grey column of the palette, otherwise $3F
+    pub greyscale_mask: u8,
+    /// Red, green and blue emphasis in bits 0-2
+    pub emphasis: u8,
+}
+
+impl Default for MaskFlags {
+    /// Both layers shown everywhere, no greyscale or emphasis
+    fn default() -> Self {
+        Self {
+            show_background: true,
+            show_sprites: true,
+            clip_background: false,
+            clip_sprites: false,
+            greyscale_mask: 0x3F,
+            emphasis: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Composited {
+    /// The palette RAM entry after greyscale, with the emphasis bits in bits 6-8 (see PPU::index_frame)
+    pub index: u16,
+    pub source: PixelSource,
+}
+
+/// Dot `x` of a line: an opaque sprite pixel covers the background unless the sprite is behind it and the
+/// background pixel is opaque too. A sprite pixel of 0, or a layer that `mask` hides or clips in the leftmost 8
+/// pixels, is transparent.
+/// With both transparent the backdrop ($3F00) shows.
+pub(crate) fn composite_pixel(
+    x: usize,
+    bg: BgPixel,
+    sprite: Option<SpritePixel>,
+    palette_ram: &[u8; 32],
+    mask: MaskFlags,
+) -> Composited {
+    let clipped = |clip: bool| x < 8 && clip;
+    let bg_pixel = if mask.show_background && !clipped(mask.clip_background) {
+        bg.pixel
+    } else {
+        0
+    };
+    let sprite = sprite
+        .filter(|sprite| sprite.pixel != 0 && mask.show_sprites && !clipped(mask.clip_sprites));
+
+    let (source, entry) = match sprite {
+        Some(sprite) if !sprite.behind_background || bg_pixel == 0 => {
+            let source = if sprite.behind_background {
+                PixelSource::SpriteBehind(sprite.palette)
+            } else {
+                PixelSource::SpriteFront(sprite.palette)
+            };
+            (source, 0x10 | sprite.palette << 2 | sprite.pixel)
+        }
+        _ if bg_pixel == 0 => (PixelSource::Backdrop, 0),
+        _ => (
+            PixelSource::Background(bg.palette),
+            bg.palette << 2 | bg_pixel,
+        ),
+    };
+
+    let color = palette_ram[entry as usize] & mask.greyscale_mask;
+    Composited {
+        index: (mask.emphasis as u16) << 6 | color as u16,
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::harness::PpuHarness;
+
+    // entry n holds color n, so a pixel's index is the palette RAM entry it came from. $3F10, $3F14, $3F18
+    // and $3F1C mirror the backdrop entries, those all hold 0.
+    const PALETTE_RAM: [u8; 32] = {
+        let mut ram = [0; 32];
+        let mut i = 0;
+        while i < 32 {
+            if i % 4 != 0 {
+                ram[i] = i as u8;
+            }
+            i += 1;
+        }
+        ram
+    };
+
+    const FRONT: SpritePixel = SpritePixel {
+        pixel: 3,
+        palette: 1,
+        behind_background: false,
+        sprite_zero: false,
+    };
+    const BEHIND: SpritePixel = SpritePixel {
+        behind_background: true,
+        ..FRONT
+    };
+
+    // OAM attributes of FRONT and BEHIND
+    const IN_FRONT: u8 = 0x01;
+    const BEHIND_BG: u8 = 0x21;
+
+    // pattern tiles: 1 is solid 1, 2 has its left half 2 and its right half transparent, 3 is solid 3
+    const BLANK_TILE: u8 = 0;
+    const HALF_TILE: u8 = 2;
+    const SOLID_TILE: u8 = 3;
+    const TILES: [[u8; 16]; 4] = [
+        [0x00; 16],
+        [
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0,
+        ],
+        [
+            0, 0, 0, 0, 0, 0, 0, 0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0,
+        ],
+        [0xFF; 16],
+    ];
+
+    // the line as runs of the same index, e.g. `05*8 00*248`
+    fn runs(line: &[u16]) -> String {
+        let mut runs: Vec<(u16, usize)> = vec![];
+        for &index in line {
+            match runs.last_mut() {
+                Some((run, count)) if *run == index => *count += 1,
+                _ => runs.push((index, 1)),
+            }
+        }
+        runs.iter()
+            .map(|(index, count)| format!("{:02x}*{}", index, count))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    struct Case {
+        name: &'static str,
+        fine_x: u8,
+        /// (x, tile, attributes) of sprites on the line
+        sprites: &'static [(u8, u8, u8)],
+        /// PPUMASK
+        mask: u8,
+        /// The line as `runs` shows it
+        expected: &'static str,
+    }
+
+    // Scanline 1 as the PPU renders it, with tiles 1, 2 and 3 in nametable columns 0, 2 and 4 using background
+    // palettes 1, 2 and 3. Sprites never show on scanline 0, OAM Y 0 puts them on lines 1-8.
+    fn render_line(case: &Case) -> Vec<u16> {
+        let mut harness = PpuHarness::new();
+        harness.upload(0x3F00, &PALETTE_RAM);
+        for (tile, pattern) in TILES.iter().enumerate() {
+            harness.upload_tile(0, tile as u8, *pattern);
+        }
+        harness
+            .write_nametable(0x2000, &[1, 0, 2, 0, 3])
+            .upload(0x23C0, &[0b10_01, 0b11])
+            .write(0x2003, 0);
+        for &(x, tile, attributes) in case.sprites {
+            for byte in [0, tile, attributes, x] {
+                harness.write(0x2004, byte);
+            }
+        }
+        harness
+            .write(0x2003, 0)
+            .write(0x2001, case.mask)
+            .scroll(case.fine_x, 0)
+            .render_scanlines(2);
+        harness.ppu.index_frame()[256..512].to_vec()
+    }
+
+    #[test]
+    fn golden_scanlines() {
+        let cases = [
+            Case {
+                name: "background",
+                fine_x: 0,
+                sprites: &[],
+                mask: 0x1E,
+                expected: "05*8 00*8 0a*4 00*12 0f*8 00*216",
+            },
+            Case {
+                name: "fine x scroll",
+                fine_x: 3,
+                sprites: &[],
+                mask: 0x1E,
+                expected: "05*5 00*8 0a*4 00*12 0f*8 00*219",
+            },
+            Case {
+                name: "background hidden",
+                fine_x: 0,
+                sprites: &[],
+                mask: 0x16,
+                expected: "00*256",
+            },
+            Case {
+                name: "leftmost 8 pixels clipped",
+                fine_x: 0,
+                sprites: &[(4, SOLID_TILE, IN_FRONT)],
+                mask: 0x18,
+                expected: "00*8 17*4 00*4 0a*4 00*12 0f*8 00*216",
+            },
+            Case {
+                name: "sprite in front",
+                fine_x: 0,
+                sprites: &[(4, SOLID_TILE, IN_FRONT)],
+                mask: 0x1E,
+                expected: "05*4 17*8 00*4 0a*4 00*12 0f*8 00*216",
+            },
+            Case {
+                name: "sprite behind shows through transparent background",
+                fine_x: 0,
+                sprites: &[(4, SOLID_TILE, BEHIND_BG), (12, SOLID_TILE, BEHIND_BG)],
+                mask: 0x1E,
+                expected: "05*8 17*8 0a*4 00*12 0f*8 00*216",
+            },
+            Case {
+                name: "sprites hidden",
+                fine_x: 0,
+                sprites: &[(4, SOLID_TILE, IN_FRONT)],
+                mask: 0x0E,
+                expected: "05*8 00*8 0a*4 00*12 0f*8 00*216",
+            },
+            Case {
+                name: "transparent sprite pixels",
+                fine_x: 0,
+                sprites: &[(0, BLANK_TILE, IN_FRONT), (32, HALF_TILE, IN_FRONT)],
+                mask: 0x1E,
+                expected: "05*8 00*8 0a*4 00*12 16*4 0f*4 00*216",
+            },
+            Case {
+                name: "greyscale",
+                fine_x: 0,
+                sprites: &[(48, SOLID_TILE, IN_FRONT)],
+                mask: 0x1F,
+                expected: "00*48 10*8 00*200",
+            },
+            Case {
+                name: "emphasis",
+                fine_x: 0,
+                sprites: &[],
+                mask: 0xBE,
+                expected: "145*8 140*8 14a*4 140*12 14f*8 140*216",
+            },
+        ];
+
+        for case in cases {
+            assert_eq!(runs(&render_line(&case)), case.expected, "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn pixel_sources() {
+        let bg = BgPixel {
+            pixel: 2,
+            palette: 3,
+        };
+        let source =
+            |bg, sprite| composite_pixel(10, bg, sprite, &PALETTE_RAM, MaskFlags::default()).source;
+
+        assert_eq!(source(bg, None), PixelSource::Background(3));
+        assert_eq!(source(bg, Some(FRONT)), PixelSource::SpriteFront(1));
+        assert_eq!(source(bg, Some(BEHIND)), PixelSource::Background(3));
+        assert_eq!(
+            source(BgPixel::default(), Some(BEHIND)),
+            PixelSource::SpriteBehind(1)
+        );
+        assert_eq!(source(BgPixel::default(), None), PixelSource::Backdrop);
+    }
+}
@@ -0,0 +1,51 @@
+// Small hand-assembled programs that time PPU status flags from the CPU side, run on the headless emulator.
+// Each one has its source (`fixtures/*.s`) and assembled bytes (`fixtures/*.bin`, loaded at $8000) next to
+// this file. They sync to the pre-render line, count fixed-length polling loops until a flag comes on,
+// then leave the count in $10 and set $11 to 1.
+
+use crate::emulator::Emulator;
+use crate::mappers::test_image;
+
+const CPU_CYCLES_PER_SCANLINE: f64 = 341.0 / 3.0;
+
+/// NROM image with `program` at $8000, every vector pointing at it, and tile 1 of CHR-ROM solid
+fn image(program: &[u8]) -> Vec<u8> {
+    let mut image = test_image(0, 1, 1);
+    let (prg, chr) = image[16..].split_at_mut(0x4000);
+
+    prg.fill(0xEA);
+    prg[..program.len()].copy_from_slice(program);
+    for vector in [0x3FFA, 0x3FFC, 0x3FFE] {
+        prg[vector..vector + 2].copy_from_slice(&0x8000_u16.to_le_bytes());
+    }
+
+    chr[0x10..0x18].fill(0xFF);
+    image
+}
+
+/// Scanlines between the pre-render line clearing the flag and the program seeing it set again
+fn scanlines_until_flag(program: &[u8], cycles_per_loop: usize) -> f64 {
+    let mut emulator = Emulator::headless();
+    emulator.load_rom_bytes(&image(program)).unwrap();
+
+    while emulator.cpu.memory.buffer[0x11] == 0 {
+        assert!(emulator.frame_count < 10, "flag never came on");
+        emulator.run_frame();
+    }
+    let loops = emulator.cpu.memory.buffer[0x10] as usize;
+    (loops * cycles_per_loop) as f64 / CPU_CYCLES_PER_SCANLINE
+}
+
+#[test]
+fn sprite_zero_hit_on_sprite_scanline() {
+    // sprite 0 at Y=$10 is drawn from scanline 17, 18 lines after the pre-render line
+    let scanlines = scanlines_until_flag(include_bytes!("fixtures/sprite_zero_hit.bin"), 9);
+    assert!((17.0..19.5).contains(&scanlines), "{}", scanlines);
+}
+
+#[test]
+fn sprite_overflow_during_evaluation() {
+    // the ninth sprite at Y=$10 is found while evaluating scanline 17, i.e. during scanline 16
+    let scanlines = scanlines_until_flag(include_bytes!("fixtures/sprite_overflow.bin"), 11);
+    assert!((16.0..18.5).contains(&scanlines), "{}", scanlines);
+}
@@ -0,0 +1,64 @@
+; Sprite overflow timing.
+; Nine sprites share Y=$10, so evaluation for scanline 17 (which runs during scanline 16) finds one too
+; many and sets the overflow flag. After syncing to the start of a frame (the pre-render line clears the
+; flag), count 11-cycle polling loops until bit 5 of PPUSTATUS comes on, then store the count in $10 and
+; set $11.
+;
+; Loaded at $8000 by the fixture runner, which points every vector at `reset`.
+
+reset:
+        sei
+        cld
+        ldx #$FF
+        txs
+        lda #$00
+        sta $2000       ; NMI off
+        sta $2001       ; rendering off
+        sta $11
+vwait1: bit $2002
+        bpl vwait1
+vwait2: bit $2002
+        bpl vwait2
+
+        ; sprites 0-8 at Y=$10, the rest off screen at Y=$FF
+        lda #$FF
+        ldx #$00
+hide:   sta $0200,x
+        inx
+        bne hide
+        lda #$10
+        ldx #$00
+nine:   sta $0200,x
+        inx
+        inx
+        inx
+        inx
+        cpx #$24
+        bne nine
+        lda #$00
+        sta $2003
+        lda #$02
+        sta $4014
+
+vwait3: bit $2002
+        bpl vwait3
+        lda #$10        ; sprites only
+        sta $2001
+
+        ; the first frame's overflow, then its clear on the next pre-render line
+ovf1:   lda $2002
+        and #$20
+        beq ovf1
+clear:  lda $2002
+        and #$20
+        bne clear
+
+        ldx #$00
+count:  inx             ; 2
+        lda $2002       ; 4
+        and #$20        ; 2
+        beq count       ; 3
+        stx $10
+        lda #$01
+        sta $11
+done:   jmp done
@@ -0,0 +1,87 @@
+; Sprite 0 hit timing.
+; Sprite 0 (a solid tile) sits at Y=$10, X=$40 over a screen full of solid background tiles, so the hit
+; should happen on scanline 17. After syncing to the start of a frame (the pre-render line clears the flag),
+; count 9-cycle polling loops until bit 6 of PPUSTATUS comes on, then store the count in $10 and set $11.
+;
+; Loaded at $8000 by the fixture runner, which points every vector at `reset` and supplies CHR-ROM
+; with tile 1 solid (plane 0 all ones).
+
+reset:
+        sei
+        cld
+        ldx #$FF
+        txs
+        lda #$00
+        sta $2000       ; NMI off
+        sta $2001       ; rendering off
+        sta $11
+vwait1: bit $2002
+        bpl vwait1
+vwait2: bit $2002
+        bpl vwait2
+
+        ; backdrop $0F, background color 1 $30
+        lda #$3F
+        sta $2006
+        lda #$00
+        sta $2006
+        lda #$0F
+        sta $2007
+        lda #$30
+        sta $2007
+
+        ; tile 1 everywhere in nametable 0 (attributes included, they don't matter)
+        lda #$20
+        sta $2006
+        lda #$00
+        sta $2006
+        lda #$01
+        ldy #$04
+        ldx #$00
+fill:   sta $2007
+        inx
+        bne fill
+        dey
+        bne fill
+
+        ; sprite 0: Y=$10, tile 1, no flags, X=$40. Every other sprite off screen at Y=$FF.
+        lda #$10
+        sta $0200
+        lda #$01
+        sta $0201
+        lda #$00
+        sta $0202
+        lda #$40
+        sta $0203
+        lda #$FF
+        ldx #$04
+hide:   sta $0200,x
+        inx
+        bne hide
+        lda #$00
+        sta $2003
+        lda #$02
+        sta $4014
+
+        lda #$00
+        sta $2005
+        sta $2005
+vwait3: bit $2002
+        bpl vwait3
+        lda #$1E        ; background and sprites, left 8 pixels included
+        sta $2001
+
+        ; the first frame's hit, then its clear on the next pre-render line
+hit1:   bit $2002
+        bvc hit1
+clear:  bit $2002
+        bvs clear
+
+        ldx #$00
+count:  inx             ; 2
+        bit $2002       ; 4
+        bvc count       ; 3
+        stx $10
+        lda #$01
+        sta $11
+done:   jmp done
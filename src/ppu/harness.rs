@@ -0,0 +1,436 @@
+// ROM-less test driver for the PPU.
+// Everything goes through the CPU-visible registers ($2000-$2007) the same way a game's init code would,
+// so a test can set up palettes, pattern tables and nametables, render a few scanlines and then assert on
+// regions of the framebuffer without needing a cartridge or a CPU.
+
+use std::{cell::RefCell, rc::Rc};
+
+use anyhow::{anyhow, bail, Result};
+
+use super::{MASTER_PALETTE, PPU, RGB};
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+pub struct PpuHarness {
+    pub ppu: PPU,
+    fb: Rc<RefCell<Vec<u32>>>,
+}
+
+impl Default for PpuHarness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PpuHarness {
+    pub fn new() -> Self {
+        let fb = Rc::new(RefCell::new(vec![0; WIDTH * HEIGHT]));
+        let mut ppu = PPU::new(Rc::clone(&fb));
+        // start on the pre-render line so the first visible scanline gets its two tiles prefetched
+        ppu.curr_scanline = -1;
+
+        Self { ppu, fb }
+    }
+
+    /// Write to one of the PPU registers ($2000-$2007), I/O bus and all (see PPU::write_register)
+    pub fn write(&mut self, address: u16, value: u8) -> &mut Self {
+        if !(0x2000..=0x2007).contains(&address) || address == 0x2002 {
+            panic!("${:04X} is not a writable PPU register", address);
+        }
+        self.ppu.write_register(address, value);
+        self
+    }
+
+    /// Read from one of the PPU registers ($2002, $2004 or $2007), I/O bus and all (see PPU::read_register)
+    pub fn read(&mut self, address: u16) -> u8 {
+        if ![0x2002, 0x2004, 0x2007].contains(&address) {
+            panic!("${:04X} is not a readable PPU register", address);
+        }
+        self.ppu.read_register(address)
+    }
+
+    /// Reset the write latch, point v at `address` with two $2006 writes and stream `bytes` through $2007.
+    /// Uses whatever VRAM increment PPUCTRL currently selects.
+    pub fn upload(&mut self, address: u16, bytes: &[u8]) -> &mut Self {
+        self.read(0x2002);
+        self.write(0x2006, (address >> 8) as u8);
+        self.write(0x2006, (address & 0xFF) as u8);
+        for &byte in bytes {
+            self.write(0x2007, byte);
+        }
+        self
+    }
+
+    /// Set the scroll for the next frame with two PPUSCROLL writes. Uploads go through the same t register,
+    /// so like a game's vblank handler, tests scroll after them.
+    pub fn scroll(&mut self, x: u8, y: u8) -> &mut Self {
+        self.read(0x2002);
+        self.write(0x2005, x);
+        self.write(0x2005, y)
+    }
+
+    /// Palettes 0-3 are background, 4-7 are sprites
+    pub fn set_palette(&mut self, palette: u8, colors: [u8; 4]) -> &mut Self {
+        self.upload(0x3F00 + (palette as u16) * 4, &colors)
+    }
+
+    /// `table` is 0 for $0000 or 1 for $1000
+    pub fn upload_tile(&mut self, table: u8, tile: u8, pattern: [u8; 16]) -> &mut Self {
+        self.upload((table as u16) * 0x1000 + (tile as u16) * 16, &pattern)
+    }
+
+    pub fn write_nametable(&mut self, address: u16, tiles: &[u8]) -> &mut Self {
+        self.upload(address, tiles)
+    }
+
+    /// Run the PPU until `count` more visible scanlines have been drawn, skipping over vblank and the pre-render line
+    pub fn render_scanlines(&mut self, count: usize) -> &mut Self {
+        for _ in 0..count {
+            while !(0..240).contains(&self.ppu.curr_scanline) {
+                self.ppu.tick();
+            }
+            self.ppu.tick();
+        }
+        self
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> RGB {
+        let [r, g, b, _] = self.fb.borrow()[y * WIDTH + x].to_be_bytes();
+        (r, g, b)
+    }
+
+    /// First pixel in the region that isn't `color`, if any
+    pub fn region_mismatch(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: RGB,
+    ) -> Option<(usize, usize, RGB)> {
+        for row in y..(y + height) {
+            for col in x..(x + width) {
+                let actual = self.pixel(col, row);
+                if actual != color {
+                    return Some((col, row, actual));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn assert_region(&self, x: usize, y: usize, width: usize, height: usize, color: RGB) {
+        if let Some((col, row, actual)) = self.region_mismatch(x, y, width, height, color) {
+            panic!(
+                "pixel ({}, {}) is {:?}, expected {:?} across {}x{} region at ({}, {})",
+                col, row, actual, color, width, height, x, y
+            );
+        }
+    }
+
+    /// Run a line-based script. Numbers are hex, `#` starts a comment. Values and bytes have to fit in a byte.
+    ///
+    /// write <reg> <value>                     write a PPU register
+    /// read <reg>                              read a PPU register (e.g. $2002 to reset the latch)
+    /// upload <addr> <bytes...>                $2006/$2007 upload starting at addr
+    /// palette <n> <c0> <c1> <c2> <c3>         set palette n (0-3 background, 4-7 sprites)
+    /// tile <table> <index> <16 bytes>         upload one tile into pattern table 0 or 1
+    /// nametable <addr> <tiles...>             write tile indices starting at addr
+    /// render <n>                              draw n visible scanlines
+    /// expect <x> <y> <w> <h> <nes color>      every pixel in the region must be that master palette color
+    pub fn run_script(&mut self, script: &str) -> Result<()> {
+        for (line_num, line) in script.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or("");
+            let args = words
+                .map(|word| u16::from_str_radix(word.trim_start_matches('$'), 16))
+                .collect::<Result<Vec<u16>, _>>()
+                .map_err(|err| anyhow!("line {}: {}: {}", line_num + 1, line, err))?;
+            // the arguments from `from` on, which are bytes
+            let bytes = |from: usize| {
+                args[from..]
+                    .iter()
+                    .map(|&value| {
+                        u8::try_from(value).map_err(|_| {
+                            anyhow!(
+                                "line {}: {}: ${:X} is more than a byte",
+                                line_num + 1,
+                                line,
+                                value
+                            )
+                        })
+                    })
+                    .collect::<Result<Vec<u8>>>()
+            };
+
+            match (command, args.len()) {
+                ("write", 2) if (0x2000..=0x2007).contains(&args[0]) && args[0] != 0x2002 => {
+                    self.write(args[0], bytes(1)?[0]);
+                }
+                ("read", 1) if [0x2002, 0x2004, 0x2007].contains(&args[0]) => {
+                    self.read(args[0]);
+                }
+                ("upload", n) | ("nametable", n) if n >= 2 => {
+                    self.upload(args[0], &bytes(1)?);
+                }
+                ("palette", 5) if args[0] < 8 => {
+                    let colors = bytes(1)?;
+                    self.set_palette(args[0] as u8, [colors[0], colors[1], colors[2], colors[3]]);
+                }
+                ("tile", 18) if args[0] < 2 => {
+                    let index = bytes(1)?[0];
+                    self.upload_tile(args[0] as u8, index, bytes(2)?.try_into().unwrap());
+                }
+                ("render", 1) => {
+                    self.render_scanlines(args[0] as usize);
+                }
+                ("expect", 5) if args[4] < 0x40 => {
+                    let [x, y, w, h, color] = [0, 1, 2, 3, 4].map(|i| args[i] as usize);
+                    if x + w > WIDTH || y + h > HEIGHT {
+                        bail!("line {}: {}: region is off screen", line_num + 1, line);
+                    }
+                    if let Some((col, row, actual)) =
+                        self.region_mismatch(x, y, w, h, MASTER_PALETTE[color])
+                    {
+                        bail!(
+                            "line {}: {}: pixel ({}, {}) is {:?}, expected {:?}",
+                            line_num + 1,
+                            line,
+                            col,
+                            row,
+                            actual,
+                            MASTER_PALETTE[color]
+                        );
+                    }
+                }
+                _ => bail!("line {}: bad command: {}", line_num + 1, line),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::PixelSource;
+
+    const SOLID_TILE: [u8; 16] = [
+        0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+
+    #[test]
+    fn renders_solid_background_row() {
+        let mut harness = PpuHarness::new();
+        harness
+            .set_palette(0, [0x0F, 0x16, 0x27, 0x18])
+            .upload_tile(0, 1, SOLID_TILE)
+            .write_nametable(0x2000, &[1; 16])
+            .write(0x2001, 0x0A)
+            .scroll(0, 0)
+            .render_scanlines(8);
+
+        harness.assert_region(0, 0, 128, 8, MASTER_PALETTE[0x16]);
+        harness.assert_region(128, 0, 128, 8, MASTER_PALETTE[0x0F]);
+    }
+
+    #[test]
+    fn fine_x_scroll_shifts_tiles_left() {
+        let mut harness = PpuHarness::new();
+        harness
+            .set_palette(0, [0x0F, 0x16, 0x27, 0x18])
+            .upload_tile(0, 1, SOLID_TILE)
+            .write_nametable(0x2000, &[0, 1, 0])
+            .write(0x2001, 0x0A)
+            .scroll(3, 0)
+            .render_scanlines(1);
+
+        harness.assert_region(0, 0, 5, 1, MASTER_PALETTE[0x0F]);
+        harness.assert_region(5, 0, 8, 1, MASTER_PALETTE[0x16]);
+        harness.assert_region(13, 0, 8, 1, MASTER_PALETTE[0x0F]);
+    }
+
+    #[test]
+    fn coarse_scroll_crosses_into_next_nametable() {
+        let mut harness = PpuHarness::new();
+        harness
+            .set_palette(0, [0x0F, 0x16, 0x27, 0x18])
+            .upload_tile(0, 1, SOLID_TILE)
+            .write_nametable(0x2400, &[1])
+            .write(0x2000, 0)
+            .write(0x2001, 0x0A)
+            .scroll(248, 0)
+            .render_scanlines(8);
+
+        // column 31 of the left nametable, then column 0 of the right one
+        harness.assert_region(0, 0, 8, 8, MASTER_PALETTE[0x0F]);
+        harness.assert_region(8, 0, 8, 8, MASTER_PALETTE[0x16]);
+
+        let mut harness = PpuHarness::new();
+        harness
+            .set_palette(0, [0x0F, 0x16, 0x27, 0x18])
+            .upload_tile(0, 1, SOLID_TILE)
+            .write_nametable(0x2020, &[1])
+            .write(0x2000, 0)
+            .write(0x2001, 0x0A)
+            .scroll(0, 12)
+            .render_scanlines(8);
+
+        // row 1 of the nametable starts 4 lines down
+        harness.assert_region(0, 0, 8, 4, MASTER_PALETTE[0x16]);
+        harness.assert_region(0, 4, 8, 4, MASTER_PALETTE[0x0F]);
+    }
+
+    #[test]
+    fn sprites_in_front_of_and_behind_background() {
+        let mut harness = PpuHarness::new();
+        harness
+            .set_palette(0, [0x0F, 0x16, 0x27, 0x18])
+            .set_palette(4, [0x0F, 0x2A, 0x2A, 0x2A])
+            .upload_tile(0, 1, SOLID_TILE)
+            .write_nametable(0x2000, &[1; 16])
+            .write(0x2003, 0);
+        // (x, attributes): in front over the background, behind it, behind but over the backdrop
+        for (x, attributes) in [(8, 0x00), (64, 0x20), (200, 0x20)] {
+            for byte in [0, 1, attributes, x] {
+                harness.write(0x2004, byte);
+            }
+        }
+        // OAMADDR left at 8 or more when rendering starts would corrupt sprites 0 and 1
+        harness
+            .write(0x2003, 0)
+            .write(0x2001, 0x1E)
+            .scroll(0, 0)
+            .render_scanlines(8);
+
+        // sprites show up a line below their OAM Y
+        harness.assert_region(8, 1, 8, 7, MASTER_PALETTE[0x2A]);
+        harness.assert_region(64, 1, 8, 7, MASTER_PALETTE[0x16]);
+        harness.assert_region(200, 1, 8, 7, MASTER_PALETTE[0x2A]);
+        harness.assert_region(8, 0, 8, 1, MASTER_PALETTE[0x16]);
+    }
+
+    #[test]
+    fn ppumask_hides_and_clips_layers() {
+        let render_with_mask = |mask| {
+            let mut harness = PpuHarness::new();
+            harness
+                .set_palette(0, [0x0F, 0x16, 0x27, 0x18])
+                .upload_tile(0, 1, SOLID_TILE)
+                .write_nametable(0x2000, &[1; 16])
+                .write(0x2001, mask)
+                .scroll(0, 0)
+                .render_scanlines(8);
+            harness
+        };
+
+        let clipped = render_with_mask(0x08);
+        clipped.assert_region(0, 0, 8, 8, MASTER_PALETTE[0x0F]);
+        clipped.assert_region(8, 0, 120, 8, MASTER_PALETTE[0x16]);
+
+        // sprites only: the background is fetched but not drawn
+        render_with_mask(0x14).assert_region(0, 0, 256, 8, MASTER_PALETTE[0x0F]);
+    }
+
+    #[test]
+    fn pixel_source_view_colors_by_layer() {
+        let mut harness = PpuHarness::new();
+        harness.ppu.show_pixel_sources = true;
+        harness
+            .set_palette(0, [0x0F, 0x16, 0x27, 0x18])
+            .upload_tile(0, 1, SOLID_TILE)
+            .write_nametable(0x2000, &[1; 16])
+            .write(0x2001, 0x0A)
+            .scroll(0, 0)
+            .render_scanlines(8);
+
+        harness.assert_region(0, 0, 128, 8, PixelSource::Background(0).debug_color());
+        harness.assert_region(128, 0, 128, 8, PixelSource::Backdrop.debug_color());
+    }
+
+    #[test]
+    fn pattern_override_ignores_ppuctrl() {
+        let mut harness = PpuHarness::new();
+        harness.ppu.bg_pattern_override = Some(0x1000);
+        harness
+            .set_palette(0, [0x0F, 0x16, 0x27, 0x18])
+            .upload_tile(1, 1, SOLID_TILE)
+            .write_nametable(0x2000, &[1; 16])
+            .write(0x2001, 0x0A)
+            .scroll(0, 0)
+            .render_scanlines(8);
+
+        harness.assert_region(0, 0, 128, 8, MASTER_PALETTE[0x16]);
+    }
+
+    #[test]
+    fn palette_reads_are_not_buffered() {
+        let mut harness = PpuHarness::new();
+        harness.upload(0x2F05, &[0xAB]).upload(0x3F05, &[0x16]);
+
+        harness.upload(0x3F05, &[]);
+        assert_eq!(harness.read(0x2007), 0x16);
+        // the buffer picked up the nametable byte under the palette
+        harness.upload(0x0000, &[]);
+        assert_eq!(harness.read(0x2007), 0xAB);
+
+        // $3000-$3EFF reads the nametables like $2000-$2EFF, through the buffer
+        harness.upload(0x2005, &[0x42]).upload(0x3005, &[]);
+        harness.read(0x2007);
+        assert_eq!(harness.read(0x2007), 0x42);
+    }
+
+    #[test]
+    fn script_reports_mismatch() {
+        let mut harness = PpuHarness::new();
+        let script = "
+            palette 0 0F 16 27 18
+            tile 0 1 FF FF FF FF FF FF FF FF 00 00 00 00 00 00 00 00
+            nametable 2000 1 1
+            write 2001 0A
+            write 2005 0
+            write 2005 0
+            render 8
+            expect 0 0 10 8 16
+            expect 10 0 8 8 16   # third tile was never written
+        ";
+
+        let err = harness.run_script(script).unwrap_err();
+        assert!(err.to_string().starts_with("line 10:"), "{}", err);
+    }
+
+    #[test]
+    fn script_rejects_values_over_a_byte() {
+        let mut harness = PpuHarness::new();
+        for line in [
+            "write 2000 1FF",
+            "upload 2000 1 100",
+            "palette 0 0F 16 27 118",
+        ] {
+            let err = harness.run_script(line).unwrap_err();
+            assert!(err.to_string().contains("more than a byte"), "{}", err);
+        }
+        assert_eq!(harness.ppu.t, 0);
+    }
+
+    #[test]
+    fn scripts_go_through_the_register_entry_points() {
+        let mut harness = PpuHarness::new();
+        // $2002 only drives its top 3 bits, the rest is the I/O bus left over from the last write
+        harness.run_script("write 2000 1F").unwrap();
+        assert_eq!(harness.read(0x2002) & 0x1F, 0x1F);
+
+        // and writes to PPUCTRL are ignored until the reset is over
+        harness.ppu.reset();
+        harness.run_script("write 2000 80").unwrap();
+        assert!(!harness.ppu.generate_nmi);
+    }
+}
@@ -20,15 +20,27 @@
 /// $2000-2FFF is normally mapped to the 2kB NES internal VRAM, providing 2 nametables with a mirroring configuration controlled by the cartridge, but it can be partly or fully remapped to ROM or RAM on the cartridge, allowing up to 4 simultaneous nametables.
 /// $3000-3EFF is usually a mirror of the 2kB region from $2000-2EFF. The PPU does not render from this address range, so this space has negligible utility.
 /// $3F00-3FFF is not configurable, always mapped to the internal palette control.
+use std::cell::RefCell;
+
+use super::NametableArrangement;
+use crate::{access_stats::AccessStats, mappers::SharedMapper};
 
 pub struct VRAM {
+    /// Nametables live at $2000-$2FFF, but with two of them mirrored only $2000-$27FF of it is used
     pub buffer: [u8; 0x4000],
+    /// Pattern table accesses go to the cartridge when one is inserted, otherwise to `buffer`.
+    /// The cartridge also decides how the nametables are mirrored; without one all four are separate.
+    pub mapper: Option<SharedMapper>,
+    /// Per-page access counts, only kept while set. Reads go through `&self`, hence the RefCell.
+    pub access_stats: RefCell<Option<AccessStats>>,
 }
 
 impl VRAM {
     pub fn new() -> Self {
         Self {
             buffer: [0; 0x4000],
+            mapper: None,
+            access_stats: RefCell::new(None),
         }
     }
 
@@ -41,11 +53,135 @@ impl VRAM {
         }
     }
 
+    /// Where in `buffer` a nametable address ($2000-$3EFF) really lives, after mirroring.
+    /// $3000-$3EFF mirrors $2000-$2EFF, then the four 1 KB nametables are folded onto physical ones.
+    fn nametable_index(&self, address: usize) -> usize {
+        let offset = (address - 0x2000) % 0x1000;
+        let (table, offset) = (offset / 0x400, offset % 0x400);
+        let arrangement = match &self.mapper {
+            Some(mapper) => mapper.borrow().nametable_arrangement(),
+            None => NametableArrangement::FourScreen,
+        };
+        let physical = match arrangement {
+            NametableArrangement::HorizontalMirror => table / 2,
+            NametableArrangement::VerticalMirror => table % 2,
+            NametableArrangement::SingleScreenA => 0,
+            NametableArrangement::SingleScreenB => 1,
+            NametableArrangement::FourScreen => table,
+        };
+        0x2000 + physical * 0x400 + offset
+    }
+
+    /// Where in `buffer` a palette address ($3F00-$3FFF) really lives: the 32 bytes repeat every $20,
+    /// and the backdrop entries of the sprite palettes ($3F10/$3F14/$3F18/$3F1C) are the background ones.
+    pub(super) fn palette_index(address: usize) -> usize {
+        let index = 0x3F00 | (address & 0x1F);
+        if index & 0x13 == 0x10 {
+            index & !0x10
+        } else {
+            index
+        }
+    }
+
+    /// Addresses above $3FFF wrap around, the bus is only 14 bits wide
     pub fn get(&self, address: usize) -> u8 {
-        self.buffer[address]
+        let address = address & 0x3FFF;
+        if let Some(stats) = self.access_stats.borrow_mut().as_mut() {
+            stats.record_read(address as u16);
+        }
+        match &self.mapper {
+            Some(mapper) if address < 0x2000 => mapper.borrow_mut().ppu_read(address as u16),
+            _ if (0x2000..0x3F00).contains(&address) => self.buffer[self.nametable_index(address)],
+            _ if address >= 0x3F00 => self.buffer[Self::palette_index(address)],
+            _ => self.buffer[address],
+        }
+    }
+
+    /// Let the cartridge know a rendered scanline went by (see Mapper::clock_scanline)
+    pub fn clock_scanline(&mut self) {
+        if let Some(mapper) = &self.mapper {
+            mapper.borrow_mut().clock_scanline();
+        }
     }
 
     pub fn set(&mut self, address: usize, value: u8) {
-        self.buffer[address] = value;
+        let address = address & 0x3FFF;
+        if let Some(stats) = self.access_stats.get_mut() {
+            stats.record_write(address as u16);
+        }
+        match &self.mapper {
+            Some(mapper) if address < 0x2000 => {
+                mapper.borrow_mut().ppu_write(address as u16, value)
+            }
+            _ if (0x2000..0x3F00).contains(&address) => {
+                let index = self.nametable_index(address);
+                self.buffer[index] = value
+            }
+            // palette RAM is only 6 bits wide
+            _ if address >= 0x3F00 => self.buffer[Self::palette_index(address)] = value & 0x3F,
+            _ => self.buffer[address] = value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::{test_image, Cartridge};
+
+    fn vram_with_header_flags(flags_6: u8) -> VRAM {
+        let mut image = test_image(0, 1, 1);
+        image[6] = flags_6;
+        let mut vram = VRAM::new();
+        vram.mapper = Some(
+            Cartridge::from_ines_bytes(&image)
+                .unwrap()
+                .into_mapper()
+                .unwrap(),
+        );
+        vram
+    }
+
+    #[test]
+    fn nametables_follow_header_mirroring() {
+        let mut horizontal = vram_with_header_flags(0);
+        horizontal.set(0x2005, 0x11);
+        horizontal.set(0x2805, 0x22);
+        assert_eq!(horizontal.get(0x2405), 0x11);
+        assert_eq!(horizontal.get(0x2C05), 0x22);
+        assert_eq!(horizontal.get(0x3005), 0x11);
+
+        let mut vertical = vram_with_header_flags(1);
+        vertical.set(0x2005, 0x11);
+        vertical.set(0x2405, 0x22);
+        assert_eq!(vertical.get(0x2805), 0x11);
+        assert_eq!(vertical.get(0x2C05), 0x22);
+    }
+
+    #[test]
+    fn four_screen_keeps_nametables_apart() {
+        let mut vram = vram_with_header_flags(0b1001);
+        for (i, address) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+            vram.set(address, i as u8 + 1);
+        }
+        assert_eq!(
+            [0x2000, 0x2400, 0x2800, 0x2C00].map(|address| vram.get(address)),
+            [1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn palette_ram_is_mirrored_and_6_bits() {
+        let mut vram = VRAM::new();
+        vram.set(0x3F10, 0x21);
+        vram.set(0x3F05, 0xD6);
+        assert_eq!(vram.get(0x3F00), 0x21);
+        assert_eq!(vram.get(0x3F05), 0x16);
+        assert_eq!(vram.get(0x3F25), 0x16);
+        assert_eq!(vram.get(0x3FE5), 0x16);
+        assert_eq!(vram.get(0x7F05), 0x16);
+        // only the backdrop entries are shared with the sprite palettes
+        vram.set(0x3F15, 0x30);
+        assert_eq!(vram.get(0x3F05), 0x16);
     }
 }
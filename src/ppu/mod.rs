@@ -1,22 +1,24 @@
+pub(crate) mod compositor;
 #[cfg(target_family = "wasm")]
 pub mod emscripten;
+#[cfg(test)]
+mod fixtures;
+pub mod harness;
 pub mod memory;
+pub mod palette;
 
-use std::{
-    cell::RefCell,
-    cmp::{max, min},
-    collections::VecDeque,
-    rc::Rc,
-};
+use std::{cell::RefCell, fmt, rc::Rc};
 
+use anyhow::{bail, Result};
 use clap::error;
-use log::error;
+use compositor::{BgPixel, MaskFlags};
+use log::trace;
 use memory::VRAM;
-use sdl2::pixels::Color;
+use serde::{Deserialize, Serialize};
 
 use crate::utils::{get_bit, set_bit};
 
-type RGB = (u8, u8, u8);
+pub type RGB = (u8, u8, u8);
 
 const MASTER_PALETTE: [RGB; 0x40] = [
     (98, 98, 98),
@@ -84,6 +86,67 @@ const MASTER_PALETTE: [RGB; 0x40] = [
     (0, 0, 0),
     (0, 0, 0),
 ];
+
+/// Packs a color the way SDL lays out `PixelFormatEnum::RGBA8888`, so the
+/// framebuffer can be filled without going through an SDL pixel format.
+pub fn to_rgba8888((r, g, b): RGB) -> u32 {
+    u32::from_be_bytes([r, g, b, 0xFF])
+}
+
+// Each emphasis bit darkens the two channels it doesn't emphasize
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+/// All 8 PPUMASK emphasis combinations of a 64 color palette, packed for the framebuffer.
+/// Indexed by (emphasis bits << 6) | color index, so the pixel loop is a single lookup.
+pub fn build_palette_lut(palette: &[RGB; 0x40]) -> [u32; 0x200] {
+    let mut lut = [0; 0x200];
+    for (i, entry) in lut.iter_mut().enumerate() {
+        let (r, g, b) = palette[i & 0x3F];
+        let emphasis = i >> 6;
+        let mut channels = [r as f32, g as f32, b as f32];
+        for emphasized in 0..3 {
+            if emphasis & (1 << emphasized) == 0 {
+                continue;
+            }
+            for (channel, value) in channels.iter_mut().enumerate() {
+                if channel != emphasized {
+                    *value *= EMPHASIS_ATTENUATION;
+                }
+            }
+        }
+        let [r, g, b] = channels.map(|value| value.round() as u8);
+        *entry = to_rgba8888((r, g, b));
+    }
+    lut
+}
+
+/// Which layer a pixel's color came from, as decided by the priority multiplexer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelSource {
+    Backdrop,
+    /// Opaque background pixel, with its palette (0-3)
+    Background(u8),
+    /// Opaque sprite pixel drawn in front of the background, with its palette (0-3)
+    SpriteFront(u8),
+    /// Opaque sprite pixel with the behind-background priority bit set that still won,
+    /// because the background was transparent there
+    SpriteBehind(u8),
+}
+
+impl PixelSource {
+    /// Flat false color for the pixel source debug view: grey backdrop, background in blues,
+    /// front sprites in reds and behind-background sprites in greens, brighter for higher palettes
+    pub fn debug_color(self) -> RGB {
+        let shade = |palette: u8| 0x60 + (palette & 0b11) * 0x30;
+        match self {
+            PixelSource::Backdrop => (0x40, 0x40, 0x40),
+            PixelSource::Background(palette) => (0, 0, shade(palette)),
+            PixelSource::SpriteFront(palette) => (shade(palette), 0, 0),
+            PixelSource::SpriteBehind(palette) => (0, shade(palette), 0),
+        }
+    }
+}
+
 pub struct PatternTable {
     pub tile_map: [[u8; 16]; 256],
 }
@@ -107,8 +170,9 @@ impl PatternTable {
         };
         let mut last_tile_pos = addr as usize;
         for k in 0..256 {
-            let tile = &vram.buffer[last_tile_pos..(last_tile_pos + 16)];
-            tile_map[k].copy_from_slice(tile);
+            for (i, byte) in tile_map[k].iter_mut().enumerate() {
+                *byte = vram.get(last_tile_pos + i);
+            }
             // for i in 0..8 {
             //     for j in 0..8 {
             //         let first_bit = (tile[i].reverse_bits() >> j) & 1;
@@ -130,9 +194,16 @@ pub struct Nametable {
     attr: Vec<Vec<u8>>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum NametableArrangement {
     HorizontalMirror,
     VerticalMirror,
+    /// All four nametables show CIRAM $000
+    SingleScreenA,
+    /// All four nametables show CIRAM $400
+    SingleScreenB,
+    /// The cartridge adds 2 KB of RAM so each nametable has its own memory
+    FourScreen,
 }
 
 pub enum Quadrant {
@@ -245,7 +316,7 @@ impl Palette {
                 0 => 0x3F01,
                 1 => 0x3F05,
                 2 => 0x3F09,
-                3 => 0x3F0C,
+                3 => 0x3F0D,
                 _ => 0,
             },
             PaletteIndex::Sprite(table_num) => match table_num {
@@ -264,17 +335,118 @@ impl Palette {
     }
 
     pub fn get_colors(&self, vram: &VRAM) -> Vec<RGB> {
-        let mut colors = vec![MASTER_PALETTE[vram.get((0x3F00)) as usize]];
-        for i in 0..3 {
-            colors.push(MASTER_PALETTE[min(63, vram.get((self.starting_addr + i)) as usize)]);
-        }
-
-        colors
+        (0..4)
+            .map(|idx| MASTER_PALETTE[self.get_color_index(vram, idx) as usize])
+            .collect()
     }
 
     pub fn get_color(&self, vram: &VRAM, idx: usize) -> RGB {
         self.get_colors(vram)[idx]
     }
+
+    /// Index into the master palette for a 2-bit pixel value (0 is the universal background color)
+    pub fn get_color_index(&self, vram: &VRAM, idx: usize) -> u8 {
+        let address = if idx == 0 {
+            0x3F00
+        } else {
+            self.starting_addr + idx - 1
+        };
+        vram.get(address) & 0x3F
+    }
+}
+
+/// One $4014 write, for tracking down sprite glitches from DMAs that happen at the wrong time
+pub struct OamDmaTransfer {
+    pub page: u8,
+    /// Scanline the PPU was on when the transfer happened (241-260 and -1 are vblank)
+    pub scanline: i32,
+    /// (OAM offset, old value, new value) for every byte the transfer changed
+    pub changes: Vec<(u8, u8, u8)>,
+}
+
+impl fmt::Display for OamDmaTransfer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "OAM DMA from ${:02X}00 on scanline {}: {} bytes changed",
+            self.page,
+            self.scanline,
+            self.changes.len()
+        )?;
+        for &(offset, old, new) in &self.changes {
+            let field = ["y", "tile", "attributes", "x"][(offset % 4) as usize];
+            write!(
+                f,
+                "\n  sprite {:2} {:<10} ${:02X} -> ${:02X}",
+                offset / 4,
+                field,
+                old,
+                new
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the PPU is: the dot it runs next, on which scanline (-1 being the pre-render line) of which frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct PpuPosition {
+    /// Frames since power on, counting up as each pre-render line finishes
+    pub frame: usize,
+    pub scanline: i32,
+    pub dot: usize,
+}
+
+impl fmt::Display for PpuPosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "frame {} line {} dot {}",
+            self.frame, self.scanline, self.dot
+        )
+    }
+}
+
+/// PPU status decoded into the terms a person would use, for overlays and scripts that show it every frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct PpuSnapshot {
+    pub scanline: i32,
+    pub dot: usize,
+    /// Pattern tables actually being fetched from ($0000 or $1000), debug overrides included
+    pub bg_pattern_table: u16,
+    pub sprite_pattern_table: u16,
+    /// 8 or 16
+    pub sprite_height: u8,
+    /// Scroll position within the 512x480 nametable layout
+    pub scroll_x: u16,
+    pub scroll_y: u16,
+    pub show_background: bool,
+    pub show_sprites: bool,
+    pub vblank: bool,
+    pub sprite_zero_hit: bool,
+    pub sprite_overflow: bool,
+}
+
+impl fmt::Display for PpuSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |set: bool, name: &'static str| if set { name } else { "-" };
+        write!(
+            f,
+            "line {:3} dot {:3}  scroll {:3},{:3}  bg ${:04X} spr ${:04X} 8x{:<2}  {} {} {} {} {}",
+            self.scanline,
+            self.dot,
+            self.scroll_x,
+            self.scroll_y,
+            self.bg_pattern_table,
+            self.sprite_pattern_table,
+            self.sprite_height,
+            flag(self.show_background, "BG"),
+            flag(self.show_sprites, "SPR"),
+            flag(self.vblank, "VBL"),
+            flag(self.sprite_zero_hit, "S0"),
+            flag(self.sprite_overflow, "OVF"),
+        )
+    }
 }
 
 /// The OAM (Object Attribute Memory) is internal memory inside the PPU that contains a display list of up to 64 sprites, where each sprite's information occupies 4 bytes.
@@ -290,12 +462,24 @@ impl Palette {
 pub struct OAM {
     // We must handle 4 bytes at a time when working with this DRAM
     sprite_info: [u8; 256],
+    // bytes written through $2004 or DMA since power-on, the rest still hold whatever the DRAM came up with
+    written: [bool; 256],
 }
 
 impl OAM {
     pub fn new() -> Self {
         Self {
             sprite_info: [0; 256],
+            written: [false; 256],
+        }
+    }
+
+    /// Bits 2-4 of the attribute bytes don't exist in the DRAM and always read back as 0
+    fn stored(offset: usize, value: u8) -> u8 {
+        if offset % 4 == 2 {
+            value & 0xE3
+        } else {
+            value
         }
     }
 }
@@ -312,42 +496,107 @@ impl SEC_OAM {
     }
 }
 
-pub struct Sprite {
-    horizontal_flip: bool,
-    vertical_flip: bool,
-    priority: bool,
-    lo_byte: u8,
-    hi_byte: u8,
+/// One of the 8 sprite output units. They're loaded for the next line during dots 257-320; on that line the
+/// X counter counts down once per dot, and when it reaches 0 the pattern shifters start shifting out pixels.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+struct SpriteUnit {
+    pattern_lo: u8,
+    pattern_hi: u8,
+    attributes: u8,
+    x_counter: u8,
+    /// Holds OAM sprite 0, the one that can set the sprite 0 hit flag
+    sprite_zero: bool,
 }
 
-impl Sprite {
-    pub fn new(
-        horizontal_flip: bool,
-        vertical_flip: bool,
-        priority: bool,
-        lo_byte: u8,
-        hi_byte: u8,
-    ) -> Self {
-        Self {
-            horizontal_flip,
-            vertical_flip,
-            priority,
-            lo_byte,
-            hi_byte,
-        }
-    }
+/// Opaque pixel coming out of the sprite units on one dot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpritePixel {
+    /// 2-bit pattern value, never 0
+    pub pixel: u8,
+    pub palette: u8,
+    pub behind_background: bool,
+    pub sprite_zero: bool,
+}
+
+/// The PPU's part of a save state: its registers, timing, rendering pipeline, OAM, its own VRAM and the
+/// frame drawn so far. Debug views and the palette in use are frontend settings and aren't saved.
+#[derive(Serialize, Deserialize)]
+pub struct PpuState {
+    num_cycles: usize,
+    curr_scanline: i32,
+    dot: usize,
+    odd_frame: bool,
+    frame: usize,
+    secondary_oam: [u8; 32],
+    index_frame: Vec<u16>,
+
+    bg_pattern_lo: u16,
+    bg_pattern_hi: u16,
+    bg_attr_lo: u16,
+    bg_attr_hi: u16,
+    sprite_units: [SpriteUnit; 8],
+
+    vram: Vec<u8>,
+    oam: Vec<u8>,
+    oam_written: Vec<bool>,
+
+    v: u16,
+    t: u16,
+    fine_x: u16,
+    w: bool,
+
+    increment: u8,
+    sprite_pattern_address: u16,
+    bg_pattern_address: u16,
+    sprite_size: bool,
+    generate_nmi: bool,
+    master_slave_select: bool,
+    num_sprites: usize,
+    sprite_zero_in_range: bool,
+    is_vblank: bool,
+    nmi_pending: bool,
+    suppress_vblank: bool,
+    sprite_hit: bool,
+    sprite_overflow: bool,
+    in_reset: bool,
+
+    read_buffer: u8,
+    oam_address: u8,
+    io_bus: u8,
+    io_bus_refreshed: [usize; 8],
+
+    is_greyscale: bool,
+    clip_background: bool,
+    clip_sprites: bool,
+    show_background: bool,
+    show_sprites: bool,
+    emphasize_red: bool,
+    emphasize_green: bool,
+    emphasize_blue: bool,
+    greyscale_mask: u8,
+    emphasis: u8,
 }
 
 pub struct PPU {
     pub num_cycles: usize,
-    pub curr_tile_row: usize,
-    pub curr_tile_col: usize,
     pub curr_scanline: i32,
+    // next dot of curr_scanline to run (0-340)
+    dot: usize,
+    // odd frames skip the last dot of the pre-render line while rendering is enabled
+    odd_frame: bool,
+    // see PpuPosition::frame
+    frame: usize,
     secondary_oam: SEC_OAM,
     fb: Rc<RefCell<Vec<u32>>>,
+    // the same frame as NES colors: bits 0-5 the palette entry (greyscale applied), bits 6-8 the emphasis bits
+    index_frame: Vec<u16>,
 
-    nametable_queue: VecDeque<TileFetch>,
-    sprite_queue: VecDeque<Sprite>,
+    // background shifters: the high byte is the tile being drawn, the low byte the next one
+    bg_pattern_lo: u16,
+    bg_pattern_hi: u16,
+    bg_attr_lo: u16,
+    bg_attr_hi: u16,
+    sprite_units: [SpriteUnit; 8],
 
     pub vram: VRAM,
     oam: OAM,
@@ -362,19 +611,26 @@ pub struct PPU {
     sprite_pattern_address: u16,
     bg_pattern_address: u16,
     sprite_size: bool,
-    mode: bool,
     pub generate_nmi: bool,
     master_slave_select: bool,
     num_sprites: usize,
+    // sprite 0 made it into secondary OAM for the next line
+    sprite_zero_in_range: bool,
     pub is_vblank: bool,
+    // the NMI output went active (vblank started with NMIs enabled, or NMIs enabled during vblank), see take_nmi
+    nmi_pending: bool,
+    // $2002 was read just before vblank starts, which keeps the flag (and NMI) from being set this frame
+    suppress_vblank: bool,
     sprite_hit: bool,
     sprite_overflow: bool,
+    // held from a reset until the end of the next vblank, writes to $2000/$2001/$2005/$2006 are ignored meanwhile
+    in_reset: bool,
 
-    base_nametable_address: usize,
     read_buffer: u8,
     oam_address: u8,
-    x_scroll: u8,
-    y_scroll: u8,
+    io_bus: u8,
+    // num_cycles when each bit of io_bus was last driven
+    io_bus_refreshed: [usize; 8],
 
     is_greyscale: bool,
     clip_background: bool,
@@ -384,9 +640,36 @@ pub struct PPU {
     emphasize_red: bool,
     emphasize_green: bool,
     emphasize_blue: bool,
+
+    // derived from PPUMASK for the palette lookup: $30 masks colors down to the grey column,
+    // emphasis holds the red/green/blue bits in bits 0-2
+    greyscale_mask: u8,
+    emphasis: u8,
+    palette_lut: [u32; 0x200],
+
+    // debug view: draw every pixel in its PixelSource color instead of its real one
+    pub show_pixel_sources: bool,
+    // debug: fetch background/sprite patterns from this table ($0000 or $1000) whatever PPUCTRL says
+    pub bg_pattern_override: Option<u16>,
+    pub sprite_pattern_override: Option<u16>,
+    // keep an OamDmaTransfer for every $4014 write until take_oam_dma_log
+    pub record_oam_dma: bool,
+    oam_dma_log: Vec<OamDmaTransfer>,
+    // pattern table tiles (0-511) written through $2007 since the last take_modified_tiles, only CHR-RAM games do this
+    modified_tiles: [bool; 512],
+    // where in CHR each 1 KB of the pattern tables read from at the last take_chr_bank_changes
+    chr_sources: [usize; 8],
+    // debug: note OAM bytes that are read before anything wrote them, until take_oam_poisoned_reads
+    pub check_oam_poisoning: bool,
+    // accuracy: set the sprite overflow flag the way the hardware's buggy scan does (see overflow_scan)
+    // instead of whenever a line has more than 8 sprites
+    pub accurate_sprite_overflow: bool,
+    oam_poisoned_reads: [bool; 256],
 }
 
-// TODO: Reading any PPU port, including write-only ports $2000, $2001, $2003, $2005, $2006, returns the PPU I/O bus's value
+// The I/O bus between the CPU and the PPU's registers holds on to the last value driven onto it, and reading
+// anything that doesn't drive all 8 bits returns the rest from there. Unrefreshed bits fade to 0 after ~600ms.
+const IO_BUS_DECAY_DOTS: usize = 3_200_000;
 
 // fn set_n_bits(num: usize, idx: u8, n: u8) -> u8 {
 //     unimplemented!()
@@ -394,7 +677,6 @@ pub struct PPU {
 
 #[derive(Debug)]
 pub struct TileFetch {
-    nt_byte: u8,
     attr_two_bit: u8,
     pt_low_byte: u8,
     pt_hi_byte: u8,
@@ -407,16 +689,21 @@ impl PPU {
             oam: OAM::new(),
             oam_address: 0,
             fb,
+            index_frame: vec![0; 256 * 240],
 
             num_cycles: 0,
-            curr_tile_row: 0,
-            curr_tile_col: 0,
+            dot: 0,
+            odd_frame: false,
+            frame: 0,
             curr_scanline: 0,
 
             secondary_oam: SEC_OAM::new(),
 
-            nametable_queue: VecDeque::new(),
-            sprite_queue: VecDeque::new(),
+            bg_pattern_lo: 0,
+            bg_pattern_hi: 0,
+            bg_attr_lo: 0,
+            bg_attr_hi: 0,
+            sprite_units: [SpriteUnit::default(); 8],
 
             v: 0,
             t: 0,
@@ -427,54 +714,348 @@ impl PPU {
             sprite_pattern_address: 0x0000,
             bg_pattern_address: 0x0000,
             sprite_size: false,
-            mode: false,
             master_slave_select: false,
             generate_nmi: false,
             num_sprites: 0,
+            sprite_zero_in_range: false,
             is_vblank: false,
+            nmi_pending: false,
+            suppress_vblank: false,
             sprite_hit: false,
             sprite_overflow: false,
+            in_reset: false,
 
-            base_nametable_address: 0x2000,
             read_buffer: 0,
-            x_scroll: 0,
-            y_scroll: 0,
+            io_bus: 0,
+            io_bus_refreshed: [0; 8],
 
             is_greyscale: false,
-            clip_background: false,
-            clip_sprites: false,
+            clip_background: true,
+            clip_sprites: true,
             show_background: false,
             show_sprites: false,
             emphasize_red: false,
             emphasize_green: false,
             emphasize_blue: false,
+
+            greyscale_mask: 0x3F,
+            emphasis: 0,
+            palette_lut: build_palette_lut(&MASTER_PALETTE),
+
+            show_pixel_sources: false,
+            bg_pattern_override: None,
+            sprite_pattern_override: None,
+            record_oam_dma: false,
+            oam_dma_log: vec![],
+            modified_tiles: [false; 512],
+            chr_sources: std::array::from_fn(|region| region * 0x400),
+            check_oam_poisoning: false,
+            accurate_sprite_overflow: false,
+            oam_poisoned_reads: [false; 256],
+        }
+    }
+
+    /// The console's reset button, which the NES wires to the PPU as well (the Famicom doesn't): PPUCTRL, PPUMASK,
+    /// the scroll, the $2005/$2006 write toggle and the $2007 read buffer are cleared. OAM, OAMADDR, the VRAM address,
+    /// the status flags and the frame timing carry on. Writes to $2000, $2001, $2005 and $2006 are ignored until the
+    /// pre-render line clears vblank.
+    pub fn reset(&mut self) {
+        self.ppu_ctrl(0);
+        self.ppu_mask(0);
+        self.t = 0;
+        self.fine_x = 0;
+        self.w = false;
+        self.read_buffer = 0;
+        self.odd_frame = false;
+        self.in_reset = true;
+    }
+
+    /// Power-on state: a reset, plus OAMADDR, the VRAM address, the status flags and the I/O bus cleared and the
+    /// frame starting over at scanline 0. OAM, palette and nametable contents are unspecified on hardware and left
+    /// as they are. `PPU::new` is this state minus the ignored writes, which would only get in the way of tests.
+    pub fn power_on(&mut self) {
+        self.reset();
+        self.v = 0;
+        self.oam_address = 0;
+        self.is_vblank = false;
+        self.sprite_hit = false;
+        self.sprite_overflow = false;
+        self.nmi_pending = false;
+        self.suppress_vblank = false;
+        self.io_bus = 0;
+        self.curr_scanline = 0;
+        self.dot = 0;
+        self.frame = 0;
+    }
+
+    pub fn save_state(&self) -> PpuState {
+        PpuState {
+            num_cycles: self.num_cycles,
+            curr_scanline: self.curr_scanline,
+            dot: self.dot,
+            odd_frame: self.odd_frame,
+            frame: self.frame,
+            bg_pattern_lo: self.bg_pattern_lo,
+            bg_pattern_hi: self.bg_pattern_hi,
+            bg_attr_lo: self.bg_attr_lo,
+            bg_attr_hi: self.bg_attr_hi,
+            sprite_units: self.sprite_units,
+            v: self.v,
+            t: self.t,
+            fine_x: self.fine_x,
+            w: self.w,
+            increment: self.increment,
+            sprite_pattern_address: self.sprite_pattern_address,
+            bg_pattern_address: self.bg_pattern_address,
+            sprite_size: self.sprite_size,
+            generate_nmi: self.generate_nmi,
+            master_slave_select: self.master_slave_select,
+            num_sprites: self.num_sprites,
+            sprite_zero_in_range: self.sprite_zero_in_range,
+            is_vblank: self.is_vblank,
+            nmi_pending: self.nmi_pending,
+            suppress_vblank: self.suppress_vblank,
+            sprite_hit: self.sprite_hit,
+            sprite_overflow: self.sprite_overflow,
+            in_reset: self.in_reset,
+            read_buffer: self.read_buffer,
+            oam_address: self.oam_address,
+            io_bus: self.io_bus,
+            io_bus_refreshed: self.io_bus_refreshed,
+            is_greyscale: self.is_greyscale,
+            clip_background: self.clip_background,
+            clip_sprites: self.clip_sprites,
+            show_background: self.show_background,
+            show_sprites: self.show_sprites,
+            emphasize_red: self.emphasize_red,
+            emphasize_green: self.emphasize_green,
+            emphasize_blue: self.emphasize_blue,
+            greyscale_mask: self.greyscale_mask,
+            emphasis: self.emphasis,
+            secondary_oam: self.secondary_oam.sprite_info,
+            index_frame: self.index_frame.clone(),
+            vram: self.vram.buffer.to_vec(),
+            oam: self.oam.sprite_info.to_vec(),
+            oam_written: self.oam.written.to_vec(),
+        }
+    }
+
+    /// Carry on from a save state. The RGB framebuffer is redrawn from the saved frame, in the current palette.
+    pub fn load_state(&mut self, state: &PpuState) -> Result<()> {
+        let (Ok(vram), Ok(oam), Ok(oam_written)) = (
+            state.vram.as_slice().try_into(),
+            state.oam.as_slice().try_into(),
+            state.oam_written.as_slice().try_into(),
+        ) else {
+            bail!("saved PPU memory doesn't match the PPU's VRAM and OAM sizes");
+        };
+        if state.index_frame.len() != self.index_frame.len() {
+            bail!(
+                "saved frame has {} pixels, the PPU draws {}",
+                state.index_frame.len(),
+                self.index_frame.len()
+            );
+        }
+        self.vram.buffer = vram;
+        self.oam.sprite_info = oam;
+        self.oam.written = oam_written;
+        self.secondary_oam.sprite_info = state.secondary_oam;
+        self.index_frame.copy_from_slice(&state.index_frame);
+        self.num_cycles = state.num_cycles;
+        self.curr_scanline = state.curr_scanline;
+        self.dot = state.dot;
+        self.odd_frame = state.odd_frame;
+        self.frame = state.frame;
+        self.bg_pattern_lo = state.bg_pattern_lo;
+        self.bg_pattern_hi = state.bg_pattern_hi;
+        self.bg_attr_lo = state.bg_attr_lo;
+        self.bg_attr_hi = state.bg_attr_hi;
+        self.sprite_units = state.sprite_units;
+        self.v = state.v;
+        self.t = state.t;
+        self.fine_x = state.fine_x;
+        self.w = state.w;
+        self.increment = state.increment;
+        self.sprite_pattern_address = state.sprite_pattern_address;
+        self.bg_pattern_address = state.bg_pattern_address;
+        self.sprite_size = state.sprite_size;
+        self.generate_nmi = state.generate_nmi;
+        self.master_slave_select = state.master_slave_select;
+        self.num_sprites = state.num_sprites;
+        self.sprite_zero_in_range = state.sprite_zero_in_range;
+        self.is_vblank = state.is_vblank;
+        self.nmi_pending = state.nmi_pending;
+        self.suppress_vblank = state.suppress_vblank;
+        self.sprite_hit = state.sprite_hit;
+        self.sprite_overflow = state.sprite_overflow;
+        self.in_reset = state.in_reset;
+        self.read_buffer = state.read_buffer;
+        self.oam_address = state.oam_address;
+        self.io_bus = state.io_bus;
+        self.io_bus_refreshed = state.io_bus_refreshed;
+        self.is_greyscale = state.is_greyscale;
+        self.clip_background = state.clip_background;
+        self.clip_sprites = state.clip_sprites;
+        self.show_background = state.show_background;
+        self.show_sprites = state.show_sprites;
+        self.emphasize_red = state.emphasize_red;
+        self.emphasize_green = state.emphasize_green;
+        self.emphasize_blue = state.emphasize_blue;
+        self.greyscale_mask = state.greyscale_mask;
+        self.emphasis = state.emphasis;
+
+        let mut fb = self.fb.borrow_mut();
+        for (pixel, &index) in fb.iter_mut().zip(&self.index_frame) {
+            *pixel = self.palette_lut[index as usize];
+        }
+        Ok(())
+    }
+
+    /// Swap in a different 64 color master palette (e.g. loaded from a .pal file)
+    pub fn set_palette(&mut self, palette: &[RGB; 0x40]) {
+        self.palette_lut = build_palette_lut(palette);
+    }
+
+    /// Which pattern table tiles were written since the last call, indexed by tile (0-255 for $0000, 256-511 for $1000)
+    pub fn take_modified_tiles(&mut self) -> [bool; 512] {
+        std::mem::replace(&mut self.modified_tiles, [false; 512])
+    }
+
+    /// Which 1 KB regions of the pattern tables ($0000-$03FF is 0, $1C00-$1FFF is 7) the mapper switched to
+    /// another CHR bank since the last call. Switching back and forth in between doesn't count.
+    pub fn take_chr_bank_changes(&mut self) -> [bool; 8] {
+        let Some(mapper) = &self.vram.mapper else {
+            return [false; 8];
+        };
+        let mapper = mapper.borrow();
+        std::array::from_fn(|region| {
+            let source = mapper.chr_source(region as u16 * 0x400);
+            std::mem::replace(&mut self.chr_sources[region], source) != source
+        })
+    }
+
+    /// OAM offsets read through $2004 or copied to a scanline's sprites while still uninitialized, since the last
+    /// call (only while `check_oam_poisoning` is set). Garbage sprites usually come from one of these.
+    pub fn take_oam_poisoned_reads(&mut self) -> Vec<u8> {
+        let reads = std::mem::replace(&mut self.oam_poisoned_reads, [false; 256]);
+        (0..=255u8)
+            .filter(|&offset| reads[offset as usize])
+            .collect()
+    }
+
+    fn note_oam_read(&mut self, offset: usize) {
+        if self.check_oam_poisoning && !self.oam.written[offset] {
+            self.oam_poisoned_reads[offset] = true;
+        }
+    }
+
+    /// The frame as NES colors rather than RGB, see capture::index_bytes
+    pub fn index_frame(&self) -> &[u16] {
+        &self.index_frame
+    }
+
+    fn mask_flags(&self) -> MaskFlags {
+        MaskFlags {
+            show_background: self.show_background,
+            show_sprites: self.show_sprites,
+            clip_background: self.clip_background,
+            clip_sprites: self.clip_sprites,
+            greyscale_mask: self.greyscale_mask,
+            emphasis: self.emphasis,
+        }
+    }
+
+    /// CPU read of one of the registers ($2000-$2007). Write-only registers read back the I/O bus,
+    /// and so do the bits the others leave undriven: the low 5 of $2002 and the top 2 of palette reads.
+    pub fn read_register(&mut self, address: u16) -> u8 {
+        let (value, driven) = match address & 7 {
+            2 => (self.ppu_status(), 0xE0),
+            4 => (self.oam_data_read(), 0xFF),
+            7 if self.v & 0x3FFF >= 0x3F00 => (self.ppu_data_read(), 0x3F),
+            7 => (self.ppu_data_read(), 0xFF),
+            _ => (0, 0x00),
+        };
+        let value = (value & driven) | (self.io_bus() & !driven);
+        self.drive_io_bus(value, driven);
+        value
+    }
+
+    /// What a CPU read of one of the registers would return, without clearing VBlank or the write latch,
+    /// moving v or refreshing the I/O bus. $2004 shows OAM at OAMADDR even while rendering.
+    pub fn peek_register(&self, address: u16) -> u8 {
+        let (value, driven) = match address & 7 {
+            2 => {
+                let status = (self.is_vblank as u8) << 7
+                    | (self.sprite_hit as u8) << 6
+                    | (self.sprite_overflow as u8) << 5;
+                (status, 0xE0)
+            }
+            4 => (self.oam.sprite_info[self.oam_address as usize], 0xFF),
+            7 if self.v & 0x3FFF >= 0x3F00 => {
+                (self.vram.buffer[VRAM::palette_index(self.v as usize)], 0x3F)
+            }
+            7 => (self.read_buffer, 0xFF),
+            _ => (0, 0x00),
+        };
+        (value & driven) | (self.io_bus() & !driven)
+    }
+
+    /// CPU write to one of the registers ($2000-$2007)
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        self.drive_io_bus(value, 0xFF);
+        match address & 7 {
+            0 | 1 | 5 | 6 if self.in_reset => {}
+            0 => self.ppu_ctrl(value),
+            1 => self.ppu_mask(value),
+            2 => {}
+            3 => self.oam_addr(value),
+            4 => self.oam_data_write(value),
+            5 => self.ppu_scroll(value),
+            6 => self.ppu_addr(value),
+            _ => self.ppu_data_write(value),
+        }
+    }
+
+    /// The I/O bus latch, with bits that haven't been driven for too long decayed to 0
+    fn io_bus(&self) -> u8 {
+        (0..8)
+            .filter(|&bit| self.num_cycles - self.io_bus_refreshed[bit] < IO_BUS_DECAY_DOTS)
+            .fold(0, |bus, bit| bus | (self.io_bus & (1 << bit)))
+    }
+
+    fn drive_io_bus(&mut self, value: u8, bits: u8) {
+        self.io_bus = (self.io_bus() & !bits) | (value & bits);
+        for bit in 0..8 {
+            if bits & (1 << bit) != 0 {
+                self.io_bus_refreshed[bit] = self.num_cycles;
+            }
         }
     }
 
     /// $2000
     pub fn ppu_ctrl(&mut self, value: u8) {
         // error!("PPUCTRL: {:b}", value);
-        self.base_nametable_address = match value & 0b11 {
-            0 => 0x2000,
-            1 => 0x2400,
-            2 => 0x2800,
-            3 => 0x2C00,
-            _ => 0x0000, // will never hit
-        };
+        // t: ...GH.. ........ <- d: ......GH
+        self.t = (self.t & !0x0C00) | ((value as u16 & 0b11) << 10);
         self.increment = if get_bit(value.into(), 2) == 0 { 1 } else { 32 };
         self.sprite_pattern_address = if get_bit(value.into(), 3) == 1 {
             0x1000
         } else {
             0x0000
         };
-        self.bg_pattern_address = if get_bit(value.into(), 4) == 0 {
-            0x0000
-        } else {
+        self.bg_pattern_address = if get_bit(value.into(), 4) == 1 {
             0x1000
+        } else {
+            0x0000
         };
-        self.mode = get_bit(value.into(), 5) == 1; // 0 for 8x8, 1 for 8x16
+        self.sprite_size = get_bit(value.into(), 5) == 1; // 0 for 8x8, 1 for 8x16
         self.master_slave_select = get_bit(value.into(), 6) == 1; // (0: read backdrop from EXT pins; 1: output color on EXT pins)
-        self.generate_nmi = get_bit(value.into(), 7) == 1; // Generate an NMI at the start of the vertical blanking interval (0: off; 1: on)
+        let generate_nmi = get_bit(value.into(), 7) == 1; // Generate an NMI at the start of the vertical blanking interval (0: off; 1: on)
+                                                          // turning NMIs on in the middle of vblank raises one right away
+        if generate_nmi && !self.generate_nmi && self.is_vblank {
+            self.nmi_pending = true;
+        }
+        self.generate_nmi = generate_nmi;
     }
 
     /// $2001
@@ -482,13 +1063,17 @@ impl PPU {
         // error!("PPUMASK {:b}", value);
 
         self.is_greyscale = get_bit(value.into(), 0) == 1;
-        self.clip_background = get_bit(value.into(), 1) == 1;
-        self.clip_sprites = get_bit(value.into(), 2) == 1;
+        // bits 1 and 2 *show* the leftmost 8 pixels, so a clear bit means clipped
+        self.clip_background = get_bit(value.into(), 1) == 0;
+        self.clip_sprites = get_bit(value.into(), 2) == 0;
         self.show_background = get_bit(value.into(), 3) == 1;
         self.show_sprites = get_bit(value.into(), 4) == 1;
         self.emphasize_red = get_bit(value.into(), 5) == 1;
         self.emphasize_green = get_bit(value.into(), 6) == 1;
         self.emphasize_blue = get_bit(value.into(), 7) == 1;
+
+        self.greyscale_mask = if self.is_greyscale { 0x30 } else { 0x3F };
+        self.emphasis = value >> 5;
     }
 
     /// $2002
@@ -514,13 +1099,12 @@ impl PPU {
         //         line); cleared after reading $2002 and at dot 1 of the
         //         pre-render line.
 
-        // TODO(backlog): setup working PPU open bus
         // clear write latch
         self.w = false;
 
         let mut val = 0b0000_0000;
 
-        if self.num_sprites > 8 {
+        if self.sprite_overflow {
             val = set_bit(val.into(), 5);
         }
 
@@ -532,6 +1116,16 @@ impl PPU {
             val = set_bit(val.into(), 7);
         }
         self.is_vblank = false;
+
+        // Racing the flag: a read on the dot before it's set reads it clear and keeps it from being set at all,
+        // one on the dot it's set or the next reads it set. Either way there's no NMI this frame.
+        if self.curr_scanline == 241 {
+            match self.dot {
+                1 => self.suppress_vblank = true,
+                2 | 3 => self.nmi_pending = false,
+                _ => {}
+            }
+        }
         val
     }
 
@@ -543,39 +1137,70 @@ impl PPU {
     }
 
     /// $2004
-    pub fn oam_data_read(&self) -> u8 {
+    pub fn oam_data_read(&mut self) -> u8 {
+        // While rendering, the bus holds whatever sprite evaluation and fetching are working on
+        if self.rendering_in_progress() {
+            return match self.dot {
+                // secondary OAM is being cleared, which reads $FF
+                1..=64 => 0xFF,
+                // the sprite fetches read each slot's Y, tile, attributes and X, then X again for the rest of
+                // their 8 dots
+                257..=320 => {
+                    let cycle = self.dot - 257;
+                    self.secondary_oam.sprite_info[cycle / 8 * 4 + (cycle % 8).min(3)]
+                }
+                // dots 321-340 and 0 sit on the first byte of secondary OAM
+                0 | 321..=340 => self.secondary_oam.sprite_info[0],
+                // evaluation reads primary OAM at OAMADDR
+                _ => self.oam.sprite_info[self.oam_address as usize],
+            };
+        }
+        self.note_oam_read(self.oam_address as usize);
         self.oam.sprite_info[self.oam_address as usize]
     }
 
     /// $2004
     pub fn oam_data_write(&mut self, value: u8) {
+        // During rendering the write is dropped, but OAMADDR still moves: the increment lands on the
+        // sprite index bits only, so it skips ahead a whole sprite
+        if self.rendering_in_progress() {
+            self.oam_address = self.oam_address.wrapping_add(4);
+            return;
+        }
         // Should we ignore writes because DMA is usually always used over this?
         // Wiki says partial writes can cause corruption
-        self.oam.sprite_info[self.oam_address as usize] = value;
+        let offset = self.oam_address as usize;
+        self.oam.sprite_info[offset] = OAM::stored(offset, value);
+        self.oam.written[offset] = true;
         self.oam_address = self.oam_address.wrapping_add(1);
     }
 
     /// $2005
     pub fn ppu_scroll(&mut self, value: u8) {
-        if self.w == false {
-            self.x_scroll = value;
+        let value = value as u16;
+        if !self.w {
+            // t: ....... ...ABCDE <- d: ABCDE...
+            // x:              FGH <- d: .....FGH
+            self.t = (self.t & !0x001F) | (value >> 3);
+            self.fine_x = value & 0b111;
             self.w = true;
         } else {
-            self.y_scroll = value;
+            // t: FGH..AB CDE..... <- d: ABCDEFGH
+            self.t = (self.t & !0x73E0) | ((value & 0b111) << 12) | ((value & 0xF8) << 2);
             self.w = false;
         }
     }
 
     /// $2006
     pub fn ppu_addr(&mut self, value: u8) {
-        error!("PPUADDR {:x}", value);
+        trace!("PPUADDR {:x}", value);
         if !self.w {
-            // update low byte of t
-            self.t = (value as u16) << 8;
+            // high byte of t, bit 14 is cleared
+            self.t = (self.t & 0x00FF) | ((value as u16 & 0x3F) << 8);
             self.w = true;
         } else {
-            // update high byte of t
-            self.t |= value as u16;
+            // low byte of t
+            self.t = (self.t & 0xFF00) | value as u16;
             self.v = self.t;
             self.w = false;
         }
@@ -583,194 +1208,406 @@ impl PPU {
 
     // $2007
     pub fn ppu_data_read(&mut self) -> u8 {
-        error!("CPU reading from VRAM at address {:x}", self.v);
-        let old_buffer = self.read_buffer;
-
-        let read_result = self.vram.get(self.v.into());
-        self.read_buffer = read_result;
+        trace!("CPU reading from VRAM at address {:x}", self.v);
+        // v is 15 bits, the PPU bus only 14
+        let address = (self.v & 0x3FFF) as usize;
+        let result = if address >= 0x3F00 {
+            // palette reads skip the buffer, which is filled from the nametable underneath ($2F00-$2FFF) instead
+            self.read_buffer = self.vram.get(address - 0x1000);
+            self.vram.get(address)
+        } else {
+            let old_buffer = self.read_buffer;
+            self.read_buffer = self.vram.get(address);
+            old_buffer
+        };
 
         // increment v by bit 2 of $2000 of VRAM
         self.v = (self.v + self.increment as u16) % 0x4000;
 
-        old_buffer
+        result
     }
 
     /// $2007
     pub fn ppu_data_write(&mut self, value: u8) {
-        error!(
+        trace!(
             "CPU writing to VRAM at address {:x} <--- {:x}",
-            self.v, value
+            self.v,
+            value
         );
         self.vram.set(self.v.into(), value);
+        if self.v < 0x2000 {
+            self.modified_tiles[(self.v / 16) as usize] = true;
+        }
 
         // increment v by bit 2 of $2000 of VRAM
         self.v = (self.v + self.increment as u16) % 0x4000;
     }
 
     /// $4014
-    pub fn oam_dma(&mut self, mem_slice: &[u8]) {
-        self.oam.sprite_info = mem_slice.try_into().unwrap();
+    pub fn oam_dma(&mut self, page: u8, mem_slice: &[u8]) {
+        let mut sprite_info: [u8; 256] = mem_slice.try_into().unwrap();
+        for (offset, byte) in sprite_info.iter_mut().enumerate() {
+            *byte = OAM::stored(offset, *byte);
+        }
+        if self.record_oam_dma {
+            let changes = (0..=255u8)
+                .zip(self.oam.sprite_info.iter().zip(sprite_info.iter()))
+                .filter(|(_, (old, new))| old != new)
+                .map(|(offset, (&old, &new))| (offset, old, new))
+                .collect();
+            self.oam_dma_log.push(OamDmaTransfer {
+                page,
+                scanline: self.curr_scanline,
+                changes,
+            });
+        }
+        self.oam.sprite_info = sprite_info;
+        self.oam.written = [true; 256];
     }
 
-    pub fn fetch_bg_tile(&mut self) -> TileFetch {
-        let pt_bg = PatternTable::from_memory(
-            PatternTableType::Background,
-            &mut self.vram,
-            self.bg_pattern_address,
-        );
+    /// Transfers recorded since the last call, oldest first (only while `record_oam_dma` is set)
+    pub fn take_oam_dma_log(&mut self) -> Vec<OamDmaTransfer> {
+        std::mem::take(&mut self.oam_dma_log)
+    }
 
-        // 8 cycles of fetch + store to shift registers (BACKGROUND)
-        let nt_byte_addr =
-            self.base_nametable_address + self.curr_tile_row * 32 + self.curr_tile_col as usize;
-        let nt_byte = self.vram.get(nt_byte_addr);
-        let attr_byte_offset = (self.curr_tile_row / 4) * 8 + (self.curr_tile_col / 4);
+    /// Fetch the tile v points at: nametable byte, attribute bits and both pattern planes
+    pub fn fetch_bg_tile(&mut self) -> TileFetch {
+        let v = self.v as usize;
+        let nt_byte = self.vram.get(0x2000 | (v & 0x0FFF));
+        // one attribute byte per 4x4 tiles, two bits per 2x2 quadrant
         let attr_byte = self
             .vram
-            .get(self.base_nametable_address + 960 + attr_byte_offset);
-        let block_i = self.curr_tile_row % 4;
-        let block_j = self.curr_tile_col % 4;
-        let quad = if block_i < 2 {
-            if block_j < 2 {
-                1
-            } else {
-                2
-            }
-        } else {
-            if block_j < 2 {
-                3
-            } else {
-                4
-            }
-        };
-        let attr_two_bit = match quad {
-            1 => attr_byte & 0b0000_0011,
-            2 => (attr_byte & 0b0000_1100) >> 2,
-            3 => (attr_byte & 0b0011_0000) >> 4,
-            4 => (attr_byte & 0b1100_0000) >> 6,
-            _ => 0,
-        };
-        let pt_low_byte =
-            pt_bg.tile_map[nt_byte as usize][(max(self.curr_scanline, 0) % 8) as usize];
-        let pt_hi_byte =
-            pt_bg.tile_map[nt_byte as usize][((max(self.curr_scanline, 0) % 8) + 8) as usize];
+            .get(0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07));
+        let attr_shift = ((v >> 4) & 0b100) | (v & 0b10);
+        let attr_two_bit = (attr_byte >> attr_shift) & 0b11;
+        let fine_y = (v >> 12) & 0b111;
+        let pattern_address = self.bg_pattern_override.unwrap_or(self.bg_pattern_address) as usize
+            + nt_byte as usize * 16
+            + fine_y;
+        let pt_low_byte = self.vram.get(pattern_address);
+        let pt_hi_byte = self.vram.get(pattern_address + 8);
 
         TileFetch {
-            nt_byte,
             attr_two_bit,
             pt_low_byte,
             pt_hi_byte,
         }
     }
-    pub fn render_tile(
-        &mut self,
-        tile_data: TileFetch,
-        curr_tile_row: usize,
-        curr_tile_col: usize,
-    ) {
-        // for now we'll only render background tile_data
-        let palette = Palette::new(PaletteIndex::Bg(tile_data.attr_two_bit));
-        if tile_data.nt_byte != 0 {
-            // panic!();
-        }
-        let pix_row = self.curr_scanline as usize;
-        let pix_col = curr_tile_col * 8;
-        for i in 0..8 {
-            let first_bit = (tile_data.pt_low_byte.reverse_bits() >> i) & 1;
-            let second_bit = (tile_data.pt_hi_byte.reverse_bits() >> i) & 1;
-            let color = (second_bit << 1) | first_bit;
-            let (r, g, b) = palette.get_color(&self.vram, color.into());
-            self.fb.borrow_mut()[(pix_row * 256 + pix_col + i) as usize] = Color::RGB(r, g, b)
-                .to_u32(&sdl2::pixels::PixelFormatEnum::RGBA8888.try_into().unwrap());
-        }
-    }
-
-    pub fn tick_scanline(&mut self, should_render: bool) {
-        // Cycles 0
-        // ---- IDLE ----
-
-        // Cycles 1-256
-        // 8 sets of 8-cycle BG tile fetches, sprite evaluation, render BG tile
-        if self.curr_scanline != -1 {
-            self.curr_tile_col = 2;
-            for _ in 0..30 {
-                // render THEN fetch
-                if should_render {
-                    let bg_tile_data = self.nametable_queue.pop_front();
-                    if let Some(bg_tile_data) = bg_tile_data {
-                        self.render_tile(bg_tile_data, self.curr_tile_row, self.curr_tile_col - 2);
-                        // also needs to take the current sprite_queue into account
-                    }
-                }
+    /// Put a fetched tile into the low 8 bits of the background shifters, with its palette spread over all 8 pixels
+    fn load_bg_shifters(&mut self, tile: &TileFetch) {
+        let spread = |bit: u8| {
+            if tile.attr_two_bit & bit != 0 {
+                0xFF
+            } else {
+                0x00
+            }
+        };
+        self.bg_pattern_lo = (self.bg_pattern_lo & 0xFF00) | tile.pt_low_byte as u16;
+        self.bg_pattern_hi = (self.bg_pattern_hi & 0xFF00) | tile.pt_hi_byte as u16;
+        self.bg_attr_lo = (self.bg_attr_lo & 0xFF00) | spread(0b01);
+        self.bg_attr_hi = (self.bg_attr_hi & 0xFF00) | spread(0b10);
+    }
 
-                let next_tile_fetch = self.fetch_bg_tile();
-                self.nametable_queue.push_back(next_tile_fetch);
+    fn shift_bg_shifters(&mut self) {
+        self.bg_pattern_lo <<= 1;
+        self.bg_pattern_hi <<= 1;
+        self.bg_attr_lo <<= 1;
+        self.bg_attr_hi <<= 1;
+    }
 
-                self.curr_tile_col += 1;
-            }
+    /// Draw dot `x` of the current scanline, from what the background shifters and sprite units put out on it
+    /// (see compositor::composite_pixel). Fine x picks which of the 8 bits past the current tile boundary the
+    /// background pixel comes from.
+    fn render_pixel(&mut self, x: usize) {
+        let bit = 15 - self.fine_x;
+        let bit_at = |shifter: u16| ((shifter >> bit) & 1) as u8;
+        let bg = BgPixel {
+            pixel: (bit_at(self.bg_pattern_hi) << 1) | bit_at(self.bg_pattern_lo),
+            palette: (bit_at(self.bg_attr_hi) << 1) | bit_at(self.bg_attr_lo),
+        };
 
-            if should_render {
-                for i in 0..2 {
-                    let bg_tile_data = self.nametable_queue.pop_front();
-                    if let Some(bg_tile_data) = bg_tile_data {
-                        self.render_tile(bg_tile_data, self.curr_tile_row, 30 + i);
-                    }
-                }
-            }
+        let sprite = self.clock_sprite_units();
+        if let Some(SpritePixel {
+            sprite_zero: true, ..
+        }) = sprite
+        {
+            self.check_sprite_zero_hit(x, bg.pixel);
+        }
 
-            self.evaluate_sprite();
+        let palette_ram = self.vram.buffer[0x3F00..0x3F20].try_into().unwrap();
+        let composited = compositor::composite_pixel(x, bg, sprite, palette_ram, self.mask_flags());
+        let pixel = if self.show_pixel_sources {
+            to_rgba8888(composited.source.debug_color())
+        } else {
+            self.palette_lut[composited.index as usize]
+        };
+        let offset = self.curr_scanline as usize * 256 + x;
+        self.fb.borrow_mut()[offset] = pixel;
+        self.index_frame[offset] = composited.index;
+    }
 
-            // Cycles 257-320
-            self.fetch_sprite_data();
+    pub fn position(&self) -> PpuPosition {
+        PpuPosition {
+            frame: self.frame,
+            scanline: self.curr_scanline,
+            dot: self.dot,
         }
-        // Cycles 321-336
-        // replenish queue
-        self.curr_tile_row = (self.curr_scanline + 1) as usize / 8;
-        self.curr_tile_col = 0;
-        let first_tile = self.fetch_bg_tile();
-        self.curr_tile_col = 1;
-        let second_tile = self.fetch_bg_tile();
-        self.nametable_queue = VecDeque::from(vec![first_tile, second_tile]);
+    }
 
-        // Cycles 337-340
-        // fetch tile 3 of next scanline two times
-        // don't think we ACTUALLY need to perform the fetch, just waste the 3 cycles
+    pub fn snapshot(&self) -> PpuSnapshot {
+        // t holds the scroll for the top of the next frame: coarse x/y, fine y and the nametable select,
+        // which is the high bit of both coordinates
+        let t = self.t;
+        let coarse_x = t & 0x1F;
+        let coarse_y = (t >> 5) & 0x1F;
+        let fine_y = (t >> 12) & 0b111;
+        PpuSnapshot {
+            scanline: self.curr_scanline,
+            dot: self.dot,
+            bg_pattern_table: self.bg_pattern_override.unwrap_or(self.bg_pattern_address),
+            sprite_pattern_table: self
+                .sprite_pattern_override
+                .unwrap_or(self.sprite_pattern_address),
+            sprite_height: if self.sprite_size { 16 } else { 8 },
+            scroll_x: ((t >> 10) & 1) * 256 + coarse_x * 8 + self.fine_x,
+            scroll_y: ((t >> 11) & 1) * 240 + coarse_y * 8 + fine_y,
+            show_background: self.show_background,
+            show_sprites: self.show_sprites,
+            vblank: self.is_vblank,
+            sprite_zero_hit: self.sprite_hit,
+            sprite_overflow: self.sprite_overflow,
+        }
+    }
 
-        self.num_cycles += 341;
+    /// Everything that decides what the PPU does next, flattened for checksumming: internal registers,
+    /// flags, the current scanline, OAM, nametables and palettes. Pattern tables belong to the cartridge.
+    pub fn state_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for word in [self.v, self.t, self.fine_x] {
+            bytes.extend(word.to_le_bytes());
+        }
+        bytes.extend(self.curr_scanline.to_le_bytes());
+        bytes.extend([
+            self.w as u8,
+            self.increment,
+            self.sprite_size as u8,
+            self.generate_nmi as u8,
+            self.is_vblank as u8,
+            self.odd_frame as u8,
+            self.sprite_hit as u8,
+            self.sprite_overflow as u8,
+            self.read_buffer,
+            self.oam_address,
+            self.greyscale_mask,
+            self.emphasis,
+            self.show_background as u8,
+            self.show_sprites as u8,
+            self.clip_background as u8,
+            self.clip_sprites as u8,
+        ]);
+        bytes.extend(self.sprite_pattern_address.to_le_bytes());
+        bytes.extend(self.bg_pattern_address.to_le_bytes());
+        bytes.extend(self.oam.sprite_info);
+        bytes.extend(&self.vram.buffer[0x2000..]);
+        bytes
     }
 
-    pub fn noop_scanline(&mut self) {
-        self.num_cycles += 341;
+    /// An opaque pixel of sprite 0 landed on dot `x`, over background pixel value `bg_pixel`
+    fn check_sprite_zero_hit(&mut self, x: usize, bg_pixel: u8) {
+        let clipped = x < 8 && (self.clip_background || self.clip_sprites);
+        if bg_pixel != 0 && x != 255 && self.show_background && self.show_sprites && !clipped {
+            self.sprite_hit = true;
+        }
     }
 
-    pub fn tick(&mut self) {
-        match self.curr_scanline {
-            -1 => {
-                // Scanline -1 (PRE)
+    /// Work done on dot `dot` (0-340) of the pre-render line or a visible one.
+    /// The same steps the hardware spreads over the line, at the dots where their results become visible.
+    fn render_dot(&mut self, dot: usize) {
+        let pre_render = self.curr_scanline == -1;
+        match dot {
+            1 if pre_render => {
                 self.is_vblank = false;
-                self.tick_scanline(false);
+                self.sprite_hit = false;
+                self.sprite_overflow = false;
+                self.in_reset = false;
+                // Rendering starting with OAMADDR at 8 or more copies the 8 bytes of its row over sprites 0 and 1
+                if self.rendering_enabled() && self.oam_address >= 8 {
+                    let row = (self.oam_address & 0xF8) as usize;
+                    self.oam.sprite_info.copy_within(row..row + 8, 0);
+                }
             }
-            0..=239 => {
-                // Scanline 0 - 239 (VISIBLE)
-                self.tick_scanline(true);
+            // Dots 1-256
+            // a pixel per dot out of the shifters, which are reloaded with the next tile fetch every 8 dots
+            1..=256 if !pre_render => {
+                let x = dot - 1;
+                self.render_pixel(x);
+                self.shift_bg_shifters();
+
+                if x % 8 == 7 {
+                    // the first two tiles were prefetched on the previous line, so this is 2 tiles ahead
+                    let tile = self.fetch_bg_tile();
+                    self.load_bg_shifters(&tile);
+                    if self.rendering_enabled() {
+                        self.increment_coarse_x();
+                    }
+                }
+                if dot == 256 && self.rendering_enabled() {
+                    self.increment_y();
+                }
             }
-            240 => {
-                // Scanline 240 (IDLE)
-                self.noop_scanline();
+            // Dots 1-64 clear secondary OAM and 65-256 evaluate sprites for the next line, all done here at once
+            // before the sprite fetches of dots 257-320. The pre-render line goes through the same steps
+            // (its evaluation never matches anything), so scanline 0 starts with a clean sprite state.
+            // With rendering disabled nothing is evaluated, so the next line has no sprites.
+            257 => {
+                self.clear_secondary_oam();
+                if self.rendering_enabled() {
+                    self.evaluate_sprite();
+                }
+                self.fetch_sprite_data();
+                if self.rendering_enabled() {
+                    self.copy_horizontal_t_to_v();
+                    // OAMADDR is cleared on every tick of the sprite fetches, so it's 0 once they're done
+                    self.oam_address = 0;
+                }
             }
-            241..=260 => {
-                // Scanline 241-260 (VBLANK)
-                self.is_vblank = true;
-                // frame's pixels are ready to be displayed now
-                // Invoke NMI ?
-                self.noop_scanline();
+            // sprite pattern fetches from $1000 are what clock the MMC3's scanline counter
+            260 if self.rendering_enabled() => self.vram.clock_scanline(),
+            // Dots 280-304 of the pre-render line
+            280 if pre_render && self.rendering_enabled() => self.copy_vertical_t_to_v(),
+            // Dots 321-336
+            // prefetch the first two tiles of the next line into the shifters
+            328 => {
+                let first_tile = self.fetch_bg_tile();
+                self.load_bg_shifters(&first_tile);
+                for _ in 0..8 {
+                    self.shift_bg_shifters();
+                }
+                if self.rendering_enabled() {
+                    self.increment_coarse_x();
+                }
             }
-            _ => {
-                self.curr_scanline = -2;
+            336 => {
+                let second_tile = self.fetch_bg_tile();
+                self.load_bg_shifters(&second_tile);
+                if self.rendering_enabled() {
+                    self.increment_coarse_x();
+                }
             }
-        };
+            // Dots 337-340 fetch tile 3 of the next line twice, which nothing uses
+            _ => {}
+        }
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.show_background || self.show_sprites
+    }
+
+    /// On the pre-render line or a visible one with rendering enabled, when OAM belongs to the sprite circuitry
+    fn rendering_in_progress(&self) -> bool {
+        self.rendering_enabled() && (-1..240).contains(&self.curr_scanline)
+    }
+
+    /// Next tile to the right, into the horizontally adjacent nametable after column 31
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// Next pixel row down: fine y first, then coarse y, switching to the vertically adjacent nametable after row 29.
+    /// Rows 30 and 31 (the attribute table) wrap to 0 without switching.
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    // v: ....A.. ...BCDEF <- t: ....A.. ...BCDEF
+    fn copy_horizontal_t_to_v(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
 
-        self.curr_scanline += 1;
-        self.curr_tile_row = (self.curr_scanline / 8) as usize;
+    // v: GHIA.BC DEF..... <- t: GHIA.BC DEF.....
+    fn copy_vertical_t_to_v(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+
+    /// Run the PPU for one dot. Returns true when that dot started vblank (dot 1 of scanline 241),
+    /// even if a $2002 read kept the flag from being set.
+    pub fn step_dot(&mut self) -> bool {
+        let dot = self.dot;
+        let mut vblank_started = false;
+        match self.curr_scanline {
+            // Scanline -1 (PRE) and 0-239 (VISIBLE)
+            -1..=239 => self.render_dot(dot),
+            // Scanline 241-260 (VBLANK), the frame's pixels are ready to be displayed now
+            241 if dot == 1 => {
+                if !std::mem::take(&mut self.suppress_vblank) {
+                    self.is_vblank = true;
+                    self.nmi_pending |= self.generate_nmi;
+                }
+                vblank_started = true;
+            }
+            // Scanline 240 (IDLE)
+            _ => {}
+        }
+
+        self.num_cycles += 1;
+        let skip_last_dot = self.curr_scanline == -1 && self.odd_frame && self.rendering_enabled();
+        if dot == 340 || (dot == 339 && skip_last_dot) {
+            self.dot = 0;
+            self.curr_scanline = match self.curr_scanline {
+                260 => -1,
+                -1 => {
+                    self.odd_frame = !self.odd_frame;
+                    self.frame += 1;
+                    0
+                }
+                scanline => scanline + 1,
+            };
+        } else {
+            self.dot += 1;
+        }
+        vblank_started
+    }
+
+    /// Whether the NMI output went active since the last call: vblank started with NMIs enabled in PPUCTRL,
+    /// or they were enabled during vblank
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    /// Run the PPU for `dots` dots, 3 per CPU cycle. Returns true if vblank started along the way.
+    pub fn step(&mut self, dots: usize) -> bool {
+        let mut vblank_started = false;
+        for _ in 0..dots {
+            vblank_started |= self.step_dot();
+        }
+        vblank_started
+    }
+
+    /// Run the PPU to the end of the current scanline
+    pub fn tick(&mut self) {
+        let scanline = self.curr_scanline;
+        while self.curr_scanline == scanline {
+            self.step_dot();
+        }
     }
 
     /// Clear the Secondary OAM from the previous scanline
@@ -778,94 +1615,563 @@ impl PPU {
     pub fn clear_secondary_oam(&mut self) {
         self.secondary_oam = SEC_OAM::new();
         self.num_sprites = 0;
+        self.sprite_zero_in_range = false;
     }
 
     /// Evaluate Sprites for next line
     /// Cycles 65 - 256 (occcurs concurrently with background fetching and current scanline rendering)
     pub fn evaluate_sprite(&mut self) {
-        let curr_scanline = self.curr_scanline as u8;
+        // nothing is in range on the pre-render line
+        if self.curr_scanline < 0 {
+            return;
+        }
+        let curr_scanline = self.curr_scanline as usize;
+        let height = if self.sprite_size { 16 } else { 8 };
         for i in 0..64 {
-            let curr_y = self.oam.sprite_info[i * 4];
-            if curr_y <= curr_scanline
-                && (self.sprite_size && curr_scanline < curr_y.wrapping_add(16)
-                    || !self.sprite_size && curr_scanline < curr_y.wrapping_add(8))
-            {
-                if self.num_sprites < 8 {
-                    for k in 0..4 {
-                        self.secondary_oam.sprite_info[self.num_sprites * 4 + k] =
-                            self.oam.sprite_info[i * 4 + k];
-                    }
-                    self.num_sprites += 1;
-                } else {
-                    self.sprite_overflow = true;
-                    break;
-                }
+            // above the sprite the subtraction wraps around, so one compare checks both ends of its Y range
+            // (done in usize so sprites at Y near 255 don't wrap back onto the top lines)
+            if curr_scanline.wrapping_sub(self.oam.sprite_info[i * 4] as usize) >= height {
+                continue;
+            }
+            if self.num_sprites == 8 {
+                self.sprite_overflow = true;
+                break;
+            }
+
+            if i == 0 {
+                self.sprite_zero_in_range = true;
+            }
+            for k in 0..4 {
+                self.note_oam_read(i * 4 + k);
+            }
+            let slot = self.num_sprites * 4;
+            self.secondary_oam.sprite_info[slot..slot + 4]
+                .copy_from_slice(&self.oam.sprite_info[i * 4..i * 4 + 4]);
+            self.num_sprites += 1;
+
+            if self.num_sprites == 8 && self.accurate_sprite_overflow {
+                self.sprite_overflow |= self.overflow_scan(i + 1, curr_scanline, height);
+                break;
+            }
+        }
+    }
+
+    /// How the hardware looks for a ninth sprite once secondary OAM is full, starting at sprite `first`.
+    /// Each miss moves it to the next sprite but also, by mistake, to the next byte within a sprite, so it goes
+    /// diagonally through OAM comparing tile numbers, attributes and X positions as if they were Y. That can
+    /// miss a ninth sprite that's there and find one that isn't.
+    fn overflow_scan(&self, first: usize, curr_scanline: usize, height: usize) -> bool {
+        let mut byte = 0;
+        for i in first..64 {
+            let y = self.oam.sprite_info[i * 4 + byte] as usize;
+            if curr_scanline.wrapping_sub(y) < height {
+                return true;
             }
+            byte = (byte + 1) % 4;
         }
+        false
     }
 
     /// Fetch Sprite Data
     /// Cycles 257 - 320
+    /// Loads the sprite units for the next scanline. Units without a sprite get transparent patterns.
     pub fn fetch_sprite_data(&mut self) {
-        for i in 0..self.num_sprites {
-            let y = self.secondary_oam.sprite_info[i * 4];
-            let tile_idx = self.secondary_oam.sprite_info[i * 4 + 1];
-            let attribute_byte = self.secondary_oam.sprite_info[i * 4 + 2];
-            let x = self.secondary_oam.sprite_info[i * 4 + 3];
-
-            let mut curr_row = (self.curr_scanline as u8 - y) % 8;
-            let mut actual_address = self.sprite_pattern_address;
-
-            if self.sprite_size {
-                let bottom = tile_idx & 1;
-
-                if bottom == 1 {
-                    actual_address = 0x1000;
-                } else {
-                    actual_address = 0x0000;
-                }
-
-                let actual_idx = (tile_idx >> 1) << 1; // basically clears last bit
-
-                actual_address += (actual_idx as u16) * 16;
+        for i in 0..8 {
+            if i >= self.num_sprites {
+                self.sprite_units[i] = SpriteUnit::default();
+                continue;
+            }
 
-                if curr_row >= 8 {
-                    actual_address += 16;
-                    curr_row = curr_row % 8;
-                }
+            let [y, tile_idx, attribute_byte, x]: [u8; 4] = self.secondary_oam.sprite_info
+                [i * 4..i * 4 + 4]
+                .try_into()
+                .unwrap();
 
-                actual_address += curr_row as u16;
-            } else {
-                actual_address += (tile_idx as u16 * 16) + curr_row as u16
+            let height = if self.sprite_size { 16 } else { 8 };
+            let mut curr_row = (self.curr_scanline as u8).wrapping_sub(y) % height;
+            // vertical flip picks the row from the bottom of the sprite, for 8x16 the bottom of the lower tile
+            if attribute_byte & 0x80 != 0 {
+                curr_row = height - 1 - curr_row;
             }
 
-            let pattern_address = actual_address;
-            let pattern_lo = self.vram.get(pattern_address.into());
-            let pattern_hi = self.vram.get((pattern_address + 8).into());
-
-            let horizontal_flip_bit = if attribute_byte & 0x20 != 0 {
-                true
+            let pattern_address = if self.sprite_size {
+                // bit 0 of the tile index picks the pattern table, the top tile is the even one and the bottom the odd
+                let table = (tile_idx as u16 & 1) * 0x1000;
+                let tile = (tile_idx & 0xFE) as u16 + (curr_row / 8) as u16;
+                table + tile * 16 + (curr_row % 8) as u16
             } else {
-                false
-            };
-            let vertical_flip_bit = if attribute_byte & 0x40 != 0 {
-                true
-            } else {
-                false
-            };
-            let priority_bit = if attribute_byte & 0x80 != 0 {
-                true
-            } else {
-                false
+                let table = self
+                    .sprite_pattern_override
+                    .unwrap_or(self.sprite_pattern_address);
+                table + tile_idx as u16 * 16 + curr_row as u16
             };
 
-            self.sprite_queue.push_back(Sprite::new(
-                horizontal_flip_bit,
-                vertical_flip_bit,
-                priority_bit,
+            let mut pattern_lo = self.vram.get(pattern_address.into());
+            let mut pattern_hi = self.vram.get((pattern_address + 8).into());
+
+            // the shifters always shift out bit 7 first, so horizontal flip reverses the bits when loading
+            if attribute_byte & 0x40 != 0 {
+                pattern_lo = pattern_lo.reverse_bits();
+                pattern_hi = pattern_hi.reverse_bits();
+            }
+
+            self.sprite_units[i] = SpriteUnit {
                 pattern_lo,
                 pattern_hi,
-            ));
+                attributes: attribute_byte,
+                x_counter: x,
+                sprite_zero: i == 0 && self.sprite_zero_in_range,
+            };
+        }
+    }
+
+    /// Advance every sprite unit by one dot of a visible scanline and return the pixel they output there.
+    /// Lower units (lower OAM indices) win when more than one is opaque.
+    pub fn clock_sprite_units(&mut self) -> Option<SpritePixel> {
+        let mut output = None;
+        for unit in self.sprite_units.iter_mut() {
+            if unit.x_counter > 0 {
+                unit.x_counter -= 1;
+                continue;
+            }
+
+            let pixel = ((unit.pattern_hi >> 7) << 1) | (unit.pattern_lo >> 7);
+            unit.pattern_lo <<= 1;
+            unit.pattern_hi <<= 1;
+            if output.is_none() && pixel != 0 {
+                output = Some(SpritePixel {
+                    pixel,
+                    palette: unit.attributes & 0b11,
+                    behind_background: unit.attributes & 0x20 != 0,
+                    sprite_zero: unit.sprite_zero,
+                });
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::{test_image, Cartridge};
+
+    fn ppu() -> PPU {
+        PPU::new(Rc::new(RefCell::new(vec![])))
+    }
+
+    #[test]
+    fn ppuctrl_nametable_select() {
+        let mut ppu = ppu();
+        for (value, address) in [(0, 0x2000), (1, 0x2400), (2, 0x2800), (3, 0x2C00)] {
+            ppu.ppu_ctrl(value);
+            assert_eq!(
+                0x2000 | (ppu.t & 0x0C00),
+                address,
+                "PPUCTRL = {:#04x}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn snapshot_decodes_scroll_and_sizes() {
+        let mut ppu = ppu();
+        ppu.ppu_ctrl(0b0011_0011);
+        ppu.ppu_scroll(13);
+        ppu.ppu_scroll(200);
+        let snapshot = ppu.snapshot();
+
+        assert_eq!(
+            (snapshot.scroll_x, snapshot.scroll_y),
+            (256 + 13, 240 + 200)
+        );
+        assert_eq!(snapshot.bg_pattern_table, 0x1000);
+        assert_eq!(snapshot.sprite_pattern_table, 0x0000);
+        assert_eq!(snapshot.sprite_height, 16);
+    }
+
+    #[test]
+    fn ppudata_writes_mark_pattern_tiles() {
+        let mut ppu = ppu();
+        for (address, value) in [(0x2006, 0x12), (0x2006, 0x34), (0x2007, 0xFF)] {
+            ppu.write_register(address, value);
+        }
+        // and the nametables aren't pattern tiles
+        for (address, value) in [(0x2006, 0x20), (0x2006, 0x00), (0x2007, 0xFF)] {
+            ppu.write_register(address, value);
+        }
+
+        let modified = ppu.take_modified_tiles();
+        assert!(modified[0x1234 / 16]);
+        assert_eq!(modified.iter().filter(|&&tile| tile).count(), 1);
+        assert!(!ppu.take_modified_tiles().contains(&true));
+    }
+
+    #[test]
+    fn notes_chr_bank_switches() {
+        // CNROM with two 8 KB CHR banks
+        let mapper = Cartridge::from_ines_bytes(&test_image(3, 1, 2))
+            .unwrap()
+            .build_mapper()
+            .unwrap();
+        let mapper = Rc::new(RefCell::new(mapper));
+        let mut ppu = ppu();
+        ppu.vram.mapper = Some(Rc::clone(&mapper));
+        assert_eq!(ppu.take_chr_bank_changes(), [false; 8]);
+
+        mapper.borrow_mut().cpu_write(0x8000, 1);
+        assert_eq!(ppu.take_chr_bank_changes(), [true; 8]);
+        assert_eq!(ppu.take_chr_bank_changes(), [false; 8]);
+        // and back again before anyone looked
+        mapper.borrow_mut().cpu_write(0x8000, 0);
+        mapper.borrow_mut().cpu_write(0x8000, 1);
+        assert_eq!(ppu.take_chr_bank_changes(), [false; 8]);
+    }
+
+    #[test]
+    fn flags_oam_reads_before_writes() {
+        let mut ppu = ppu();
+        ppu.check_oam_poisoning = true;
+        ppu.oam_addr(4);
+        ppu.oam_data_write(0x20);
+        ppu.oam_addr(4);
+        ppu.oam_data_read();
+        ppu.oam_addr(5);
+        ppu.oam_data_read();
+        assert_eq!(ppu.take_oam_poisoned_reads(), vec![5]);
+
+        ppu.oam_dma(0x02, &[0xFF; 256]);
+        ppu.oam_addr(5);
+        ppu.oam_data_read();
+        assert!(ppu.take_oam_poisoned_reads().is_empty());
+    }
+
+    #[test]
+    fn oamdata_during_rendering() {
+        let mut ppu = PPU::new(Rc::new(RefCell::new(vec![0; 256 * 240])));
+        ppu.oam_addr(2);
+        ppu.oam_data_write(0xFF);
+        ppu.oam_addr(2);
+        assert_eq!(ppu.oam_data_read(), 0xE3);
+
+        ppu.ppu_mask(0x18);
+        while (ppu.curr_scanline, ppu.dot) != (10, 30) {
+            ppu.step_dot();
+        }
+        assert_eq!(ppu.oam_data_read(), 0xFF);
+        ppu.oam_addr(2);
+        ppu.oam_data_write(0x12);
+        assert_eq!(ppu.oam_address, 6);
+        assert_eq!(ppu.oam.sprite_info[2], 0xE3);
+
+        // OAMADDR is left at 0 by the sprite fetches
+        ppu.step(300);
+        assert_eq!(ppu.oam_address, 0);
+    }
+
+    #[test]
+    fn rendering_starts_with_oamaddr_row_copied_to_sprite_0() {
+        let mut ppu = PPU::new(Rc::new(RefCell::new(vec![0; 256 * 240])));
+        let oam: Vec<u8> = (0..=255).map(|byte| byte & 0xE3).collect();
+        ppu.oam_dma(0x02, &oam);
+        while ppu.curr_scanline != 250 {
+            ppu.step_dot();
+        }
+        ppu.oam_addr(0x1A);
+        ppu.ppu_mask(0x18);
+        while (ppu.curr_scanline, ppu.dot) != (-1, 2) {
+            ppu.step_dot();
         }
+        assert_eq!(ppu.oam.sprite_info[..8], oam[0x18..0x20]);
+        assert_eq!(ppu.oam.sprite_info[8..], oam[8..]);
+    }
+
+    #[test]
+    fn overflow_scan_goes_diagonally() {
+        // sprites 0-7 on line 20, then sprites 8 and 9
+        let overflow = |sprite_8: [u8; 4], sprite_9: [u8; 4], accurate| {
+            let mut oam = [0xFF; 256];
+            for sprite in oam[..32].chunks_mut(4) {
+                sprite.copy_from_slice(&[16, 0, 0, 0]);
+            }
+            oam[32..36].copy_from_slice(&sprite_8);
+            oam[36..40].copy_from_slice(&sprite_9);
+
+            let mut ppu = ppu();
+            ppu.accurate_sprite_overflow = accurate;
+            ppu.oam_dma(0x02, &oam);
+            ppu.curr_scanline = 20;
+            ppu.clear_secondary_oam();
+            ppu.evaluate_sprite();
+            ppu.sprite_overflow
+        };
+
+        // the hardware compares sprite 9's tile number, not its Y
+        let tile_in_range = ([200, 0, 0, 0], [200, 18, 0, 0]);
+        let y_in_range = ([200, 0, 0, 0], [16, 200, 0, 0]);
+        assert!(!overflow(tile_in_range.0, tile_in_range.1, false));
+        assert!(overflow(tile_in_range.0, tile_in_range.1, true));
+        assert!(overflow(y_in_range.0, y_in_range.1, false));
+        assert!(!overflow(y_in_range.0, y_in_range.1, true));
+    }
+
+    #[test]
+    fn ppuctrl_bits() {
+        let mut ppu = ppu();
+        ppu.ppu_ctrl(0);
+        assert_eq!(ppu.increment, 1);
+        assert_eq!(ppu.sprite_pattern_address, 0x0000);
+        assert_eq!(ppu.bg_pattern_address, 0x0000);
+        assert!(!ppu.sprite_size);
+        assert!(!ppu.master_slave_select);
+        assert!(!ppu.generate_nmi);
+
+        ppu.ppu_ctrl(1 << 2);
+        assert_eq!(ppu.increment, 32);
+        ppu.ppu_ctrl(1 << 3);
+        assert_eq!(ppu.sprite_pattern_address, 0x1000);
+        assert_eq!(ppu.bg_pattern_address, 0x0000);
+        ppu.ppu_ctrl(1 << 4);
+        assert_eq!(ppu.bg_pattern_address, 0x1000);
+        assert_eq!(ppu.sprite_pattern_address, 0x0000);
+        ppu.ppu_ctrl(1 << 5);
+        assert!(ppu.sprite_size);
+        ppu.ppu_ctrl(1 << 6);
+        assert!(ppu.master_slave_select);
+        ppu.ppu_ctrl(1 << 7);
+        assert!(ppu.generate_nmi);
+        assert_eq!(ppu.t & 0x0C00, 0);
+    }
+
+    #[test]
+    fn ppumask_bits() {
+        let mut ppu = ppu();
+        ppu.ppu_mask(0);
+        assert!(!ppu.is_greyscale);
+        assert!(ppu.clip_background);
+        assert!(ppu.clip_sprites);
+        assert!(!ppu.show_background);
+        assert!(!ppu.show_sprites);
+        assert_eq!(ppu.emphasis, 0);
+        assert_eq!(ppu.greyscale_mask, 0x3F);
+
+        ppu.ppu_mask(1 << 0);
+        assert!(ppu.is_greyscale);
+        assert_eq!(ppu.greyscale_mask, 0x30);
+        ppu.ppu_mask(1 << 1);
+        assert!(!ppu.clip_background);
+        assert!(ppu.clip_sprites);
+        ppu.ppu_mask(1 << 2);
+        assert!(!ppu.clip_sprites);
+        assert!(ppu.clip_background);
+        ppu.ppu_mask(1 << 3);
+        assert!(ppu.show_background && !ppu.show_sprites);
+        ppu.ppu_mask(1 << 4);
+        assert!(ppu.show_sprites && !ppu.show_background);
+
+        for (bit, emphasis) in [(5, 0b001), (6, 0b010), (7, 0b100)] {
+            ppu.ppu_mask(1 << bit);
+            assert_eq!(ppu.emphasis, emphasis);
+            assert_eq!(
+                [ppu.emphasize_red, ppu.emphasize_green, ppu.emphasize_blue],
+                [bit == 5, bit == 6, bit == 7]
+            );
+        }
+    }
+
+    #[test]
+    fn reset_ignores_some_writes_until_vblank_ends() {
+        let mut ppu = PPU::new(Rc::new(RefCell::new(vec![0; 256 * 240])));
+        ppu.write_register(0x2000, 0x80);
+        ppu.write_register(0x2003, 0x10);
+        ppu.write_register(0x2006, 0x21);
+        ppu.write_register(0x2006, 0x08);
+        ppu.reset();
+        assert!(!ppu.generate_nmi);
+        assert_eq!((ppu.oam_address, ppu.v), (0x10, 0x2108));
+
+        ppu.write_register(0x2000, 0x80);
+        ppu.write_register(0x2003, 0x20);
+        assert_eq!((ppu.generate_nmi, ppu.oam_address), (false, 0x20));
+
+        // past dot 1 of the pre-render line
+        ppu.step(261 * 341 + 2);
+        ppu.write_register(0x2000, 0x80);
+        assert!(ppu.generate_nmi);
+
+        ppu.power_on();
+        assert_eq!((ppu.oam_address, ppu.v, ppu.curr_scanline), (0, 0, 0));
+    }
+
+    #[test]
+    fn vblank_starts_on_dot_1_of_scanline_241() {
+        let mut ppu = PPU::new(Rc::new(RefCell::new(vec![0; 256 * 240])));
+        ppu.step(241 * 341);
+        assert_eq!((ppu.curr_scanline, ppu.is_vblank), (241, false));
+        assert!(!ppu.step_dot() && ppu.step_dot());
+        assert!(ppu.is_vblank);
+
+        // the pre-render line clears it again, on its dot 1 too
+        ppu.step(341 - 2 + 19 * 341 + 1);
+        assert_eq!(ppu.snapshot().dot, 1);
+        assert_eq!((ppu.curr_scanline, ppu.is_vblank), (-1, true));
+        ppu.step_dot();
+        assert!(!ppu.is_vblank);
+    }
+
+    #[test]
+    fn position_counts_frames_from_power_on() {
+        let mut ppu = PPU::new(Rc::new(RefCell::new(vec![0; 256 * 240])));
+        assert_eq!(ppu.position(), PpuPosition::default());
+        ppu.step(261 * 341 + 5);
+        assert_eq!(
+            ppu.position(),
+            PpuPosition {
+                frame: 0,
+                scanline: -1,
+                dot: 5
+            }
+        );
+        ppu.step(341 - 5 + 2);
+        assert_eq!(
+            ppu.position(),
+            PpuPosition {
+                frame: 1,
+                scanline: 0,
+                dot: 2
+            }
+        );
+
+        ppu.power_on();
+        assert_eq!(ppu.position(), PpuPosition::default());
+    }
+
+    #[test]
+    fn ppudata_reads_mask_v_to_the_ppu_bus() {
+        let mut ppu = ppu();
+        ppu.vram.set(0x2005, 0x42);
+        // $6005 is $2005 on the bus, which goes through the read buffer like any other nametable byte
+        ppu.v = 0x6005;
+        assert_eq!(ppu.ppu_data_read(), 0);
+        assert_eq!(ppu.ppu_data_read(), 0x42);
+    }
+
+    #[test]
+    fn undriven_bits_read_the_decaying_io_bus() {
+        let mut ppu = ppu();
+        ppu.write_register(0x2000, 0x5A);
+        assert_eq!(ppu.read_register(0x2005), 0x5A);
+        assert_eq!(ppu.read_register(0x2002), 0x1A);
+
+        // a palette read drives only the low 6 bits
+        ppu.write_register(0x2006, 0x3F);
+        ppu.write_register(0x2006, 0x00);
+        ppu.write_register(0x2007, 0x21);
+        ppu.write_register(0x2006, 0x3F);
+        ppu.write_register(0x2006, 0x00);
+        ppu.write_register(0x2003, 0xC0);
+        assert_eq!(ppu.read_register(0x2007), 0xE1);
+
+        ppu.num_cycles += IO_BUS_DECAY_DOTS;
+        assert_eq!(ppu.read_register(0x2001), 0);
+    }
+
+    #[test]
+    fn odd_frames_are_one_dot_shorter_when_rendering() {
+        let mut ppu = PPU::new(Rc::new(RefCell::new(vec![0; 256 * 240])));
+        let mut frame_lengths = vec![];
+        for mask in [0x08, 0x08, 0x08, 0x00, 0x00] {
+            ppu.ppu_mask(mask);
+            let start = ppu.num_cycles;
+            while !ppu.step_dot() {}
+            frame_lengths.push(ppu.num_cycles - start);
+        }
+        // the first frame starts at scanline 0, so is the pre-render line short of a whole one
+        assert_eq!(frame_lengths[1..], [89342, 89341, 89342, 89342]);
+    }
+
+    #[test]
+    fn reading_status_races_vblank() {
+        // (next dot to run when $2002 is read, flag read, flag left set for the rest of vblank, NMI)
+        for (dot, read, stays_set, nmi) in [
+            (0, 0, true, true),
+            (1, 0, false, false),
+            (2, 0x80, false, false),
+            (4, 0x80, false, true),
+        ] {
+            let mut ppu = PPU::new(Rc::new(RefCell::new(vec![0; 256 * 240])));
+            ppu.ppu_ctrl(0x80);
+            while (ppu.curr_scanline, ppu.dot) != (241, dot) {
+                ppu.step_dot();
+            }
+            assert_eq!(ppu.ppu_status() & 0x80, read, "dot {}", dot);
+            ppu.step(20);
+            assert_eq!(
+                (ppu.is_vblank, ppu.take_nmi()),
+                (stays_set, nmi),
+                "dot {}",
+                dot
+            );
+        }
+    }
+
+    fn sprite_line(ppu: &mut PPU) -> Vec<Option<u8>> {
+        (0..256)
+            .map(|_| ppu.clock_sprite_units().map(|sprite| sprite.pixel))
+            .collect()
+    }
+
+    #[test]
+    fn sprite_units_wait_for_x_and_flip() {
+        let mut ppu = ppu();
+        // tile 1: left half is pixel value 1, right half value 2
+        ppu.vram.buffer[0x10] = 0xF0;
+        ppu.vram.buffer[0x18] = 0x0F;
+        let mut oam = [0xFF; 256];
+        oam[0..4].copy_from_slice(&[10, 1, 0x00, 4]);
+        oam[4..8].copy_from_slice(&[10, 1, 0x40, 100]); // horizontally flipped
+        ppu.oam_dma(2, &oam);
+
+        ppu.curr_scanline = 10;
+        ppu.clear_secondary_oam();
+        ppu.evaluate_sprite();
+        ppu.fetch_sprite_data();
+        let line = sprite_line(&mut ppu);
+
+        assert_eq!(line[..4], [None; 4]);
+        assert_eq!(line[4..8], [Some(1); 4]);
+        assert_eq!(line[8..12], [Some(2); 4]);
+        assert_eq!(line[12], None);
+        assert_eq!(line[100..104], [Some(2); 4]);
+        assert_eq!(line[104..108], [Some(1); 4]);
+        assert_eq!(line[108], None);
+    }
+
+    #[test]
+    fn tall_sprites_span_two_tiles() {
+        let mut ppu = ppu();
+        ppu.ppu_ctrl(0x20);
+        // tiles $02/$03 in the $1000 table: solid top tile, left half of the bottom one
+        ppu.vram.buffer[0x1020..0x1028].fill(0xFF);
+        ppu.vram.buffer[0x1030..0x1038].fill(0xF0);
+        let mut oam = [0xFF; 256];
+        oam[0..4].copy_from_slice(&[20, 0x03, 0x00, 0]);
+        oam[4..8].copy_from_slice(&[20, 0x03, 0x80, 16]); // vertically flipped
+        ppu.oam_dma(2, &oam);
+
+        let mut rows = vec![];
+        for scanline in [20, 29] {
+            ppu.curr_scanline = scanline;
+            ppu.clear_secondary_oam();
+            ppu.evaluate_sprite();
+            ppu.fetch_sprite_data();
+            rows.push(sprite_line(&mut ppu));
+        }
+
+        assert_eq!(rows[0][..8], [Some(1); 8]);
+        assert_eq!(rows[0][16..24], [[Some(1); 4], [None; 4]].concat());
+        assert_eq!(rows[1][..8], [[Some(1); 4], [None; 4]].concat());
+        assert_eq!(rows[1][16..24], [Some(1); 8]);
     }
 }
@@ -0,0 +1,141 @@
+// The 64 colors worked out from the composite signal the PPU puts out, instead of a fixed table.
+// Each color is a square wave between two voltage levels (from the upper two bits of its index), in phase with one
+// of 12 points on the color subcarrier (from the lower four). Decoding one subcarrier cycle of it the way a TV
+// would gives luma and chroma, which are turned into RGB. See Bisqwit's palette generator and the NTSC video
+// page on the nesdev wiki.
+
+use std::f32::consts::PI;
+
+use super::RGB;
+
+// Output voltages relative to sync, for the low and high halves of the wave at each brightness level
+const SIGNAL_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+
+/// Knobs for the generated palette, like the ones in Nestopia's and Mesen's palette settings.
+/// The defaults give a palette close to the usual hand-tuned ones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NtscPalette {
+    /// Rotates every color around the color wheel, in degrees
+    pub hue: f32,
+    /// 0 is greyscale, 1 the signal as decoded
+    pub saturation: f32,
+    /// Scales luma, 1 leaves it alone
+    pub brightness: f32,
+    /// Spreads the signal away from (above 1) or towards (below 1) mid grey
+    pub contrast: f32,
+    /// Of the display, 2.2 leaves the decoded signal linear
+    pub gamma: f32,
+}
+
+impl Default for NtscPalette {
+    fn default() -> Self {
+        Self {
+            hue: 0.0,
+            saturation: 1.0,
+            brightness: 1.0,
+            contrast: 1.0,
+            gamma: 1.8,
+        }
+    }
+}
+
+impl NtscPalette {
+    /// The 64 colors, for `PPU::set_palette`
+    pub fn generate(&self) -> [RGB; 0x40] {
+        std::array::from_fn(|index| self.color(index as u8))
+    }
+
+    fn color(&self, index: u8) -> RGB {
+        let level = (index >> 4) as usize & 0b11;
+        let hue = index & 0x0F;
+        // hue 0 stays high (greys), $D stays low (the darker greys), $E and $F are forced to black
+        let (low, high) = match hue {
+            0x0 => (SIGNAL_HIGH[level], SIGNAL_HIGH[level]),
+            0x1..=0xC => (SIGNAL_LOW[level], SIGNAL_HIGH[level]),
+            0xD => (SIGNAL_LOW[level], SIGNAL_LOW[level]),
+            _ => (BLACK, BLACK),
+        };
+
+        // 12 samples per subcarrier cycle, the wave being high for the 6 that line up with its hue
+        let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+        for phase in 0..12 {
+            let in_phase = (hue as usize + phase + 8) % 12 < 6;
+            let voltage = if in_phase { high } else { low };
+            let v = (voltage - BLACK) / (WHITE - BLACK);
+            let v = ((v - 0.5) * self.contrast + 0.5) * self.brightness / 12.0;
+            let angle = PI / 6.0 * phase as f32 + self.hue.to_radians();
+            y += v;
+            i += v * angle.cos();
+            q += v * angle.sin();
+        }
+        let (i, q) = (i * self.saturation, q * self.saturation);
+
+        // FCC YIQ to RGB
+        let channel = |value: f32| {
+            let linear = if value <= 0.0 {
+                0.0
+            } else {
+                value.powf(2.2 / self.gamma)
+            };
+            (linear * 255.95).clamp(0.0, 255.0) as u8
+        };
+        (
+            channel(y + 0.946882 * i + 0.623557 * q),
+            channel(y - 0.274788 * i - 0.635691 * q),
+            channel(y - 1.108545 * i + 1.709007 * q),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_grey((r, g, b): RGB) -> bool {
+        r.abs_diff(g) <= 1 && g.abs_diff(b) <= 1
+    }
+
+    #[test]
+    fn greys_and_blacks_have_no_chroma() {
+        let palette = NtscPalette::default().generate();
+        for index in [0x00, 0x10, 0x20, 0x30, 0x0D, 0x1D, 0x2D, 0x3D] {
+            assert!(is_grey(palette[index]), "${:02X}", index);
+        }
+        for index in [0x0E, 0x0F, 0x1E, 0x1F, 0x2E, 0x2F, 0x3E, 0x3F] {
+            assert_eq!(palette[index], (0, 0, 0), "${:02X}", index);
+        }
+        assert_eq!(palette[0x20], (255, 255, 255));
+        assert!(palette[0x00].0 < palette[0x10].0);
+    }
+
+    #[test]
+    fn knobs_shift_the_colors() {
+        let default = NtscPalette::default().generate();
+        // $16 is red, $1A green and $12 blue
+        assert!(default[0x16].0 > default[0x16].2);
+        assert!(default[0x1A].1 > default[0x1A].0);
+        assert!(default[0x12].2 > default[0x12].1);
+
+        let greyscale = NtscPalette {
+            saturation: 0.0,
+            ..Default::default()
+        };
+        assert!(greyscale.generate().into_iter().all(is_grey));
+
+        // a third of the way around the wheel turns red into what used to be 4 hues further along
+        let rotated = NtscPalette {
+            hue: 120.0,
+            ..Default::default()
+        };
+        assert_eq!(rotated.generate()[0x16], default[0x12]);
+
+        let darker = NtscPalette {
+            brightness: 0.5,
+            ..Default::default()
+        };
+        assert!(darker.generate()[0x20].0 < default[0x20].0);
+    }
+}
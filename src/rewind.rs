@@ -0,0 +1,225 @@
+// Rewinding: a ring of save states (see save_state::SaveState) taken every few frames, which Emulator::rewind walks
+// back through. Only the newest is kept whole. Each older one is a delta against the one after it, and they hardly
+// differ: a few hundred bytes of RAM and VRAM, a few registers. The CBOR encoding packs small numbers into fewer
+// bytes, so a value that grows or shrinks shifts everything after it by a byte or two. Plain XOR deltas would
+// come out as big as the state after that, so the deltas copy runs of the base state and resync after a shift.
+
+use std::collections::VecDeque;
+
+// how far a delta looks for the next run that matches the base
+const MAX_LITERAL: usize = 64;
+const MAX_SHIFT: usize = 4;
+// bytes that have to match for a run to count as one, so a stray equal byte doesn't break up a literal
+const MIN_RUN: usize = 8;
+
+pub struct RewindBuffer {
+    /// Frames between snapshots
+    pub interval: usize,
+    /// Snapshots kept, the oldest are dropped beyond it
+    pub capacity: usize,
+    newest: Option<Vec<u8>>,
+    // oldest first, each against the snapshot after it (the last one against `newest`)
+    older: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    pub fn new(interval: usize, capacity: usize) -> Self {
+        assert!(
+            interval > 0 && capacity > 0,
+            "rewinding needs snapshots to keep"
+        );
+        Self {
+            interval,
+            capacity,
+            newest: None,
+            older: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.newest.replace(state) {
+            self.older
+                .push_back(encode_delta(self.newest.as_ref().unwrap(), &previous));
+        }
+        if self.len() > self.capacity {
+            self.older.pop_front();
+        }
+    }
+
+    /// The newest snapshot, after which the one before it is the newest
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+        self.newest = self
+            .older
+            .pop_back()
+            .map(|delta| apply_delta(&newest, &delta));
+        Some(newest)
+    }
+
+    pub fn len(&self) -> usize {
+        self.newest.iter().len() + self.older.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Bytes the snapshots take up
+    pub fn size(&self) -> usize {
+        self.newest.as_ref().map_or(0, Vec::len) + self.older.iter().map(Vec::len).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.older.clear();
+    }
+}
+
+/// `target` as runs of `base`: each step copies some bytes of `base`, adds some bytes of its own and then skips
+/// some of `base`, all three counts as LEB128
+pub fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < target.len() {
+        let copy = common_run(&target[i..], base.get(j..).unwrap_or_default());
+        i += copy;
+        j += copy;
+
+        let (literal, skip) = resync(base, target, i, j);
+        write_varint(&mut delta, copy);
+        write_varint(&mut delta, literal);
+        delta.extend(&target[i..i + literal]);
+        write_varint(&mut delta, skip);
+        i += literal;
+        j += skip;
+    }
+    delta
+}
+
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut target = vec![];
+    let (mut pos, mut j) = (0, 0);
+    while pos < delta.len() {
+        let copy = read_varint(delta, &mut pos);
+        target.extend(&base[j..j + copy]);
+        j += copy;
+        let literal = read_varint(delta, &mut pos);
+        target.extend(&delta[pos..pos + literal]);
+        pos += literal;
+        j += read_varint(delta, &mut pos);
+    }
+    target
+}
+
+fn common_run(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// How many bytes of `target` from `i` to take as they are, and how many of `base` from `j` to skip, for the two
+/// to line up again. Without a match nearby, the rest of `target` up to MAX_LITERAL bytes, skipping as much of
+/// `base` as there is left of it.
+fn resync(base: &[u8], target: &[u8], i: usize, j: usize) -> (usize, usize) {
+    let remaining = target.len() - i;
+    if j >= base.len() {
+        return (remaining, 0);
+    }
+    for literal in 1..=MAX_LITERAL.min(remaining) {
+        let wanted = &target[i + literal..];
+        let run = MIN_RUN.min(wanted.len());
+        for skip in literal.saturating_sub(MAX_SHIFT)..=literal + MAX_SHIFT {
+            if base.get(j + skip..j + skip + run) == Some(&wanted[..run]) {
+                return (literal, skip);
+            }
+        }
+    }
+    let literal = MAX_LITERAL.min(remaining);
+    (literal, literal.min(base.len() - j))
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // deterministic filler that doesn't repeat itself
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deltas_survive_shifted_bytes() {
+        let base = noise(10_000, 1);
+        let mut target = base.clone();
+        target[3] = 0x55;
+        // a value that grew a byte, one that shrank, then changes on both sides of them
+        target.insert(20, 0x18);
+        target.remove(5000);
+        target[7000] ^= 0xFF;
+        target.extend([1, 2, 3]);
+
+        let delta = encode_delta(&base, &target);
+        assert_eq!(apply_delta(&base, &delta), target);
+        assert!(delta.len() < 64, "{} byte delta", delta.len());
+
+        // nothing in common, and nothing to start from
+        let other = noise(300, 2);
+        assert_eq!(apply_delta(&base, &encode_delta(&base, &other)), other);
+        assert_eq!(apply_delta(&[], &encode_delta(&[], &other)), other);
+        // a base shorter than the literals that replace it
+        let short: Vec<u8> = (0..10).collect();
+        assert_eq!(apply_delta(&short, &encode_delta(&short, &other)), other);
+        assert!(apply_delta(&base, &encode_delta(&base, &[])).is_empty());
+    }
+
+    #[test]
+    fn pops_newest_first_and_drops_the_oldest() {
+        let mut buffer = RewindBuffer::new(1, 3);
+        let states: Vec<Vec<u8>> = (0..5)
+            .map(|n| {
+                let mut state = noise(1000, 7);
+                state[n * 10] = 0xAA;
+                state
+            })
+            .collect();
+        for state in &states {
+            buffer.push(state.clone());
+        }
+        assert_eq!(buffer.len(), 3);
+        assert!(buffer.size() < 1200, "{} bytes", buffer.size());
+
+        assert_eq!(buffer.pop().as_ref(), Some(&states[4]));
+        assert_eq!(buffer.pop().as_ref(), Some(&states[3]));
+        assert_eq!(buffer.pop().as_ref(), Some(&states[2]));
+        assert_eq!(buffer.pop(), None);
+        assert!(buffer.is_empty());
+    }
+}
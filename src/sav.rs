@@ -0,0 +1,122 @@
+// Battery saves (.sav): the cartridge's battery-backed PRG-RAM as a raw dump, no header and nothing after it.
+// That's the format FCEUX, Mesen and most other emulators use, so a save moves between them by copying the file.
+// The dump has to be as big as the PRG-RAM of the board nemsys builds for the ROM, which is what loading it checks
+// against; a NES 2.0 header may declare another size (see Cartridge::save_size), but that RAM isn't emulated.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+
+use crate::mappers::Cartridge;
+
+/// `<rom name>.sav` in `save_dir`, or next to the ROM without one
+pub fn path_for(rom: &Path, save_dir: Option<&Path>) -> PathBuf {
+    match (save_dir, rom.file_name()) {
+        (Some(dir), Some(_)) => dir.join(key_for(rom)),
+        _ => rom.with_extension("sav"),
+    }
+}
+
+/// The storage key of `rom`'s save, `<rom name>.sav` (see storage::StorageBackend)
+pub fn key_for(rom: &Path) -> String {
+    let name = rom.file_name().unwrap_or_default();
+    Path::new(name)
+        .with_extension("sav")
+        .to_string_lossy()
+        .into()
+}
+
+/// Read a save made for `rom`, checking its size against the PRG-RAM of its board
+fn read_for(rom: &str, path: &Path) -> Result<Vec<u8>> {
+    let cartridge = Cartridge::from_ines_rom(rom)?;
+    if !cartridge.battery {
+        bail!("{} has no battery, so nothing to save", rom);
+    }
+    let mapper = cartridge.build_mapper()?;
+    let Some(ram) = mapper.prg_ram() else {
+        bail!("{}'s board has no PRG-RAM to save", rom);
+    };
+
+    let save = fs::read(path)?;
+    if save.len() != ram.len() {
+        bail!(
+            "{} is {} bytes, {} has {} bytes of battery RAM",
+            path.display(),
+            save.len(),
+            rom,
+            ram.len()
+        );
+    }
+    Ok(save)
+}
+
+/// Copy a save from another emulator to where nemsys keeps `rom`'s, returning that path
+pub fn import(rom: &str, from: &Path, save_dir: &Path) -> Result<PathBuf> {
+    let save = read_for(rom, from)?;
+    let path = path_for(Path::new(rom), Some(save_dir));
+    fs::write(&path, save)?;
+    Ok(path)
+}
+
+/// Copy nemsys' save for `rom` to `to`, returning where it was copied from
+pub fn export(rom: &str, to: &Path, save_dir: &Path) -> Result<PathBuf> {
+    let path = path_for(Path::new(rom), Some(save_dir));
+    if !path.exists() {
+        bail!("no save for {} at {}", rom, path.display());
+    }
+    let save = read_for(rom, &path)?;
+    fs::write(to, save)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::mappers::test_image;
+
+    #[test]
+    fn saves_round_trip_and_are_size_checked() {
+        let dir = env::temp_dir().join(format!("nemsys-sav-{}", std::process::id()));
+        let saves = dir.join("saves");
+        fs::create_dir_all(&saves).unwrap();
+
+        // NROM with a battery
+        let mut image = test_image(0, 1, 1);
+        image[6] |= 0b10;
+        let rom = dir.join("game.nes");
+        fs::write(&rom, image).unwrap();
+        let rom = rom.to_str().unwrap();
+
+        let foreign: Vec<u8> = (0..0x2000).map(|i| i as u8).collect();
+        fs::write(dir.join("fceux.sav"), &foreign).unwrap();
+        fs::write(dir.join("short.sav"), [0; 0x1000]).unwrap();
+
+        let imported = import(rom, &dir.join("fceux.sav"), &saves).unwrap();
+        assert_eq!(imported, saves.join("game.sav"));
+        export(rom, &dir.join("mesen.sav"), &saves).unwrap();
+        let exported = fs::read(dir.join("mesen.sav")).unwrap();
+        let short = import(rom, &dir.join("short.sav"), &saves);
+
+        // NES 2.0 declaring 32 KB of battery RAM, which the board doesn't have, so it's still 8 KB
+        let mut nes2 = fs::read(rom).unwrap();
+        nes2[7] = 0x08;
+        nes2[10] = 0x90;
+        let nes2_rom = dir.join("nes2.nes");
+        fs::write(&nes2_rom, nes2).unwrap();
+        let nes2_rom = nes2_rom.to_str().unwrap();
+        fs::write(dir.join("large.sav"), [0; 0x8000]).unwrap();
+        let large = import(nes2_rom, &dir.join("large.sav"), &saves);
+        let fits = import(nes2_rom, &dir.join("fceux.sav"), &saves);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(exported, foreign);
+        assert!(short.is_err());
+        assert!(large.is_err());
+        assert!(fits.is_ok());
+    }
+}
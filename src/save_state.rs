@@ -0,0 +1,93 @@
+// Save states: the whole machine (CPU, RAM, APU, controllers, PPU and the cartridge's registers and RAM) as bytes
+// that put it back exactly as it was, kept wherever the emulator's StorageBackend keeps saves. They are the magic,
+// the format version as a little-endian u32, then the state as CBOR. The ROMs aren't in it, only their mapper
+// number and checksum, and a state only loads into the game those match.
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cpu::CpuState,
+    mappers::{CartridgeId, MapperState},
+    ppu::PpuState,
+};
+
+pub const MAGIC: &[u8; 4] = b"NSST";
+/// Bumped whenever what a component saves changes. States from other versions are refused instead of misread.
+pub const VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveState {
+    pub cpu: CpuState,
+    pub ppu: PpuState,
+    /// The game the state was made with, None without a cartridge
+    pub cartridge: Option<CartridgeId>,
+    /// None without a cartridge
+    pub mapper: Option<MapperState>,
+    pub frame_count: usize,
+    pub lag_frame_count: usize,
+    pub last_frame_lagged: bool,
+}
+
+impl SaveState {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        ciborium::into_writer(self, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let Some(body) = bytes.strip_prefix(MAGIC) else {
+            bail!("not a nemsys save state");
+        };
+        let Some((version, state)) = body.split_first_chunk::<4>() else {
+            bail!("save state is truncated");
+        };
+        let version = u32::from_le_bytes(*version);
+        if version != VERSION {
+            bail!(
+                "save state is format version {}, this build reads version {}",
+                version,
+                VERSION
+            );
+        }
+        ciborium::from_reader(state).map_err(|err| anyhow!("save state is corrupt: {}", err))
+    }
+}
+
+/// The storage key of `rom`'s state slot, `<rom name>.state` (see storage::StorageBackend)
+pub fn key_for(rom: &Path) -> String {
+    let name = rom.file_name().unwrap_or_default();
+    Path::new(name)
+        .with_extension("state")
+        .to_string_lossy()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    #[test]
+    fn other_files_and_versions_are_refused() {
+        let bytes = Emulator::headless().save_state().to_bytes().unwrap();
+        assert!(SaveState::from_bytes(&bytes).is_ok());
+
+        let mut newer = bytes.clone();
+        newer[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let err = SaveState::from_bytes(&newer).err().unwrap();
+        assert!(err.to_string().contains("format version"), "{}", err);
+
+        assert!(SaveState::from_bytes(b"NES\x1a").is_err());
+        assert!(SaveState::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn states_are_named_after_the_rom() {
+        assert_eq!(key_for(Path::new("roms/smb.nes")), "smb.state");
+    }
+}
@@ -0,0 +1,45 @@
+// Where battery saves and save states are kept between runs. The emulator names each thing it keeps with a key (a
+// file name such as "game.sav" or "game.state") and hands the bytes to a StorageBackend, so integrators can put them in a database or sync them to a
+// server instead. FileStorage, the default, keeps every key as a file in one directory.
+
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use anyhow::Result;
+
+pub trait StorageBackend {
+    /// What was last stored under `key`, or None if nothing was
+    fn load(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    fn store(&self, key: &str, data: &[u8]) -> Result<()>;
+    /// Where `key` is kept, for messages (a path, a URL, ...)
+    fn describe(&self, key: &str) -> String;
+}
+
+/// Each key is a file of that name in `dir`
+pub struct FileStorage {
+    pub dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+impl StorageBackend for FileStorage {
+    fn load(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.dir.join(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn store(&self, key: &str, data: &[u8]) -> Result<()> {
+        fs::write(self.dir.join(key), data)?;
+        Ok(())
+    }
+
+    fn describe(&self, key: &str) -> String {
+        self.dir.join(key).display().to_string()
+    }
+}
@@ -0,0 +1,36 @@
+// Output convention used by blargg's test ROMs and a lot of 6502 homebrew test suites:
+// $6000        status: $80 while running, $81 when the ROM wants the console reset, otherwise the final result code
+// $6001-$6003  $DE $B0 $61 once the status byte is valid
+// $6004-       zero-terminated ASCII text describing the result
+
+use crate::cpu::memory::Memory;
+
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TestRomStatus {
+    NotStarted,
+    Running,
+    ResetRequested,
+    Finished(u8),
+}
+
+pub fn status(memory: &mut Memory) -> TestRomStatus {
+    if (0..3).any(|i| memory.fetch_absolute(0x6001 + i) != SIGNATURE[i as usize]) {
+        return TestRomStatus::NotStarted;
+    }
+
+    match memory.fetch_absolute(0x6000) {
+        0x80 => TestRomStatus::Running,
+        0x81 => TestRomStatus::ResetRequested,
+        code => TestRomStatus::Finished(code),
+    }
+}
+
+pub fn output_text(memory: &mut Memory) -> String {
+    let text: Vec<u8> = (0x6004..0x8000)
+        .map(|address| memory.fetch_absolute(address))
+        .take_while(|&c| c != 0)
+        .collect();
+    String::from_utf8_lossy(&text).into_owned()
+}
@@ -0,0 +1,251 @@
+// Finding and downloading the external suites the test_cpu harnesses run against.
+// Files are looked up relative to the working directory first (a checkout with nes6502/ and nestest/ in it),
+// then in the cache that `nemsys fetch-tests` fills.
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::utils::checksum;
+
+const SINGLE_STEP_URL: &str =
+    "https://raw.githubusercontent.com/SingleStepTests/ProcessorTests/main/nes6502/v1";
+const TEST_ROMS_URL: &str = "https://raw.githubusercontent.com/christopherpow/nes-test-roms/master";
+
+const MANIFEST: &str = "checksums.txt";
+
+// checksums (see utils::checksum) of the nestest files the trace comparison is known to pass against. The URLs
+// follow a branch, so a download that no longer matches is refused rather than recorded.
+const NESTEST_CHECKSUMS: &[(&str, u64)] = &[
+    ("nestest.nes", 0x5999_d1c3_bb85_bc49),
+    ("nestest.log", 0x7819_eb26_d189_b56b),
+];
+
+/// A file of a test suite: where it lives relative to the cache, where to get it and, for files the harnesses
+/// depend on byte for byte, the checksum it has to have
+pub struct TestFile {
+    pub path: String,
+    pub url: String,
+    pub expected: Option<u64>,
+}
+
+pub fn single_step_files() -> Vec<TestFile> {
+    (0..=255)
+        .map(|opcode| TestFile {
+            path: format!("nes6502/v1/{:02x}.json", opcode),
+            url: format!("{}/{:02x}.json", SINGLE_STEP_URL, opcode),
+            expected: None,
+        })
+        .collect()
+}
+
+pub fn test_rom_files() -> Vec<TestFile> {
+    NESTEST_CHECKSUMS
+        .iter()
+        .map(|&(name, checksum)| TestFile {
+            path: format!("nestest/{}", name),
+            url: format!("{}/other/{}", TEST_ROMS_URL, name),
+            expected: Some(checksum),
+        })
+        .collect()
+}
+
+/// $NEMSYS_CACHE_DIR, otherwise nemsys/tests under the XDG cache directory
+pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("NEMSYS_CACHE_DIR") {
+        return PathBuf::from(dir);
+    }
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("nemsys").join("tests")
+}
+
+/// `relative` in the working directory if it's there, otherwise in the cache
+pub fn locate(relative: &str) -> PathBuf {
+    let local = Path::new(relative);
+    if local.exists() {
+        local.to_path_buf()
+    } else {
+        cache_dir().join(relative)
+    }
+}
+
+/// Checksums of every file fetched into a cache directory, recorded when it was downloaded
+#[derive(Default, Debug, PartialEq)]
+pub struct Manifest {
+    entries: BTreeMap<String, u64>,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (hash, path) = line
+                .split_once("  ")
+                .ok_or_else(|| anyhow!("bad manifest line: {}", line))?;
+            entries.insert(path.to_string(), u64::from_str_radix(hash, 16)?);
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn load(dir: &Path) -> Result<Self> {
+        match fs::read_to_string(dir.join(MANIFEST)) {
+            Ok(text) => Self::parse(&text),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let text: String = self
+            .entries
+            .iter()
+            .map(|(path, hash)| format!("{:016x}  {}\n", hash, path))
+            .collect();
+        fs::write(dir.join(MANIFEST), text)?;
+        Ok(())
+    }
+
+    /// Whether `path` in `dir` exists and still matches its recorded checksum
+    pub fn is_intact(&self, dir: &Path, path: &str) -> bool {
+        match (self.entries.get(path), fs::read(dir.join(path))) {
+            (Some(&expected), Ok(bytes)) => checksum(&bytes) == expected,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct FetchReport {
+    pub downloaded: usize,
+    pub cached: usize,
+    pub failed: Vec<(String, anyhow::Error)>,
+}
+
+/// Download every file that isn't already intact in `dir`. Downloads go through `curl`.
+/// Files that fail are reported rather than aborting the rest.
+pub fn fetch(dir: &Path, files: &[TestFile]) -> Result<FetchReport> {
+    let mut manifest = Manifest::load(dir)?;
+    let mut report = FetchReport::default();
+
+    for file in files {
+        let recorded = manifest.entries.get(&file.path);
+        let outdated = file.expected.is_some() && recorded != file.expected.as_ref();
+        if !outdated && manifest.is_intact(dir, &file.path) {
+            report.cached += 1;
+            continue;
+        }
+        match download(dir, file) {
+            Ok(hash) => {
+                manifest.entries.insert(file.path.clone(), hash);
+                report.downloaded += 1;
+            }
+            Err(err) => report.failed.push((file.path.clone(), err)),
+        }
+        // keep what's been fetched so far if the run is interrupted
+        manifest.save(dir)?;
+    }
+
+    Ok(report)
+}
+
+fn download(dir: &Path, file: &TestFile) -> Result<u64> {
+    let destination = dir.join(&file.path);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // download next to the destination so a partial file never takes its place
+    let partial = destination.with_extension("part");
+    let status = Command::new("curl")
+        .args([
+            "--fail",
+            "--silent",
+            "--show-error",
+            "--location",
+            "--output",
+        ])
+        .arg(&partial)
+        .arg(&file.url)
+        .status()
+        .map_err(|err| anyhow!("couldn't run curl: {}", err))?;
+    if !status.success() {
+        let _ = fs::remove_file(&partial);
+        bail!("curl failed for {} ({})", file.url, status);
+    }
+
+    let hash = checksum(&fs::read(&partial)?);
+    if let Some(expected) = file.expected.filter(|&expected| expected != hash) {
+        let _ = fs::remove_file(&partial);
+        bail!(
+            "{} has checksum {:016x}, expected {:016x}",
+            file.url,
+            hash,
+            expected
+        );
+    }
+    fs::rename(&partial, &destination)?;
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_round_trips_and_detects_changes() {
+        let dir = env::temp_dir().join(format!("nemsys-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.json"), b"[]").unwrap();
+
+        let mut manifest = Manifest::default();
+        manifest
+            .entries
+            .insert("a.json".to_string(), checksum(b"[]"));
+        manifest.save(&dir).unwrap();
+
+        let loaded = Manifest::load(&dir).unwrap();
+        assert_eq!(loaded, manifest);
+        assert!(loaded.is_intact(&dir, "a.json"));
+
+        fs::write(dir.join("a.json"), b"[").unwrap();
+        assert!(!loaded.is_intact(&dir, "a.json"));
+        assert!(!loaded.is_intact(&dir, "missing.json"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nestest_checksums_match_the_checked_in_copies() {
+        for file in test_rom_files() {
+            let bytes = fs::read(&file.path).unwrap();
+            assert_eq!(Some(checksum(&bytes)), file.expected, "{}", file.path);
+        }
+    }
+
+    #[test]
+    fn downloads_with_the_wrong_checksum_are_refused() {
+        let dir = env::temp_dir().join(format!("nemsys-fetch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source.bin");
+        fs::write(&source, b"nestest").unwrap();
+        let file = |expected| TestFile {
+            path: "cache/nestest.nes".to_string(),
+            url: format!("file://{}", source.display()),
+            expected: Some(expected),
+        };
+
+        let report = fetch(&dir, &[file(checksum(b"something else"))]).unwrap();
+        let refused = report.failed.len() == 1 && !dir.join("cache/nestest.nes").exists();
+        let report = fetch(&dir, &[file(checksum(b"nestest"))]).unwrap();
+        let fetched = report.downloaded == 1 && dir.join("cache/nestest.nes").exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(refused);
+        assert!(fetched);
+    }
+}
@@ -9,3 +9,10 @@ pub fn set_bit(num: usize, idx: u8) -> u8 {
 pub fn unset_bit(num: usize, idx: u8) -> u8 {
     (num & !(1 << idx)) as u8
 }
+
+/// 64-bit FNV-1a. Only meant to catch accidental differences (corrupted downloads, diverging emulator state), not tampering.
+pub fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}